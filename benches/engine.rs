@@ -0,0 +1,46 @@
+//! Engine throughput on generated input. Compare the hashers with
+//!
+//! ```text
+//! cargo bench --bench engine
+//! cargo bench --bench engine --features fast-hash
+//! ```
+
+use criterion::{BatchSize, Criterion, Throughput, criterion_group, criterion_main};
+use payments_engine::generate::Generator;
+use payments_engine::{Engine, Transaction, TxType};
+
+const ROWS: usize = 1_000_000;
+const CLIENTS: u16 = 10_000;
+
+fn process(c: &mut Criterion) {
+    let rows: Vec<_> = Generator::new(7, CLIENTS).take(ROWS).collect();
+    let deposits = rows.iter().filter(|tx| tx.kind == TxType::Deposit).count();
+    let mut group = c.benchmark_group("process");
+    group.throughput(Throughput::Elements(ROWS as u64));
+    group.sample_size(10);
+    for (name, presized) in [("new", false), ("with_capacity", true)] {
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || {
+                    let rows: Vec<_> = rows.iter().map(|tx| Transaction { ..*tx }).collect();
+                    let engine = match presized {
+                        true => Engine::with_capacity(CLIENTS as usize, deposits),
+                        false => Engine::new(),
+                    };
+                    (engine, rows)
+                },
+                |(mut engine, rows)| {
+                    for tx in rows {
+                        engine.process(tx).unwrap();
+                    }
+                    engine
+                },
+                BatchSize::PerIteration,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, process);
+criterion_main!(benches);
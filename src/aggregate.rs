@@ -0,0 +1,127 @@
+//! Global view over the accounts reports of regional runs: every client's
+//! balances summed over the runs it appears in (`aggregate` subcommand).
+//!
+//! Unlike [`crate::merge`], which combines whole engines, this works on the
+//! reports alone: the runs may have been processed anywhere, and only their
+//! `accounts.csv` files are at hand. A client in several runs gets the sum
+//! of its available and held funds. Whether it is locked is settled by a
+//! [`LockPolicy`] when the runs disagree:
+//!
+//! * `any` (the default) — locked if any run locked it: a chargeback in one
+//!   region freezes the client everywhere;
+//! * `all` — locked only if every run that has the client locked it;
+//! * `fail` — refuse to aggregate, naming the client and two runs that
+//!   disagree.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::aggregate::{LockPolicy, aggregate};
+//! use payments_engine::report;
+//! use rust_decimal_macros::dec;
+//!
+//! let read = |csv: &str| report::read_accounts(csv.as_bytes()).unwrap();
+//! let eu = read("client,available,held,total,locked\n1,5,1,6,false\n2,3,0,3,true\n");
+//! let us = read("client,available,held,total,locked\n1,2.5,0,2.5,false\n2,1,0,1,false\n");
+//! let runs = || vec![("eu".to_string(), eu.clone()), ("us".to_string(), us.clone())];
+//!
+//! let global = aggregate(runs(), LockPolicy::Any).unwrap();
+//! assert_eq!(global.accounts[&1].available, dec!(7.5));
+//! assert_eq!(global.accounts[&1].held, dec!(1));
+//! assert!(global.accounts[&2].locked);
+//! assert_eq!((global.shared, global.split_locks.as_slice()), (2, &[2][..]));
+//!
+//! assert!(!aggregate(runs(), LockPolicy::All).unwrap().accounts[&2].locked);
+//! let err = aggregate(runs(), LockPolicy::Fail).unwrap_err();
+//! assert_eq!(err.to_string(), "client 2 is locked in eu but not in us");
+//! ```
+
+use crate::errors::Result;
+use crate::fasthash::Map;
+use crate::models::Account;
+use anyhow::bail;
+use std::str::FromStr;
+
+/// How to settle a client locked in some runs but not in others.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LockPolicy {
+    /// Locked if locked in any run.
+    #[default]
+    Any,
+    /// Locked only if locked in every run that has the client.
+    All,
+    /// Refuse to aggregate.
+    Fail,
+}
+
+impl LockPolicy {
+    /// Names accepted by [`LockPolicy::from_str`], for `--locked`.
+    pub const NAMES: [&'static str; 3] = ["any", "all", "fail"];
+}
+
+impl FromStr for LockPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "any" => Ok(LockPolicy::Any),
+            "all" => Ok(LockPolicy::All),
+            "fail" => Ok(LockPolicy::Fail),
+            _ => Err(format!(
+                "unknown lock policy {s:?} (one of {})",
+                Self::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+/// Result of [`aggregate`].
+#[derive(Debug, Clone, Default)]
+pub struct Aggregated {
+    pub accounts: Map<u16, Account>,
+    /// Clients found in more than one run.
+    pub shared: usize,
+    /// Clients the runs disagreed on being locked, in ascending order.
+    pub split_locks: Vec<u16>,
+}
+
+/// Sum the `(label, accounts)` reports of several runs under `locks`;
+/// labels only name the runs in errors.
+pub fn aggregate(runs: Vec<(String, Map<u16, Account>)>, locks: LockPolicy) -> Result<Aggregated> {
+    let mut out = Aggregated::default();
+    // runs of each client so far, and the first run locking / not locking it
+    let mut seen: Map<u16, (usize, Option<usize>, Option<usize>)> = Map::default();
+    for (i, (_, accounts)) in runs.iter().enumerate() {
+        for (&client, acc) in accounts {
+            let (count, locked_in, open_in) = seen.entry(client).or_default();
+            *count += 1;
+            match acc.locked {
+                true => locked_in.get_or_insert(i),
+                false => open_in.get_or_insert(i),
+            };
+            let total = out.accounts.entry(client).or_insert(Account {
+                locked: acc.locked,
+                ..Account::default()
+            });
+            total.available += acc.available;
+            total.held += acc.held;
+        }
+    }
+
+    let mut clients: Vec<_> = seen.into_iter().collect();
+    clients.sort_unstable_by_key(|(client, _)| *client);
+    for (client, (count, locked_in, open_in)) in clients {
+        if count > 1 {
+            out.shared += 1;
+        }
+        let (Some(locked_in), Some(open_in)) = (locked_in, open_in) else {
+            continue;
+        };
+        if locks == LockPolicy::Fail {
+            let (a, b) = (&runs[locked_in].0, &runs[open_in].0);
+            bail!("client {client} is locked in {a} but not in {b}");
+        }
+        out.split_locks.push(client);
+        out.accounts.get_mut(&client).expect("seen client").locked = locks == LockPolicy::Any;
+    }
+    Ok(out)
+}
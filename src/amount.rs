@@ -0,0 +1,305 @@
+//! Amount parsing: the plain input format, and amounts written with
+//! thousand separators or a decimal comma, as some partner exports do
+//! (`1,234.56`, `1.234,56`).
+//!
+//! ### Plain amounts
+//!
+//! [`parse_amount`] reads every `amount` field of the input (through
+//! [`deserialize`]), with a fixed policy rather than whatever the decimal
+//! library happens to accept:
+//!
+//! | input                  | read as         | rule                                   |
+//! | ---------------------- | --------------- | -------------------------------------- |
+//! | `12.50`, `-3`, `+3`    | 12.50, -3, 3    | optional sign, digits, optional point  |
+//! | `.5`, `5.`, `007`      | 0.5, 5, 7       | either side of the point may be empty  |
+//! | `1e3`, `2.5E-2`        | 1000, 0.025     | exponent applied exactly, no floats    |
+//! | `1.5000…0` (40 places) | 1.5000…0 (28)   | zeros past 28 places are dropped       |
+//! | `0.1…1` (29 places)    | error           | no rounding: precision would be lost   |
+//! | `1_000`, `1 000`       | error           | no separators without a locale         |
+//! | `0x10`, `NaN`, `inf`   | error           | decimal digits only                    |
+//! | `-`, `.`, `1e`, `1e+`  | error           | a number needs digits                  |
+//! | `1e10000`              | error           | exponents have at most four digits     |
+//! | `8e28`                 | error           | beyond the 96-bit range of amounts     |
+//!
+//! The scale is kept as written (`1.50` stays 1.50), and an exponent
+//! shifts it (`1.50e1` is 15.0, `1.5e1` is 15).
+//!
+//! ### Localized amounts
+//!
+//! The input format has plain amounts (`1234.56`), and by default anything
+//! else is a parse error that drops the row. With an [`AmountLocale`] hint
+//! for the file (`--amount-locale`), amounts are normalized before the row
+//! is read instead:
+//!
+//! | hint    | group | decimal | `1,234.56` | `1.234,56` | `1.234`             |
+//! | ------- | ----- | ------- | ---------- | ---------- | ------------------- |
+//! | `plain` | —     | `.`     | error      | error      | 1.234               |
+//! | `en`    | `,`   | `.`     | 1234.56    | error      | 1.234               |
+//! | `de`    | `.`   | `,`     | error      | 1234.56    | 1234                |
+//! | `auto`  | either| either  | 1234.56    | 1234.56    | 1.234, *ambiguous*  |
+//!
+//! Groups must be well-formed: one to three digits, then groups of exactly
+//! three. Some values cannot be told apart, and are parsed but reported as
+//! [`Parsed::ambiguous`] so the caller can flag them:
+//!
+//! * under `auto`, a single separator followed by exactly three digits
+//!   (`1,234`, `1.234`) is read as a decimal point, as a plain amount would
+//!   be, though it may well be a thousands separator;
+//! * under `de`, an amount that is not valid German notation but is a valid
+//!   plain amount (`1.5`) is read as plain.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::amount::{AmountLocale, parse_amount};
+//! use rust_decimal_macros::dec;
+//!
+//! assert_eq!(parse_amount("1.5e3").unwrap(), dec!(1500));
+//! assert_eq!(parse_amount(".25").unwrap(), dec!(0.25));
+//! assert!(parse_amount("1_000").is_err());
+//!
+//! let de: AmountLocale = "de".parse().unwrap();
+//! assert_eq!(de.parse("-1.234.567,5").unwrap().amount, dec!(-1234567.5));
+//! assert!(de.parse("1.2345,0").is_err());
+//!
+//! let auto = AmountLocale::Auto;
+//! let p = auto.parse("1,234").unwrap();
+//! assert_eq!((p.amount, p.ambiguous), (dec!(1.234), true));
+//! let p = auto.parse("1.234,00").unwrap();
+//! assert_eq!((p.amount, p.ambiguous), (dec!(1234.00), false));
+//! ```
+
+use rust_decimal::Decimal;
+use serde::Deserializer;
+use serde::de::{self, Visitor};
+use std::fmt;
+use std::str::FromStr;
+
+/// Significant fraction digits an amount may have.
+const MAX_SCALE: usize = 28;
+
+/// Regular expression for the syntax [`parse_amount`] accepts: optional
+/// sign, digits on either side of an optional point, optional exponent of
+/// at most four digits. The scale and range limits are not syntax and are
+/// left to the parser.
+pub const PATTERN: &str = r"^[+-]?([0-9]+\.?[0-9]*|\.[0-9]+)([eE][+-]?[0-9]{1,4})?$";
+
+/// Read a plain amount (already trimmed) under the policy in the module
+/// docs.
+pub fn parse_amount(raw: &str) -> Result<Decimal, String> {
+    let bad = |why: &str| format!("{raw:?} is not an amount: {why}");
+    let digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    let (negative, body) = match raw.strip_prefix('-') {
+        Some(body) => (true, body),
+        None => (false, raw.strip_prefix('+').unwrap_or(raw)),
+    };
+    let (number, exp) = match body.split_once(['e', 'E']) {
+        Some((number, exp)) => (number, Some(exp)),
+        None => (body, None),
+    };
+    let (int, frac) = number.split_once('.').unwrap_or((number, ""));
+    if !digits(int) || !digits(frac) {
+        return Err(bad("expected decimal digits"));
+    }
+    if int.is_empty() && frac.is_empty() {
+        return Err(bad("no digits"));
+    }
+    let exp: i64 = match exp {
+        None => 0,
+        Some(exp) => {
+            let magnitude = exp.strip_prefix(['+', '-']).unwrap_or(exp);
+            if magnitude.is_empty() || !digits(magnitude) {
+                return Err(bad("malformed exponent"));
+            }
+            if magnitude.len() > 4 {
+                return Err(bad("exponent out of range"));
+            }
+            exp.parse().expect("at most four digits")
+        }
+    };
+
+    let mut mantissa = format!("{int}{frac}");
+    let mut scale = frac.len() as i64 - exp;
+    while scale > MAX_SCALE as i64 && mantissa.ends_with('0') {
+        mantissa.pop();
+        scale -= 1;
+    }
+    if scale > MAX_SCALE as i64 {
+        return Err(bad("more than 28 decimal places"));
+    }
+    let significant = mantissa.trim_start_matches('0');
+    if significant.is_empty() {
+        return Ok(Decimal::new(0, scale.max(0) as u32));
+    }
+    // 29 digits fit an i128; more cannot fit the 96-bit mantissa
+    let zeros = (-scale).max(0) as usize;
+    if significant.len() + zeros > 29 {
+        return Err(bad("out of range"));
+    }
+    let value: i128 = format!("{significant}{}", "0".repeat(zeros))
+        .parse()
+        .expect("at most 29 digits");
+    let value = if negative { -value } else { value };
+    Decimal::try_from_i128_with_scale(value, scale.max(0) as u32).map_err(|_| bad("out of range"))
+}
+
+/// Serde `deserialize_with` for an optional `amount` field: empty is
+/// `None`, anything else goes through [`parse_amount`].
+pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Decimal>, D::Error> {
+    d.deserialize_option(AmountVisitor)
+}
+
+struct AmountVisitor;
+
+impl<'de> Visitor<'de> for AmountVisitor {
+    type Value = Option<Decimal>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a decimal amount")
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, d: D) -> Result<Self::Value, D::Error> {
+        d.deserialize_str(self)
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<Self::Value, E> {
+        if s.is_empty() {
+            return Ok(None);
+        }
+        parse_amount(s).map(Some).map_err(E::custom)
+    }
+}
+
+/// Separator convention of the amounts in one input file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AmountLocale {
+    /// No grouping, `.` as decimal point: the input format itself.
+    #[default]
+    Plain,
+    /// `,` groups thousands, `.` is the decimal point.
+    En,
+    /// `.` groups thousands, `,` is the decimal point.
+    De,
+    /// Decide per amount; see the module docs.
+    Auto,
+}
+
+/// An amount read under an [`AmountLocale`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Parsed {
+    pub amount: Decimal,
+    /// Another reading of the text was possible; see the module docs.
+    pub ambiguous: bool,
+}
+
+impl AmountLocale {
+    /// Names accepted by [`AmountLocale::from_str`], for `--amount-locale`.
+    pub const NAMES: [&'static str; 4] = ["plain", "en", "de", "auto"];
+
+    /// Read `raw` (already trimmed) under this convention.
+    pub fn parse(self, raw: &str) -> Result<Parsed, String> {
+        let (sign, body) = match raw.strip_prefix('-') {
+            Some(body) => ("-", body),
+            None => ("", raw.strip_prefix('+').unwrap_or(raw)),
+        };
+        let malformed = || format!("{raw:?} is not a well-formed {} amount", self.name());
+        let clear = |plain: Option<String>| plain.ok_or_else(malformed);
+        let (plain, ambiguous) = match self {
+            AmountLocale::Plain => (body.to_owned(), false),
+            AmountLocale::En => (clear(grouped(body, ',', '.'))?, false),
+            AmountLocale::De => match grouped(body, '.', ',') {
+                Some(plain) => (plain, false),
+                None if parse_amount(body).is_ok() => (body.to_owned(), true),
+                None => return Err(malformed()),
+            },
+            AmountLocale::Auto => {
+                let (commas, points) = (body.matches(',').count(), body.matches('.').count());
+                match (commas, points) {
+                    (0, 0) => (body.to_owned(), false),
+                    (_, 0) | (0, _) if commas + points > 1 => {
+                        let (group, point) = if commas > 0 { (',', '.') } else { ('.', ',') };
+                        (clear(grouped(body, group, point))?, false)
+                    }
+                    (_, 0) | (0, _) => {
+                        let (int, frac) = body.split_once([',', '.']).expect("one separator");
+                        let ambiguous = frac.len() == 3 && !int.is_empty() && !int.starts_with('0');
+                        (format!("{int}.{frac}"), ambiguous)
+                    }
+                    _ if body.rfind(',') > body.rfind('.') => {
+                        (clear(grouped(body, '.', ','))?, false)
+                    }
+                    _ => (clear(grouped(body, ',', '.'))?, false),
+                }
+            }
+        };
+        let amount = parse_amount(&format!("{sign}{plain}"))?;
+        Ok(Parsed { amount, ambiguous })
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            AmountLocale::Plain => "plain",
+            AmountLocale::En => "en",
+            AmountLocale::De => "de",
+            AmountLocale::Auto => "auto",
+        }
+    }
+}
+
+impl FromStr for AmountLocale {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [
+            AmountLocale::Plain,
+            AmountLocale::En,
+            AmountLocale::De,
+            AmountLocale::Auto,
+        ]
+        .into_iter()
+        .find(|l| l.name() == s)
+        .ok_or_else(|| {
+            format!(
+                "unknown amount locale {s:?} (one of {})",
+                Self::NAMES.join(", ")
+            )
+        })
+    }
+}
+
+/// `body` with well-formed `group` separators removed and `point` turned
+/// into `.`; `None` if the grouping is malformed or `point` comes twice.
+fn grouped(body: &str, group: char, point: char) -> Option<String> {
+    let (int, frac) = match body.split_once(point) {
+        Some((int, frac)) => (int, Some(frac)),
+        None => (body, None),
+    };
+    let digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    if frac.is_some_and(|f| !digits(f)) {
+        return None;
+    }
+    let mut groups = int.split(group);
+    let first = groups.next()?;
+    if !digits(first) || (int.contains(group) && !(1..=3).contains(&first.len())) {
+        return None;
+    }
+    let mut plain = first.to_owned();
+    for g in groups {
+        if g.len() != 3 || !digits(g) {
+            return None;
+        }
+        plain.push_str(g);
+    }
+    if let Some(frac) = frac {
+        plain.push('.');
+        plain.push_str(frac);
+    }
+    Some(plain)
+}
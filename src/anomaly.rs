@@ -0,0 +1,213 @@
+//! Rows the engine cannot apply, grouped into anomaly classes, and the
+//! per-class policy deciding what happens to them.
+//!
+//! Every class defaults to [`Action::Ignore`], the historical behaviour:
+//! the row is dropped without trace. Deployments that want more can log,
+//! keep a record (see [`Engine::rejections`](crate::Engine::rejections)) or
+//! abort processing, independently for each class.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::anomaly::{Action, Anomaly, AnomalyPolicy};
+//! use payments_engine::engine::EngineConfig;
+//! use payments_engine::{Engine, Transaction, TxType};
+//! use rust_decimal_macros::dec;
+//!
+//! let mut anomalies = AnomalyPolicy::default();
+//! anomalies.set(Anomaly::InsufficientFunds, Action::Record);
+//! anomalies.set(Anomaly::ClientMismatch, Action::Fatal);
+//! let mut eng = Engine::with_config(EngineConfig { anomalies, ..Default::default() });
+//!
+//! eng.process(Transaction { kind: TxType::Withdrawal, client: 1, tx: 1, amount: Some(dec!(5)) })
+//!     .unwrap();
+//! assert_eq!(eng.rejections()[0].anomaly, Anomaly::InsufficientFunds);
+//!
+//! eng.process(Transaction { kind: TxType::Deposit, client: 1, tx: 2, amount: Some(dec!(5)) })
+//!     .unwrap();
+//! let foreign = Transaction { kind: TxType::Dispute, client: 2, tx: 2, amount: None };
+//! assert!(eng.process(foreign).is_err());
+//! ```
+
+use crate::models::Transaction;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Why a row was not applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Anomaly {
+    /// Deposit, withdrawal, hold, refund or move without an amount.
+    MissingAmount,
+    /// Deposit, withdrawal, hold, refund or move with a zero or negative
+    /// amount.
+    NonPositiveAmount,
+    /// Any row for an account locked by a chargeback.
+    LockedAccount,
+    /// Withdrawal, hold or move larger than the available funds (of its
+    /// bucket).
+    InsufficientFunds,
+    /// Deposit, withdrawal or hold reusing the id of an earlier one.
+    DuplicateTx,
+    /// Dispute, resolve, chargeback, release, capture or refund naming a
+    /// transaction that does not exist.
+    UnknownTx,
+    /// Row referring to another client's transaction.
+    ClientMismatch,
+    /// Dispute of a deposit or refund that is already under dispute.
+    AlreadyDisputed,
+    /// Resolve or chargeback of a deposit or refund that is not under
+    /// dispute.
+    NotDisputed,
+    /// Release or capture of a hold that was already released, captured or
+    /// expired.
+    HoldNotActive,
+    /// Refund that would take the refunded total past the original
+    /// withdrawal.
+    OverRefund,
+    /// Amount above the configured maximum for its type (see
+    /// [`AmountLimits`](crate::engine::AmountLimits)).
+    AmountTooLarge,
+    /// Row whose idempotency key was already applied for the client (see
+    /// [`crate::idempotency`]).
+    DuplicateKey,
+    /// Release of more than an escrowed deposit has left in escrow, or of
+    /// one under dispute (see [`crate::escrow`]).
+    OverRelease,
+    /// Move from a bucket to itself, or a row naming a bucket it cannot use
+    /// (see [`crate::bucket`]).
+    BadBucket,
+}
+
+impl Anomaly {
+    /// Every class, in declaration order.
+    pub const ALL: [Anomaly; 15] = [
+        Anomaly::MissingAmount,
+        Anomaly::NonPositiveAmount,
+        Anomaly::LockedAccount,
+        Anomaly::InsufficientFunds,
+        Anomaly::DuplicateTx,
+        Anomaly::UnknownTx,
+        Anomaly::ClientMismatch,
+        Anomaly::AlreadyDisputed,
+        Anomaly::NotDisputed,
+        Anomaly::HoldNotActive,
+        Anomaly::OverRefund,
+        Anomaly::AmountTooLarge,
+        Anomaly::DuplicateKey,
+        Anomaly::OverRelease,
+        Anomaly::BadBucket,
+    ];
+
+    /// Kebab-case name as used on the command line and in reject files.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Anomaly::MissingAmount => "missing-amount",
+            Anomaly::NonPositiveAmount => "non-positive-amount",
+            Anomaly::LockedAccount => "locked-account",
+            Anomaly::InsufficientFunds => "insufficient-funds",
+            Anomaly::DuplicateTx => "duplicate-tx",
+            Anomaly::UnknownTx => "unknown-tx",
+            Anomaly::ClientMismatch => "client-mismatch",
+            Anomaly::AlreadyDisputed => "already-disputed",
+            Anomaly::NotDisputed => "not-disputed",
+            Anomaly::HoldNotActive => "hold-not-active",
+            Anomaly::OverRefund => "over-refund",
+            Anomaly::AmountTooLarge => "amount-too-large",
+            Anomaly::DuplicateKey => "duplicate-key",
+            Anomaly::OverRelease => "over-release",
+            Anomaly::BadBucket => "bad-bucket",
+        }
+    }
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Anomaly {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Anomaly::ALL
+            .into_iter()
+            .find(|a| a.as_str() == s)
+            .ok_or_else(|| format!("unknown anomaly class {s:?}"))
+    }
+}
+
+/// What to do with a row of a given [`Anomaly`] class.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Action {
+    /// Drop the row silently.
+    #[default]
+    Ignore,
+    /// Drop the row and log a warning.
+    Log,
+    /// Drop the row and keep it in [`Engine::rejections`](crate::Engine::rejections).
+    Record,
+    /// Stop: [`Engine::process`](crate::Engine::process) returns an error.
+    Fatal,
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ignore" => Ok(Action::Ignore),
+            "log" => Ok(Action::Log),
+            "record" => Ok(Action::Record),
+            "fatal" => Ok(Action::Fatal),
+            _ => Err(format!(
+                "unknown anomaly action {s:?} (use ignore, log, record or fatal)"
+            )),
+        }
+    }
+}
+
+/// Anomaly class → action. Classes without an entry are ignored.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AnomalyPolicy {
+    actions: HashMap<Anomaly, Action>,
+}
+
+impl AnomalyPolicy {
+    pub fn set(&mut self, anomaly: Anomaly, action: Action) {
+        self.actions.insert(anomaly, action);
+    }
+
+    pub fn action(&self, anomaly: Anomaly) -> Action {
+        self.actions.get(&anomaly).copied().unwrap_or_default()
+    }
+}
+
+/// A row dropped under [`Action::Record`].
+#[derive(Debug)]
+pub struct Rejection {
+    /// Row sequence number (see [`Engine::seq`](crate::Engine::seq)).
+    pub seq: u64,
+    pub anomaly: Anomaly,
+    pub tx: Transaction,
+}
+
+/// A negative-amount deposit or withdrawal applied as the opposite
+/// operation under [`EngineConfig::normalize_negative`](crate::engine::EngineConfig::normalize_negative).
+#[derive(Debug)]
+pub struct Normalization {
+    /// Row sequence number (see [`Engine::seq`](crate::Engine::seq)).
+    pub seq: u64,
+    /// The row as read, before normalization.
+    pub tx: Transaction,
+}
+
+/// Parse a `class=action` override as given to `--anomaly`.
+pub fn parse_override(s: &str) -> Result<(Anomaly, Action), String> {
+    let (class, action) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected CLASS=ACTION, got {s:?}"))?;
+    Ok((class.parse()?, action.parse()?))
+}
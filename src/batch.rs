@@ -0,0 +1,226 @@
+//! Batch boundaries within a continuous stream: per-batch summaries and
+//! end-of-batch rules, giving daily-file semantics to a run that never
+//! restarts.
+//!
+//! The engine counts every row into the current batch; [`Engine::end_batch`]
+//! closes it, evaluates the [`BatchRules`] of
+//! [`EngineConfig::batch_rules`](crate::engine::EngineConfig::batch_rules),
+//! and returns a [`BatchSummary`]. The CLI ends a batch wherever the
+//! optional `batch` input column changes value and at the end of the input.
+//!
+//! Rules flag rather than lock: here an account is only ever locked by a
+//! chargeback, and every chargeback locks already, so a rule such as "lock
+//! accounts with more than N chargebacks this batch" would never fire. The
+//! rule offered counts disputes opened, the usual early sign; flagged
+//! clients are listed in the summary and raised as
+//! [`Event::DisputeBurst`] for an operator to act on.
+//!
+//! Batch counters are not saved in snapshots, so a run resumed mid-batch
+//! starts counting that batch afresh; checkpointing at batch ends
+//! (`--snapshot-every batch`) avoids that.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::batch::BatchRules;
+//! use payments_engine::engine::EngineConfig;
+//! use payments_engine::{Engine, Transaction, TxType};
+//! use rust_decimal_macros::dec;
+//!
+//! let batch_rules = BatchRules { max_disputes: Some(1) };
+//! let mut eng = Engine::with_config(EngineConfig { batch_rules, ..Default::default() });
+//! let row = |kind, tx, amount| Transaction { kind, client: 4, tx, amount };
+//! for tx in 1..=2 {
+//!     eng.process(row(TxType::Deposit, tx, Some(dec!(10)))).unwrap();
+//!     eng.process(row(TxType::Dispute, tx, None)).unwrap();
+//! }
+//!
+//! let day1 = eng.end_batch().unwrap();
+//! assert_eq!((day1.batch, day1.applied, day1.deposited), (1, 4, dec!(20)));
+//! assert_eq!(day1.flagged, [4]);
+//!
+//! eng.process(row(TxType::Resolve, 1, None)).unwrap();
+//! let day2 = eng.end_batch().unwrap();
+//! assert_eq!((day2.batch, day2.first_seq, day2.last_seq), (2, 5, 5));
+//! assert!(day2.flagged.is_empty());
+//! ```
+
+use crate::engine::Engine;
+use crate::errors::Result;
+use crate::fasthash::Map;
+use crate::models::{Transaction, TxType};
+use crate::notify::Event;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::io::Write;
+
+/// Checks run when a batch ends.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BatchRules {
+    /// Flag clients that opened more disputes than this in the batch.
+    pub max_disputes: Option<u32>,
+}
+
+/// What one batch did, as returned by [`Engine::end_batch`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BatchSummary {
+    /// 1-based batch number within the engine's life.
+    pub batch: u64,
+    /// Sequence numbers of the batch's first and last rows; `first_seq` is
+    /// past `last_seq` for an empty batch.
+    pub first_seq: u64,
+    pub last_seq: u64,
+    pub applied: u64,
+    pub rejected: u64,
+    /// Sums of applied deposits and withdrawals.
+    pub deposited: Decimal,
+    pub withdrawn: Decimal,
+    pub chargebacks: u64,
+    /// Clients caught by the [`BatchRules`], ascending.
+    pub flagged: Vec<u16>,
+}
+
+/// Counters of the batch in progress.
+#[derive(Debug, Clone)]
+pub(crate) struct Tally {
+    number: u64,
+    first_seq: u64,
+    applied: u64,
+    rejected: u64,
+    deposited: Decimal,
+    withdrawn: Decimal,
+    chargebacks: u64,
+    /// Disputes opened per client.
+    disputes: Map<u16, u32>,
+}
+
+impl Tally {
+    /// Batch `number`, starting with row `first_seq`.
+    fn new(number: u64, first_seq: u64) -> Self {
+        Self {
+            number,
+            first_seq,
+            applied: 0,
+            rejected: 0,
+            deposited: Decimal::ZERO,
+            withdrawn: Decimal::ZERO,
+            chargebacks: 0,
+            disputes: Map::default(),
+        }
+    }
+
+    /// Count an applied row.
+    pub(crate) fn applied(&mut self, tx: &Transaction) {
+        self.applied += 1;
+        match tx.kind {
+            TxType::Deposit => self.deposited += tx.amount.unwrap_or_default(),
+            TxType::Withdrawal => self.withdrawn += tx.amount.unwrap_or_default(),
+            TxType::Dispute => *self.disputes.entry(tx.client).or_default() += 1,
+            TxType::Chargeback => self.chargebacks += 1,
+            _ => {}
+        }
+    }
+
+    pub(crate) fn rejected(&mut self) {
+        self.rejected += 1;
+    }
+}
+
+impl Default for Tally {
+    fn default() -> Self {
+        Self::new(1, 1)
+    }
+}
+
+impl Engine {
+    /// Number of the batch in progress (1-based).
+    pub fn batch(&self) -> u64 {
+        self.batch.number
+    }
+
+    /// Close the current batch: evaluate the batch rules, raising an alert
+    /// for every client they flag, and start the next batch with the next
+    /// row. See [`crate::batch`].
+    pub fn end_batch(&mut self) -> Result<BatchSummary> {
+        let next = Tally::new(self.batch.number + 1, self.seq + 1);
+        let done = std::mem::replace(&mut self.batch, next);
+        let mut bursts: Vec<(u16, u32)> = match self.config().batch_rules.max_disputes {
+            Some(limit) => done
+                .disputes
+                .iter()
+                .filter(|&(_, &n)| n > limit)
+                .map(|(&client, &n)| (client, n))
+                .collect(),
+            None => Vec::new(),
+        };
+        bursts.sort_unstable();
+        for &(client, disputes) in &bursts {
+            let event = Event::DisputeBurst {
+                client,
+                batch: done.number,
+                disputes,
+                limit: self.config().batch_rules.max_disputes.unwrap_or_default(),
+            };
+            for sink in &mut self.sinks {
+                sink.notify(&event)?;
+            }
+            self.flag_alert(&event);
+        }
+        Ok(BatchSummary {
+            batch: done.number,
+            first_seq: done.first_seq,
+            last_seq: self.seq,
+            applied: done.applied,
+            rejected: done.rejected,
+            deposited: done.deposited,
+            withdrawn: done.withdrawn,
+            chargebacks: done.chargebacks,
+            flagged: bursts.into_iter().map(|(client, _)| client).collect(),
+        })
+    }
+}
+
+/// Writes [`BatchSummary`]s as CSV
+/// (`batch,label,first_seq,last_seq,applied,rejected,deposited,withdrawn,chargebacks,flagged`),
+/// one row per batch as it ends; `flagged` lists client ids separated by
+/// spaces.
+pub struct SummaryWriter<W: Write> {
+    wtr: csv::Writer<W>,
+}
+
+impl<W: Write> SummaryWriter<W> {
+    pub fn new(out: W) -> Result<Self> {
+        let mut wtr = csv::Writer::from_writer(out);
+        wtr.write_record([
+            "batch",
+            "label",
+            "first_seq",
+            "last_seq",
+            "applied",
+            "rejected",
+            "deposited",
+            "withdrawn",
+            "chargebacks",
+            "flagged",
+        ])?;
+        Ok(Self { wtr })
+    }
+
+    /// Write the summary of the batch labelled `label` and flush it.
+    pub fn write(&mut self, label: &str, summary: &BatchSummary) -> Result<()> {
+        let flagged: Vec<String> = summary.flagged.iter().map(u16::to_string).collect();
+        self.wtr.write_record([
+            summary.batch.to_string(),
+            label.to_owned(),
+            summary.first_seq.to_string(),
+            summary.last_seq.to_string(),
+            summary.applied.to_string(),
+            summary.rejected.to_string(),
+            summary.deposited.normalize().to_string(),
+            summary.withdrawn.normalize().to_string(),
+            summary.chargebacks.to_string(),
+            flagged.join(" "),
+        ])?;
+        self.wtr.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,249 @@
+//! Named sub-balances ("buckets") per client: `main`, `savings`,
+//! `rewards`, … chosen row by row with optional `bucket` and `to_bucket`
+//! columns.
+//!
+//! A client's account is the sum of its buckets; the accounts report is
+//! unchanged. Rows without a bucket, or naming `main`, use the main bucket.
+//!
+//! * `deposit` credits the bucket it names, and `withdrawal` debits it if
+//!   the bucket has the funds ([`Anomaly::InsufficientFunds`] otherwise);
+//! * `move,client,tx,amount` shifts available funds from `bucket` to
+//!   `to_bucket` within the account. Moves change no totals and do not take
+//!   up their tx id;
+//! * disputes, resolves and charge-backs act on the bucket the deposit
+//!   landed in, whatever bucket the row names, and so do escrow releases
+//!   (see [`crate::escrow`]);
+//! * holds, their releases and captures, refunds and promotional credits
+//!   (see [`crate::promo`]) use the main bucket.
+//!
+//! A move from a bucket to itself, and a bucket other than main on a row
+//! that always uses main, are rejected as [`Anomaly::BadBucket`]. A dispute
+//! row may name its deposit's bucket, but no other.
+//!
+//! Only buckets other than main are stored; main is what is left of the
+//! account, so inputs without a `bucket` column cost nothing extra. Main
+//! can go negative like any account when a dispute holds funds that were
+//! moved away; see [`Engine::buckets`] for the per-bucket balances and
+//! `--buckets-output` for the CLI report.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::bucket::{Balance, RowBuckets};
+//! use payments_engine::processor::RowMeta;
+//! use payments_engine::{Engine, Transaction, TxType};
+//! use rust_decimal_macros::dec;
+//!
+//! let mut eng = Engine::new();
+//! let row = |kind, tx, amount| Transaction { kind, client: 1, tx, amount: Some(amount) };
+//! let savings = RowMeta {
+//!     buckets: RowBuckets { bucket: "savings", to_bucket: "" },
+//!     ..Default::default()
+//! };
+//!
+//! eng.process(row(TxType::Deposit, 1, dec!(100))).unwrap();
+//! eng.process_row(row(TxType::Deposit, 2, dec!(50)), savings).unwrap();
+//! eng.process_row(row(TxType::Move, 3, dec!(20)), savings).unwrap(); // back to main
+//! eng.process(Transaction { kind: TxType::Dispute, client: 1, tx: 2, amount: None })
+//!     .unwrap();
+//!
+//! let balance = |available, held| Balance { available, held };
+//! assert_eq!(
+//!     eng.buckets(1),
+//!     [
+//!         ("main".to_string(), balance(dec!(120), dec!(0))),
+//!         ("savings".to_string(), balance(dec!(-20), dec!(50))),
+//!     ]
+//! );
+//! assert_eq!(eng.accounts[&1].total(), dec!(150));
+//! ```
+
+use crate::anomaly::Anomaly;
+use crate::dispute::State;
+use crate::engine::Engine;
+use crate::fasthash::Map;
+use crate::models::{Account, TxType};
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+
+/// Name of the bucket rows use unless they name another.
+pub const MAIN: &str = "main";
+
+/// Balances of one bucket.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Balance {
+    pub available: Decimal,
+    pub held: Decimal,
+}
+
+impl Balance {
+    pub fn total(&self) -> Decimal {
+        self.available + self.held
+    }
+}
+
+/// The buckets a row names: its `bucket` and, for a move, `to_bucket`.
+/// Empty names mean [`MAIN`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RowBuckets<'a> {
+    pub bucket: &'a str,
+    pub to_bucket: &'a str,
+}
+
+impl<'a> RowBuckets<'a> {
+    /// The bucket the row uses.
+    pub fn from(&self) -> &'a str {
+        name(self.bucket)
+    }
+
+    /// The bucket a move goes to.
+    pub fn to(&self) -> &'a str {
+        name(self.to_bucket)
+    }
+
+    /// `true` if the row names no bucket but main.
+    pub fn is_main(&self) -> bool {
+        self.from() == MAIN && self.to() == MAIN
+    }
+}
+
+fn name(bucket: &str) -> &str {
+    match bucket {
+        "" => MAIN,
+        b => b,
+    }
+}
+
+/// Buckets other than main by client, and the bucket of every deposit that
+/// landed outside main.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Book {
+    pub(crate) balances: Map<u16, BTreeMap<String, Balance>>,
+    pub(crate) deposits: Map<u32, String>,
+}
+
+impl Book {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.balances.is_empty() && self.deposits.is_empty()
+    }
+
+    /// Available funds of `bucket` that a withdrawal or move may take: never
+    /// more than the account has available. A `credit` line (see
+    /// [`crate::credit`]) is drawn through main.
+    pub(crate) fn spendable(
+        &self,
+        client: u16,
+        acc: &Account,
+        bucket: &str,
+        credit: Decimal,
+    ) -> Decimal {
+        let others = self.balances.get(&client);
+        let own = match bucket {
+            MAIN => {
+                let moved: Decimal = others.into_iter().flatten().map(|(_, b)| b.available).sum();
+                acc.available - moved + credit
+            }
+            b => others
+                .and_then(|o| o.get(b))
+                .map_or(Decimal::ZERO, |b| b.available),
+        };
+        own.min(acc.available + credit)
+    }
+
+    /// Mirror a balance change of `bucket`; main needs none.
+    pub(crate) fn adjust(&mut self, client: u16, bucket: &str, available: Decimal, held: Decimal) {
+        if bucket == MAIN {
+            return;
+        }
+        let b = self
+            .balances
+            .entry(client)
+            .or_default()
+            .entry(bucket.to_owned())
+            .or_default();
+        b.available += available;
+        b.held += held;
+    }
+
+    /// Mirror the effect of a dispute of deposit `tx` that moved to `state`
+    /// over `amount` (see `Engine::dispute_effect`).
+    pub(crate) fn dispute(&mut self, client: u16, tx: u32, state: State, amount: Decimal) {
+        let Some(bucket) = self.deposits.get(&tx).cloned() else {
+            return;
+        };
+        match state {
+            State::Open => self.adjust(client, &bucket, -amount, amount),
+            State::Resolved => self.adjust(client, &bucket, amount, -amount),
+            State::ChargedBack => {
+                self.adjust(client, &bucket, Decimal::ZERO, -amount);
+                self.deposits.remove(&tx);
+            }
+            State::None => {}
+        }
+    }
+
+    /// The bucket deposit `tx` landed in.
+    pub(crate) fn of_deposit(&self, tx: u32) -> &str {
+        self.deposits.get(&tx).map_or(MAIN, String::as_str)
+    }
+}
+
+impl Engine {
+    /// Every bucket of `client` with its balances, main first and the rest
+    /// by name; empty for an unknown client.
+    pub fn buckets(&self, client: u16) -> Vec<(String, Balance)> {
+        let Some(acc) = self.accounts.get(&client) else {
+            return Vec::new();
+        };
+        let others = self.buckets.balances.get(&client);
+        let mut main = Balance {
+            available: acc.available,
+            held: acc.held,
+        };
+        for b in others.into_iter().flat_map(|o| o.values()) {
+            main.available -= b.available;
+            main.held -= b.held;
+        }
+        let rest = others
+            .into_iter()
+            .flatten()
+            .map(|(name, b)| (name.clone(), *b));
+        std::iter::once((MAIN.to_owned(), main))
+            .chain(rest)
+            .collect()
+    }
+
+    /// Clients with a bucket other than main, in ascending order.
+    pub fn bucketed_clients(&self) -> Vec<u16> {
+        let mut clients: Vec<u16> = self.buckets.balances.keys().copied().collect();
+        clients.sort_unstable();
+        clients
+    }
+
+    /// The bucket deposit `tx` landed in, if the engine has it.
+    pub fn deposit_bucket(&self, tx: u32) -> Option<&str> {
+        self.deposits
+            .contains_key(&tx)
+            .then(|| self.buckets.of_deposit(tx))
+    }
+}
+
+/// Why `buckets` cannot be used by a row of `kind` referring to a deposit
+/// that landed in `deposit` (main for rows naming no deposit), if so.
+pub(crate) fn check(
+    kind: TxType,
+    buckets: RowBuckets,
+    deposit: &str,
+) -> std::result::Result<(), Anomaly> {
+    let ok = match kind {
+        TxType::Deposit | TxType::Withdrawal => buckets.to() == MAIN,
+        TxType::Move => buckets.from() != buckets.to(),
+        TxType::Dispute | TxType::Resolve | TxType::Chargeback | TxType::Release => {
+            buckets.to() == MAIN && [MAIN, deposit].contains(&buckets.from())
+        }
+        TxType::Hold | TxType::Capture | TxType::Refund | TxType::Promo => buckets.is_main(),
+    };
+    match ok {
+        true => Ok(()),
+        false => Err(Anomaly::BadBucket),
+    }
+}
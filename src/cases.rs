@@ -0,0 +1,222 @@
+//! Case files for flagged accounts: one self-contained JSON evidence bundle
+//! per client a risk rule fired for, so an investigator starts from a file
+//! rather than from the logs.
+//!
+//! A [`CaseRecorder`] attached to an engine ([`CaseRecorder::attach`])
+//! listens to its alerts (see [`crate::notify`]) and turns on history. Once
+//! processing is done, [`CaseRecorder::export`] writes `case-<client>.json`
+//! for every client with at least one alert, holding:
+//!
+//! * `account` – the final balances, as in the report;
+//! * `triggers` – every alert raised for the client, in order: chargebacks,
+//!   locks, held-funds threshold crossings, structuring patterns;
+//! * `transactions` – the client's applied rows recorded since the recorder
+//!   was attached, each with the balances right after it;
+//! * `rejected` – the client's rejected rows, for anomaly classes handled
+//!   with the `record` action.
+//!
+//! History is what makes the bundle self-contained, and it costs one entry
+//! per applied row of every client, flagged or not. A recorder built
+//! [`with_retention`](CaseRecorder::with_retention) bounds that history with
+//! [`Engine::set_history_retention`] and keeps only the latest
+//! [`Retention::last`] alerts per client; alerts carry no row number, so
+//! [`Retention::max_age`] applies to history only.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::cases::CaseRecorder;
+//! use payments_engine::{Engine, Transaction, TxType};
+//! use rust_decimal_macros::dec;
+//!
+//! let mut eng = Engine::new();
+//! let cases = CaseRecorder::new();
+//! cases.attach(&mut eng);
+//! for (kind, client, amount) in [
+//!     (TxType::Deposit, 1, Some(dec!(5))),
+//!     (TxType::Deposit, 2, Some(dec!(9))),
+//!     (TxType::Dispute, 1, None),
+//!     (TxType::Chargeback, 1, None),
+//! ] {
+//!     let tx = client as u32;
+//!     eng.process(Transaction { kind, client, tx, amount }).unwrap();
+//! }
+//!
+//! assert_eq!(cases.flagged(), [1]);
+//! let case = cases.case(&eng, 1).unwrap();
+//! assert_eq!(case.triggers.len(), 2); // chargeback + lock
+//! assert_eq!(case.transactions.len(), 3);
+//! assert!(case.account.locked);
+//! ```
+
+use crate::anomaly::Anomaly;
+use crate::engine::Engine;
+use crate::errors::Result;
+use crate::history::Retention;
+use crate::models::{AccountRow, TxType};
+use crate::notify::{Event, NotificationSink};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Alerts per client, shared between the recorder and its sink.
+type Alerts = Arc<Mutex<BTreeMap<u16, Vec<Event>>>>;
+
+/// Collects alerts per client and exports case files; see the module docs.
+#[derive(Debug, Default, Clone)]
+pub struct CaseRecorder {
+    alerts: Alerts,
+    retention: Retention,
+}
+
+/// The evidence bundle for one client.
+#[derive(Serialize)]
+pub struct Case {
+    pub client: u16,
+    /// [`Engine::seq`] at export time: the case reflects rows up to here.
+    pub as_of_seq: u64,
+    pub account: AccountRow,
+    pub triggers: Vec<Event>,
+    pub transactions: Vec<CaseRow>,
+    pub rejected: Vec<CaseRejection>,
+}
+
+/// An applied row and the account right after it.
+#[derive(Debug, Serialize)]
+pub struct CaseRow {
+    pub seq: u64,
+    pub tx: u32,
+    #[serde(rename = "type")]
+    pub kind: TxType,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub locked: bool,
+}
+
+/// A rejected row and why.
+#[derive(Debug, Serialize)]
+pub struct CaseRejection {
+    pub seq: u64,
+    pub tx: u32,
+    #[serde(rename = "type")]
+    pub kind: TxType,
+    pub amount: Option<Decimal>,
+    pub anomaly: Anomaly,
+}
+
+struct Sink {
+    alerts: Alerts,
+    last: Option<usize>,
+}
+
+impl NotificationSink for Sink {
+    fn notify(&mut self, event: &Event) -> Result<()> {
+        let mut alerts = self.alerts.lock().expect("case recorder poisoned");
+        let events = alerts.entry(event.client()).or_default();
+        events.push(event.clone());
+        // like history: drop the excess once it is half the log
+        if let Some(n) = self.last
+            && events.len() >= 2 * n.max(1)
+        {
+            events.drain(..events.len() - n);
+        }
+        Ok(())
+    }
+}
+
+impl CaseRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Recorder keeping only what `retention` allows; see the module docs.
+    pub fn with_retention(retention: Retention) -> Self {
+        Self {
+            retention,
+            ..Self::default()
+        }
+    }
+
+    /// Listen to `engine`'s alerts and record its history from now on.
+    pub fn attach(&self, engine: &mut Engine) {
+        engine.enable_history();
+        engine.set_history_retention(self.retention);
+        engine.add_sink(Box::new(Sink {
+            alerts: self.alerts.clone(),
+            last: self.retention.last,
+        }));
+    }
+
+    /// Clients with at least one alert, ascending.
+    pub fn flagged(&self) -> Vec<u16> {
+        self.lock().keys().copied().collect()
+    }
+
+    /// The case of `client` as of now; `None` if it raised no alert.
+    pub fn case(&self, engine: &Engine, client: u16) -> Option<Case> {
+        let alerts = self.lock();
+        let events = alerts.get(&client)?;
+        let skip = self
+            .retention
+            .last
+            .map_or(0, |n| events.len().saturating_sub(n));
+        let triggers = events[skip..].to_vec();
+        drop(alerts);
+        let account = engine.accounts.get(&client).cloned().unwrap_or_default();
+        Some(Case {
+            client,
+            as_of_seq: engine.seq(),
+            account: AccountRow::from((&client, &account)),
+            triggers,
+            transactions: engine
+                .history(client)
+                .iter()
+                .map(|e| CaseRow {
+                    seq: e.seq,
+                    tx: e.tx,
+                    kind: e.kind,
+                    available: e.available,
+                    held: e.held,
+                    locked: e.locked,
+                })
+                .collect(),
+            rejected: engine
+                .rejections()
+                .iter()
+                .filter(|r| r.tx.client == client)
+                .map(|r| CaseRejection {
+                    seq: r.seq,
+                    tx: r.tx.tx,
+                    kind: r.tx.kind,
+                    amount: r.tx.amount,
+                    anomaly: r.anomaly,
+                })
+                .collect(),
+        })
+    }
+
+    /// Write `case-<client>.json` into `dir` (created if missing) for every
+    /// flagged client. Returns the paths written, in client order.
+    pub fn export(&self, engine: &Engine, dir: &Path) -> Result<Vec<PathBuf>> {
+        fs::create_dir_all(dir)?;
+        let mut written = Vec::new();
+        for client in self.flagged() {
+            let case = self
+                .case(engine, client)
+                .expect("flagged client has alerts");
+            let path = dir.join(format!("case-{client}.json"));
+            let mut out = BufWriter::new(File::create(&path)?);
+            serde_json::to_writer_pretty(&mut out, &case)?;
+            out.flush()?;
+            written.push(path);
+        }
+        Ok(written)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<u16, Vec<Event>>> {
+        self.alerts.lock().expect("case recorder poisoned")
+    }
+}
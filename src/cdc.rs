@@ -0,0 +1,133 @@
+//! Change-data-capture output: one JSON record per applied row with the
+//! account before and after it, so downstream systems apply incremental
+//! updates instead of diffing full reports.
+//!
+//! A [`CdcWriter`] consumes the [`AccountDelta`] stream of
+//! [`Engine::watch`](crate::Engine::watch) and keeps its own copy of the
+//! balances (a [`Replica`]) to fill in `before` and `after`. Seed it with
+//! the engine's state when processing continues from a snapshot, so the
+//! first records start from the restored balances. Held funds released by
+//! an expiring authorization come as a `release` of the hold's tx id.
+//!
+//! ```json
+//! {"seq":5,"client":2,"tx":4,"type":"dispute",
+//!  "before":{"available":"10","held":"0","total":"10","locked":false},
+//!  "after":{"available":"0","held":"10","total":"10","locked":false}}
+//! ```
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::cdc::CdcWriter;
+//! use payments_engine::replica::Replica;
+//! use payments_engine::{Engine, Transaction, TxType};
+//! use rust_decimal_macros::dec;
+//!
+//! let mut eng = Engine::new();
+//! let feed = eng.watch(|_| true);
+//! let mut cdc = CdcWriter::new(Vec::new(), Replica::from_engine(&eng));
+//! eng.process(Transaction { kind: TxType::Deposit, client: 2, tx: 4, amount: Some(dec!(10)) })
+//!     .unwrap();
+//! eng.process(Transaction { kind: TxType::Dispute, client: 2, tx: 4, amount: None }).unwrap();
+//! for delta in feed.pending() {
+//!     cdc.record(&delta).unwrap();
+//! }
+//!
+//! let out = String::from_utf8(cdc.into_inner().unwrap()).unwrap();
+//! let last: serde_json::Value = serde_json::from_str(out.lines().nth(1).unwrap()).unwrap();
+//! assert_eq!(last["type"], "dispute");
+//! assert_eq!(last["before"]["available"], "10");
+//! assert_eq!(last["after"]["held"], "10");
+//! ```
+
+use crate::errors::Result;
+use crate::feed::AccountDelta;
+use crate::models::{Account, TxType};
+use crate::replica::Replica;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::io::Write;
+
+/// An account's balances at one point of a [`Change`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Balances {
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+impl From<&Account> for Balances {
+    fn from(acc: &Account) -> Self {
+        Self {
+            available: acc.available.normalize(),
+            held: acc.held.normalize(),
+            total: acc.total().normalize(),
+            locked: acc.locked,
+        }
+    }
+}
+
+/// One CDC record: an applied row and its account around it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Change {
+    pub seq: u64,
+    pub client: u16,
+    pub tx: u32,
+    #[serde(rename = "type")]
+    pub kind: TxType,
+    pub before: Balances,
+    pub after: Balances,
+}
+
+/// Writes [`Change`]s as NDJSON; see the module docs.
+pub struct CdcWriter<W: Write> {
+    out: W,
+    view: Replica,
+    written: u64,
+}
+
+impl<W: Write> CdcWriter<W> {
+    /// Start from the balances in `view`: [`Replica::new`] for a fresh
+    /// engine, [`Replica::from_engine`] for one restored from a snapshot.
+    pub fn new(out: W, view: Replica) -> Self {
+        Self {
+            out,
+            view,
+            written: 0,
+        }
+    }
+
+    /// Write the record of `delta`; deltas must come in order.
+    pub fn record(&mut self, delta: &AccountDelta) -> Result<()> {
+        let before = self.view.account(delta.client).cloned().unwrap_or_default();
+        self.view.apply(delta);
+        let change = Change {
+            seq: delta.seq,
+            client: delta.client,
+            tx: delta.tx,
+            kind: delta.kind,
+            before: Balances::from(&before),
+            after: Balances::from(&self.view.accounts()[&delta.client]),
+        };
+        serde_json::to_writer(&mut self.out, &change)?;
+        self.out.write_all(b"\n")?;
+        self.written += 1;
+        Ok(())
+    }
+
+    /// Records written so far.
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+
+    /// Flush and return the writer.
+    pub fn into_inner(mut self) -> Result<W> {
+        self.flush()?;
+        Ok(self.out)
+    }
+}
@@ -0,0 +1,95 @@
+//! Chargeback records for card-network reporting: for every chargeback
+//! applied, the transaction it reversed, the amount and when its dispute
+//! was opened and closed.
+//!
+//! A [`ChargebackLog`] follows the [`AccountDelta`] stream of
+//! [`Engine::watch`](crate::Engine::watch): a dispute notes its sequence
+//! number, a resolve forgets it, and a chargeback turns it into a
+//! [`ChargebackRecord`]. Only open disputes are tracked, so the log costs
+//! memory per open dispute plus one record per chargeback. A dispute opened
+//! before the log was attached (e.g. before a snapshot was taken) has no
+//! opening sequence number.
+//!
+//! [`crate::report::write_chargebacks`] writes the records as CSV.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::chargeback::ChargebackLog;
+//! use payments_engine::{Engine, Transaction, TxType};
+//! use rust_decimal_macros::dec;
+//!
+//! let mut eng = Engine::new();
+//! let feed = eng.watch(|_| true);
+//! let mut log = ChargebackLog::new();
+//! for (kind, amount) in [
+//!     (TxType::Deposit, Some(dec!(40))),
+//!     (TxType::Dispute, None),
+//!     (TxType::Chargeback, None),
+//! ] {
+//!     eng.process(Transaction { kind, client: 3, tx: 9, amount }).unwrap();
+//! }
+//! feed.pending().for_each(|delta| log.observe(&delta));
+//!
+//! let cb = &log.records()[0];
+//! assert_eq!((cb.tx, cb.client, cb.amount), (9, 3, dec!(40)));
+//! assert_eq!((cb.dispute_seq, cb.chargeback_seq), (Some(2), 3));
+//! ```
+
+use crate::fasthash::Map;
+use crate::feed::AccountDelta;
+use crate::models::TxType;
+use rust_decimal::Decimal;
+
+/// One applied chargeback.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChargebackRecord {
+    /// The charged-back transaction: a deposit, or a withdrawal whose
+    /// refunds were disputed.
+    pub tx: u32,
+    pub client: u16,
+    /// Amount reversed: the deposit, or the disputed refund total.
+    pub amount: Decimal,
+    /// Row that opened the dispute; `None` if it predates the log.
+    pub dispute_seq: Option<u64>,
+    /// Row of the chargeback.
+    pub chargeback_seq: u64,
+}
+
+/// Chargebacks seen on a delta stream; see the module docs.
+#[derive(Debug, Default)]
+pub struct ChargebackLog {
+    /// Sequence number of the dispute currently open on each tx.
+    opened: Map<u32, u64>,
+    records: Vec<ChargebackRecord>,
+}
+
+impl ChargebackLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take in one delta; deltas must come in order.
+    pub fn observe(&mut self, delta: &AccountDelta) {
+        match delta.kind {
+            TxType::Dispute => {
+                self.opened.insert(delta.tx, delta.seq);
+            }
+            TxType::Resolve => {
+                self.opened.remove(&delta.tx);
+            }
+            TxType::Chargeback => self.records.push(ChargebackRecord {
+                tx: delta.tx,
+                client: delta.client,
+                amount: -delta.held,
+                dispute_seq: self.opened.remove(&delta.tx),
+                chargeback_seq: delta.seq,
+            }),
+            _ => {}
+        }
+    }
+
+    /// Chargebacks in the order they were applied.
+    pub fn records(&self) -> &[ChargebackRecord] {
+        &self.records
+    }
+}
@@ -0,0 +1,126 @@
+//! Periodic snapshots during long ingests, so monitoring can follow progress
+//! and a crash loses at most one interval of work.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::checkpoint::Interval;
+//! use std::time::Duration;
+//!
+//! assert_eq!("100000".parse::<Interval>().unwrap(), Interval::Rows(100_000));
+//! assert_eq!("10m".parse::<Interval>().unwrap(), Interval::Every(Duration::from_secs(600)));
+//! assert!("18446744073709551615h".parse::<Interval>().is_err());
+//! ```
+
+use crate::engine::Engine;
+use crate::errors::Result;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// How often to checkpoint: every N input rows, every wall-clock period, or
+/// at every batch end (see [`crate::batch`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interval {
+    Rows(u64),
+    Every(Duration),
+    Batch,
+}
+
+impl FromStr for Interval {
+    type Err = String;
+
+    /// A bare number is a row count; `s`, `m` or `h` suffixes give a period.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s == "batch" {
+            return Ok(Interval::Batch);
+        }
+        let (num, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+        let n: u64 = num.parse().map_err(|_| format!("bad interval {s:?}"))?;
+        if n == 0 {
+            return Err("interval must be positive".into());
+        }
+        let per_unit = match unit {
+            "" => return Ok(Interval::Rows(n)),
+            "s" => 1,
+            "m" => 60,
+            "h" => 3600,
+            _ => return Err(format!("bad interval unit in {s:?} (use s, m or h)")),
+        };
+        let secs = n
+            .checked_mul(per_unit)
+            .ok_or_else(|| format!("interval {s:?} is too long"))?;
+        Ok(Interval::Every(Duration::from_secs(secs)))
+    }
+}
+
+/// Decides when the next checkpoint is due and writes it.
+pub struct Checkpointer {
+    interval: Interval,
+    path: PathBuf,
+    last_row: u64,
+    last_at: Instant,
+}
+
+impl Checkpointer {
+    /// Checkpoints go to `path`, replaced atomically each time.
+    pub fn new(interval: Interval, path: &Path) -> Self {
+        Self {
+            interval,
+            path: path.to_path_buf(),
+            last_row: 0,
+            last_at: Instant::now(),
+        }
+    }
+
+    /// Count rows from `rows` instead of zero, for a resumed run.
+    pub fn starting_at(mut self, rows: u64) -> Self {
+        self.last_row = rows;
+        self
+    }
+
+    /// `true` if a checkpoint is due after `rows` input rows.
+    pub fn due(&self, rows: u64) -> bool {
+        match self.interval {
+            Interval::Rows(n) => rows - self.last_row >= n,
+            Interval::Every(d) => self.last_at.elapsed() >= d,
+            Interval::Batch => false,
+        }
+    }
+
+    /// `true` if checkpoints are taken at batch ends instead.
+    pub fn at_batch_ends(&self) -> bool {
+        self.interval == Interval::Batch
+    }
+
+    /// Write a snapshot now and restart the interval. Callers record the
+    /// input position first with [`Engine::set_input_rows`].
+    pub fn write(&mut self, engine: &Engine, rows: u64) -> Result<()> {
+        write_snapshot_atomic(engine, &self.path)?;
+        self.last_row = rows;
+        self.last_at = Instant::now();
+        tracing::info!(
+            rows,
+            seq = engine.seq(),
+            path = %self.path.display(),
+            "checkpoint"
+        );
+        Ok(())
+    }
+}
+
+/// Write a snapshot to a temporary sibling file, then rename it over `path`,
+/// so readers (and a crash) never see a half-written snapshot.
+pub fn write_snapshot_atomic(engine: &Engine, path: &Path) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    {
+        let file = File::create(&tmp)?;
+        engine.write_snapshot(BufWriter::new(&file))?;
+        file.sync_all()?;
+    }
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
@@ -0,0 +1,161 @@
+//! Time source for the engine's time-based features, so they can be made
+//! deterministic in tests and replays.
+//!
+//! The engine reads the time through the [`Clock`] set with
+//! [`Engine::set_clock`](crate::Engine::set_clock); it defaults to the
+//! [`SystemClock`]. A [`FixedClock`] stands still, and a [`ManualClock`] is
+//! moved by its owner, e.g. from a `timestamp` column as rows are read
+//! (see [`parse_timestamp`]), so a replay of the same input expires the same
+//! holds whenever it runs.
+//!
+//! Times are whole seconds since the Unix epoch. Today the clock drives
+//! [`EngineConfig::hold_expiry_secs`](crate::engine::EngineConfig::hold_expiry_secs)
+//! and interest on credit lines (see [`crate::credit`]); row-counted windows such as
+//! [`EngineConfig::hold_expiry`](crate::engine::EngineConfig::hold_expiry)
+//! do not read it.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::clock::{parse_timestamp, ManualClock};
+//! use payments_engine::engine::EngineConfig;
+//! use payments_engine::{Engine, Transaction, TxType};
+//! use rust_decimal_macros::dec;
+//!
+//! let clock = ManualClock::new(parse_timestamp("2026-10-01T09:00:00Z").unwrap());
+//! let mut eng = Engine::with_config(EngineConfig { hold_expiry_secs: Some(3600), ..Default::default() });
+//! eng.set_clock(Box::new(clock.clone()));
+//!
+//! let row = |kind, tx, amount| Transaction { kind, client: 1, tx, amount };
+//! eng.process(row(TxType::Deposit, 1, Some(dec!(100)))).unwrap();
+//! eng.process(row(TxType::Hold, 2, Some(dec!(40)))).unwrap();
+//!
+//! clock.advance(parse_timestamp("2026-10-01T09:59:00Z").unwrap());
+//! eng.process(row(TxType::Deposit, 3, Some(dec!(1)))).unwrap();
+//! assert_eq!(eng.accounts[&1].held, dec!(40));
+//!
+//! clock.advance(parse_timestamp("2026-10-01T10:00:01Z").unwrap());
+//! eng.process(row(TxType::Deposit, 4, Some(dec!(1)))).unwrap();
+//! assert_eq!(eng.accounts[&1].held, dec!(0));
+//! ```
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Source of the current time, in seconds since the Unix epoch.
+pub trait Clock: Send {
+    fn now(&self) -> u64;
+}
+
+/// The operating system's wall clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs())
+    }
+}
+
+/// A clock that always reads the same time.
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock(pub u64);
+
+impl Clock for FixedClock {
+    fn now(&self) -> u64 {
+        self.0
+    }
+}
+
+/// A clock moved by hand. Clones share the same time, so one can be given
+/// to the engine and another kept to drive it.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock(Arc<AtomicU64>);
+
+impl ManualClock {
+    pub fn new(now: u64) -> Self {
+        Self(Arc::new(AtomicU64::new(now)))
+    }
+
+    /// Move the clock forward to `now`; an earlier time is ignored, so rows
+    /// stamped slightly out of order never turn the clock back.
+    pub fn advance(&self, now: u64) {
+        self.0.fetch_max(now, Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Seconds since the Unix epoch of an input timestamp: epoch seconds or
+/// milliseconds (told apart by magnitude, as in [`crate::sort`]), or RFC
+/// 3339 (`2026-10-01T09:00:00Z`, `2026-10-01 11:00:00.250+02:00`; fractions
+/// are dropped). `None` if it is neither.
+pub fn parse_timestamp(s: &str) -> Option<u64> {
+    if let Ok(n) = s.parse::<u64>() {
+        // 10^11 seconds is in the year 5138
+        return Some(if n >= 100_000_000_000 { n / 1000 } else { n });
+    }
+    let b = s.as_bytes();
+    if b.len() < 20 || b[4] != b'-' || b[7] != b'-' || b[13] != b':' || b[16] != b':' {
+        return None;
+    }
+    if !matches!(b[10], b'T' | b't' | b' ') {
+        return None;
+    }
+    let num = |r| num_in(s, r);
+    let (year, month, day) = (num(0..4)?, num(5..7)?, num(8..10)?);
+    let (hour, min, sec) = (num(11..13)?, num(14..16)?, num(17..19)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || min > 59 || sec > 60 {
+        return None;
+    }
+    let mut rest = &s[19..];
+    if let Some(frac) = rest.strip_prefix('.') {
+        let digits = frac.bytes().take_while(u8::is_ascii_digit).count();
+        if digits == 0 {
+            return None;
+        }
+        rest = &frac[digits..];
+    }
+    let offset = match rest {
+        "Z" | "z" => 0,
+        _ if rest.len() == 6 && rest.as_bytes()[3] == b':' => {
+            let sign = match rest.as_bytes()[0] {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            let (oh, om) = (num_in(rest, 1..3)?, num_in(rest, 4..6)?);
+            sign * (oh * 3600 + om * 60)
+        }
+        _ => return None,
+    };
+    let secs = days_from_civil(year, month, day) * 86_400 + hour * 3600 + min * 60 + sec - offset;
+    u64::try_from(secs).ok()
+}
+
+/// Digits of `s` in `r` as a number.
+fn num_in(s: &str, r: std::ops::Range<usize>) -> Option<i64> {
+    let digits = s.get(r)?;
+    digits
+        .bytes()
+        .all(|c| c.is_ascii_digit())
+        .then(|| digits.parse().ok())?
+}
+
+/// Days since 1970-01-01 of a civil date (H. Hinnant's algorithm, the
+/// inverse of the bank statements' `Date::from_unix_days`).
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
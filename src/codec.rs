@@ -0,0 +1,211 @@
+//! Compact binary encoding for `Transaction` streams.
+//!
+//! A stream starts with a 5-byte header — the magic `PETX` followed by a
+//! format version byte — and is followed by variable-length records:
+//!
+//! | field  | encoding                                                      |
+//! | ------ | ------------------------------------------------------------- |
+//! | tag    | 1 byte: bit 3 set if an amount follows; the other bits hold the kind index into [`TxType::ALL`] (low bits 0-2, high bits 4-7) |
+//! | client | LEB128 varint                                                 |
+//! | tx     | LEB128 varint                                                 |
+//! | amount | 1 byte scale (bit 7 = negative) + LEB128 varint mantissa      |
+//!
+//! A typical deposit encodes in 6-9 bytes and decodes without any text
+//! parsing, while preserving full decimal precision.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::codec::{TxDecoder, TxEncoder};
+//! use payments_engine::{Transaction, TxType};
+//! use rust_decimal_macros::dec;
+//!
+//! let mut buf = Vec::new();
+//! let mut enc = TxEncoder::new(&mut buf).unwrap();
+//! enc.write(&Transaction { kind: TxType::Deposit, client: 1, tx: 7, amount: Some(dec!(1.5)) })
+//!     .unwrap();
+//! enc.finish().unwrap();
+//!
+//! let txs: Vec<_> = TxDecoder::new(buf.as_slice()).unwrap().collect::<Result<_, _>>().unwrap();
+//! assert_eq!(txs[0].tx, 7);
+//! assert_eq!(txs[0].amount, Some(dec!(1.5)));
+//! ```
+
+use crate::errors::Result;
+use crate::models::{Transaction, TxType};
+use anyhow::{Context, bail};
+use rust_decimal::Decimal;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+
+/// Leading bytes of every binary transaction stream.
+pub const MAGIC: [u8; 4] = *b"PETX";
+/// Current encoding version written by [`TxEncoder`].
+pub const VERSION: u8 = 1;
+
+const AMOUNT_BIT: u8 = 0b1000;
+const NEGATIVE_BIT: u8 = 0b1000_0000;
+
+/// Writes a header followed by one record per [`TxEncoder::write`] call.
+pub struct TxEncoder<W: Write> {
+    out: BufWriter<W>,
+}
+
+impl<W: Write> TxEncoder<W> {
+    /// Wrap `out` and emit the stream header.
+    pub fn new(out: W) -> Result<Self> {
+        let mut out = BufWriter::new(out);
+        out.write_all(&MAGIC)?;
+        out.write_all(&[VERSION])?;
+        Ok(Self { out })
+    }
+
+    /// Append one transaction.
+    pub fn write(&mut self, tx: &Transaction) -> Result<()> {
+        let kind = TxType::ALL.iter().position(|k| *k == tx.kind).unwrap() as u8;
+        // the kind skips bit 3, so streams from when it fit in bits 0-2 still decode
+        let mut tag = (kind & 0b0111) | ((kind & !0b0111) << 1);
+        if tx.amount.is_some() {
+            tag |= AMOUNT_BIT;
+        }
+        self.out.write_all(&[tag])?;
+        write_varint(&mut self.out, tx.client.into())?;
+        write_varint(&mut self.out, tx.tx.into())?;
+        if let Some(amount) = tx.amount {
+            let mut scale = amount.scale() as u8;
+            if amount.is_sign_negative() {
+                scale |= NEGATIVE_BIT;
+            }
+            self.out.write_all(&[scale])?;
+            write_varint(&mut self.out, amount.mantissa().unsigned_abs())?;
+        }
+        Ok(())
+    }
+
+    /// Flush buffered records and return the underlying writer.
+    pub fn finish(self) -> Result<W> {
+        self.out.into_inner().map_err(|e| e.into_error().into())
+    }
+}
+
+fn write_varint(out: &mut impl Write, mut v: u128) -> io::Result<()> {
+    loop {
+        let byte = (v & 0x7f) as u8;
+        v >>= 7;
+        if v == 0 {
+            return out.write_all(&[byte]);
+        }
+        out.write_all(&[byte | 0x80])?;
+    }
+}
+
+/// Iterator over the transactions of a binary stream.
+///
+/// The header is validated up front; a truncated trailing record or a
+/// malformed field yields an error item and ends the iteration.
+pub struct TxDecoder<R: Read> {
+    src: BufReader<R>,
+    record: u64,
+    failed: bool,
+}
+
+impl<R: Read> TxDecoder<R> {
+    /// Wrap `src` and validate the stream header.
+    pub fn new(src: R) -> Result<Self> {
+        let mut src = BufReader::new(src);
+        let mut header = [0u8; 5];
+        src.read_exact(&mut header)
+            .context("binary stream shorter than its header")?;
+        if header[..4] != MAGIC {
+            bail!("not a binary transaction stream (bad magic)");
+        }
+        if header[4] != VERSION {
+            bail!("unsupported binary stream version {}", header[4]);
+        }
+        Ok(Self {
+            src,
+            record: 0,
+            failed: false,
+        })
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        let mut b = [0u8; 1];
+        self.src
+            .read_exact(&mut b)
+            .with_context(|| format!("record {}: truncated", self.record))?;
+        Ok(b[0])
+    }
+
+    fn varint(&mut self, max_bits: u32) -> Result<u128> {
+        let mut v: u128 = 0;
+        let mut shift = 0;
+        loop {
+            let b = self.byte()?;
+            v |= u128::from(b & 0x7f) << shift;
+            if b & 0x80 == 0 {
+                break;
+            }
+            shift += 7;
+            if shift >= max_bits {
+                bail!("record {}: varint overflows {max_bits} bits", self.record);
+            }
+        }
+        if max_bits < 128 && v >> max_bits != 0 {
+            bail!("record {}: varint overflows {max_bits} bits", self.record);
+        }
+        Ok(v)
+    }
+
+    fn decode(&mut self, tag: u8) -> Result<Transaction> {
+        let index = (tag & 0b0111) | ((tag & 0b1111_0000) >> 1);
+        let Some(&kind) = TxType::ALL.get(index as usize) else {
+            bail!("record {}: unknown kind {index}", self.record);
+        };
+        let client = self.varint(16)? as u16;
+        let tx = self.varint(32)? as u32;
+        let amount = if tag & AMOUNT_BIT != 0 {
+            let scale = self.byte()?;
+            let mantissa = self.varint(96)? as i128;
+            let mantissa = if scale & NEGATIVE_BIT != 0 {
+                -mantissa
+            } else {
+                mantissa
+            };
+            let scale = u32::from(scale & !NEGATIVE_BIT);
+            Some(
+                Decimal::try_from_i128_with_scale(mantissa, scale)
+                    .map_err(|e| anyhow::anyhow!("record {}: {e}", self.record))?,
+            )
+        } else {
+            None
+        };
+        Ok(Transaction {
+            kind,
+            client,
+            tx,
+            amount,
+        })
+    }
+}
+
+impl<R: Read> Iterator for TxDecoder<R> {
+    type Item = Result<Transaction>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let mut tag = [0u8; 1];
+        match self.src.read(&mut tag) {
+            Ok(0) => return None,
+            Ok(_) => {}
+            Err(e) => {
+                self.failed = true;
+                return Some(Err(e.into()));
+            }
+        }
+        self.record += 1;
+        let item = self.decode(tag[0]);
+        self.failed = item.is_err();
+        Some(item)
+    }
+}
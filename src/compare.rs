@@ -0,0 +1,192 @@
+//! State comparison between two engines — the basis of shadow runs, where
+//! a candidate build or policy is replayed over the same input as a trusted
+//! ("golden") state and any divergence blocks the rollout.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::{Engine, Transaction, TxType, compare};
+//! use rust_decimal_macros::dec;
+//!
+//! let dep = |amount| Transaction { kind: TxType::Deposit, client: 1, tx: 1, amount: Some(amount) };
+//! let mut golden = Engine::new();
+//! golden.process(dep(dec!(1))).unwrap();
+//! let mut candidate = Engine::new();
+//! candidate.process(dep(dec!(2))).unwrap();
+//!
+//! let diffs = compare::diff(&golden, &candidate);
+//! assert_eq!(diffs.len(), 1);
+//! ```
+
+use crate::engine::Engine;
+use crate::hold;
+use crate::models::Account;
+use rust_decimal::Decimal;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+/// One point where the two states disagree. `None` means the item is
+/// absent on that side.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Divergence {
+    /// Balances or lock state differ for a client.
+    Account {
+        client: u16,
+        left: Option<Account>,
+        right: Option<Account>,
+    },
+    /// A deposit or refund is under dispute on one side only, or disputes
+    /// disagree on client or amount.
+    OpenDispute {
+        tx: u32,
+        left: Option<(u16, Decimal)>,
+        right: Option<(u16, Decimal)>,
+    },
+    /// An authorization is active on one side only, or the two disagree on
+    /// client or amount.
+    ActiveHold {
+        tx: u32,
+        left: Option<(u16, Decimal)>,
+        right: Option<(u16, Decimal)>,
+    },
+    /// The refunded total of a withdrawal differs (`None`: nothing refunded).
+    Refunded {
+        tx: u32,
+        left: Option<(u16, Decimal)>,
+        right: Option<(u16, Decimal)>,
+    },
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let acc = |a: &Option<Account>| match a {
+            Some(a) => format!(
+                "available={} held={} locked={}",
+                a.available, a.held, a.locked
+            ),
+            None => "absent".into(),
+        };
+        let dis = |d: &Option<(u16, Decimal)>| match d {
+            Some((client, amount)) => format!("client={client} amount={amount}"),
+            None => "not disputed".into(),
+        };
+        match self {
+            Divergence::Account {
+                client,
+                left,
+                right,
+            } => write!(f, "client {client}: {} vs {}", acc(left), acc(right)),
+            Divergence::OpenDispute { tx, left, right } => {
+                write!(f, "dispute tx {tx}: {} vs {}", dis(left), dis(right))
+            }
+            Divergence::ActiveHold { tx, left, right } => {
+                let hold = |h: &Option<(u16, Decimal)>| match h {
+                    Some((client, amount)) => format!("client={client} amount={amount}"),
+                    None => "not active".into(),
+                };
+                write!(f, "hold tx {tx}: {} vs {}", hold(left), hold(right))
+            }
+            Divergence::Refunded { tx, left, right } => {
+                let refunded = |r: &Option<(u16, Decimal)>| match r {
+                    Some((client, amount)) => format!("client={client} refunded={amount}"),
+                    None => "not refunded".into(),
+                };
+                write!(
+                    f,
+                    "withdrawal tx {tx}: {} vs {}",
+                    refunded(left),
+                    refunded(right)
+                )
+            }
+        }
+    }
+}
+
+/// Every divergence between `left` and `right`, accounts first (by client),
+/// then open disputes, active holds and refund totals (by tx id).
+pub fn diff(left: &Engine, right: &Engine) -> Vec<Divergence> {
+    let mut out = Vec::new();
+
+    let clients: BTreeSet<u16> = left
+        .accounts
+        .keys()
+        .chain(right.accounts.keys())
+        .copied()
+        .collect();
+    for client in clients {
+        let (l, r) = (left.accounts.get(&client), right.accounts.get(&client));
+        if l != r {
+            out.push(Divergence::Account {
+                client,
+                left: l.cloned(),
+                right: r.cloned(),
+            });
+        }
+    }
+
+    let open = |e: &Engine| -> HashMap<u32, (u16, Decimal)> {
+        e.open_disputes()
+            .map(|(tx, client, amount)| (tx, (client, amount)))
+            .collect()
+    };
+    let (l_open, r_open) = (open(left), open(right));
+    let txs: BTreeSet<u32> = l_open.keys().chain(r_open.keys()).copied().collect();
+    for tx in txs {
+        let (l, r) = (l_open.get(&tx).copied(), r_open.get(&tx).copied());
+        if l != r {
+            out.push(Divergence::OpenDispute {
+                tx,
+                left: l,
+                right: r,
+            });
+        }
+    }
+
+    let active = |e: &Engine, tx: &u32| {
+        e.holds
+            .get(tx)
+            .filter(|h| h.state == hold::State::Active)
+            .map(|h| (h.client, h.amount))
+    };
+    let txs: BTreeSet<u32> = left
+        .holds
+        .iter()
+        .chain(&right.holds)
+        .filter(|(_, h)| h.state == hold::State::Active)
+        .map(|(tx, _)| *tx)
+        .collect();
+    for tx in txs {
+        let (l, r) = (active(left, &tx), active(right, &tx));
+        if l != r {
+            out.push(Divergence::ActiveHold {
+                tx,
+                left: l,
+                right: r,
+            });
+        }
+    }
+
+    let refunded = |e: &Engine, tx: &u32| {
+        e.withdrawals
+            .get(tx)
+            .filter(|w| !w.refunded.is_zero())
+            .map(|w| (w.client, w.refunded))
+    };
+    let txs: BTreeSet<u32> = left
+        .withdrawals
+        .iter()
+        .chain(&right.withdrawals)
+        .filter(|(_, w)| !w.refunded.is_zero())
+        .map(|(tx, _)| *tx)
+        .collect();
+    for tx in txs {
+        let (l, r) = (refunded(left, &tx), refunded(right, &tx));
+        if l != r {
+            out.push(Divergence::Refunded {
+                tx,
+                left: l,
+                right: r,
+            });
+        }
+    }
+    out
+}
@@ -0,0 +1,376 @@
+//! Shell completion scripts generated from a clap [`Command`].
+//!
+//! The scripts are derived from the same definition the parser uses, so a
+//! new subcommand or flag completes as soon as it exists: subcommand names
+//! (and aliases), long and short flags, the possible values of flags that
+//! list them, and file names for any other flag value or positional
+//! argument.
+//!
+//! ### Example
+//! ```rust
+//! use clap::{Arg, Command};
+//! use payments_engine::completions::{self, Shell};
+//!
+//! let cli = Command::new("tool").subcommand(
+//!     Command::new("run").arg(
+//!         Arg::new("format")
+//!             .long("format")
+//!             .value_parser(["csv", "binary"]),
+//!     ),
+//! );
+//! let mut out = Vec::new();
+//! completions::generate(Shell::Bash, &cli, &mut out).unwrap();
+//! let script = String::from_utf8(out).unwrap();
+//! assert!(script.contains("complete -F _tool tool"));
+//! assert!(script.contains(r#"COMPREPLY=($(compgen -W "csv binary" -- "$cur"))"#));
+//! ```
+
+use crate::errors::Result;
+use clap::{Arg, Command};
+use std::io::Write;
+use std::str::FromStr;
+
+/// Shells a script can be generated for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl Shell {
+    pub const ALL: [&'static str; 3] = ["bash", "zsh", "fish"];
+}
+
+impl FromStr for Shell {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            _ => Err(format!("unsupported shell {s:?}")),
+        }
+    }
+}
+
+/// Write the completion script for `cmd` (named after
+/// [`Command::get_name`]) to `out`.
+pub fn generate<W: Write>(shell: Shell, cmd: &Command, out: &mut W) -> Result<()> {
+    let mut cmd = cmd.clone();
+    // propagates global flags and adds --help / --version
+    cmd.build();
+    let mut nodes = Vec::new();
+    collect(&cmd, "", &mut nodes);
+    let script = match shell {
+        Shell::Bash => bash(cmd.get_name(), &nodes),
+        Shell::Zsh => zsh(cmd.get_name(), &nodes),
+        Shell::Fish => fish(cmd.get_name(), &nodes),
+    };
+    out.write_all(script.as_bytes())?;
+    Ok(())
+}
+
+/// One command or subcommand, flattened out of the tree.
+struct Node<'a> {
+    /// Subcommand names from the root joined with `__`; empty for the root.
+    path: String,
+    cmd: &'a Command,
+}
+
+fn collect<'a>(cmd: &'a Command, path: &str, nodes: &mut Vec<Node<'a>>) {
+    nodes.push(Node {
+        path: path.to_string(),
+        cmd,
+    });
+    for sub in cmd.get_subcommands() {
+        collect(sub, &child_path(path, sub), nodes);
+    }
+}
+
+/// Names a subcommand answers to: its own and its aliases.
+fn names(cmd: &Command) -> Vec<&str> {
+    std::iter::once(cmd.get_name())
+        .chain(cmd.get_all_aliases())
+        .collect()
+}
+
+fn visible_args(cmd: &Command) -> impl Iterator<Item = &Arg> {
+    cmd.get_arguments().filter(|a| !a.is_hide_set())
+}
+
+/// `--long` and `-s` spellings of a flag.
+fn spellings(arg: &Arg) -> Vec<String> {
+    let long = arg.get_long().map(|l| format!("--{l}"));
+    let short = arg.get_short().map(|s| format!("-{s}"));
+    long.into_iter().chain(short).collect()
+}
+
+fn takes_value(arg: &Arg) -> bool {
+    arg.get_action().takes_values()
+}
+
+fn possible_values(arg: &Arg) -> Vec<String> {
+    arg.get_possible_values()
+        .iter()
+        .filter(|v| !v.is_hide_set())
+        .map(|v| v.get_name().to_string())
+        .collect()
+}
+
+/// First line of an argument's or command's help, if any.
+fn summary(help: Option<&clap::builder::StyledStr>) -> String {
+    help.map(|h| h.to_string())
+        .and_then(|h| h.lines().next().map(str::to_string))
+        .unwrap_or_default()
+}
+
+fn bash(bin: &str, nodes: &[Node]) -> String {
+    let func = format!("_{}", bin.replace('-', "_"));
+    let mut s = format!(
+        "{func}() {{\n    local cur prev cmd i\n    cur=\"${{COMP_WORDS[COMP_CWORD]}}\"\n    \
+         prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"\n    cmd=\"\"\n\n    \
+         for ((i = 1; i < COMP_CWORD; i++)); do\n        case \"$cmd,${{COMP_WORDS[i]}}\" in\n"
+    );
+    for node in nodes {
+        for sub in node.cmd.get_subcommands() {
+            let path = child_path(&node.path, sub);
+            for name in names(sub) {
+                s += &format!("            \"{},{name}\") cmd=\"{path}\" ;;\n", node.path);
+            }
+        }
+    }
+    s += "        esac\n    done\n\n    case \"$cmd\" in\n";
+    for node in nodes {
+        s += &format!("        \"{}\")\n", node.path);
+        let mut with_values = String::new();
+        let mut files = Vec::new();
+        for arg in visible_args(node.cmd).filter(|a| !a.is_positional() && takes_value(a)) {
+            let flags = spellings(arg).join("|");
+            let values = possible_values(arg);
+            if values.is_empty() {
+                files.push(flags);
+            } else {
+                with_values += &format!(
+                    "                {flags}) COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return ;;\n",
+                    values.join(" ")
+                );
+            }
+        }
+        if !files.is_empty() {
+            with_values += &format!(
+                "                {}) COMPREPLY=($(compgen -f -- \"$cur\")); return ;;\n",
+                files.join("|")
+            );
+        }
+        if !with_values.is_empty() {
+            s += &format!("            case \"$prev\" in\n{with_values}            esac\n");
+        }
+        let mut words: Vec<String> = visible_args(node.cmd)
+            .filter(|a| !a.is_positional())
+            .flat_map(spellings)
+            .collect();
+        words.extend(node.cmd.get_subcommands().flat_map(names).map(String::from));
+        let positional: Vec<&Arg> = visible_args(node.cmd)
+            .filter(|a| a.is_positional())
+            .collect();
+        let values: Vec<String> = positional.iter().flat_map(|a| possible_values(a)).collect();
+        let files = values.is_empty() && !positional.is_empty();
+        words.extend(values);
+        s += &format!(
+            "            COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n",
+            words.join(" ")
+        );
+        if files {
+            s += "            [[ \"$cur\" != -* ]] && COMPREPLY+=($(compgen -f -- \"$cur\"))\n";
+        }
+        s += "            ;;\n";
+    }
+    s += &format!("    esac\n}}\n\ncomplete -F {func} {bin}\n");
+    s
+}
+
+fn zsh(bin: &str, nodes: &[Node]) -> String {
+    let func = format!("_{}", bin.replace('-', "_"));
+    let mut s = format!(
+        "#compdef {bin}\n\n{func}() {{\n    local cmd=\"\" i depth=0\n    \
+         for ((i = 2; i < CURRENT; i++)); do\n        case \"$cmd,${{words[i]}}\" in\n"
+    );
+    for node in nodes {
+        for sub in node.cmd.get_subcommands() {
+            let path = child_path(&node.path, sub);
+            for name in names(sub) {
+                s += &format!(
+                    "            \"{},{name}\") cmd=\"{path}\"; depth=$((i - 1)) ;;\n",
+                    node.path
+                );
+            }
+        }
+    }
+    // drop the subcommand words so _arguments sees the subcommand as the
+    // command being completed
+    s += "        esac\n    done\n    words=(\"${(@)words[depth+1,-1]}\")\n    \
+          (( CURRENT -= depth ))\n\n    case \"$cmd\" in\n";
+    for node in nodes {
+        s += &format!("        \"{}\")\n", node.path);
+        let mut specs = Vec::new();
+        for arg in visible_args(node.cmd).filter(|a| !a.is_positional()) {
+            let help = match summary(arg.get_help()) {
+                h if h.is_empty() => String::new(),
+                h => format!("[{}]", zsh_escape(&h)),
+            };
+            let value = if !takes_value(arg) {
+                String::new()
+            } else {
+                let values = possible_values(arg);
+                let name = arg.get_id().as_str();
+                if values.is_empty() {
+                    format!(":{name}:_files")
+                } else {
+                    format!(":{name}:({})", values.join(" "))
+                }
+            };
+            let repeat = if matches!(arg.get_action(), clap::ArgAction::Append) {
+                "*"
+            } else {
+                ""
+            };
+            let spellings = spellings(arg);
+            let eq = if takes_value(arg) { "=" } else { "" };
+            for flag in &spellings {
+                let eq = if flag.starts_with("--") { eq } else { "" };
+                specs.push(format!("'{repeat}{flag}{eq}{help}{value}'"));
+            }
+        }
+        for arg in visible_args(node.cmd).filter(|a| a.is_positional()) {
+            let name = arg.get_id().as_str();
+            let values = possible_values(arg);
+            let action = if values.is_empty() {
+                "_files".to_string()
+            } else {
+                format!("({})", values.join(" "))
+            };
+            let position = match arg.get_num_args() {
+                Some(n) if n.max_values() > 1 => "*".to_string(),
+                _ => arg.get_index().unwrap_or(1).to_string(),
+            };
+            specs.push(format!("'{position}:{name}:{action}'"));
+        }
+        let subs: Vec<String> = node
+            .cmd
+            .get_subcommands()
+            .flat_map(|c| {
+                let about = zsh_escape(&summary(c.get_about()));
+                names(c)
+                    .into_iter()
+                    .map(move |n| format!("'{n}:{about}'"))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        if !subs.is_empty() {
+            s += &format!(
+                "            local -a subcommands=(\n                {}\n            )\n            \
+                 if [[ \"${{words[CURRENT]}}\" != -* ]]; then\n                \
+                 _describe -t commands command subcommands\n            fi\n",
+                subs.join("\n                ")
+            );
+        }
+        s += &format!(
+            "            _arguments -s \\\n                {}\n            ;;\n",
+            specs.join(" \\\n                ")
+        );
+    }
+    s += &format!("    esac\n}}\n\n{func} \"$@\"\n");
+    s
+}
+
+fn fish(bin: &str, nodes: &[Node]) -> String {
+    let mut s = String::new();
+    for node in nodes {
+        // fish has no notion of a subcommand path: a node is current when
+        // its name was seen and none of its own subcommands was
+        let here = if node.path.is_empty() {
+            "__fish_use_subcommand".to_string()
+        } else {
+            let own = names(node.cmd).join(" ");
+            let subs: Vec<&str> = node.cmd.get_subcommands().flat_map(names).collect();
+            if subs.is_empty() {
+                format!("__fish_seen_subcommand_from {own}")
+            } else {
+                format!(
+                    "__fish_seen_subcommand_from {own}; and not __fish_seen_subcommand_from {}",
+                    subs.join(" ")
+                )
+            }
+        };
+        for sub in node.cmd.get_subcommands() {
+            let about = fish_escape(&summary(sub.get_about()));
+            for name in names(sub) {
+                s += &format!("complete -c {bin} -n '{here}' -f -a {name} -d '{about}'\n");
+            }
+        }
+        let values: Vec<String> = visible_args(node.cmd)
+            .filter(|a| a.is_positional())
+            .flat_map(possible_values)
+            .collect();
+        if !values.is_empty() {
+            s += &format!(
+                "complete -c {bin} -n '{here}' -f -a '{}'\n",
+                values.join(" ")
+            );
+        }
+        for arg in visible_args(node.cmd).filter(|a| !a.is_positional()) {
+            // global flags are completed everywhere by the root's lines
+            if arg.is_global_set() && !node.path.is_empty() {
+                continue;
+            }
+            let mut line = format!("complete -c {bin}");
+            if !node.path.is_empty() {
+                line += &format!(" -n '{here}'");
+            }
+            if let Some(long) = arg.get_long() {
+                line += &format!(" -l {long}");
+            }
+            if let Some(short) = arg.get_short() {
+                line += &format!(" -s {short}");
+            }
+            if takes_value(arg) {
+                let values = possible_values(arg);
+                if values.is_empty() {
+                    line += " -r -F";
+                } else {
+                    line += &format!(" -x -a '{}'", values.join(" "));
+                }
+            }
+            let help = summary(arg.get_help());
+            if !help.is_empty() {
+                line += &format!(" -d '{}'", fish_escape(&help));
+            }
+            s += &line;
+            s.push('\n');
+        }
+    }
+    s
+}
+
+fn child_path(parent: &str, sub: &Command) -> String {
+    if parent.is_empty() {
+        sub.get_name().to_string()
+    } else {
+        format!("{parent}__{}", sub.get_name())
+    }
+}
+
+/// Escape help text for a single-quoted `_arguments` spec or `_describe`
+/// entry.
+fn zsh_escape(s: &str) -> String {
+    s.replace('\'', "'\\''")
+        .replace('[', "\\[")
+        .replace(']', "\\]")
+        .replace(':', "\\:")
+}
+
+fn fish_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('\'', "\\'")
+}
@@ -0,0 +1,193 @@
+//! `--config FILE`: command-line flags kept in a TOML file.
+//!
+//! Every key is the long name of a top-level flag and takes the value that
+//! flag would: a string, a number, `true` for a switch (`false` leaves it
+//! off), or an array for repeatable flags. The file is turned into
+//! command-line arguments placed *before* the real ones, so anything given on
+//! the command line wins; repeatable flags get the file's values first.
+//!
+//! Only the flat subset of TOML those values need is understood: one
+//! `key = value` per line, `#` comments, basic and literal strings, and
+//! single-line arrays. Tables are rejected.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::config;
+//!
+//! let text = r#"
+//! ## defaults shared by the nightly jobs
+//! hold-expiry = 5000
+//! normalize-negative = true
+//! anomaly = ["insufficient-funds=record", "client-mismatch=fatal"]
+//! rejects = "out/rejects.csv"
+//! "#;
+//! let entries = config::parse(text).unwrap();
+//! assert_eq!(
+//!     config::to_args(&entries),
+//!     [
+//!         "--hold-expiry=5000",
+//!         "--normalize-negative",
+//!         "--anomaly=insufficient-funds=record",
+//!         "--anomaly=client-mismatch=fatal",
+//!         "--rejects=out/rejects.csv",
+//!     ]
+//! );
+//! ```
+
+use crate::errors::Result;
+use anyhow::{Context, anyhow, bail};
+
+/// A configuration value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    /// Strings and numbers alike, as the flag would receive them.
+    Scalar(String),
+    Bool(bool),
+    List(Vec<String>),
+}
+
+/// One `key = value` line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub key: String,
+    pub value: Value,
+    /// 1-based line number, for error messages.
+    pub line: usize,
+}
+
+/// Parse a configuration file.
+pub fn parse(text: &str) -> Result<Vec<Entry>> {
+    let mut entries: Vec<Entry> = Vec::new();
+    for (idx, raw) in text.lines().enumerate() {
+        let line = idx + 1;
+        let stmt = strip_comment(raw).trim();
+        if stmt.is_empty() {
+            continue;
+        }
+        if stmt.starts_with('[') {
+            bail!("line {line}: tables are not supported; use top-level keys");
+        }
+        let (key, value) = stmt
+            .split_once('=')
+            .ok_or_else(|| anyhow!("line {line}: expected `key = value`"))?;
+        let key = key.trim();
+        if key.is_empty()
+            || !key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            bail!("line {line}: bad key {key:?}");
+        }
+        if entries.iter().any(|e| e.key == key) {
+            bail!("line {line}: duplicate key {key:?}");
+        }
+        let value = parse_value(value.trim()).with_context(|| format!("line {line}: {key}"))?;
+        entries.push(Entry {
+            key: key.to_string(),
+            value,
+            line,
+        });
+    }
+    Ok(entries)
+}
+
+/// Command-line arguments equivalent to `entries`, in file order.
+pub fn to_args(entries: &[Entry]) -> Vec<String> {
+    let mut args = Vec::new();
+    for e in entries {
+        match &e.value {
+            Value::Bool(true) => args.push(format!("--{}", e.key)),
+            Value::Bool(false) => {}
+            Value::Scalar(v) => args.push(format!("--{}={v}", e.key)),
+            Value::List(vs) => args.extend(vs.iter().map(|v| format!("--{}={v}", e.key))),
+        }
+    }
+    args
+}
+
+/// `line` up to the first `#` outside a string.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return &line[..i],
+            _ => {}
+        }
+        escaped = false;
+    }
+    line
+}
+
+fn parse_value(s: &str) -> Result<Value> {
+    match s {
+        "true" => return Ok(Value::Bool(true)),
+        "false" => return Ok(Value::Bool(false)),
+        _ => {}
+    }
+    if let Some(inner) = s.strip_prefix('[') {
+        let inner = inner
+            .strip_suffix(']')
+            .ok_or_else(|| anyhow!("unterminated array (arrays must fit on one line)"))?;
+        let mut items = Vec::new();
+        let mut rest = inner.trim();
+        while !rest.is_empty() {
+            let (item, tail) = scalar(rest)?;
+            items.push(item);
+            rest = tail.trim_start();
+            rest = match rest.strip_prefix(',') {
+                Some(r) => r.trim_start(),
+                None if rest.is_empty() => rest,
+                None => bail!("expected `,` between array items"),
+            };
+        }
+        return Ok(Value::List(items));
+    }
+    match scalar(s)? {
+        (v, "") => Ok(Value::Scalar(v)),
+        (_, tail) => bail!("unexpected {tail:?} after value"),
+    }
+}
+
+/// One string or bare number at the start of `s`, and what follows it.
+fn scalar(s: &str) -> Result<(String, &str)> {
+    if let Some(body) = s.strip_prefix('\'') {
+        let end = body
+            .find('\'')
+            .ok_or_else(|| anyhow!("unterminated string"))?;
+        return Ok((body[..end].to_string(), body[end + 1..].trim_start()));
+    }
+    if let Some(body) = s.strip_prefix('"') {
+        let mut out = String::new();
+        let mut chars = body.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => return Ok((out, body[i + 1..].trim_start())),
+                '\\' => out.push(match chars.next().map(|(_, e)| e) {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some(e @ ('"' | '\\')) => e,
+                    other => bail!("unsupported escape \\{}", other.unwrap_or(' ')),
+                }),
+                c => out.push(c),
+            }
+        }
+        bail!("unterminated string");
+    }
+    let end = s.find([',', ' ', '\t']).unwrap_or(s.len());
+    let bare = &s[..end];
+    let numeric = bare
+        .trim_start_matches(['+', '-'])
+        .chars()
+        .all(|c| c.is_ascii_digit() || c == '.' || c == '_');
+    if bare.is_empty() || !numeric {
+        bail!("expected a string, number, boolean or array, got {s:?}");
+    }
+    Ok((bare.replace('_', ""), &s[end..]))
+}
@@ -0,0 +1,202 @@
+//! Credit lines: accounts that may draw past their deposits up to a limit,
+//! with optional interest on what they draw.
+//!
+//! A client with a limit in
+//! [`EngineConfig::credit_limits`](crate::engine::EngineConfig::credit_limits)
+//! may take its available funds below zero, down to minus the limit, with
+//! withdrawals and holds (and moves out of its main bucket, see
+//! [`crate::bucket`]). Nothing else changes: whatever raises available
+//! (deposits, refunds, releases) pays the drawn amount back. The drawn
+//! amount is how far available is below zero; [`Engine::credit_line`]
+//! reports it with the headroom left and the utilization.
+//!
+//! With
+//! [`EngineConfig::credit_interest`](crate::engine::EngineConfig::credit_interest),
+//! an annual rate (`0.18` for 18%), simple interest accrues on the drawn
+//! amount over the engine's [`Clock`](crate::clock::Clock) time,
+//! actual/365. Interest is owed on the line and never taken from the
+//! balances, so the accounts report, live deltas and invariant checks only
+//! ever see the rows. It is settled whenever a row of the client is
+//! processed, at the balance the client had until then, and when the line
+//! is read.
+//!
+//! A chargeback locks a credit account like any other, so it cannot draw
+//! any more, and interest stops at the lock: the drawn amount and interest
+//! stay outstanding as they were. A dispute may take available past minus
+//! the limit (over a deposit already spent); utilization is then above 1
+//! and there is no headroom.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::clock::ManualClock;
+//! use payments_engine::engine::EngineConfig;
+//! use payments_engine::{Engine, Transaction, TxType};
+//! use rust_decimal_macros::dec;
+//!
+//! let config = EngineConfig {
+//!     credit_limits: [(1, dec!(100))].into(),
+//!     credit_interest: Some(dec!(0.10)),
+//!     ..Default::default()
+//! };
+//! let mut eng = Engine::with_config(config);
+//! let clock = ManualClock::new(0);
+//! eng.set_clock(Box::new(clock.clone()));
+//! let row = |kind, tx, amount| Transaction { kind, client: 1, tx, amount };
+//!
+//! eng.process(row(TxType::Deposit, 1, Some(dec!(50)))).unwrap();
+//! eng.process(row(TxType::Withdrawal, 2, Some(dec!(120)))).unwrap();
+//! eng.process(row(TxType::Withdrawal, 3, Some(dec!(40)))).unwrap(); // past the limit
+//! let line = eng.credit_line(1).unwrap();
+//! assert_eq!((line.drawn, line.headroom, line.utilization), (dec!(70), dec!(30), dec!(0.7)));
+//!
+//! clock.advance(365 * 86_400);
+//! assert_eq!(eng.credit_line(1).unwrap().interest, dec!(7));
+//!
+//! // a chargeback locks the account and stops interest
+//! eng.process(row(TxType::Dispute, 1, None)).unwrap();
+//! eng.process(row(TxType::Chargeback, 1, None)).unwrap();
+//! clock.advance(2 * 365 * 86_400);
+//! let line = eng.credit_line(1).unwrap();
+//! assert_eq!((line.drawn, line.interest), (dec!(120), dec!(7)));
+//! ```
+
+use crate::engine::Engine;
+use crate::errors::Result;
+use crate::models::Account;
+use anyhow::{Context, bail};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Seconds in the actual/365 interest year.
+const SECS_PER_YEAR: u64 = 365 * 86_400;
+
+/// A client's credit line as of now, from [`Engine::credit_line`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CreditLine {
+    pub limit: Decimal,
+    /// How far available is below zero.
+    pub drawn: Decimal,
+    /// What is left to draw: the limit minus the drawn amount, never below
+    /// zero.
+    pub headroom: Decimal,
+    /// Drawn amount over the limit; above 1 past the limit.
+    pub utilization: Decimal,
+    /// Interest accrued so far.
+    pub interest: Decimal,
+}
+
+/// Interest of one credit line, settled up to `since` (clock seconds).
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Accrual {
+    pub(crate) interest: Decimal,
+    pub(crate) since: u64,
+}
+
+impl Engine {
+    /// The credit line of `client` with interest up to now, if it has one.
+    pub fn credit_line(&self, client: u16) -> Option<CreditLine> {
+        let limit = *self.config().credit_limits.get(&client)?;
+        let acc = self.accounts.get(&client).cloned().unwrap_or_default();
+        let drawn = drawn(&acc);
+        let interest = self.credit.get(&client).map_or(Decimal::ZERO, |a| {
+            a.interest + self.pending_interest(&acc, a.since)
+        });
+        Some(CreditLine {
+            limit,
+            drawn,
+            headroom: (limit - drawn).max(Decimal::ZERO),
+            utilization: drawn / limit,
+            interest,
+        })
+    }
+
+    /// Clients with a credit line, in ascending order.
+    pub fn credit_clients(&self) -> Vec<u16> {
+        let mut clients: Vec<u16> = self.config().credit_limits.keys().copied().collect();
+        clients.sort_unstable();
+        clients
+    }
+
+    /// How far `client` may take available below zero.
+    pub(crate) fn credit_limit(&self, client: u16) -> Decimal {
+        match self.config().credit_limits.is_empty() {
+            true => Decimal::ZERO,
+            false => self
+                .config()
+                .credit_limits
+                .get(&client)
+                .copied()
+                .unwrap_or_default(),
+        }
+    }
+
+    /// `true` if interest accrues on the credit line of `client`.
+    pub(crate) fn accrues(&self, client: u16) -> bool {
+        self.config().credit_interest.is_some() && self.config().credit_limits.contains_key(&client)
+    }
+
+    /// Settle the interest of `client` up to now, before its balances change.
+    pub(crate) fn accrue(&mut self, client: u16) {
+        if !self.accrues(client) {
+            return;
+        }
+        let now = self.now();
+        let pending = match (self.credit.get(&client), self.accounts.get(&client)) {
+            (Some(a), Some(acc)) => self.pending_interest(acc, a.since),
+            _ => Decimal::ZERO,
+        };
+        let accrual = self.credit.entry(client).or_insert(Accrual {
+            interest: Decimal::ZERO,
+            since: now,
+        });
+        accrual.interest += pending;
+        accrual.since = accrual.since.max(now);
+    }
+
+    /// Interest on what `acc` has drawn from clock time `since` until now.
+    fn pending_interest(&self, acc: &Account, since: u64) -> Decimal {
+        let Some(rate) = self.config().credit_interest else {
+            return Decimal::ZERO;
+        };
+        let drawn = drawn(acc);
+        if acc.locked || drawn.is_zero() {
+            return Decimal::ZERO;
+        }
+        let secs = self.now().saturating_sub(since);
+        drawn * rate * Decimal::from(secs) / Decimal::from(SECS_PER_YEAR)
+    }
+}
+
+fn drawn(acc: &Account) -> Decimal {
+    (-acc.available).max(Decimal::ZERO)
+}
+
+/// Read credit limits: one `client,limit` pair per line, blank lines and
+/// `#` comments ignored. Limits must be positive.
+pub fn read_limits(path: &Path) -> Result<HashMap<u16, Decimal>> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("reading credit limits {}", path.display()))?;
+    let mut limits = HashMap::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let at = || format!("{}:{}", path.display(), i + 1);
+        let Some((client, limit)) = line.split_once(',') else {
+            bail!("{}: expected CLIENT,LIMIT, got {line:?}", at());
+        };
+        let client: u16 = client
+            .trim()
+            .parse()
+            .with_context(|| format!("{}: bad client id {client:?}", at()))?;
+        let limit = match limit.trim().parse::<Decimal>() {
+            Ok(l) if l > Decimal::ZERO => l,
+            _ => bail!("{}: limit must be a positive amount, got {limit:?}", at()),
+        };
+        limits.insert(client, limit);
+    }
+    Ok(limits)
+}
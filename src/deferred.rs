@@ -0,0 +1,118 @@
+//! Late-arriving transactions: disputes kept until the transaction they
+//! name shows up, instead of being dropped as unknown.
+//!
+//! Exports are not always in order, and a dispute sometimes comes a few
+//! rows before the deposit it disputes. With
+//! [`EngineConfig::defer_unknown_disputes`](crate::engine::EngineConfig::defer_unknown_disputes)
+//! on, a dispute, resolve or chargeback naming a tx id the engine has not
+//! seen is not rejected but deferred ([`ProcessResult::Deferred`]). When a
+//! deposit with that id is applied, or a refund makes a withdrawal with
+//! that id disputable, the rows waiting for it are retried in their
+//! original order, right after that row and under its sequence number;
+//! their deltas reach watchers like any other, but the caller only gets
+//! the result of the row it passed in.
+//!
+//! Rows still waiting at the end of the input are permanently unmatched:
+//! [`Engine::close_deferred`] hands each to the anomaly policy as
+//! [`Anomaly::UnknownTx`], under its own sequence number, and returns
+//! them for a report. Deferred rows are saved in snapshots, so a run that
+//! checkpoints and resumes still matches them.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::engine::EngineConfig;
+//! use payments_engine::feed::ProcessResult;
+//! use payments_engine::{Engine, Transaction, TxType};
+//! use rust_decimal_macros::dec;
+//!
+//! let mut eng = Engine::with_config(EngineConfig { defer_unknown_disputes: true, ..Default::default() });
+//! let row = |kind, tx, amount| Transaction { kind, client: 3, tx, amount };
+//!
+//! let r = eng.process_with_result(row(TxType::Dispute, 8, None)).unwrap();
+//! assert!(matches!(r, ProcessResult::Deferred { seq: 1, .. }));
+//! eng.process(row(TxType::Dispute, 9, None)).unwrap();
+//! assert_eq!(eng.deferred_rows(), 2);
+//!
+//! // the deposit arrives: its dispute goes through right after it
+//! eng.process(row(TxType::Deposit, 8, Some(dec!(25)))).unwrap();
+//! assert_eq!(eng.accounts[&3].held, dec!(25));
+//!
+//! let unmatched = eng.close_deferred().unwrap();
+//! assert_eq!((unmatched.len(), unmatched[0].seq, unmatched[0].tx.tx), (1, 2, 9));
+//! assert_eq!(eng.deferred_rows(), 0);
+//! ```
+
+use crate::anomaly::Anomaly;
+use crate::bucket::RowBuckets;
+use crate::engine::Engine;
+use crate::errors::Result;
+use crate::feed::ProcessResult;
+use crate::models::Transaction;
+
+/// A row waiting for the transaction it names.
+#[derive(Debug)]
+pub struct DeferredRow {
+    /// [`Engine::seq`] of the row when it came in.
+    pub seq: u64,
+    pub tx: Transaction,
+    pub key: Option<String>,
+}
+
+impl Clone for DeferredRow {
+    fn clone(&self) -> Self {
+        Self {
+            seq: self.seq,
+            tx: Transaction { ..self.tx },
+            key: self.key.clone(),
+        }
+    }
+}
+
+impl Engine {
+    /// Rows waiting for the transaction they name.
+    pub fn deferred_rows(&self) -> usize {
+        self.deferred.values().map(Vec::len).sum()
+    }
+
+    /// Give up on every deferred row: each goes through the anomaly policy
+    /// as [`Anomaly::UnknownTx`]. Returns them in input order; fails like
+    /// [`Engine::process`] if the policy says
+    /// [`Action::Fatal`](crate::anomaly::Action::Fatal).
+    pub fn close_deferred(&mut self) -> Result<Vec<DeferredRow>> {
+        let mut rows: Vec<DeferredRow> = std::mem::take(&mut self.deferred)
+            .into_values()
+            .flatten()
+            .collect();
+        rows.sort_by_key(|r| r.seq);
+        for row in &rows {
+            self.reject(row.seq, Anomaly::UnknownTx, Transaction { ..row.tx })?;
+        }
+        Ok(rows)
+    }
+
+    /// Keep `tx` until a row brings the transaction it names.
+    pub(crate) fn defer(&mut self, tx: Transaction, key: Option<&str>) -> ProcessResult {
+        let deferred = ProcessResult::Deferred {
+            seq: self.seq,
+            client: tx.client,
+            tx: tx.tx,
+        };
+        self.deferred.entry(tx.tx).or_default().push(DeferredRow {
+            seq: self.seq,
+            tx,
+            key: key.map(str::to_owned),
+        });
+        deferred
+    }
+
+    /// Retry the rows waiting for transaction `tx`, which just arrived.
+    pub(crate) fn retry_deferred(&mut self, tx: u32) -> Result<()> {
+        let Some(rows) = self.deferred.remove(&tx) else {
+            return Ok(());
+        };
+        for row in rows {
+            self.apply_row(row.tx, row.key.as_deref(), RowBuckets::default())?;
+        }
+        Ok(())
+    }
+}
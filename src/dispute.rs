@@ -0,0 +1,150 @@
+//! Dispute lifecycle of a single credit as an explicit state machine.
+//!
+//! Disputes refer to transactions that credited a client: deposits and
+//! refunds (named by the refunded withdrawal's id). The disputed amount is
+//! held at the credited client, and a charge-back reverses the credit.
+//!
+//! ```text
+//!            Open              Resolve
+//!   None ─────────▶ Open ─────────────▶ Resolved
+//!                    │  ▲                  │
+//!         Chargeback │  └──────────────────┘
+//!                    ▼         Open
+//!               ChargedBack
+//! ```
+//!
+//! A resolved credit may be disputed again; a charge-back is final. Every
+//! other transition is rejected with an [`IllegalTransition`], leaving the
+//! state unchanged. Balance effects stay in the engine; this module only
+//! decides which transitions are legal.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::dispute::{State, StateMachine, Transition};
+//!
+//! let mut sm = StateMachine::new();
+//! sm.apply(Transition::Open).unwrap();
+//! sm.apply(Transition::Resolve).unwrap();
+//! assert_eq!(sm.state(), State::Resolved);
+//!
+//! // resolving twice is illegal and leaves the state alone
+//! let err = sm.apply(Transition::Resolve).unwrap_err();
+//! assert_eq!((err.from, err.transition), (State::Resolved, Transition::Resolve));
+//! assert_eq!(sm.state(), State::Resolved);
+//!
+//! sm.apply(Transition::Open).unwrap();
+//! sm.apply(Transition::Chargeback).unwrap();
+//! assert!(sm.apply(Transition::Open).is_err());
+//! ```
+
+use crate::anomaly::Anomaly;
+use crate::models::TxType;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Where a credit is in its dispute lifecycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum State {
+    /// Never disputed.
+    #[default]
+    None,
+    /// Under dispute: the amount is held.
+    Open,
+    /// The last dispute was resolved in the client's favour.
+    Resolved,
+    /// Reversed; the account was locked.
+    ChargedBack,
+}
+
+/// A dispute-related input row, as seen by the state machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transition {
+    Open,
+    Resolve,
+    Chargeback,
+}
+
+impl Transition {
+    /// The transition a row of `kind` requests, if any.
+    pub fn for_kind(kind: TxType) -> Option<Self> {
+        match kind {
+            TxType::Dispute => Some(Transition::Open),
+            TxType::Resolve => Some(Transition::Resolve),
+            TxType::Chargeback => Some(Transition::Chargeback),
+            _ => None,
+        }
+    }
+}
+
+/// `transition` is not allowed from `from`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IllegalTransition {
+    pub from: State,
+    pub transition: Transition,
+}
+
+impl fmt::Display for IllegalTransition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "cannot {:?} a dispute in state {:?}",
+            self.transition, self.from
+        )
+    }
+}
+
+impl std::error::Error for IllegalTransition {}
+
+impl From<IllegalTransition> for Anomaly {
+    fn from(e: IllegalTransition) -> Self {
+        match e.transition {
+            Transition::Open => Anomaly::AlreadyDisputed,
+            Transition::Resolve | Transition::Chargeback => Anomaly::NotDisputed,
+        }
+    }
+}
+
+/// Dispute state of one credit plus the rules for moving it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StateMachine {
+    state: State,
+}
+
+impl StateMachine {
+    /// A credit that was never disputed.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resume from a known state (e.g. restored from a snapshot).
+    pub fn from_state(state: State) -> Self {
+        Self { state }
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// `true` while the disputed amount is held.
+    pub fn is_open(&self) -> bool {
+        self.state == State::Open
+    }
+
+    /// The state `transition` leads to from `from`, if it is legal.
+    pub fn next(from: State, transition: Transition) -> Result<State, IllegalTransition> {
+        match (from, transition) {
+            (State::None | State::Resolved, Transition::Open) => Ok(State::Open),
+            (State::Open, Transition::Resolve) => Ok(State::Resolved),
+            (State::Open, Transition::Chargeback) => Ok(State::ChargedBack),
+            _ => Err(IllegalTransition { from, transition }),
+        }
+    }
+
+    /// Move to the next state, or leave the state unchanged and report why
+    /// not.
+    pub fn apply(&mut self, transition: Transition) -> Result<State, IllegalTransition> {
+        self.state = Self::next(self.state, transition)?;
+        Ok(self.state)
+    }
+}
@@ -0,0 +1,248 @@
+//! Text encoding of CSV inputs: byte-order marks and UTF-16.
+//!
+//! Spreadsheet and banking exports do not always produce plain UTF-8. A
+//! [`Decoder`] wraps the raw input and hands the CSV reader UTF-8 without a
+//! byte-order mark, transcoding UTF-16 on the fly. In [`Encoding::Auto`]
+//! mode the encoding is taken from the BOM, or guessed from NUL bytes in the
+//! first character for BOM-less UTF-16, and is plain UTF-8 otherwise.
+//! CRLF and bare CR line endings need nothing here: the CSV reader accepts
+//! `\n`, `\r\n` and `\r` alike.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::encoding::{Decoder, Encoding};
+//! use std::io::Read;
+//!
+//! // "type,client\r\n" as exported by a UTF-16LE tool, BOM first
+//! let mut raw = vec![0xFF, 0xFE];
+//! raw.extend("type,client\r\n".encode_utf16().flat_map(u16::to_le_bytes));
+//!
+//! let mut dec = Decoder::new(raw.as_slice(), Encoding::Auto);
+//! let mut text = String::new();
+//! dec.read_to_string(&mut text).unwrap();
+//! assert_eq!(text, "type,client\r\n");
+//! assert_eq!(dec.detected(), Some(Encoding::Utf16Le));
+//! ```
+
+use std::io::{self, Read};
+use std::str::FromStr;
+
+const UTF8_BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+const UTF16LE_BOM: &[u8] = &[0xFF, 0xFE];
+const UTF16BE_BOM: &[u8] = &[0xFE, 0xFF];
+
+/// Raw bytes pulled from the input per UTF-16 refill.
+const CHUNK: usize = 8 * 1024;
+
+/// Encoding of a text input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    /// Detect from the byte-order mark or the first bytes.
+    #[default]
+    Auto,
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl Encoding {
+    /// Names accepted by [`Encoding::from_str`], for `--input-encoding`.
+    pub const NAMES: [&'static str; 4] = ["auto", "utf-8", "utf-16le", "utf-16be"];
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(Encoding::Auto),
+            "utf-8" | "utf8" => Ok(Encoding::Utf8),
+            "utf-16le" | "utf16le" => Ok(Encoding::Utf16Le),
+            "utf-16be" | "utf16be" => Ok(Encoding::Utf16Be),
+            _ => Err(format!("unknown encoding {s:?}")),
+        }
+    }
+}
+
+/// UTF-8 view of an input in any [`Encoding`]; see the module docs.
+///
+/// A leading BOM is dropped even when the encoding is given explicitly.
+/// Malformed UTF-16 (an unpaired surrogate, an odd trailing byte) is an
+/// [`io::ErrorKind::InvalidData`] error naming the byte offset.
+pub struct Decoder<R> {
+    inner: R,
+    requested: Encoding,
+    /// Resolved on the first read.
+    encoding: Option<Encoding>,
+    /// Decoded bytes not yet handed out, from `pos` on.
+    out: Vec<u8>,
+    pos: usize,
+    /// UTF-16 bytes read but not decoded yet (odd byte, high surrogate).
+    carry: Vec<u8>,
+    /// Input offset of the first byte in `carry`.
+    offset: u64,
+    /// Malformed input found while decoding; reported once the text before
+    /// it has been read, after which the input ends.
+    failed: Option<io::Error>,
+    done: bool,
+}
+
+impl<R: Read> Decoder<R> {
+    pub fn new(inner: R, encoding: Encoding) -> Self {
+        Self {
+            inner,
+            requested: encoding,
+            encoding: None,
+            out: Vec::new(),
+            pos: 0,
+            carry: Vec::new(),
+            offset: 0,
+            failed: None,
+            done: false,
+        }
+    }
+
+    /// The encoding in use, once the first read has resolved it.
+    pub fn detected(&self) -> Option<Encoding> {
+        self.encoding
+    }
+
+    /// Read the first bytes, drop a BOM and settle on an encoding.
+    fn sniff(&mut self) -> io::Result<Encoding> {
+        let mut head = [0u8; 4];
+        let mut len = 0;
+        while len < head.len() {
+            match self.inner.read(&mut head[len..]) {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let head = &head[..len];
+        let (bom, from_bom) = if head.starts_with(UTF8_BOM) {
+            (UTF8_BOM.len(), Some(Encoding::Utf8))
+        } else if head.starts_with(UTF16LE_BOM) {
+            (UTF16LE_BOM.len(), Some(Encoding::Utf16Le))
+        } else if head.starts_with(UTF16BE_BOM) {
+            (UTF16BE_BOM.len(), Some(Encoding::Utf16Be))
+        } else {
+            (0, None)
+        };
+        let encoding = match (self.requested, from_bom) {
+            (Encoding::Auto, Some(e)) => e,
+            // an ASCII first character has a NUL high byte in UTF-16
+            (Encoding::Auto, None) => match head {
+                [a, 0, ..] if *a != 0 => Encoding::Utf16Le,
+                [0, b, ..] if *b != 0 => Encoding::Utf16Be,
+                _ => Encoding::Utf8,
+            },
+            (requested, _) => requested,
+        };
+        // only a BOM that matches the encoding is one; anything else is data
+        let bom = if from_bom == Some(encoding) { bom } else { 0 };
+        self.offset = bom as u64;
+        match encoding {
+            Encoding::Utf16Le | Encoding::Utf16Be => self.carry.extend_from_slice(&head[bom..]),
+            _ => self.out.extend_from_slice(&head[bom..]),
+        }
+        Ok(encoding)
+    }
+
+    /// Decode more UTF-16 into `out`; leaves it empty only at the end.
+    fn refill_utf16(&mut self, little_endian: bool) -> io::Result<()> {
+        self.out.clear();
+        self.pos = 0;
+        let mut raw = [0u8; CHUNK];
+        while self.out.is_empty() {
+            let n = match self.inner.read(&mut raw) {
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            self.carry.extend_from_slice(&raw[..n]);
+            let eof = n == 0;
+            let mut units: Vec<u16> = self
+                .carry
+                .chunks_exact(2)
+                .map(|b| {
+                    if little_endian {
+                        u16::from_le_bytes([b[0], b[1]])
+                    } else {
+                        u16::from_be_bytes([b[0], b[1]])
+                    }
+                })
+                .collect();
+            // a high surrogate needs the next unit, which may not be here yet
+            if !eof && units.last().is_some_and(|u| (0xD800..0xDC00).contains(u)) {
+                units.pop();
+            }
+            let mut at = self.offset;
+            for c in char::decode_utf16(units.iter().copied()) {
+                match c {
+                    Ok(c) => {
+                        let mut buf = [0u8; 4];
+                        self.out
+                            .extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                        at += 2 * c.len_utf16() as u64;
+                    }
+                    Err(e) => {
+                        self.fail(format!(
+                            "invalid UTF-16 at byte {at}: unpaired surrogate {:#06X}",
+                            e.unpaired_surrogate()
+                        ));
+                        return Ok(());
+                    }
+                }
+            }
+            self.carry.drain(..2 * units.len());
+            self.offset = at;
+            if eof {
+                if !self.carry.is_empty() {
+                    self.fail(format!(
+                        "invalid UTF-16 at byte {at}: input ends mid-character"
+                    ));
+                }
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn fail(&mut self, msg: String) {
+        self.failed = Some(io::Error::new(io::ErrorKind::InvalidData, msg));
+        self.carry.clear();
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let encoding = match self.encoding {
+            Some(e) => e,
+            None => {
+                let e = self.sniff()?;
+                self.encoding = Some(e);
+                e
+            }
+        };
+        if self.pos == self.out.len() {
+            if let Some(e) = self.failed.take() {
+                self.done = true;
+                return Err(e);
+            }
+            if self.done {
+                return Ok(0);
+            }
+            match encoding {
+                Encoding::Utf16Le => self.refill_utf16(true)?,
+                Encoding::Utf16Be => self.refill_utf16(false)?,
+                // UTF-8 passes straight through once the sniffed bytes are out
+                _ => return self.inner.read(buf),
+            }
+        }
+        let n = buf.len().min(self.out.len() - self.pos);
+        buf[..n].copy_from_slice(&self.out[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
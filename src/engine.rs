@@ -22,102 +22,1214 @@
 //! assert_eq!(acc.available, rust_decimal_macros::dec!(0.5));
 //! ```
 
+use crate::anomaly::{Action, Anomaly, AnomalyPolicy, Normalization, Rejection};
+use crate::batch::{BatchRules, Tally};
+use crate::bucket::{self, Book, MAIN, RowBuckets};
+use crate::checksum::Sha256;
+use crate::clock::{Clock, SystemClock};
+use crate::credit::Accrual;
+use crate::deferred::DeferredRow;
+use crate::deposit::DepositStore;
+use crate::dispute::{State, StateMachine, Transition};
 use crate::errors::Result;
+use crate::escrow;
+use crate::fasthash::Map;
+use crate::feed::{AccountDelta, ProcessResult, Watch, Watcher};
+use crate::fork::Undo;
+use crate::history::{HistoryEntry, Retention};
+use crate::hold;
 use crate::models::{Account, Transaction, TxType};
+use crate::notes::{AlertFlag, Annotations};
+use crate::notify::{Event, NotificationSink};
+use crate::processor::RowMeta;
+use crate::promo;
+use crate::structuring::Detector;
+use crate::trace::ClientTrace;
+use anyhow::bail;
 use rust_decimal::Decimal;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 
 /// Internal record kept for every *deposit* so later dispute/resolve/chargeback
-/// can reference the original amount & client.
-#[derive(Debug)]
-struct StoredTx {
-    client: u16,
-    amount: Decimal,
-    under_dispute: bool,
+/// can reference the original amount & client. Stored packed, see
+/// [`crate::deposit`].
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct StoredTx {
+    pub(crate) client: u16,
+    pub(crate) amount: Decimal,
+    pub(crate) dispute: StateMachine,
+}
+
+/// Behaviour knobs fixed when the engine is built.
+#[derive(Debug, Clone, Default)]
+pub struct EngineConfig {
+    /// How each class of unappliable row is handled.
+    pub anomalies: AnomalyPolicy,
+    /// Largest amount a row may carry; see [`AmountLimits`].
+    pub max_amounts: AmountLimits,
+    /// Release authorizations still active after this many further rows
+    /// (see [`crate::hold`]); `None` keeps them until released or captured.
+    pub hold_expiry: Option<u64>,
+    /// Also release authorizations still active this many seconds after
+    /// they were made, as told by the engine's [`Clock`].
+    pub hold_expiry_secs: Option<u64>,
+    /// Apply a deposit with a negative amount as a withdrawal of the
+    /// absolute amount, and vice versa, instead of rejecting it as
+    /// [`Anomaly::NonPositiveAmount`]. Every such row is kept in
+    /// [`Engine::normalizations`].
+    pub normalize_negative: bool,
+    /// Re-check the account of every row once it is processed and fail
+    /// with an [`crate::invariants::Violation`] on the first row that broke
+    /// an invariant.
+    pub check_invariants: bool,
+    /// Drop a deposit's record once it is charged back, keeping only its id
+    /// and client in [`Engine::reclaimed`] so the id stays taken. A
+    /// charge-back is final, so no later row can tell the difference.
+    pub gc_deposits: bool,
+    /// Checks run by [`Engine::end_batch`].
+    pub batch_rules: BatchRules,
+    /// Keep dispute-family rows naming an unknown tx until it arrives,
+    /// instead of rejecting them as [`Anomaly::UnknownTx`]; see
+    /// [`crate::deferred`].
+    pub defer_unknown_disputes: bool,
+    /// Flag the client of every alert of a kind; see [`crate::notes`].
+    pub alert_flags: Vec<AlertFlag>,
+    /// Clients whose deposits are held until released; see
+    /// [`crate::escrow`].
+    pub escrow_clients: HashSet<u16>,
+    /// How far each listed client may take its available funds below zero;
+    /// see [`crate::credit`].
+    pub credit_limits: HashMap<u16, Decimal>,
+    /// Annual interest rate on drawn credit, as a fraction (`0.18` for
+    /// 18%); see [`crate::credit`].
+    pub credit_interest: Option<Decimal>,
+    /// Take back what is left of a promotional credit this many seconds of
+    /// clock time after it was granted; see [`crate::promo`].
+    pub promo_expiry_secs: Option<u64>,
+}
+
+/// Upper bounds on row amounts, so a fat-fingered exponent upstream is
+/// rejected as [`Anomaly::AmountTooLarge`] instead of parking an absurd
+/// balance on an account. Checked with the other amount guards, before the
+/// row can open an account, and after [`EngineConfig::normalize_negative`].
+///
+/// ### Example
+/// ```rust
+/// use payments_engine::anomaly::Anomaly;
+/// use payments_engine::engine::{AmountLimits, EngineConfig};
+/// use payments_engine::feed::ProcessResult;
+/// use payments_engine::{Engine, Transaction, TxType};
+/// use rust_decimal_macros::dec;
+///
+/// let mut max_amounts = AmountLimits::default();
+/// max_amounts.set(None, dec!(1_000_000));
+/// max_amounts.set(Some(TxType::Withdrawal), dec!(10_000));
+/// let mut eng = Engine::with_config(EngineConfig { max_amounts, ..Default::default() });
+///
+/// let row = |kind, tx, amount| Transaction { kind, client: 1, tx, amount: Some(amount) };
+/// let r = eng.process_with_result(row(TxType::Deposit, 1, dec!(2_000_000_000_000))).unwrap();
+/// assert!(matches!(r, ProcessResult::Rejected { anomaly: Anomaly::AmountTooLarge, .. }));
+/// assert!(eng.accounts.is_empty());
+///
+/// eng.process(row(TxType::Deposit, 2, dec!(50_000))).unwrap();
+/// let r = eng.process_with_result(row(TxType::Withdrawal, 3, dec!(20_000))).unwrap();
+/// assert!(matches!(r, ProcessResult::Rejected { anomaly: Anomaly::AmountTooLarge, .. }));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AmountLimits {
+    /// Maximum for every type that carries an amount.
+    pub all: Option<Decimal>,
+    /// Maximum for one type, taking precedence over `all`.
+    pub per_type: HashMap<TxType, Decimal>,
+}
+
+impl AmountLimits {
+    /// Bound one type's amounts, or every type's with `None`.
+    pub fn set(&mut self, kind: Option<TxType>, max: Decimal) {
+        match kind {
+            Some(kind) => {
+                self.per_type.insert(kind, max);
+            }
+            None => self.all = Some(max),
+        }
+    }
+
+    /// The largest amount accepted on a `kind` row, if bounded.
+    pub fn max_for(&self, kind: TxType) -> Option<Decimal> {
+        self.per_type.get(&kind).copied().or(self.all)
+    }
+}
+
+/// Internal record kept for every applied withdrawal so refunds can be
+/// checked against it.
+#[derive(Debug, Clone)]
+pub(crate) struct StoredWithdrawal {
+    pub(crate) client: u16,
+    pub(crate) amount: Decimal,
+    /// Sum of refunds applied so far; never exceeds `amount`.
+    pub(crate) refunded: Decimal,
+    /// Dispute over the refunds, see [`crate::dispute`].
+    pub(crate) dispute: StateMachine,
+    /// Refunded total held when the current or last dispute was opened.
+    pub(crate) disputed: Decimal,
+}
+
+/// Internal record of a card authorization, see [`crate::hold`].
+#[derive(Debug, Clone)]
+pub(crate) struct StoredHold {
+    pub(crate) client: u16,
+    pub(crate) amount: Decimal,
+    pub(crate) state: hold::State,
+    /// [`Engine::seq`] of the hold row.
+    pub(crate) seq: u64,
+    /// [`Engine::now`] at the hold row, if time-based expiry was on then.
+    pub(crate) at: Option<u64>,
+}
+
+/// Summary returned by [`Engine::finalize`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finalized {
+    /// Rows passed to [`Engine::process`].
+    pub rows: u64,
+    pub accounts: usize,
+    /// Charged-back deposits dropped under [`EngineConfig::gc_deposits`],
+    /// this run and any restored from a snapshot.
+    pub reclaimed_deposits: usize,
+    /// See [`Engine::state_hash`].
+    pub state_hash: String,
+}
+
+/// Answer of [`Engine::can_withdraw`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Approve,
+    /// The row would be rejected as this anomaly.
+    Decline(Anomaly),
 }
 
 /// Streaming payments engine. Feed rows via [`Engine::process`] then read
 /// `engine.accounts` to generate the final report.
 pub struct Engine {
-    pub accounts: HashMap<u16, Account>,
-    deposits: HashMap<u32, StoredTx>,
+    pub accounts: Map<u16, Account>,
+    pub(crate) deposits: DepositStore,
+    /// Client of each charged-back deposit dropped under
+    /// [`EngineConfig::gc_deposits`], by tx id.
+    pub(crate) reclaimed: Map<u32, u16>,
+    /// Applied withdrawals by tx id, for refunds.
+    pub(crate) withdrawals: Map<u32, StoredWithdrawal>,
+    /// Card authorizations by tx id (their own id space).
+    pub(crate) holds: Map<u32, StoredHold>,
+    /// `(seq, tx)` of holds in creation order, for the expiry sweep. Entries
+    /// of closed holds are dropped when they reach the front.
+    pub(crate) hold_queue: VecDeque<(u64, u32)>,
+    /// Clients whose balances or lock state were changed by an applied row.
+    pub(crate) touched: HashSet<u16>,
+    /// Idempotency keys of applied rows per client, with the tx id applied
+    /// under each.
+    pub(crate) keys: Map<u16, Map<String, u32>>,
+    /// Number of rows passed to [`Engine::process`] so far.
+    pub(crate) seq: u64,
+    /// Input rows a frontend has consumed, including rows it filtered out;
+    /// saved in snapshots so an interrupted run can resume.
+    pub(crate) input_rows: u64,
+    /// Per-client log of applied rows; `None` unless history is enabled.
+    pub(crate) history: Option<HashMap<u16, Vec<HistoryEntry>>>,
+    /// See [`Engine::set_history_retention`].
+    pub(crate) history_retention: Retention,
+    /// Alert destinations, see [`Engine::add_sink`].
+    pub(crate) sinks: Vec<Box<dyn NotificationSink>>,
+    /// Raise [`Event::HeldThreshold`] when held funds reach this amount.
+    held_alert: Option<Decimal>,
+    /// See [`Engine::set_structuring`].
+    pub(crate) structuring: Option<Detector>,
+    /// See [`Engine::set_trace`].
+    pub(crate) trace: Option<ClientTrace>,
+    config: EngineConfig,
+    /// Rows dropped under [`Action::Record`].
+    pub(crate) rejections: Vec<Rejection>,
+    /// Rows rewritten under [`EngineConfig::normalize_negative`].
+    pub(crate) normalizations: Vec<Normalization>,
+    /// Live subscribers, see [`Engine::watch`].
+    pub(crate) watchers: Vec<Watcher>,
+    /// Rows waiting for the tx id they name, see [`crate::deferred`].
+    pub(crate) deferred: Map<u32, Vec<DeferredRow>>,
+    /// Flags and notes per client, see [`crate::notes`].
+    pub(crate) annotations: Map<u16, Annotations>,
+    /// Funds still in escrow by deposit tx id, see [`crate::escrow`].
+    pub(crate) escrow: Map<u32, Decimal>,
+    /// Buckets other than main, see [`crate::bucket`].
+    pub(crate) buckets: Book,
+    /// Interest owed on credit lines, see [`crate::credit`].
+    pub(crate) credit: Map<u16, Accrual>,
+    /// Promotional credits, see [`crate::promo`].
+    pub(crate) promos: promo::Book,
+    /// Counters of the batch in progress, see [`crate::batch`].
+    pub(crate) batch: Tally,
+    /// Time source, see [`crate::clock`].
+    clock: Box<dyn Clock>,
+    /// Undo log of the open [`Engine::fork`], if any.
+    pub(crate) journal: Option<Vec<Undo>>,
 }
 
 impl Engine {
     /// Create a new empty engine.
     pub fn new() -> Self {
         Self {
-            accounts: HashMap::new(),
-            deposits: HashMap::new(),
+            accounts: Map::default(),
+            deposits: DepositStore::default(),
+            reclaimed: Map::default(),
+            withdrawals: Map::default(),
+            holds: Map::default(),
+            hold_queue: VecDeque::new(),
+            touched: HashSet::new(),
+            keys: Map::default(),
+            seq: 0,
+            input_rows: 0,
+            history: None,
+            history_retention: Retention::default(),
+            sinks: Vec::new(),
+            held_alert: None,
+            config: EngineConfig::default(),
+            rejections: Vec::new(),
+            normalizations: Vec::new(),
+            watchers: Vec::new(),
+            structuring: None,
+            trace: None,
+            deferred: Map::default(),
+            annotations: Map::default(),
+            escrow: Map::default(),
+            buckets: Book::default(),
+            credit: Map::default(),
+            promos: promo::Book::default(),
+            batch: Tally::default(),
+            clock: Box::new(SystemClock),
+            journal: None,
         }
     }
 
-    /// Apply one transaction to the internal state.
-    pub fn process(&mut self, tx: Transaction) -> Result<()> {
-        // guard: negative or zero amounts are invalid
-        if matches!(tx.kind, TxType::Deposit | TxType::Withdrawal) {
-            if let Some(a) = tx.amount {
-                if a <= Decimal::ZERO {
-                    return Ok(());
+    /// Empty engine with room for `clients` accounts and `txs` deposits
+    /// (the bulk of the stored records) before its maps have to grow, so a
+    /// large run of known size does not rehash along the way.
+    pub fn with_capacity(clients: usize, txs: usize) -> Self {
+        let mut eng = Self::new();
+        eng.accounts.reserve(clients);
+        eng.deposits.reserve(txs);
+        eng
+    }
+
+    /// Empty engine with non-default behaviour.
+    pub fn with_config(config: EngineConfig) -> Self {
+        let mut eng = Self::new();
+        eng.config = config;
+        eng
+    }
+
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    /// Replace the configuration, e.g. after restoring a snapshot (snapshots
+    /// carry state only).
+    pub fn set_config(&mut self, config: EngineConfig) {
+        self.config = config;
+    }
+
+    /// Read the time from `clock` from now on (the system clock by
+    /// default).
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// The current time of the engine's clock, in seconds since the Unix
+    /// epoch.
+    pub fn now(&self) -> u64 {
+        self.clock.now()
+    }
+
+    /// Rows rejected so far whose anomaly class is set to [`Action::Record`],
+    /// in processing order.
+    pub fn rejections(&self) -> &[Rejection] {
+        &self.rejections
+    }
+
+    /// Negative-amount rows applied as the opposite operation, in processing
+    /// order (empty unless [`EngineConfig::normalize_negative`] is set).
+    pub fn normalizations(&self) -> &[Normalization] {
+        &self.normalizations
+    }
+
+    /// Deliver every future [`Event`] to `sink` as well.
+    pub fn add_sink(&mut self, sink: Box<dyn NotificationSink>) {
+        self.sinks.push(sink);
+    }
+
+    /// Alert when a dispute pushes a client's held funds to `threshold` or
+    /// above (`None` disables the alert).
+    pub fn set_held_alert(&mut self, threshold: Option<Decimal>) {
+        self.held_alert = threshold;
+    }
+
+    /// Subscribe to the [`AccountDelta`] of every row applied from now on
+    /// to a client for which `client_filter` returns `true`, and of every
+    /// expiring hold (sent as a [`TxType::Release`] of the hold). Dropping
+    /// the returned [`Watch`] unsubscribes.
+    pub fn watch(&mut self, client_filter: impl Fn(u16) -> bool + Send + 'static) -> Watch {
+        let (tx, rx) = std::sync::mpsc::channel();
+        self.watchers.push(Watcher {
+            filter: Box::new(client_filter),
+            tx,
+        });
+        Watch::new(rx)
+    }
+
+    /// Flush all attached sinks.
+    pub fn flush_sinks(&mut self) -> Result<()> {
+        self.sinks.iter_mut().try_for_each(|s| s.flush())
+    }
+
+    /// End-of-input hook every frontend calls exactly once, whether input
+    /// ran out or a shutdown was requested: flushes alert sinks and the
+    /// client trace and returns the final summary. The engine stays usable
+    /// for reporting afterwards.
+    pub fn finalize(&mut self) -> Result<Finalized> {
+        self.flush_sinks()?;
+        if let Some(trace) = self.trace.as_mut() {
+            trace.flush()?;
+        }
+        Ok(Finalized {
+            rows: self.seq,
+            accounts: self.accounts.len(),
+            reclaimed_deposits: self.reclaimed.len(),
+            state_hash: self.state_hash(),
+        })
+    }
+
+    /// Sequence number of the last row passed to [`Engine::process`]
+    /// (1-based; `0` before the first row).
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Input position recorded with [`Engine::set_input_rows`] (or restored
+    /// from a snapshot).
+    pub fn input_rows(&self) -> u64 {
+        self.input_rows
+    }
+
+    /// Record how many input rows have been consumed so far. Unlike
+    /// [`Engine::seq`] this counts rows the frontend skipped or could not
+    /// parse, so it is the offset to restart reading from.
+    pub fn set_input_rows(&mut self, rows: u64) {
+        self.input_rows = rows;
+    }
+
+    /// `true` if at least one transaction actually changed this client's
+    /// account during the run (rows that were ignored do not count).
+    pub fn is_touched(&self, client: u16) -> bool {
+        self.touched.contains(&client)
+    }
+
+    /// Canonical SHA-256 (hex) over every account balance and lock flag plus
+    /// all open disputes, active holds, refund totals, the ids of reclaimed
+    /// deposits, idempotency keys (see [`crate::idempotency`]), escrowed
+    /// funds (see [`crate::escrow`]), buckets other than main (see
+    /// [`crate::bucket`]), credit interest (see [`crate::credit`]),
+    /// promotional credits (see [`crate::promo`]) and the rows waiting for
+    /// the tx id they name, in arrival order (see [`crate::deferred`]).
+    /// Two engines that converged to the
+    /// same state hash equal regardless of map iteration order or decimal
+    /// scale (`1.50` and `1.5` hash the same). Every section is tagged, and
+    /// the accounts and open disputes are counted, so entries of one section
+    /// never hash like another's.
+    pub fn state_hash(&self) -> String {
+        let canon = |d: Decimal| d.normalize().serialize();
+        let mut h = Sha256::new();
+        hash_accounts(&mut h, self.accounts.iter());
+
+        let mut open: Vec<_> = self.open_disputes().collect();
+        open.sort_by_key(|(tx, ..)| *tx);
+        h.update(b"D");
+        h.update(&(open.len() as u64).to_le_bytes());
+        for (tx, client, amount) in open {
+            h.update(&tx.to_le_bytes());
+            h.update(&client.to_le_bytes());
+            h.update(&canon(amount));
+        }
+
+        let mut active: Vec<_> = self
+            .holds
+            .iter()
+            .filter(|(_, hold)| hold.state == hold::State::Active)
+            .collect();
+        active.sort_by_key(|(tx, _)| **tx);
+        for (tx, hold) in active {
+            h.update(b"H");
+            h.update(&tx.to_le_bytes());
+            h.update(&hold.client.to_le_bytes());
+            h.update(&canon(hold.amount));
+        }
+
+        let mut refunded: Vec<_> = self
+            .withdrawals
+            .iter()
+            .filter(|(_, w)| !w.refunded.is_zero())
+            .collect();
+        refunded.sort_by_key(|(tx, _)| **tx);
+        for (tx, w) in refunded {
+            h.update(b"R");
+            h.update(&tx.to_le_bytes());
+            h.update(&w.client.to_le_bytes());
+            h.update(&canon(w.refunded));
+        }
+
+        let mut reclaimed: Vec<_> = self.reclaimed.iter().collect();
+        reclaimed.sort_by_key(|(tx, _)| **tx);
+        for (tx, client) in reclaimed {
+            h.update(b"G");
+            h.update(&tx.to_le_bytes());
+            h.update(&client.to_le_bytes());
+        }
+
+        let mut keys: Vec<_> = self
+            .keys
+            .iter()
+            .flat_map(|(client, keys)| keys.iter().map(move |(key, tx)| (client, key, tx)))
+            .collect();
+        keys.sort_unstable();
+        for (client, key, tx) in keys {
+            h.update(b"K");
+            h.update(&client.to_le_bytes());
+            h.update(&(key.len() as u32).to_le_bytes());
+            h.update(key.as_bytes());
+            h.update(&tx.to_le_bytes());
+        }
+
+        let mut escrowed: Vec<_> = self.escrow.iter().collect();
+        escrowed.sort_by_key(|(tx, _)| **tx);
+        for (tx, amount) in escrowed {
+            h.update(b"E");
+            h.update(&tx.to_le_bytes());
+            h.update(&canon(*amount));
+        }
+
+        let mut bucketed: Vec<_> = self.buckets.balances.iter().collect();
+        bucketed.sort_by_key(|(client, _)| **client);
+        for (client, buckets) in bucketed {
+            for (name, b) in buckets {
+                h.update(b"B");
+                h.update(&client.to_le_bytes());
+                h.update(&(name.len() as u32).to_le_bytes());
+                h.update(name.as_bytes());
+                h.update(&canon(b.available));
+                h.update(&canon(b.held));
+            }
+        }
+
+        let mut owed: Vec<_> = self
+            .credit
+            .iter()
+            .filter(|(_, a)| !a.interest.is_zero())
+            .collect();
+        owed.sort_by_key(|(client, _)| **client);
+        for (client, a) in owed {
+            h.update(b"C");
+            h.update(&client.to_le_bytes());
+            h.update(&canon(a.interest));
+        }
+
+        let mut promos: Vec<_> = self.promos.grants.iter().collect();
+        promos.sort_by_key(|(client, _)| **client);
+        for (client, grants) in promos {
+            for g in grants {
+                h.update(b"P");
+                h.update(&g.tx.to_le_bytes());
+                h.update(&client.to_le_bytes());
+                h.update(&canon(g.amount));
+                h.update(&canon(g.used));
+                h.update(&canon(g.expired));
+            }
+        }
+
+        let mut deferred: Vec<_> = self.deferred.values().flatten().collect();
+        deferred.sort_by_key(|r| r.seq);
+        for r in deferred {
+            let kind = r.tx.kind.as_str();
+            let key = r.key.as_deref().unwrap_or_default();
+            h.update(b"W");
+            h.update(&r.seq.to_le_bytes());
+            h.update(&(kind.len() as u32).to_le_bytes());
+            h.update(kind.as_bytes());
+            h.update(&r.tx.client.to_le_bytes());
+            h.update(&r.tx.tx.to_le_bytes());
+            match r.tx.amount {
+                Some(amount) => {
+                    h.update(b"\x01");
+                    h.update(&canon(amount));
                 }
+                None => h.update(b"\x00"),
             }
+            h.update(&(key.len() as u32).to_le_bytes());
+            h.update(key.as_bytes());
+        }
+        h.hex_digest()
+    }
+
+    /// Canonical SHA-256 (hex) over balances and lock flags only, leaving
+    /// out accounts that are zero and unlocked: the state a
+    /// [`crate::replica::Replica`] can rebuild from deltas (rejected rows
+    /// may open an empty account but send no delta).
+    pub fn accounts_hash(&self) -> String {
+        accounts_digest(&self.accounts)
+    }
+
+    /// Tx id of the row applied for `client` under idempotency key `key`,
+    /// if any.
+    pub fn idempotent_tx(&self, client: u16, key: &str) -> Option<u32> {
+        self.keys.get(&client)?.get(key).copied()
+    }
+
+    /// Whether a withdrawal of `amount` by `client` would be applied if it
+    /// were the next row, by the rules [`Engine::process`] uses: the amount
+    /// guards and [`EngineConfig::max_amounts`], the lock flag and the
+    /// available balance of the main bucket (see [`crate::bucket`]) plus
+    /// any credit line (see [`crate::credit`]). Nothing is changed; an
+    /// unknown client has no funds. There is no tx id to check, so the
+    /// withdrawal may still be rejected as [`Anomaly::DuplicateTx`].
+    ///
+    /// Hold expiry runs before each row, so under
+    /// [`EngineConfig::hold_expiry`] the next row may find more available
+    /// than this answer counts on.
+    ///
+    /// ### Example
+    /// ```rust
+    /// use payments_engine::anomaly::Anomaly;
+    /// use payments_engine::engine::Decision;
+    /// use payments_engine::{Engine, Transaction, TxType};
+    /// use rust_decimal_macros::dec;
+    ///
+    /// let mut eng = Engine::new();
+    /// eng.process(Transaction { kind: TxType::Deposit, client: 1, tx: 1, amount: Some(dec!(30)) })
+    ///     .unwrap();
+    /// assert_eq!(eng.can_withdraw(1, dec!(30)), Decision::Approve);
+    /// assert_eq!(eng.can_withdraw(1, dec!(31)), Decision::Decline(Anomaly::InsufficientFunds));
+    /// assert_eq!(eng.can_withdraw(1, dec!(-1)), Decision::Decline(Anomaly::NonPositiveAmount));
+    /// ```
+    pub fn can_withdraw(&self, client: u16, amount: Decimal) -> Decision {
+        let verdict = self
+            .check_amount(TxType::Withdrawal, Some(amount))
+            .and_then(|amount| {
+                let acc = self.accounts.get(&client).cloned().unwrap_or_default();
+                if acc.locked {
+                    Err(Anomaly::LockedAccount)
+                } else if self
+                    .buckets
+                    .spendable(client, &acc, MAIN, self.credit_limit(client))
+                    < amount
+                {
+                    Err(Anomaly::InsufficientFunds)
+                } else {
+                    Ok(())
+                }
+            });
+        match verdict {
+            Ok(()) => Decision::Approve,
+            Err(anomaly) => Decision::Decline(anomaly),
         }
+    }
+
+    /// `(tx, client, held amount)` of every open dispute, over deposits and
+    /// refunds alike, in no particular order.
+    pub(crate) fn open_disputes(&self) -> impl Iterator<Item = (u32, u16, Decimal)> + '_ {
+        let deposits = self
+            .deposits
+            .iter()
+            .filter(|(_, d)| d.dispute.is_open())
+            .map(|(tx, d)| (tx, d.client, d.amount));
+        let refunds = self
+            .withdrawals
+            .iter()
+            .filter(|(_, w)| w.dispute.is_open())
+            .map(|(tx, w)| (*tx, w.client, w.disputed));
+        deposits.chain(refunds)
+    }
+
+    /// Apply one transaction to the internal state. Rows that cannot be
+    /// applied are handled according to the anomaly policy; only
+    /// [`Action::Fatal`] turns them into an error.
+    pub fn process(&mut self, tx: Transaction) -> Result<()> {
+        self.process_with_result(tx).map(drop)
+    }
+
+    /// [`Engine::process`], also reporting what the row did: the balance
+    /// and lock changes if it was applied, or its anomaly class if not.
+    pub fn process_with_result(&mut self, tx: Transaction) -> Result<ProcessResult> {
+        self.process_row(tx, RowMeta::default())
+    }
+
+    /// [`Engine::process_with_result`] for a row carrying an idempotency
+    /// key (see [`crate::idempotency`]) or naming buckets (see
+    /// [`crate::bucket`]): a row whose client already had a row applied
+    /// under the same key is rejected as [`Anomaly::DuplicateKey`]. `None`
+    /// or an empty key checks nothing.
+    pub fn process_row(&mut self, tx: Transaction, meta: RowMeta) -> Result<ProcessResult> {
+        self.seq += 1;
+        self.expire_holds();
+        self.expire_promos();
+        let tx = self.normalize(tx);
+        let result = self.apply_row(tx, meta.key(), meta.buckets)?;
+        if let ProcessResult::Applied(delta) = &result
+            && matches!(delta.kind, TxType::Deposit | TxType::Refund)
+            && !self.deferred.is_empty()
+        {
+            self.retry_deferred(delta.tx)?;
+        }
+        Ok(result)
+    }
+
+    /// Check and apply one row under the current sequence number.
+    pub(crate) fn apply_row(
+        &mut self,
+        tx: Transaction,
+        key: Option<&str>,
+        buckets: RowBuckets,
+    ) -> Result<ProcessResult> {
+        self.save_row(&tx, key, buckets);
+        self.accrue(tx.client);
+
+        let before = self.accounts.get(&tx.client).cloned().unwrap_or_default();
+        let mut events = Vec::new();
+        let flow = self.config.check_invariants.then(|| self.flow(&tx));
+        let outcome = match key.and_then(|k| self.idempotent_tx(tx.client, k)) {
+            Some(_) => Err(Anomaly::DuplicateKey),
+            None => self.apply(&tx, buckets, &mut events),
+        };
+        if let Some(flow) = flow {
+            self.check_row(&tx, &before, flow, outcome.is_ok())?;
+        }
+        if outcome == Err(Anomaly::UnknownTx)
+            && self.config.defer_unknown_disputes
+            && Transition::for_kind(tx.kind).is_some()
+        {
+            return Ok(self.defer(tx, key));
+        }
+        self.trace_row(&tx, key, &before, outcome.err())?;
+        if let Err(anomaly) = outcome {
+            let rejected = ProcessResult::Rejected {
+                seq: self.seq,
+                client: tx.client,
+                tx: tx.tx,
+                anomaly,
+            };
+            self.batch.rejected();
+            self.reject(self.seq, anomaly, tx)?;
+            return Ok(rejected);
+        }
+        self.batch.applied(&tx);
+        self.touched.insert(tx.client);
+        if let Some(key) = key {
+            self.keys
+                .entry(tx.client)
+                .or_default()
+                .insert(key.to_owned(), tx.tx);
+        }
+        self.record_history(tx.client, tx.tx, tx.kind);
+        if tx.kind == TxType::Deposit
+            && let Some(detector) = self.structuring.as_mut()
+            && let Some(flag) =
+                detector.observe(self.seq, tx.client, tx.tx, tx.amount.unwrap_or_default())
+        {
+            events.push(Event::Structuring {
+                client: flag.client,
+                tx: tx.tx,
+                deposits: flag.deposits.clone(),
+                total: flag.total,
+            });
+        }
+        for event in &events {
+            for sink in &mut self.sinks {
+                sink.notify(event)?;
+            }
+            self.flag_alert(event);
+        }
+        let after = &self.accounts[&tx.client];
+        let delta = AccountDelta {
+            seq: self.seq,
+            client: tx.client,
+            tx: tx.tx,
+            kind: tx.kind,
+            available: after.available - before.available,
+            held: after.held - before.held,
+            locked: after.locked && !before.locked,
+        };
+        self.send(delta.clone());
+        Ok(ProcessResult::Applied(delta))
+    }
+
+    /// Fold in the state of an engine that saw a disjoint set of clients,
+    /// with sequence numbers from the same row stream (see
+    /// [`crate::parallel`]).
+    pub(crate) fn absorb(&mut self, other: Engine) {
+        self.accounts.extend(other.accounts);
+        self.deposits.extend(other.deposits);
+        self.reclaimed.extend(other.reclaimed);
+        self.withdrawals.extend(other.withdrawals);
+        self.holds.extend(other.holds);
+        self.hold_queue.extend(other.hold_queue);
+        self.hold_queue.make_contiguous().sort_unstable();
+        self.touched.extend(other.touched);
+        self.deferred.extend(other.deferred);
+        self.annotations.extend(other.annotations);
+        self.escrow.extend(other.escrow);
+        self.buckets.balances.extend(other.buckets.balances);
+        self.buckets.deposits.extend(other.buckets.deposits);
+        self.credit.extend(other.credit);
+        self.promos.grants.extend(other.promos.grants);
+        self.promos.ids.extend(other.promos.ids);
+        self.promos.queue.extend(other.promos.queue);
+        self.promos.queue.make_contiguous().sort_unstable();
+        for (client, keys) in other.keys {
+            self.keys.entry(client).or_default().extend(keys);
+        }
+        self.seq = self.seq.max(other.seq);
+        self.input_rows = self.input_rows.max(other.input_rows);
+        self.rejections.extend(other.rejections);
+        self.rejections.sort_by_key(|r| r.seq);
+        self.normalizations.extend(other.normalizations);
+        self.normalizations.sort_by_key(|n| n.seq);
+        if let (Some(mine), Some(theirs)) = (self.history.as_mut(), other.history) {
+            mine.extend(theirs);
+        }
+    }
+
+    /// Under [`EngineConfig::normalize_negative`], turn a negative deposit
+    /// into a withdrawal (and vice versa), noting the original row.
+    fn normalize(&mut self, tx: Transaction) -> Transaction {
+        let opposite = match tx.kind {
+            TxType::Deposit => TxType::Withdrawal,
+            TxType::Withdrawal => TxType::Deposit,
+            _ => return tx,
+        };
+        match tx.amount {
+            Some(a) if self.config.normalize_negative && a < Decimal::ZERO => {
+                let flipped = Transaction {
+                    kind: opposite,
+                    amount: Some(-a),
+                    ..tx
+                };
+                self.normalizations
+                    .push(Normalization { seq: self.seq, tx });
+                flipped
+            }
+            _ => tx,
+        }
+    }
+
+    /// Update balances for `tx`, or say why it cannot be applied. State is
+    /// untouched on error, apart from creating the account.
+    fn apply(
+        &mut self,
+        tx: &Transaction,
+        buckets: RowBuckets,
+        events: &mut Vec<Event>,
+    ) -> std::result::Result<(), Anomaly> {
+        let amount = self.check_amount(tx.kind, tx.amount)?;
+        let credit = self.credit_limit(tx.client);
 
         // create account on first valid activity
         let acc = self.accounts.entry(tx.client).or_default();
 
         // ignore any operation on a locked account
         if acc.locked {
-            return Ok(());
+            return Err(Anomaly::LockedAccount);
+        }
+        if tx.kind == TxType::Move || !buckets.is_main() {
+            bucket::check(tx.kind, buckets, self.buckets.of_deposit(tx.tx))?;
         }
 
         match tx.kind {
-            TxType::Deposit => {
-                let amount = tx.amount.unwrap();
+            // a repeated deposit id is ignored, which also makes replaying
+            // an already-applied stretch of input harmless for deposits
+            TxType::Deposit | TxType::Promo
+                if self.deposits.contains_key(&tx.tx)
+                    || self.reclaimed.contains_key(&tx.tx)
+                    || self.promos.contains(tx.tx) =>
+            {
+                Err(Anomaly::DuplicateTx)
+            }
+            TxType::Promo => {
                 acc.available += amount;
+                let grant = promo::Grant {
+                    tx: tx.tx,
+                    amount,
+                    used: Decimal::ZERO,
+                    expired: Decimal::ZERO,
+                    expires: self
+                        .config
+                        .promo_expiry_secs
+                        .map(|ttl| self.clock.now() + ttl),
+                };
+                self.promos.grant(tx.client, grant);
+                Ok(())
+            }
+            TxType::Deposit => {
+                let escrowed = self.config.escrow_clients.contains(&tx.client);
+                if escrowed {
+                    acc.held += amount;
+                    self.escrow.insert(tx.tx, amount);
+                } else {
+                    acc.available += amount;
+                }
+                if buckets.from() != MAIN {
+                    let (available, held) = match escrowed {
+                        true => (Decimal::ZERO, amount),
+                        false => (amount, Decimal::ZERO),
+                    };
+                    self.buckets
+                        .adjust(tx.client, buckets.from(), available, held);
+                    self.buckets
+                        .deposits
+                        .insert(tx.tx, buckets.from().to_owned());
+                }
                 self.deposits.insert(
                     tx.tx,
                     StoredTx {
                         client: tx.client,
                         amount,
-                        under_dispute: false,
+                        dispute: StateMachine::new(),
                     },
                 );
+                Ok(())
+            }
+            TxType::Withdrawal if self.withdrawals.contains_key(&tx.tx) => {
+                Err(Anomaly::DuplicateTx)
+            }
+            TxType::Withdrawal if acc.available + credit < amount => {
+                Err(Anomaly::InsufficientFunds)
+            }
+            TxType::Withdrawal
+                if (buckets.from() != MAIN || !self.buckets.is_empty())
+                    && self
+                        .buckets
+                        .spendable(tx.client, acc, buckets.from(), credit)
+                        < amount =>
+            {
+                Err(Anomaly::InsufficientFunds)
             }
             TxType::Withdrawal => {
-                let amount = tx.amount.unwrap();
-                if acc.available >= amount {
-                    acc.available -= amount;
+                acc.available -= amount;
+                self.promos.consume(tx.client, amount);
+                self.buckets
+                    .adjust(tx.client, buckets.from(), -amount, Decimal::ZERO);
+                self.withdrawals.insert(
+                    tx.tx,
+                    StoredWithdrawal {
+                        client: tx.client,
+                        amount,
+                        refunded: Decimal::ZERO,
+                        dispute: StateMachine::new(),
+                        disputed: Decimal::ZERO,
+                    },
+                );
+                Ok(())
+            }
+            TxType::Refund => {
+                let w = match self.withdrawals.get_mut(&tx.tx) {
+                    None => return Err(Anomaly::UnknownTx),
+                    Some(w) if w.client != tx.client => return Err(Anomaly::ClientMismatch),
+                    Some(w) => w,
+                };
+                if w.refunded + amount > w.amount {
+                    return Err(Anomaly::OverRefund);
                 }
+                w.refunded += amount;
+                acc.available += amount;
+                Ok(())
             }
-            TxType::Dispute => {
-                if let Some(dep) = self.deposits.get_mut(&tx.tx) {
-                    if !dep.under_dispute && dep.client == tx.client {
-                        dep.under_dispute = true;
-                        acc.available -= dep.amount;
-                        acc.held += dep.amount;
-                    }
+            TxType::Move
+                if self
+                    .buckets
+                    .spendable(tx.client, acc, buckets.from(), credit)
+                    < amount =>
+            {
+                Err(Anomaly::InsufficientFunds)
+            }
+            TxType::Move => {
+                let (from, to) = (buckets.from(), buckets.to());
+                self.buckets.adjust(tx.client, from, -amount, Decimal::ZERO);
+                self.buckets.adjust(tx.client, to, amount, Decimal::ZERO);
+                Ok(())
+            }
+            TxType::Hold if self.holds.contains_key(&tx.tx) => Err(Anomaly::DuplicateTx),
+            TxType::Hold if acc.available + credit < amount => Err(Anomaly::InsufficientFunds),
+            TxType::Hold => {
+                acc.available -= amount;
+                acc.held += amount;
+                self.holds.insert(
+                    tx.tx,
+                    StoredHold {
+                        client: tx.client,
+                        amount,
+                        state: hold::State::Active,
+                        seq: self.seq,
+                        at: self.config.hold_expiry_secs.map(|_| self.clock.now()),
+                    },
+                );
+                self.hold_queue.push_back((self.seq, tx.tx));
+                Ok(())
+            }
+            TxType::Release
+                if !self.holds.contains_key(&tx.tx) && self.escrow.contains_key(&tx.tx) =>
+            {
+                let dep = self.deposits.get(&tx.tx).expect("escrowed deposit");
+                if dep.client != tx.client {
+                    return Err(Anomaly::ClientMismatch);
                 }
+                let escrowed = self.escrow.get_mut(&tx.tx).expect("escrowed deposit");
+                let released = escrow::release(acc, escrowed, tx.amount, dep.dispute.is_open())?;
+                let bucket = self.buckets.of_deposit(tx.tx).to_owned();
+                self.buckets.adjust(tx.client, &bucket, released, -released);
+                Ok(())
             }
-            TxType::Resolve => {
-                if let Some(dep) = self.deposits.get_mut(&tx.tx) {
-                    if dep.under_dispute && dep.client == tx.client {
-                        dep.under_dispute = false;
-                        acc.available += dep.amount;
-                        acc.held -= dep.amount;
-                    }
+            TxType::Release | TxType::Capture => {
+                let held = match self.holds.get_mut(&tx.tx) {
+                    None => return Err(Anomaly::UnknownTx),
+                    Some(h) if h.client != tx.client => return Err(Anomaly::ClientMismatch),
+                    Some(h) => h,
+                };
+                let transition = hold::Transition::for_kind(tx.kind).expect("hold-closing row");
+                held.state = held.state.next(transition)?;
+                acc.held -= held.amount;
+                if held.state == hold::State::Released {
+                    acc.available += held.amount;
                 }
+                Ok(())
             }
-            TxType::Chargeback => {
-                if let Some(dep) = self.deposits.get_mut(&tx.tx) {
-                    if dep.under_dispute && dep.client == tx.client {
-                        dep.under_dispute = false;
-                        acc.held -= dep.amount;
-                        acc.locked = true;
+            // disputes apply to credits — a deposit, or the refunds of a
+            // withdrawal — and hold the amount at the client it credited
+            TxType::Dispute | TxType::Resolve | TxType::Chargeback => {
+                let transition = Transition::for_kind(tx.kind).expect("dispute-family row");
+                if let Some(mut dep) = self.deposits.get(&tx.tx) {
+                    if dep.client != tx.client {
+                        return Err(Anomaly::ClientMismatch);
+                    }
+                    let state = dep.dispute.apply(transition)?;
+                    self.deposits.set_state(tx.tx, state);
+                    // funds still in escrow are held already
+                    let amount = match (state, self.escrow.get(&tx.tx)) {
+                        (State::ChargedBack, Some(_)) => {
+                            self.escrow.remove(&tx.tx);
+                            dep.amount
+                        }
+                        (_, Some(escrowed)) => dep.amount - escrowed,
+                        (_, None) => dep.amount,
+                    };
+                    Self::dispute_effect(acc, state, amount, tx, self.held_alert, events);
+                    self.buckets.dispute(tx.client, tx.tx, state, amount);
+                    if state == State::ChargedBack && self.config.gc_deposits {
+                        self.deposits.remove(&tx.tx);
+                        self.reclaimed.insert(tx.tx, tx.client);
+                    }
+                    return Ok(());
+                }
+                if let Some(&client) = self.reclaimed.get(&tx.tx) {
+                    if client != tx.client {
+                        return Err(Anomaly::ClientMismatch);
+                    }
+                    let illegal = StateMachine::from_state(State::ChargedBack)
+                        .apply(transition)
+                        .expect_err("a charge-back is final");
+                    return Err(illegal.into());
+                }
+                let w = match self.withdrawals.get_mut(&tx.tx) {
+                    Some(w) if w.refunded.is_zero() && !w.dispute.is_open() => {
+                        return Err(Anomaly::UnknownTx);
                     }
+                    None => return Err(Anomaly::UnknownTx),
+                    Some(w) if w.client != tx.client => return Err(Anomaly::ClientMismatch),
+                    Some(w) => w,
+                };
+                let state = w.dispute.apply(transition)?;
+                match state {
+                    State::Open => w.disputed = w.refunded,
+                    State::ChargedBack => w.refunded -= w.disputed,
+                    _ => {}
+                }
+                Self::dispute_effect(acc, state, w.disputed, tx, self.held_alert, events);
+                Ok(())
+            }
+        }
+    }
+
+    /// The amount of a `kind` row, or why it is unacceptable: money
+    /// movements need a positive amount within the configured bounds.
+    fn check_amount(
+        &self,
+        kind: TxType,
+        amount: Option<Decimal>,
+    ) -> std::result::Result<Decimal, Anomaly> {
+        match amount {
+            None if kind.carries_amount() => Err(Anomaly::MissingAmount),
+            Some(a) if kind.carries_amount() && a <= Decimal::ZERO => {
+                Err(Anomaly::NonPositiveAmount)
+            }
+            Some(a)
+                if kind.carries_amount()
+                    && self.config.max_amounts.max_for(kind).is_some_and(|m| a > m) =>
+            {
+                Err(Anomaly::AmountTooLarge)
+            }
+            a => Ok(a.unwrap_or_default()),
+        }
+    }
+
+    /// Balance effect of a dispute that just moved to `state` over `amount`.
+    fn dispute_effect(
+        acc: &mut Account,
+        state: State,
+        amount: Decimal,
+        tx: &Transaction,
+        held_alert: Option<Decimal>,
+        events: &mut Vec<Event>,
+    ) {
+        match state {
+            State::Open => {
+                acc.available -= amount;
+                acc.held += amount;
+                if let Some(threshold) = held_alert
+                    && acc.held >= threshold
+                    && acc.held - amount < threshold
+                {
+                    events.push(Event::HeldThreshold {
+                        client: tx.client,
+                        tx: tx.tx,
+                        held: acc.held,
+                        threshold,
+                    });
                 }
             }
+            State::Resolved => {
+                acc.available += amount;
+                acc.held -= amount;
+            }
+            State::ChargedBack => {
+                acc.held -= amount;
+                acc.locked = true;
+                events.push(Event::Chargeback {
+                    client: tx.client,
+                    tx: tx.tx,
+                    amount,
+                });
+                events.push(Event::AccountLocked {
+                    client: tx.client,
+                    tx: tx.tx,
+                });
+            }
+            State::None => unreachable!("no transition leads back to None"),
+        }
+    }
+
+    /// Release every active hold whose expiry window, in rows or in clock
+    /// time, ended before the current row. Holds are swept in creation
+    /// order, which is also time order as long as the clock does not go
+    /// back.
+    pub(crate) fn expire_holds(&mut self) {
+        let (rows, secs) = (self.config.hold_expiry, self.config.hold_expiry_secs);
+        if rows.is_none() && secs.is_none() {
+            return;
+        }
+        let now = secs.map(|_| self.clock.now());
+        let expired = |created: u64, held: &StoredHold, seq: u64| {
+            rows.is_some_and(|ttl| created + ttl < seq)
+                || matches!((secs, now, held.at), (Some(ttl), Some(now), Some(at)) if at + ttl < now)
+        };
+        while let Some(&(created, tx)) = self.hold_queue.front() {
+            let active = match self.holds.get(&tx) {
+                Some(held)
+                    if held.state == hold::State::Active && !expired(created, held, self.seq) =>
+                {
+                    break;
+                }
+                Some(held) => (held.state == hold::State::Active).then_some(held.client),
+                None => None,
+            };
+            self.hold_queue.pop_front();
+            self.save_queue_pop((created, tx));
+            let Some(client) = active else {
+                continue;
+            };
+            self.save_expiry(tx, client);
+            self.accrue(client);
+            let held = self.holds.get_mut(&tx).expect("active hold");
+            held.state = hold::State::Expired;
+            let amount = held.amount;
+            let acc = self.accounts.get_mut(&client).expect("hold has an account");
+            acc.held -= amount;
+            acc.available += amount;
+            self.touched.insert(client);
+            // watchers see an expiry as the release it amounts to
+            self.send(AccountDelta {
+                seq: self.seq,
+                client,
+                tx,
+                kind: TxType::Release,
+                available: amount,
+                held: -amount,
+                locked: false,
+            });
+            self.record_history(client, tx, TxType::Release);
+        }
+    }
+
+    /// Deliver `delta` to the watchers of its client.
+    pub(crate) fn send(&mut self, delta: AccountDelta) {
+        // a failed send means the Watch was dropped
+        self.watchers
+            .retain(|w| !(w.filter)(delta.client) || w.tx.send(delta.clone()).is_ok());
+    }
+
+    /// Enforce the anomaly policy on row `seq`.
+    pub(crate) fn reject(&mut self, seq: u64, anomaly: Anomaly, tx: Transaction) -> Result<()> {
+        match self.config.anomalies.action(anomaly) {
+            Action::Ignore => {}
+            Action::Log => tracing::warn!(
+                seq,
+                client = tx.client,
+                tx = tx.tx,
+                %anomaly,
+                "row rejected"
+            ),
+            Action::Record => self.rejections.push(Rejection { seq, anomaly, tx }),
+            Action::Fatal => bail!(
+                "row {seq}: {anomaly} ({} by client {}, tx {})",
+                tx.kind.as_str(),
+                tx.client,
+                tx.tx
+            ),
         }
         Ok(())
     }
 }
+
+/// Feed `accounts` into `h` in client order: the canonical account section
+/// of [`Engine::state_hash`].
+fn hash_accounts<'a>(h: &mut Sha256, accounts: impl Iterator<Item = (&'a u16, &'a Account)>) {
+    let canon = |d: Decimal| d.normalize().serialize();
+    let mut clients: Vec<_> = accounts.collect();
+    clients.sort_by_key(|(id, _)| **id);
+    // tagged and counted so the entries can't be read as the next section's
+    h.update(b"A");
+    h.update(&(clients.len() as u64).to_le_bytes());
+    for (id, acc) in clients {
+        h.update(&id.to_le_bytes());
+        h.update(&canon(acc.available));
+        h.update(&canon(acc.held));
+        h.update(&[acc.locked as u8]);
+    }
+}
+
+/// See [`Engine::accounts_hash`].
+pub(crate) fn accounts_digest(accounts: &Map<u16, Account>) -> String {
+    let mut h = Sha256::new();
+    let empty = Account::default();
+    hash_accounts(&mut h, accounts.iter().filter(|(_, acc)| **acc != empty));
+    h.hex_digest()
+}
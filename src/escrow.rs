@@ -0,0 +1,77 @@
+//! Escrow accounts, for marketplace flows: a buyer's payment is credited
+//! to the seller's account but only becomes spendable as milestones are
+//! met.
+//!
+//! Deposits of a client listed in
+//! [`EngineConfig::escrow_clients`](crate::engine::EngineConfig::escrow_clients)
+//! go to held instead of available and stay there until released by
+//! `release` rows naming the deposit's tx id: with an amount, that much is
+//! released (a milestone); without one, whatever is left. Releasing more
+//! than is left in escrow is rejected as [`Anomaly::OverRelease`]. Holds
+//! and escrowed deposits share the `release` row type; a tx id naming a
+//! hold is a hold release, as without escrow.
+//!
+//! A dispute of an escrowed deposit holds only the part already released,
+//! as the rest is held already, and nothing can be released while the
+//! dispute is open. A resolve returns the released part to available and
+//! the rest stays in escrow; a chargeback takes the whole deposit out of
+//! held and closes its escrow.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::engine::EngineConfig;
+//! use payments_engine::{Engine, Transaction, TxType};
+//! use rust_decimal_macros::dec;
+//!
+//! let config = EngineConfig { escrow_clients: [1].into(), ..Default::default() };
+//! let mut eng = Engine::with_config(config);
+//! let row = |kind, amount| Transaction { kind, client: 1, tx: 9, amount };
+//! let balances = |eng: &Engine| (eng.accounts[&1].available, eng.accounts[&1].held);
+//!
+//! eng.process(row(TxType::Deposit, Some(dec!(100)))).unwrap();
+//! assert_eq!(balances(&eng), (dec!(0), dec!(100)));
+//! eng.process(row(TxType::Release, Some(dec!(30)))).unwrap(); // first milestone
+//! assert_eq!((balances(&eng), eng.escrowed(9)), ((dec!(30), dec!(70)), Some(dec!(70))));
+//!
+//! eng.process(row(TxType::Dispute, None)).unwrap(); // holds the released 30
+//! assert_eq!(balances(&eng), (dec!(0), dec!(100)));
+//! eng.process(row(TxType::Resolve, None)).unwrap();
+//! eng.process(row(TxType::Release, None)).unwrap(); // the rest
+//! assert_eq!((balances(&eng), eng.escrowed(9)), ((dec!(100), dec!(0)), Some(dec!(0))));
+//! ```
+
+use crate::anomaly::Anomaly;
+use crate::engine::Engine;
+use crate::models::Account;
+use rust_decimal::Decimal;
+
+impl Engine {
+    /// Funds of deposit `tx` still in escrow, if it was made to an escrow
+    /// account.
+    pub fn escrowed(&self, tx: u32) -> Option<Decimal> {
+        self.escrow.get(&tx).copied()
+    }
+}
+
+/// Release `amount` (all that is left if `None`) of the `escrowed` funds of
+/// a deposit to available; `disputed` if the deposit is under dispute.
+/// Returns the amount released.
+pub(crate) fn release(
+    acc: &mut Account,
+    escrowed: &mut Decimal,
+    amount: Option<Decimal>,
+    disputed: bool,
+) -> Result<Decimal, Anomaly> {
+    let amount = match amount {
+        Some(a) if a <= Decimal::ZERO => return Err(Anomaly::NonPositiveAmount),
+        Some(a) => a,
+        None => *escrowed,
+    };
+    if disputed || amount.is_zero() || amount > *escrowed {
+        return Err(Anomaly::OverRelease);
+    }
+    *escrowed -= amount;
+    acc.held -= amount;
+    acc.available += amount;
+    Ok(amount)
+}
@@ -0,0 +1,468 @@
+//! Why a row was applied or rejected, in words: the account before it, the
+//! rules the engine checked, the decision and the account after.
+//!
+//! [`Engine::explain`] processes a row like [`Engine::process_with_result`]
+//! and returns an [`Explanation`] alongside; [`Engine::explain_row`] does
+//! the same for [`Engine::process_row`], checking the row's idempotency key
+//! and buckets too. The rules are listed in the
+//! order the engine checks them, with the values each one looked at; the
+//! verdict comes from the engine itself, so the first rule guarding the
+//! anomaly it reported is the one that failed and later ones were never
+//! reached. Its [`Display`](std::fmt::Display) form is the narrative the
+//! `explain` subcommand prints.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::anomaly::Anomaly;
+//! use payments_engine::explain::Verdict;
+//! use payments_engine::{Engine, Transaction, TxType};
+//! use rust_decimal_macros::dec;
+//!
+//! let mut eng = Engine::new();
+//! eng.process(Transaction { kind: TxType::Deposit, client: 7, tx: 1, amount: Some(dec!(12)) })
+//!     .unwrap();
+//!
+//! let why = eng
+//!     .explain(Transaction { kind: TxType::Withdrawal, client: 7, tx: 2, amount: Some(dec!(50)) })
+//!     .unwrap();
+//! assert_eq!(why.outcome, Err(Anomaly::InsufficientFunds));
+//! let failed = why.rules.iter().find(|r| r.verdict == Verdict::Failed).unwrap();
+//! assert_eq!(failed.text, "available funds cover the amount (12.0000 available, 50.0000 asked)");
+//! assert_eq!(why.before, why.after);
+//! assert!(why.to_string().contains("Decision: rejected (insufficient-funds)"));
+//! ```
+
+use crate::anomaly::Anomaly;
+use crate::bucket::MAIN;
+use crate::dispute::{State, Transition};
+use crate::engine::Engine;
+use crate::errors::Result;
+use crate::feed::ProcessResult;
+use crate::hold;
+use crate::models::{Account, Transaction, TxType};
+use crate::processor::RowMeta;
+use rust_decimal::Decimal;
+use std::fmt;
+
+/// How a rule fared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Passed,
+    Failed,
+    /// An earlier rule failed first.
+    NotReached,
+}
+
+/// One check the engine makes on a row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    /// What the rule requires, with the values it looked at.
+    pub text: String,
+    /// The anomaly the row is rejected as when the rule fails.
+    pub guards: Anomaly,
+    pub verdict: Verdict,
+}
+
+/// A processed row and the reasons for its outcome; see the module docs.
+#[derive(Debug)]
+pub struct Explanation {
+    /// [`Engine::seq`] of the row.
+    pub seq: u64,
+    pub row: Transaction,
+    /// The row's idempotency key, if it carries one.
+    pub key: Option<String>,
+    /// The buckets the row named, as `(bucket, to_bucket)`, unless both
+    /// are main.
+    pub buckets: Option<(String, String)>,
+    /// The row as applied, if negative-amount normalization rewrote it.
+    pub normalized: Option<Transaction>,
+    /// The client's account just before the row (after any hold expiry it
+    /// triggered) and just after it; `None` before its first row.
+    pub before: Option<Account>,
+    pub rules: Vec<Rule>,
+    pub outcome: std::result::Result<(), Anomaly>,
+    /// The row names a tx id not seen yet and waits for it (see
+    /// [`crate::deferred`]); `outcome` is then [`Anomaly::UnknownTx`].
+    pub deferred: bool,
+    pub after: Option<Account>,
+}
+
+impl Engine {
+    /// Process `tx` and explain the outcome; see [`crate::explain`].
+    pub fn explain(&mut self, tx: Transaction) -> Result<Explanation> {
+        self.explain_row(tx, RowMeta::default())
+    }
+
+    /// [`Engine::explain`] for a row carrying `meta`, processed like
+    /// [`Engine::process_row`].
+    pub fn explain_row(&mut self, tx: Transaction, meta: RowMeta) -> Result<Explanation> {
+        // run the expiry sweep the row would trigger, so the rules see the
+        // balances the row will be checked against
+        self.seq += 1;
+        self.expire_holds();
+        self.seq -= 1;
+
+        let row = Transaction { ..tx };
+        let normalized = self.normalized(&row);
+        let before = self.accounts.get(&row.client).cloned();
+        let mut rules = self.rules(normalized.as_ref().unwrap_or(&row), meta);
+        let result = self.process_row(tx, meta)?;
+        let deferred = matches!(result, ProcessResult::Deferred { .. });
+        let outcome = match result {
+            ProcessResult::Applied(_) => Ok(()),
+            ProcessResult::Rejected { anomaly, .. } => Err(anomaly),
+            ProcessResult::Deferred { .. } => Err(Anomaly::UnknownTx),
+        };
+        let failed = outcome
+            .err()
+            .and_then(|a| rules.iter().position(|r| r.guards == a));
+        for (i, rule) in rules.iter_mut().enumerate() {
+            rule.verdict = match failed {
+                Some(f) if i == f => Verdict::Failed,
+                Some(f) if i > f => Verdict::NotReached,
+                _ => Verdict::Passed,
+            };
+        }
+        Ok(Explanation {
+            seq: self.seq,
+            after: self.accounts.get(&row.client).cloned(),
+            row,
+            key: meta.key().map(str::to_owned),
+            buckets: (!meta.buckets.is_main()).then(|| {
+                let (from, to) = (meta.buckets.from(), meta.buckets.to());
+                (from.to_owned(), to.to_owned())
+            }),
+            normalized,
+            before,
+            rules,
+            outcome,
+            deferred,
+        })
+    }
+
+    /// `tx` as [`EngineConfig::normalize_negative`](crate::engine::EngineConfig::normalize_negative)
+    /// would rewrite it, if it would.
+    fn normalized(&self, tx: &Transaction) -> Option<Transaction> {
+        let kind = match tx.kind {
+            TxType::Deposit => TxType::Withdrawal,
+            TxType::Withdrawal => TxType::Deposit,
+            _ => return None,
+        };
+        let amount = tx.amount.filter(|a| *a < Decimal::ZERO)?;
+        self.config().normalize_negative.then_some(Transaction {
+            kind,
+            amount: Some(-amount),
+            ..*tx
+        })
+    }
+
+    /// The rules `tx` carrying `meta` is checked against, in order, all
+    /// marked passed.
+    fn rules(&self, tx: &Transaction, meta: RowMeta) -> Vec<Rule> {
+        let mut rules = Vec::new();
+        let mut rule = |guards, text: String| {
+            rules.push(Rule {
+                text,
+                guards,
+                verdict: Verdict::Passed,
+            })
+        };
+        if let Some(key) = meta.key() {
+            let used = match self.idempotent_tx(tx.client, key) {
+                Some(earlier) => format!(" (tx {earlier} used it)"),
+                None => String::new(),
+            };
+            rule(
+                Anomaly::DuplicateKey,
+                format!(
+                    "no earlier row of client {} used key {key:?}{used}",
+                    tx.client
+                ),
+            );
+        }
+        let amount = tx.amount.unwrap_or_default();
+        if tx.kind.carries_amount() {
+            rule(Anomaly::MissingAmount, "the row carries an amount".into());
+            rule(
+                Anomaly::NonPositiveAmount,
+                format!("the amount is positive ({})", money(amount)),
+            );
+            if let Some(max) = self.config().max_amounts.max_for(tx.kind) {
+                rule(
+                    Anomaly::AmountTooLarge,
+                    format!(
+                        "the amount is at most {} for a {}",
+                        money(max),
+                        tx.kind.as_str()
+                    ),
+                );
+            }
+        }
+        let acc = self.accounts.get(&tx.client).cloned().unwrap_or_default();
+        rule(
+            Anomaly::LockedAccount,
+            format!("account {} is not locked", tx.client),
+        );
+        let buckets = meta.buckets;
+        if tx.kind == TxType::Move || !buckets.is_main() {
+            let named = match buckets.to() {
+                MAIN if tx.kind != TxType::Move => buckets.from().to_owned(),
+                to => format!("{} to {to}", buckets.from()),
+            };
+            let text = match tx.kind {
+                TxType::Move => format!("the move is between two buckets ({named})"),
+                kind => format!("a {} may use the buckets it names ({named})", kind.as_str()),
+            };
+            rule(Anomaly::BadBucket, text);
+        }
+        let credit = self.credit_limit(tx.client);
+        let spendable = self
+            .buckets
+            .spendable(tx.client, &acc, buckets.from(), credit);
+        let from_bucket = || {
+            format!(
+                "bucket {} covers the amount ({} spendable, {} asked)",
+                buckets.from(),
+                money(spendable),
+                money(amount)
+            )
+        };
+        let available = || match credit.is_zero() {
+            true => format!(
+                "available funds cover the amount ({} available, {} asked)",
+                money(acc.available),
+                money(amount)
+            ),
+            false => format!(
+                "available funds and credit cover the amount ({} available, {} credit, {} asked)",
+                money(acc.available),
+                money(credit),
+                money(amount)
+            ),
+        };
+        match tx.kind {
+            TxType::Deposit | TxType::Promo => rule(
+                Anomaly::DuplicateTx,
+                format!("no earlier deposit or promo used tx {}", tx.tx),
+            ),
+            TxType::Withdrawal => {
+                rule(
+                    Anomaly::DuplicateTx,
+                    format!("no earlier withdrawal used tx {}", tx.tx),
+                );
+                // the bucket is checked after the account as a whole
+                match acc.available + credit < amount
+                    || buckets.from() == MAIN && self.buckets.is_empty()
+                {
+                    true => rule(Anomaly::InsufficientFunds, available()),
+                    false => rule(Anomaly::InsufficientFunds, from_bucket()),
+                }
+            }
+            TxType::Move => rule(Anomaly::InsufficientFunds, from_bucket()),
+            TxType::Hold => {
+                rule(
+                    Anomaly::DuplicateTx,
+                    format!("no earlier hold used tx {}", tx.tx),
+                );
+                rule(Anomaly::InsufficientFunds, available());
+            }
+            TxType::Refund => {
+                rule(Anomaly::UnknownTx, format!("withdrawal {} exists", tx.tx));
+                let w = self.withdrawals.get(&tx.tx);
+                rule(
+                    Anomaly::ClientMismatch,
+                    owned_by("withdrawal", tx, w.map(|w| w.client)),
+                );
+                let (refunded, limit) =
+                    w.map_or((Decimal::ZERO, Decimal::ZERO), |w| (w.refunded, w.amount));
+                rule(
+                    Anomaly::OverRefund,
+                    format!(
+                        "refunds stay within the withdrawal ({} refunded + {} ≤ {})",
+                        money(refunded),
+                        money(amount),
+                        money(limit)
+                    ),
+                );
+            }
+            TxType::Release
+                if !self.holds.contains_key(&tx.tx) && self.escrowed(tx.tx).is_some() =>
+            {
+                let dep = self.deposits.get(&tx.tx).expect("escrowed deposit");
+                rule(
+                    Anomaly::ClientMismatch,
+                    owned_by("deposit", tx, Some(dep.client)),
+                );
+                let escrowed = self.escrowed(tx.tx).unwrap_or_default();
+                let asked = match tx.amount {
+                    Some(a) => {
+                        rule(
+                            Anomaly::NonPositiveAmount,
+                            format!("the amount is positive ({})", money(a)),
+                        );
+                        money(a)
+                    }
+                    None => "the rest".into(),
+                };
+                let text = match dep.dispute.is_open() {
+                    true => "the deposit is not under dispute (under dispute)".into(),
+                    false => format!(
+                        "escrow covers the release ({} in escrow, {asked} asked)",
+                        money(escrowed)
+                    ),
+                };
+                rule(Anomaly::OverRelease, text);
+            }
+            TxType::Release | TxType::Capture => {
+                rule(Anomaly::UnknownTx, format!("hold {} exists", tx.tx));
+                let held = self.holds.get(&tx.tx);
+                rule(
+                    Anomaly::ClientMismatch,
+                    owned_by("hold", tx, held.map(|h| h.client)),
+                );
+                let state = held.map_or(hold::State::Active, |h| h.state);
+                rule(
+                    Anomaly::HoldNotActive,
+                    format!("the hold is active ({})", state.as_str()),
+                );
+            }
+            TxType::Dispute | TxType::Resolve | TxType::Chargeback => {
+                let (what, client, state) = if let Some(dep) = self.deposits.get(&tx.tx) {
+                    ("deposit", Some(dep.client), dep.dispute.state())
+                } else if let Some(&client) = self.reclaimed.get(&tx.tx) {
+                    ("deposit", Some(client), State::ChargedBack)
+                } else if let Some(w) = self.withdrawals.get(&tx.tx) {
+                    ("refunded withdrawal", Some(w.client), w.dispute.state())
+                } else {
+                    ("deposit or refunded withdrawal", None, State::None)
+                };
+                rule(Anomaly::UnknownTx, format!("{what} {} exists", tx.tx));
+                rule(Anomaly::ClientMismatch, owned_by(what, tx, client));
+                let state = dispute_state(state);
+                match Transition::for_kind(tx.kind).expect("dispute-family row") {
+                    Transition::Open => rule(
+                        Anomaly::AlreadyDisputed,
+                        format!("the {what} is not under dispute or charged back ({state})"),
+                    ),
+                    Transition::Resolve | Transition::Chargeback => rule(
+                        Anomaly::NotDisputed,
+                        format!("the {what} is under dispute ({state})"),
+                    ),
+                }
+            }
+        }
+        rules
+    }
+}
+
+/// Rule text for a record of `client` having to belong to the row's client.
+fn owned_by(what: &str, tx: &Transaction, client: Option<u16>) -> String {
+    match client {
+        Some(c) if c != tx.client => {
+            format!(
+                "the {what} belongs to client {} (it is client {c}'s)",
+                tx.client
+            )
+        }
+        _ => format!("the {what} belongs to client {}", tx.client),
+    }
+}
+
+fn dispute_state(state: State) -> &'static str {
+    match state {
+        State::None => "undisputed",
+        State::Open => "under dispute",
+        State::Resolved => "resolved",
+        State::ChargedBack => "charged back",
+    }
+}
+
+fn money(d: Decimal) -> String {
+    format!("{d:.4}")
+}
+
+fn account(acc: Option<&Account>) -> String {
+    match acc {
+        None => "no account yet".into(),
+        Some(a) => format!(
+            "available {}, held {}, total {}{}",
+            money(a.available),
+            money(a.held),
+            money(a.total()),
+            if a.locked { ", locked" } else { "" }
+        ),
+    }
+}
+
+/// What a rejection means, for the decision line.
+fn reason(anomaly: Anomaly) -> &'static str {
+    match anomaly {
+        Anomaly::MissingAmount => "the row has no amount",
+        Anomaly::NonPositiveAmount => "the amount is zero or negative",
+        Anomaly::LockedAccount => "the account was locked by a chargeback",
+        Anomaly::InsufficientFunds => "the client did not have enough available funds",
+        Anomaly::DuplicateTx => "the tx id was already used",
+        Anomaly::UnknownTx => "the transaction it refers to does not exist",
+        Anomaly::ClientMismatch => "the transaction it refers to is another client's",
+        Anomaly::AlreadyDisputed => "the transaction is already disputed or charged back",
+        Anomaly::NotDisputed => "the transaction is not under dispute",
+        Anomaly::HoldNotActive => "the hold was already closed",
+        Anomaly::OverRefund => "it would refund more than was withdrawn",
+        Anomaly::AmountTooLarge => "the amount is above the configured maximum",
+        Anomaly::DuplicateKey => "its idempotency key was already applied",
+        Anomaly::OverRelease => "it would release more than is left in escrow",
+        Anomaly::BadBucket => "it names a bucket it cannot use",
+    }
+}
+
+fn describe(tx: &Transaction) -> String {
+    let amount = tx.amount.map(|a| format!(" of {}", money(a)));
+    format!(
+        "{}{} by client {}, tx {}",
+        tx.kind.as_str(),
+        amount.unwrap_or_default(),
+        tx.client,
+        tx.tx
+    )
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Row {}: {}", self.seq, describe(&self.row))?;
+        if let Some(key) = &self.key {
+            writeln!(f, "Idempotency key: {key}")?;
+        }
+        match &self.buckets {
+            Some((from, to)) if to == MAIN && self.row.kind != TxType::Move => {
+                writeln!(f, "Bucket: {from}")?
+            }
+            Some((from, to)) => writeln!(f, "Buckets: {from} to {to}")?,
+            None => {}
+        }
+        if let Some(n) = &self.normalized {
+            writeln!(f, "Negative amount: processed as a {}", describe(n))?;
+        }
+        writeln!(f, "Before: {}", account(self.before.as_ref()))?;
+        writeln!(f, "Rules:")?;
+        for rule in &self.rules {
+            let mark = match rule.verdict {
+                Verdict::Passed => "ok  ",
+                Verdict::Failed => "FAIL",
+                Verdict::NotReached => "--  ",
+            };
+            writeln!(f, "  {mark} {}", rule.text)?;
+        }
+        match self.outcome {
+            Ok(()) => writeln!(f, "Decision: applied")?,
+            Err(_) if self.deferred => {
+                writeln!(f, "Decision: deferred until tx {} arrives", self.row.tx)?
+            }
+            Err(a) => writeln!(f, "Decision: rejected ({a}): {}", reason(a))?,
+        }
+        if self.after == self.before {
+            writeln!(f, "After: unchanged")
+        } else {
+            writeln!(f, "After: {}", account(self.after.as_ref()))
+        }
+    }
+}
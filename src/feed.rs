@@ -0,0 +1,124 @@
+//! Per-row processing results for integrators building real-time balance
+//! feeds: what each row did to its account, without diffing whole account
+//! maps after every row. [`Engine::watch`](crate::Engine::watch) pushes the
+//! same deltas for selected clients to another thread, e.g. a dashboard of
+//! VIP accounts during a large batch run.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::feed::ProcessResult;
+//! use payments_engine::anomaly::Anomaly;
+//! use payments_engine::{Engine, Transaction, TxType};
+//! use rust_decimal_macros::dec;
+//!
+//! let mut eng = Engine::new();
+//! let dep = Transaction { kind: TxType::Deposit, client: 1, tx: 1, amount: Some(dec!(10)) };
+//! let dis = Transaction { kind: TxType::Dispute, client: 1, tx: 1, amount: None };
+//! eng.process_with_result(dep).unwrap();
+//!
+//! let ProcessResult::Applied(delta) = eng.process_with_result(dis).unwrap() else {
+//!     panic!("dispute should apply");
+//! };
+//! assert_eq!((delta.available, delta.held, delta.locked), (dec!(-10), dec!(10), false));
+//!
+//! let again = Transaction { kind: TxType::Dispute, client: 1, tx: 1, amount: None };
+//! assert!(matches!(
+//!     eng.process_with_result(again).unwrap(),
+//!     ProcessResult::Rejected { anomaly: Anomaly::AlreadyDisputed, .. }
+//! ));
+//! ```
+
+use crate::anomaly::Anomaly;
+use crate::models::TxType;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::sync::mpsc::{Receiver, Sender};
+
+/// Change one applied row made to its account.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AccountDelta {
+    /// Row sequence number (see [`Engine::seq`](crate::Engine::seq)).
+    pub seq: u64,
+    pub client: u16,
+    pub tx: u32,
+    pub kind: TxType,
+    /// Signed change of available funds.
+    pub available: Decimal,
+    /// Signed change of held funds.
+    pub held: Decimal,
+    /// `true` if this row locked the account.
+    pub locked: bool,
+}
+
+/// Outcome of [`Engine::process_with_result`](crate::Engine::process_with_result).
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProcessResult {
+    Applied(AccountDelta),
+    /// The row was not applied; the anomaly policy has already been
+    /// enforced.
+    Rejected {
+        seq: u64,
+        client: u16,
+        tx: u32,
+        anomaly: Anomaly,
+    },
+    /// The row names a tx id not seen yet and waits for it; see
+    /// [`crate::deferred`].
+    Deferred {
+        seq: u64,
+        client: u16,
+        tx: u32,
+    },
+}
+
+/// Live view of the [`AccountDelta`]s of the clients selected in
+/// [`Engine::watch`](crate::Engine::watch).
+///
+/// Iterating blocks until the next delta and ends once the engine is
+/// dropped, so consume it on another thread; on the processing thread use
+/// [`Watch::pending`] instead. Deltas queue up unboundedly until read.
+///
+/// ### Example
+/// ```rust
+/// use payments_engine::{Engine, Transaction, TxType};
+/// use rust_decimal_macros::dec;
+///
+/// let mut eng = Engine::new();
+/// let vip = eng.watch(|client| client == 7);
+/// let dashboard = std::thread::spawn(move || vip.map(|d| d.available).sum::<rust_decimal::Decimal>());
+///
+/// for (client, tx) in [(7, 1), (8, 2), (7, 3)] {
+///     eng.process(Transaction { kind: TxType::Deposit, client, tx, amount: Some(dec!(5)) })
+///         .unwrap();
+/// }
+/// drop(eng);
+/// assert_eq!(dashboard.join().unwrap(), dec!(10));
+/// ```
+pub struct Watch {
+    rx: Receiver<AccountDelta>,
+}
+
+impl Watch {
+    pub(crate) fn new(rx: Receiver<AccountDelta>) -> Self {
+        Self { rx }
+    }
+
+    /// Deltas already queued, without blocking.
+    pub fn pending(&self) -> impl Iterator<Item = AccountDelta> + '_ {
+        self.rx.try_iter()
+    }
+}
+
+impl Iterator for Watch {
+    type Item = AccountDelta;
+
+    fn next(&mut self) -> Option<AccountDelta> {
+        self.rx.recv().ok()
+    }
+}
+
+/// Engine-side end of a [`Watch`].
+pub(crate) struct Watcher {
+    pub(crate) filter: Box<dyn Fn(u16) -> bool + Send>,
+    pub(crate) tx: Sender<AccountDelta>,
+}
@@ -0,0 +1,81 @@
+//! Ingest-time filters: decide which input rows reach the engine at all.
+//!
+//! Unlike [`report::ReportFilter`](crate::report::ReportFilter), which only
+//! hides accounts from the output, rows rejected here are never processed.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::filter::IngestFilter;
+//! use payments_engine::{Transaction, TxType};
+//!
+//! let filter = IngestFilter {
+//!     exclude_clients: [99].into(),
+//!     ..Default::default()
+//! };
+//! let tx = Transaction { kind: TxType::Dispute, client: 99, tx: 1, amount: None };
+//! assert!(!filter.admits(&tx));
+//!
+//! // "gross" balances: ignore the whole dispute lifecycle
+//! let gross = IngestFilter {
+//!     types: Some([TxType::Deposit, TxType::Withdrawal].into()),
+//!     ..Default::default()
+//! };
+//! assert!(!gross.admits(&Transaction { client: 1, ..tx }));
+//! ```
+
+use crate::errors::Result;
+use crate::models::{Transaction, TxType};
+use anyhow::Context;
+use std::collections::HashSet;
+use std::fs;
+use std::hash::Hash;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Row-level admission rules applied before [`Engine::process`](crate::Engine::process).
+#[derive(Debug, Default, Clone)]
+pub struct IngestFilter {
+    /// If set, only rows for these clients are processed.
+    pub include_clients: Option<HashSet<u16>>,
+    /// Rows for these clients are dropped (wins over `include_clients`).
+    pub exclude_clients: HashSet<u16>,
+    /// If set, only rows of these types are processed.
+    pub types: Option<HashSet<TxType>>,
+    /// Rows with these tx ids are dropped. Because disputes, resolves and
+    /// chargebacks reuse the id of the deposit they reference, excluding a
+    /// deposit's id removes its whole lifecycle ("what if it never happened").
+    pub exclude_txs: HashSet<u32>,
+}
+
+impl IngestFilter {
+    /// `true` if the row should be processed.
+    pub fn admits(&self, tx: &Transaction) -> bool {
+        if self.exclude_clients.contains(&tx.client) || self.exclude_txs.contains(&tx.tx) {
+            return false;
+        }
+        self.include_clients
+            .as_ref()
+            .is_none_or(|ids| ids.contains(&tx.client))
+            && self.types.as_ref().is_none_or(|ts| ts.contains(&tx.kind))
+    }
+}
+
+/// Read an id list (client or tx ids): one id per line, blank lines and `#`
+/// comments ignored.
+pub fn read_id_list<T>(path: &Path) -> Result<HashSet<T>>
+where
+    T: FromStr + Eq + Hash,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    let text =
+        fs::read_to_string(path).with_context(|| format!("reading id list {}", path.display()))?;
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| (i, line.split('#').next().unwrap_or("").trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(i, line)| {
+            line.parse::<T>()
+                .with_context(|| format!("{}:{}: bad id {line:?}", path.display(), i + 1))
+        })
+        .collect()
+}
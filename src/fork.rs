@@ -0,0 +1,387 @@
+//! What-if branches: apply hypothetical rows to an engine, look at the
+//! outcome and throw it away.
+//!
+//! [`Engine::fork`] opens a [`Fork`] on the engine's live state instead of
+//! deep-cloning it. While the fork is open, the engine keeps the previous
+//! value of every entry a row is about to change (the row's account and tx
+//! records, plus whatever a hold expiry sweep touches) in an undo log, and
+//! dropping the fork plays the log back. A branch therefore costs memory
+//! and time per row applied in it, not per account or deposit held.
+//!
+//! The fork borrows the engine mutably for its whole life, so nothing else
+//! can process rows or read balances mid-branch; through the fork itself
+//! the engine is readable (it derefs to [`Engine`]). Side channels are
+//! suspended: watchers, alert sinks, history, the client trace and
+//! structuring detection see nothing of a branch, and rejections,
+//! normalizations and batch counts recorded in it are dropped with it.
+//! Sequence numbers continue from the engine's and are reset on drop.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::feed::ProcessResult;
+//! use payments_engine::{Engine, Transaction, TxType};
+//! use rust_decimal_macros::dec;
+//!
+//! let mut eng = Engine::new();
+//! eng.process(Transaction { kind: TxType::Deposit, client: 1, tx: 1, amount: Some(dec!(100)) })
+//!     .unwrap();
+//! let hash = eng.state_hash();
+//!
+//! let withdraw = |tx, amount| Transaction { kind: TxType::Withdrawal, client: 1, tx, amount: Some(amount) };
+//! {
+//!     let mut branch = eng.fork();
+//!     let r = branch.process_with_result(withdraw(2, dec!(80))).unwrap();
+//!     assert!(matches!(r, ProcessResult::Applied(_)));
+//!     assert_eq!(branch.accounts[&1].available, dec!(20));
+//!     // would a second withdrawal go through after the first?
+//!     let r = branch.process_with_result(withdraw(3, dec!(30))).unwrap();
+//!     assert!(matches!(r, ProcessResult::Rejected { .. }));
+//! }
+//!
+//! assert_eq!(eng.accounts[&1].available, dec!(100));
+//! assert_eq!(eng.state_hash(), hash);
+//! ```
+
+use crate::batch::Tally;
+use crate::bucket::{Balance, RowBuckets};
+use crate::credit::Accrual;
+use crate::deferred::DeferredRow;
+use crate::engine::{Engine, StoredHold, StoredTx, StoredWithdrawal};
+use crate::errors::Result;
+use crate::feed::{ProcessResult, Watcher};
+use crate::history::HistoryEntry;
+use crate::models::{Account, Transaction, TxType};
+use crate::notes::Annotations;
+use crate::notify::NotificationSink;
+use crate::processor::RowMeta;
+use crate::promo::Grant;
+use crate::structuring::Detector;
+use crate::trace::ClientTrace;
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Deref;
+
+/// Previous value of one piece of engine state, restored when a fork is
+/// dropped.
+#[derive(Debug)]
+pub(crate) enum Undo {
+    Account(u16, Option<Account>),
+    Deposit(u32, Option<StoredTx>),
+    Reclaimed(u32, Option<u16>),
+    Withdrawal(u32, Option<StoredWithdrawal>),
+    Hold(u32, Option<StoredHold>),
+    /// Client was not yet in the touched set.
+    Touched(u16),
+    /// Key was not yet claimed by the client.
+    Key(u16, String),
+    /// Entry popped off the front of the hold queue.
+    QueuePop((u64, u32)),
+    /// Length of the hold queue before a hold row.
+    QueueLen(usize),
+    /// Rows waiting for a tx id.
+    Deferred(u32, Option<Vec<DeferredRow>>),
+    Annotations(u16, Option<Annotations>),
+    /// Funds in escrow of a deposit.
+    Escrow(u32, Option<Decimal>),
+    /// Buckets of a client other than main.
+    Buckets(u16, Option<BTreeMap<String, Balance>>),
+    /// Bucket a deposit landed in, if not main.
+    DepositBucket(u32, Option<String>),
+    /// Interest settled on a client's credit line.
+    Credit(u16, Option<Accrual>),
+    /// Promotional credits of a client.
+    Promos(u16, Option<Vec<Grant>>),
+    /// Promotional tx id not yet taken.
+    PromoId(u32),
+    /// Entry popped off the front of the promo expiry queue.
+    PromoQueuePop((u64, u32)),
+    /// Length of the promo expiry queue before a row.
+    PromoQueueLen(usize),
+}
+
+/// A what-if branch of an [`Engine`]; see the module docs.
+pub struct Fork<'a> {
+    engine: &'a mut Engine,
+    seq: u64,
+    input_rows: u64,
+    rejections: usize,
+    normalizations: usize,
+    batch: Tally,
+    watchers: Vec<Watcher>,
+    sinks: Vec<Box<dyn NotificationSink>>,
+    history: Option<HashMap<u16, Vec<HistoryEntry>>>,
+    structuring: Option<Detector>,
+    trace: Option<ClientTrace>,
+}
+
+impl Engine {
+    /// Open a what-if branch on the current state; dropping it restores
+    /// the state as of now. See [`crate::fork`].
+    pub fn fork(&mut self) -> Fork<'_> {
+        self.journal = Some(Vec::new());
+        Fork {
+            seq: self.seq,
+            input_rows: self.input_rows,
+            rejections: self.rejections.len(),
+            normalizations: self.normalizations.len(),
+            batch: self.batch.clone(),
+            watchers: std::mem::take(&mut self.watchers),
+            sinks: std::mem::take(&mut self.sinks),
+            history: self.history.take(),
+            structuring: self.structuring.take(),
+            trace: self.trace.take(),
+            engine: self,
+        }
+    }
+
+    /// Inside a fork, keep what row `tx` may change.
+    pub(crate) fn save_row(&mut self, tx: &Transaction, key: Option<&str>, buckets: RowBuckets) {
+        if self.journal.is_none() {
+            return;
+        }
+        let (client, id) = (tx.client, tx.tx);
+        let claims = key.filter(|k| self.idempotent_tx(client, k).is_none());
+        let deferred = (self.config().defer_unknown_disputes || !self.deferred.is_empty())
+            .then(|| self.deferred.get(&id).cloned());
+        let escrow = (!self.config().escrow_clients.is_empty() || !self.escrow.is_empty())
+            .then(|| self.escrow.get(&id).copied());
+        let bucketed = (!buckets.is_main() || !self.buckets.is_empty()).then(|| {
+            (
+                self.buckets.balances.get(&client).cloned(),
+                self.buckets.deposits.get(&id).cloned(),
+            )
+        });
+        let credit = self
+            .accrues(client)
+            .then(|| self.credit.get(&client).copied());
+        let promos = (tx.kind == TxType::Promo || !self.promos.is_empty()).then(|| {
+            (
+                self.promos.grants.get(&client).cloned(),
+                !self.promos.contains(id),
+            )
+        });
+        let log = self.journal.as_mut().expect("fork open");
+        log.push(Undo::Account(client, self.accounts.get(&client).cloned()));
+        log.push(Undo::Deposit(id, self.deposits.get(&id)));
+        log.push(Undo::Reclaimed(id, self.reclaimed.get(&id).copied()));
+        log.push(Undo::Withdrawal(id, self.withdrawals.get(&id).cloned()));
+        log.push(Undo::Hold(id, self.holds.get(&id).cloned()));
+        log.push(Undo::QueueLen(self.hold_queue.len()));
+        if let Some(rows) = deferred {
+            log.push(Undo::Deferred(id, rows));
+        }
+        if let Some(escrowed) = escrow {
+            log.push(Undo::Escrow(id, escrowed));
+        }
+        if let Some((balances, deposit)) = bucketed {
+            log.push(Undo::Buckets(client, balances));
+            log.push(Undo::DepositBucket(id, deposit));
+        }
+        if let Some(accrual) = credit {
+            log.push(Undo::Credit(client, accrual));
+        }
+        if let Some((grants, free)) = promos {
+            log.push(Undo::Promos(client, grants));
+            log.push(Undo::PromoQueueLen(self.promos.queue.len()));
+            if free {
+                log.push(Undo::PromoId(id));
+            }
+        }
+        if !self.touched.contains(&client) {
+            log.push(Undo::Touched(client));
+        }
+        if let Some(key) = claims {
+            log.push(Undo::Key(client, key.to_owned()));
+        }
+    }
+
+    /// Inside a fork, keep what expiring hold `tx` of `client` changes.
+    pub(crate) fn save_expiry(&mut self, tx: u32, client: u16) {
+        let credit = self
+            .accrues(client)
+            .then(|| self.credit.get(&client).copied());
+        let Some(log) = self.journal.as_mut() else {
+            return;
+        };
+        log.push(Undo::Hold(tx, self.holds.get(&tx).cloned()));
+        log.push(Undo::Account(client, self.accounts.get(&client).cloned()));
+        if let Some(accrual) = credit {
+            log.push(Undo::Credit(client, accrual));
+        }
+        if !self.touched.contains(&client) {
+            log.push(Undo::Touched(client));
+        }
+    }
+
+    /// Inside a fork, keep what expiring promotional credit `entry` of
+    /// `client` changes.
+    pub(crate) fn save_promo_expiry(&mut self, entry: (u64, u32), client: u16) {
+        let Some(log) = self.journal.as_mut() else {
+            return;
+        };
+        log.push(Undo::PromoQueuePop(entry));
+        log.push(Undo::Promos(
+            client,
+            self.promos.grants.get(&client).cloned(),
+        ));
+        log.push(Undo::Account(client, self.accounts.get(&client).cloned()));
+        if !self.touched.contains(&client) {
+            log.push(Undo::Touched(client));
+        }
+    }
+
+    /// Inside a fork, keep the annotations of `client` about to change.
+    pub(crate) fn save_annotations(&mut self, client: u16) {
+        if let Some(log) = self.journal.as_mut() {
+            log.push(Undo::Annotations(
+                client,
+                self.annotations.get(&client).cloned(),
+            ));
+        }
+    }
+
+    /// Inside a fork, keep an entry popped off the hold queue.
+    pub(crate) fn save_queue_pop(&mut self, entry: (u64, u32)) {
+        if let Some(log) = self.journal.as_mut() {
+            log.push(Undo::QueuePop(entry));
+        }
+    }
+
+    fn undo(&mut self, entry: Undo) {
+        match entry {
+            Undo::Account(client, Some(acc)) => {
+                self.accounts.insert(client, acc);
+            }
+            Undo::Account(client, None) => {
+                self.accounts.remove(&client);
+            }
+            Undo::Deposit(tx, Some(dep)) => self.deposits.insert(tx, dep),
+            Undo::Deposit(tx, None) => {
+                self.deposits.remove(&tx);
+            }
+            Undo::Reclaimed(tx, Some(client)) => {
+                self.reclaimed.insert(tx, client);
+            }
+            Undo::Reclaimed(tx, None) => {
+                self.reclaimed.remove(&tx);
+            }
+            Undo::Withdrawal(tx, Some(w)) => {
+                self.withdrawals.insert(tx, w);
+            }
+            Undo::Withdrawal(tx, None) => {
+                self.withdrawals.remove(&tx);
+            }
+            Undo::Hold(tx, Some(held)) => {
+                self.holds.insert(tx, held);
+            }
+            Undo::Hold(tx, None) => {
+                self.holds.remove(&tx);
+            }
+            Undo::Touched(client) => {
+                self.touched.remove(&client);
+            }
+            Undo::Key(client, key) => {
+                if let Some(keys) = self.keys.get_mut(&client) {
+                    keys.remove(&key);
+                    if keys.is_empty() {
+                        self.keys.remove(&client);
+                    }
+                }
+            }
+            Undo::QueuePop(entry) => self.hold_queue.push_front(entry),
+            Undo::QueueLen(len) => self.hold_queue.truncate(len),
+            Undo::Deferred(tx, Some(rows)) => {
+                self.deferred.insert(tx, rows);
+            }
+            Undo::Deferred(tx, None) => {
+                self.deferred.remove(&tx);
+            }
+            Undo::Annotations(client, Some(annotations)) => {
+                self.annotations.insert(client, annotations);
+            }
+            Undo::Annotations(client, None) => {
+                self.annotations.remove(&client);
+            }
+            Undo::Escrow(tx, Some(escrowed)) => {
+                self.escrow.insert(tx, escrowed);
+            }
+            Undo::Escrow(tx, None) => {
+                self.escrow.remove(&tx);
+            }
+            Undo::Buckets(client, Some(balances)) => {
+                self.buckets.balances.insert(client, balances);
+            }
+            Undo::Buckets(client, None) => {
+                self.buckets.balances.remove(&client);
+            }
+            Undo::DepositBucket(tx, Some(bucket)) => {
+                self.buckets.deposits.insert(tx, bucket);
+            }
+            Undo::DepositBucket(tx, None) => {
+                self.buckets.deposits.remove(&tx);
+            }
+            Undo::Credit(client, Some(accrual)) => {
+                self.credit.insert(client, accrual);
+            }
+            Undo::Credit(client, None) => {
+                self.credit.remove(&client);
+            }
+            Undo::Promos(client, Some(grants)) => {
+                self.promos.grants.insert(client, grants);
+            }
+            Undo::Promos(client, None) => {
+                self.promos.grants.remove(&client);
+            }
+            Undo::PromoId(tx) => {
+                self.promos.ids.remove(&tx);
+            }
+            Undo::PromoQueuePop(entry) => self.promos.queue.push_front(entry),
+            Undo::PromoQueueLen(len) => self.promos.queue.truncate(len),
+        }
+    }
+}
+
+impl Fork<'_> {
+    /// [`Engine::process`] on the branch.
+    pub fn process(&mut self, tx: Transaction) -> Result<()> {
+        self.engine.process(tx)
+    }
+
+    /// [`Engine::process_with_result`] on the branch.
+    pub fn process_with_result(&mut self, tx: Transaction) -> Result<ProcessResult> {
+        self.engine.process_with_result(tx)
+    }
+
+    /// [`Engine::process_row`] on the branch.
+    pub fn process_row(&mut self, tx: Transaction, meta: RowMeta) -> Result<ProcessResult> {
+        self.engine.process_row(tx, meta)
+    }
+}
+
+impl Deref for Fork<'_> {
+    type Target = Engine;
+
+    fn deref(&self) -> &Engine {
+        self.engine
+    }
+}
+
+impl Drop for Fork<'_> {
+    fn drop(&mut self) {
+        let eng = &mut *self.engine;
+        let log = eng.journal.take().unwrap_or_default();
+        for entry in log.into_iter().rev() {
+            eng.undo(entry);
+        }
+        eng.seq = self.seq;
+        eng.input_rows = self.input_rows;
+        eng.rejections.truncate(self.rejections);
+        eng.normalizations.truncate(self.normalizations);
+        eng.batch = std::mem::take(&mut self.batch);
+        eng.watchers = std::mem::take(&mut self.watchers);
+        eng.sinks = std::mem::take(&mut self.sinks);
+        eng.history = self.history.take();
+        eng.structuring = self.structuring.take();
+        eng.trace = self.trace.take();
+    }
+}
@@ -0,0 +1,360 @@
+//! Source-of-funds attribution: which deposits paid for each withdrawal.
+//!
+//! A [`FundsTracer`] follows the rows an engine applied (their
+//! [`AccountDelta`]s) and keeps every credit as a *lot*: a deposit, or a
+//! refund of an earlier withdrawal. Money leaving the client — a withdrawal,
+//! or a hold once captured — is taken from the client's lots oldest first
+//! (FIFO), or newest first under [`Order::Lifo`] as for the cost basis of
+//! crypto-style assets, and recorded as [`Match`]es. What is left are the
+//! unspent lots; what each lot paid out is its realized amount (see
+//! [`write_realized`]).
+//!
+//! Disputes follow the engine: a disputed lot is frozen (skipped by FIFO)
+//! until resolved, and a charge-back removes what is left of it. A debit no
+//! lot can cover — after a charge-back of already spent funds, say — is
+//! matched to no lot. Holds are attributed when captured, not when placed,
+//! so released and expired holds leave the lots alone.
+//!
+//! Promotional credits (see [`crate::promo`]) are lots too. As in the
+//! engine, withdrawals spend them before any other lot and captures never
+//! do, and an expiry takes away what is left of one.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::feed::ProcessResult;
+//! use payments_engine::funds::FundsTracer;
+//! use payments_engine::{Engine, Transaction, TxType};
+//! use rust_decimal_macros::dec;
+//!
+//! let mut eng = Engine::new();
+//! let mut tracer = FundsTracer::new();
+//! let rows = [
+//!     (TxType::Deposit, 1, dec!(30)),
+//!     (TxType::Deposit, 2, dec!(50)),
+//!     (TxType::Withdrawal, 3, dec!(40)),
+//! ];
+//! for (kind, tx, amount) in rows {
+//!     let row = Transaction { kind, client: 1, tx, amount: Some(amount) };
+//!     if let ProcessResult::Applied(delta) = eng.process_with_result(row).unwrap() {
+//!         tracer.observe(&delta);
+//!     }
+//! }
+//!
+//! // the withdrawal used all of tx 1 and 10 of tx 2
+//! let paid: Vec<_> = tracer.matches().iter().map(|m| (m.lot, m.amount)).collect();
+//! assert_eq!(paid, [(Some(1), dec!(30)), (Some(2), dec!(10))]);
+//! let left: Vec<_> = tracer.unspent().map(|l| (l.tx, l.remaining)).collect();
+//! assert_eq!(left, [(2, dec!(40))]);
+//! ```
+
+use crate::errors::Result;
+use crate::feed::AccountDelta;
+use crate::models::TxType;
+use csv::WriterBuilder;
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::str::FromStr;
+
+/// Where a lot's money came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Deposit,
+    /// A refund of the withdrawal the lot's tx id names.
+    Refund,
+    /// A promotional credit (see [`crate::promo`]).
+    Promo,
+}
+
+impl Source {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Source::Deposit => "deposit",
+            Source::Refund => "refund",
+            Source::Promo => "promo",
+        }
+    }
+}
+
+/// Which lots a debit takes first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Order {
+    /// Oldest first.
+    #[default]
+    Fifo,
+    /// Newest first.
+    Lifo,
+}
+
+impl Order {
+    /// Names accepted by [`Order::from_str`], for `--order`.
+    pub const NAMES: [&'static str; 2] = ["fifo", "lifo"];
+}
+
+impl FromStr for Order {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "fifo" => Ok(Order::Fifo),
+            "lifo" => Ok(Order::Lifo),
+            _ => Err(format!(
+                "unknown lot order {s:?} (one of {})",
+                Self::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+/// One credit of a client and what is left of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lot {
+    pub client: u16,
+    pub tx: u32,
+    pub source: Source,
+    pub amount: Decimal,
+    pub remaining: Decimal,
+    /// Paid out to debits so far.
+    pub realized: Decimal,
+    /// Under dispute: not available to debits until resolved.
+    pub frozen: bool,
+}
+
+/// Part of a debit paid from one lot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+    pub client: u16,
+    /// The withdrawal, or the captured hold.
+    pub debit: u32,
+    pub kind: TxType,
+    /// Tx id of the lot, `None` for the part no lot covered.
+    pub lot: Option<u32>,
+    pub source: Option<Source>,
+    pub amount: Decimal,
+}
+
+/// FIFO (or LIFO) attribution of debits to credits; see the module docs.
+#[derive(Debug, Default)]
+pub struct FundsTracer {
+    /// Every lot per client, oldest first.
+    lots: BTreeMap<u16, Vec<Lot>>,
+    matches: Vec<Match>,
+    order: Order,
+}
+
+impl FundsTracer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A tracer taking lots in `order`.
+    ///
+    /// ### Example
+    /// ```rust
+    /// use payments_engine::feed::ProcessResult;
+    /// use payments_engine::funds::{FundsTracer, Order};
+    /// use payments_engine::{Engine, Transaction, TxType};
+    /// use rust_decimal_macros::dec;
+    ///
+    /// let mut eng = Engine::new();
+    /// let mut tracer = FundsTracer::with_order(Order::Lifo);
+    /// let rows = [
+    ///     (TxType::Deposit, 1, dec!(30)),
+    ///     (TxType::Deposit, 2, dec!(50)),
+    ///     (TxType::Withdrawal, 3, dec!(60)),
+    /// ];
+    /// for (kind, tx, amount) in rows {
+    ///     let row = Transaction { kind, client: 1, tx, amount: Some(amount) };
+    ///     if let ProcessResult::Applied(delta) = eng.process_with_result(row).unwrap() {
+    ///         tracer.observe(&delta);
+    ///     }
+    /// }
+    ///
+    /// // the withdrawal used all of tx 2 and 10 of tx 1
+    /// let realized: Vec<_> = tracer.lots().map(|l| (l.tx, l.realized)).collect();
+    /// assert_eq!(realized, [(1, dec!(10)), (2, dec!(50))]);
+    /// ```
+    pub fn with_order(order: Order) -> Self {
+        Self {
+            order,
+            ..Self::default()
+        }
+    }
+
+    /// Account for one applied row.
+    pub fn observe(&mut self, delta: &AccountDelta) {
+        let lots = self.lots.entry(delta.client).or_default();
+        let credit = |source| Lot {
+            client: delta.client,
+            tx: delta.tx,
+            source,
+            amount: delta.available,
+            remaining: delta.available,
+            realized: Decimal::ZERO,
+            frozen: false,
+        };
+        match delta.kind {
+            TxType::Deposit => lots.push(credit(Source::Deposit)),
+            TxType::Refund => lots.push(credit(Source::Refund)),
+            TxType::Promo if delta.available > Decimal::ZERO => lots.push(credit(Source::Promo)),
+            // an expiry takes back what is left of the credit
+            TxType::Promo => {
+                for lot in lots
+                    .iter_mut()
+                    .filter(|l| l.source == Source::Promo && l.tx == delta.tx)
+                {
+                    lot.remaining = Decimal::ZERO;
+                }
+            }
+            TxType::Withdrawal => self.debit(delta, -delta.available),
+            TxType::Capture => self.debit(delta, -delta.held),
+            TxType::Dispute | TxType::Resolve | TxType::Chargeback => {
+                // the engine looks for a deposit first, then for refunds
+                let deposit = lots
+                    .iter()
+                    .any(|l| l.source == Source::Deposit && l.tx == delta.tx);
+                let source = if deposit {
+                    Source::Deposit
+                } else {
+                    Source::Refund
+                };
+                for lot in lots
+                    .iter_mut()
+                    .filter(|l| l.source == source && l.tx == delta.tx)
+                {
+                    match delta.kind {
+                        TxType::Dispute => lot.frozen = true,
+                        TxType::Resolve => lot.frozen = false,
+                        _ => lot.remaining = Decimal::ZERO,
+                    }
+                }
+            }
+            TxType::Hold | TxType::Release | TxType::Move => {}
+        }
+    }
+
+    /// Take `amount` from the client's unfrozen lots in the tracer's order;
+    /// a withdrawal takes promotional credits before the rest and a capture
+    /// never does, as in the engine.
+    fn debit(&mut self, delta: &AccountDelta, mut amount: Decimal) {
+        let lots = self.lots.entry(delta.client).or_default();
+        let matched = |lot: Option<&Lot>, amount| Match {
+            client: delta.client,
+            debit: delta.tx,
+            kind: delta.kind,
+            lot: lot.map(|l| l.tx),
+            source: lot.map(|l| l.source),
+            amount,
+        };
+        let (promos, mut rest): (Vec<_>, Vec<_>) = lots
+            .iter_mut()
+            .filter(|l| !l.frozen)
+            .partition(|l| l.source == Source::Promo);
+        let promos = match delta.kind {
+            TxType::Withdrawal => promos,
+            _ => Vec::new(),
+        };
+        if self.order == Order::Lifo {
+            rest.reverse();
+        }
+        for lot in promos.into_iter().chain(rest) {
+            if amount.is_zero() {
+                break;
+            }
+            let take = amount.min(lot.remaining);
+            if take > Decimal::ZERO {
+                lot.remaining -= take;
+                lot.realized += take;
+                amount -= take;
+                self.matches.push(matched(Some(lot), take));
+            }
+        }
+        if amount > Decimal::ZERO {
+            self.matches.push(matched(None, amount));
+        }
+    }
+
+    /// Every match so far, in the order the debits were applied.
+    pub fn matches(&self) -> &[Match] {
+        &self.matches
+    }
+
+    /// Every lot, by client and then age.
+    pub fn lots(&self) -> impl Iterator<Item = &Lot> {
+        self.lots.values().flatten()
+    }
+
+    /// Lots with money left, by client and then age.
+    pub fn unspent(&self) -> impl Iterator<Item = &Lot> {
+        self.lots
+            .values()
+            .flatten()
+            .filter(|l| l.remaining > Decimal::ZERO)
+    }
+}
+
+/// Write matches as CSV (`client,debit,type,lot,source,amount`), the
+/// unfunded part with empty `lot` and `source`. Returns the number of rows
+/// written.
+pub fn write_matches<W: Write>(tracer: &FundsTracer, sink: W) -> Result<usize> {
+    let mut wtr = WriterBuilder::new().from_writer(sink);
+    wtr.write_record(["client", "debit", "type", "lot", "source", "amount"])?;
+    for m in tracer.matches() {
+        wtr.write_record([
+            m.client.to_string(),
+            m.debit.to_string(),
+            m.kind.as_str().to_string(),
+            m.lot.map(|tx| tx.to_string()).unwrap_or_default(),
+            m.source.map(Source::as_str).unwrap_or_default().to_string(),
+            format!("{:.4}", m.amount.round_dp(4)),
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(tracer.matches().len())
+}
+
+/// Write every lot with what it paid out as CSV
+/// (`client,tx,source,amount,realized,remaining`), by client and then age.
+/// A charge-back empties a lot without realizing it. Returns the number of
+/// rows written.
+pub fn write_realized<W: Write>(tracer: &FundsTracer, sink: W) -> Result<usize> {
+    let mut wtr = WriterBuilder::new().from_writer(sink);
+    wtr.write_record(["client", "tx", "source", "amount", "realized", "remaining"])?;
+    let fmt = |d: Decimal| format!("{:.4}", d.round_dp(4));
+    let mut written = 0;
+    for lot in tracer.lots() {
+        wtr.write_record([
+            lot.client.to_string(),
+            lot.tx.to_string(),
+            lot.source.as_str().to_string(),
+            fmt(lot.amount),
+            fmt(lot.realized),
+            fmt(lot.remaining),
+        ])?;
+        written += 1;
+    }
+    wtr.flush()?;
+    Ok(written)
+}
+
+/// Write unspent lots as CSV (`client,tx,source,amount,remaining,frozen`).
+/// Returns the number of rows written.
+pub fn write_lots<W: Write>(tracer: &FundsTracer, sink: W) -> Result<usize> {
+    let mut wtr = WriterBuilder::new().from_writer(sink);
+    wtr.write_record(["client", "tx", "source", "amount", "remaining", "frozen"])?;
+    let fmt = |d: Decimal| format!("{:.4}", d.round_dp(4));
+    let mut written = 0;
+    for lot in tracer.unspent() {
+        wtr.write_record([
+            lot.client.to_string(),
+            lot.tx.to_string(),
+            lot.source.as_str().to_string(),
+            fmt(lot.amount),
+            fmt(lot.remaining),
+            lot.frozen.to_string(),
+        ])?;
+        written += 1;
+    }
+    wtr.flush()?;
+    Ok(written)
+}
@@ -0,0 +1,107 @@
+//! Deterministic synthetic input for benchmarks and self-checks.
+//!
+//! [`Generator`] yields a reproducible mix of deposits, withdrawals and
+//! dispute rows from a seed: the same seed always gives the same rows.
+//! Dispute, resolve and chargeback rows name an earlier deposit of the same
+//! client, so the dispute paths get exercised, including rejected ones
+//! (resolving a deposit that is not under dispute, for instance).
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::generate::Generator;
+//! use payments_engine::Engine;
+//!
+//! let rows: Vec<_> = Generator::new(42, 100).take(1000).collect();
+//! assert!(rows.iter().all(|tx| tx.client < 100));
+//!
+//! let mut eng = Engine::new();
+//! for tx in Generator::new(42, 100).take(1000) {
+//!     eng.process(tx).unwrap();
+//! }
+//! let again = Generator::new(42, 100).take(1000).fold(Engine::new(), |mut e, tx| {
+//!     e.process(tx).unwrap();
+//!     e
+//! });
+//! assert_eq!(eng.state_hash(), again.state_hash());
+//! ```
+
+use crate::models::{Transaction, TxType};
+use rust_decimal::Decimal;
+
+/// Endless iterator of synthetic rows; see the module docs.
+pub struct Generator {
+    state: u64,
+    clients: u16,
+    next_tx: u32,
+    /// `(tx, client)` of deposits so far, for dispute rows to refer to.
+    deposits: Vec<(u32, u16)>,
+}
+
+impl Generator {
+    /// Rows for clients `0..clients` (at least one), seeded with `seed`.
+    pub fn new(seed: u64, clients: u16) -> Self {
+        Self {
+            // xorshift must not start at zero
+            state: seed ^ 0x9E37_79B9_7F4A_7C15,
+            clients: clients.max(1),
+            next_tx: 1,
+            deposits: Vec::new(),
+        }
+    }
+
+    /// xorshift64*: fast, and good enough for test data.
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    /// Amount between 0.0001 and 1000.0000.
+    fn amount(&mut self) -> Decimal {
+        Decimal::new(self.below(10_000_000) as i64 + 1, 4)
+    }
+}
+
+impl Iterator for Generator {
+    type Item = Transaction;
+
+    fn next(&mut self) -> Option<Transaction> {
+        // per mille: 60% deposits, 25% withdrawals, then dispute rows
+        let roll = self.below(1000);
+        if roll >= 850 && !self.deposits.is_empty() {
+            let pick = self.below(self.deposits.len() as u64) as usize;
+            let (tx, client) = self.deposits[pick];
+            let kind = match roll {
+                850..=929 => TxType::Dispute,
+                930..=994 => TxType::Resolve,
+                _ => TxType::Chargeback,
+            };
+            return Some(Transaction {
+                kind,
+                client,
+                tx,
+                amount: None,
+            });
+        }
+        let client = self.below(u64::from(self.clients)) as u16;
+        let tx = self.next_tx;
+        self.next_tx = self.next_tx.wrapping_add(1);
+        let kind = if roll < 600 {
+            self.deposits.push((tx, client));
+            TxType::Deposit
+        } else {
+            TxType::Withdrawal
+        };
+        Some(Transaction {
+            kind,
+            client,
+            tx,
+            amount: Some(self.amount()),
+        })
+    }
+}
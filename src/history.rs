@@ -0,0 +1,160 @@
+//! Optional per-client history of applied rows, enabling point-in-time
+//! balance queries ("what was the balance when this withdrawal was
+//! attempted?").
+//!
+//! Hold and promo credit expiries are recorded too, under the row they
+//! expired ahead of, so balances after them are right even for a client
+//! with no later row.
+//!
+//! History costs one entry per applied row, so it is off unless the engine
+//! is built with [`Engine::with_history`] or has it turned on with
+//! [`Engine::enable_history`]. It is not part of snapshots. For long-running
+//! engines, [`Engine::set_history_retention`] bounds it per client by count
+//! and by age, counted in rows as with [`EngineConfig::hold_expiry`](crate::engine::EngineConfig::hold_expiry).
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::history::Retention;
+//! use payments_engine::{Engine, Transaction, TxType};
+//! use rust_decimal_macros::dec;
+//!
+//! let mut eng = Engine::with_history();
+//! eng.process(Transaction { kind: TxType::Deposit, client: 7, tx: 1, amount: Some(dec!(10)) })
+//!     .unwrap();
+//! eng.process(Transaction { kind: TxType::Withdrawal, client: 7, tx: 2, amount: Some(dec!(4)) })
+//!     .unwrap();
+//!
+//! assert_eq!(eng.balance_at(7, 1).unwrap().available, dec!(10));
+//! assert_eq!(eng.balance_at(7, 2).unwrap().available, dec!(6));
+//! assert!(eng.balance_at(7, 0).is_none());
+//!
+//! // keep only the latest entry per client
+//! eng.set_history_retention(Retention { last: Some(1), ..Default::default() });
+//! assert_eq!(eng.history(7).len(), 1);
+//! assert!(eng.balance_at(7, 1).is_none());
+//! ```
+
+use crate::engine::Engine;
+use crate::models::{Account, TxType};
+use rust_decimal::Decimal;
+
+/// Rows between sweeps of every client's history for stale entries, so
+/// clients that went quiet do not keep theirs forever. Client ids are
+/// `u16`, so a sweep costs at most one step per row in between.
+const SWEEP_EVERY: u64 = 1 << 16;
+
+/// How much history (or other per-client log) to keep; the default keeps
+/// everything.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Retention {
+    /// Keep at most this many of the latest entries per client.
+    pub last: Option<usize>,
+    /// Drop entries more than this many rows older than the latest row.
+    pub max_age: Option<u64>,
+}
+
+impl Retention {
+    /// How many of the oldest `entries` fall outside the policy as of row
+    /// `now`, given each entry's row number.
+    pub fn stale<T>(&self, entries: &[T], now: u64, seq: impl Fn(&T) -> u64) -> usize {
+        let by_count = self.last.map_or(0, |n| entries.len().saturating_sub(n));
+        let by_age = self
+            .max_age
+            .map_or(0, |age| entries.partition_point(|e| seq(e) + age < now));
+        by_count.max(by_age)
+    }
+}
+
+/// Account state right after one applied row or expiry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    /// Row sequence number (see [`Engine::seq`]).
+    pub seq: u64,
+    pub tx: u32,
+    pub kind: TxType,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub locked: bool,
+}
+
+impl Engine {
+    /// Empty engine that records per-client history.
+    pub fn with_history() -> Self {
+        let mut eng = Self::new();
+        eng.enable_history();
+        eng
+    }
+
+    /// Record history from the next row on (a no-op if already recording).
+    pub fn enable_history(&mut self) {
+        self.history.get_or_insert_with(Default::default);
+    }
+
+    /// Keep only the history `retention` allows from now on. Entries past
+    /// it are hidden at once and dropped once they make up half a client's
+    /// log, keeping the cost per row constant.
+    pub fn set_history_retention(&mut self, retention: Retention) {
+        self.history_retention = retention;
+    }
+
+    /// Applied rows for `client` in processing order, within the retention
+    /// policy (empty if history is disabled or the client has no applied
+    /// rows).
+    pub fn history(&self, client: u16) -> &[HistoryEntry] {
+        let entries = self
+            .history
+            .as_ref()
+            .and_then(|h| h.get(&client))
+            .map_or(&[][..], Vec::as_slice);
+        &entries[self.history_retention.stale(entries, self.seq, |e| e.seq)..]
+    }
+
+    /// Account state after every row with sequence number `<= seq` was
+    /// processed. `None` if the client had no applied row by then, history
+    /// is disabled or retention dropped the entries.
+    pub fn balance_at(&self, client: u16, seq: u64) -> Option<Account> {
+        let entries = self.history(client);
+        let idx = entries.partition_point(|e| e.seq <= seq);
+        let e = entries[..idx].last()?;
+        Some(Account {
+            available: e.available,
+            held: e.held,
+            locked: e.locked,
+        })
+    }
+
+    /// Record `client`'s account as it stands after row `tx` of type `kind`
+    /// was applied, or after hold or promo credit `tx` expired (recorded as
+    /// a `release` or a `promo`, as watchers see them).
+    pub(crate) fn record_history(&mut self, client: u16, tx: u32, kind: TxType) {
+        let Some(history) = self.history.as_mut() else {
+            return;
+        };
+        let acc = &self.accounts[&client];
+        let entries = history.entry(client).or_default();
+        entries.push(HistoryEntry {
+            seq: self.seq,
+            tx,
+            kind,
+            available: acc.available,
+            held: acc.held,
+            locked: acc.locked,
+        });
+        let (retention, now) = (self.history_retention, self.seq);
+        compact(entries, retention, now);
+        if retention.max_age.is_some() && now.is_multiple_of(SWEEP_EVERY) {
+            history.retain(|_, entries| {
+                compact(entries, retention, now);
+                !entries.is_empty()
+            });
+        }
+    }
+}
+
+/// Drop the stale prefix of `entries` once it is at least half of them.
+fn compact(entries: &mut Vec<HistoryEntry>, retention: Retention, now: u64) {
+    let stale = retention.stale(entries, now, |e| e.seq);
+    if stale > 0 && stale * 2 >= entries.len() {
+        entries.drain(..stale);
+    }
+}
@@ -0,0 +1,146 @@
+//! Card authorizations ("holds"): funds reserved from available into held
+//! without a dispute, later either released back or captured as a settled
+//! withdrawal.
+//!
+//! ```text
+//!            Release
+//!   Active ──────────▶ Released
+//!      │  │
+//!      │  └──────────▶ Expired
+//!      │ Capture   (expiry sweep)
+//!      ▼
+//!   Captured
+//! ```
+//!
+//! A hold is its own transaction (`hold,client,tx,amount`); `release` and
+//! `capture` rows reference it by tx id. Holds live in their own id space
+//! and lifecycle, separate from deposits and disputes.
+//!
+//! With [`EngineConfig::hold_expiry`](crate::engine::EngineConfig::hold_expiry)
+//! set to `n`, a hold neither released nor captured within `n` further rows
+//! expires: its funds go back to available, as with a release.
+//! [`EngineConfig::hold_expiry_secs`](crate::engine::EngineConfig::hold_expiry_secs)
+//! does the same by the engine's [`Clock`](crate::clock::Clock). An expired hold is not part
+//! of the row's [`ProcessResult`](crate::feed::ProcessResult); the client's
+//! history (see [`crate::history`]) records it as a release.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::{Engine, Transaction, TxType};
+//! use rust_decimal_macros::dec;
+//!
+//! let mut eng = Engine::new();
+//! let row = |kind, tx, amount| Transaction { kind, client: 1, tx, amount };
+//! eng.process(row(TxType::Deposit, 1, Some(dec!(100)))).unwrap();
+//! eng.process(row(TxType::Hold, 2, Some(dec!(30)))).unwrap();
+//! eng.process(row(TxType::Hold, 3, Some(dec!(20)))).unwrap();
+//! assert_eq!((eng.accounts[&1].available, eng.accounts[&1].held), (dec!(50), dec!(50)));
+//!
+//! eng.process(row(TxType::Capture, 2, None)).unwrap(); // settles 30
+//! eng.process(row(TxType::Release, 3, None)).unwrap(); // frees 20
+//! assert_eq!((eng.accounts[&1].available, eng.accounts[&1].held), (dec!(70), dec!(0)));
+//! ```
+//!
+//! Expiry after two further rows:
+//! ```rust
+//! use payments_engine::engine::EngineConfig;
+//! use payments_engine::{Engine, Transaction, TxType};
+//! use rust_decimal_macros::dec;
+//!
+//! let mut eng = Engine::with_config(EngineConfig { hold_expiry: Some(2), ..Default::default() });
+//! let row = |kind, tx, amount| Transaction { kind, client: 1, tx, amount };
+//! eng.process(row(TxType::Deposit, 1, Some(dec!(10)))).unwrap();
+//! eng.process(row(TxType::Hold, 2, Some(dec!(4)))).unwrap();
+//! eng.process(row(TxType::Deposit, 3, Some(dec!(1)))).unwrap();
+//! eng.process(row(TxType::Deposit, 4, Some(dec!(1)))).unwrap();
+//! assert_eq!(eng.accounts[&1].held, dec!(4)); // still active
+//!
+//! eng.process(row(TxType::Capture, 2, None)).unwrap(); // too late: expired first
+//! assert_eq!((eng.accounts[&1].available, eng.accounts[&1].held), (dec!(12), dec!(0)));
+//! ```
+
+use crate::anomaly::Anomaly;
+use crate::models::TxType;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Where an authorization is in its lifecycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum State {
+    /// Funds are held.
+    Active,
+    /// Funds went back to available.
+    Released,
+    /// Funds left the account as a settled withdrawal.
+    Captured,
+    /// Never closed; the expiry sweep returned the funds to available.
+    Expired,
+}
+
+impl State {
+    /// Snake-case name as used in reports.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            State::Active => "active",
+            State::Released => "released",
+            State::Captured => "captured",
+            State::Expired => "expired",
+        }
+    }
+
+    /// The state `transition` leads to from `self`, if it is legal.
+    pub fn next(self, transition: Transition) -> Result<State, IllegalTransition> {
+        match (self, transition) {
+            (State::Active, Transition::Release) => Ok(State::Released),
+            (State::Active, Transition::Capture) => Ok(State::Captured),
+            _ => Err(IllegalTransition {
+                from: self,
+                transition,
+            }),
+        }
+    }
+}
+
+/// A row closing an authorization.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transition {
+    Release,
+    Capture,
+}
+
+impl Transition {
+    /// The transition a row of `kind` requests, if any.
+    pub fn for_kind(kind: TxType) -> Option<Self> {
+        match kind {
+            TxType::Release => Some(Transition::Release),
+            TxType::Capture => Some(Transition::Capture),
+            _ => None,
+        }
+    }
+}
+
+/// `transition` is not allowed from `from`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IllegalTransition {
+    pub from: State,
+    pub transition: Transition,
+}
+
+impl fmt::Display for IllegalTransition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "cannot {:?} a hold in state {:?}",
+            self.transition, self.from
+        )
+    }
+}
+
+impl std::error::Error for IllegalTransition {}
+
+impl From<IllegalTransition> for Anomaly {
+    fn from(_: IllegalTransition) -> Self {
+        Anomaly::HoldNotActive
+    }
+}
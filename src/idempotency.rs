@@ -0,0 +1,89 @@
+//! Idempotency keys: an optional `idempotency_key` column naming the
+//! operation a row performs, independently of its tx id.
+//!
+//! Upstream retry logic may resend an operation under a fresh tx id, which
+//! the engine's duplicate-id check cannot catch. With keys, a row is applied
+//! at most once per `(client, key)`: a later row of the same client with the
+//! same key is rejected as
+//! [`Anomaly::DuplicateKey`](crate::anomaly::Anomaly::DuplicateKey) and
+//! handled by the anomaly policy like any other rejection (e.g. kept with
+//! `record`). Only applied rows claim their key, so a retry of a row that
+//! was rejected, say for insufficient funds, can still go through. Rows
+//! without a key, or with an empty one, are not checked.
+//!
+//! Keys are kept for the lifetime of the engine and saved in snapshots, so
+//! a retry arriving in the next day's file is caught too.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::anomaly::{Action, Anomaly, AnomalyPolicy};
+//! use payments_engine::engine::EngineConfig;
+//! use payments_engine::idempotency::KeyedRow;
+//! use payments_engine::processor::RowMeta;
+//! use payments_engine::Engine;
+//! use rust_decimal_macros::dec;
+//!
+//! let csv = "type,client,tx,amount,idempotency_key\n\
+//!            deposit,1,1,10.0,pay-77\n\
+//!            deposit,1,2,10.0,pay-77\n\
+//!            deposit,1,3,5.0,\n";
+//! let mut anomalies = AnomalyPolicy::default();
+//! anomalies.set(Anomaly::DuplicateKey, Action::Record);
+//! let mut eng = Engine::with_config(EngineConfig { anomalies, ..Default::default() });
+//!
+//! let mut rdr = csv::ReaderBuilder::new().from_reader(csv.as_bytes());
+//! for row in rdr.deserialize::<KeyedRow>() {
+//!     let (tx, key) = row.unwrap().split();
+//!     eng.process_row(tx, RowMeta::keyed(key.as_deref())).unwrap();
+//! }
+//!
+//! assert_eq!(eng.accounts[&1].available, dec!(15));
+//! assert_eq!(eng.idempotent_tx(1, "pay-77"), Some(1));
+//! assert_eq!(eng.rejections()[0].tx.tx, 2);
+//! ```
+
+use crate::models::{Transaction, TxType};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+/// An input row with optional `idempotency_key`, `batch`, `timestamp`,
+/// `bucket`, `to_bucket` and `tenant` columns next to the regular
+/// transaction fields (for `batch` see [`crate::batch`], for `timestamp`
+/// [`crate::clock`], for the buckets [`crate::bucket`], for `tenant`
+/// [`crate::tenant`]).
+#[derive(Debug, Deserialize)]
+pub struct KeyedRow {
+    #[serde(rename = "type")]
+    pub kind: TxType,
+    pub client: u16,
+    pub tx: u32,
+    #[serde(default, deserialize_with = "crate::amount::deserialize")]
+    pub amount: Option<Decimal>,
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
+    #[serde(default)]
+    pub batch: Option<String>,
+    #[serde(default)]
+    pub timestamp: Option<String>,
+    #[serde(default)]
+    pub bucket: Option<String>,
+    #[serde(default)]
+    pub to_bucket: Option<String>,
+    #[serde(default)]
+    pub tenant: Option<String>,
+}
+
+impl KeyedRow {
+    /// Separate the key from the transaction itself; an empty key is none.
+    /// The batch label, timestamp, buckets and tenant are dropped, so take
+    /// them first if needed.
+    pub fn split(self) -> (Transaction, Option<String>) {
+        let tx = Transaction {
+            kind: self.kind,
+            client: self.client,
+            tx: self.tx,
+            amount: self.amount,
+        };
+        (tx, self.idempotency_key.filter(|k| !k.is_empty()))
+    }
+}
@@ -0,0 +1,181 @@
+//! What an ingest run did, in one serializable [`IngestSummary`]: rows and
+//! bytes read, throughput, rows per transaction type and rows not applied
+//! by reason.
+//!
+//! [`Engine::process_reader`] runs a CSV input through the engine and
+//! returns one. Frontends with their own read loop fill an [`IngestTally`]
+//! row by row instead; the CLI does, and logs the result or writes it as
+//! JSON (`--ingest-summary`), so both report the same numbers.
+//!
+//! Reasons for not applying a row are the engine's anomaly classes
+//! (`insufficient-funds`, …), the size limits of [`crate::limits`]
+//! (`oversized-row`, …), `unparsable`, and whatever the frontend skips rows
+//! for (the CLI's `filtered` and `screened`). Rows deferred by
+//! [`crate::deferred`] are counted apart, as they may still apply.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::Engine;
+//!
+//! let csv = "type,client,tx,amount\n\
+//!            deposit,1,1,10\n\
+//!            withdrawal,1,2,25\n\
+//!            deposit,1,oops,1\n\
+//!            withdrawal,1,3,4\n";
+//! let mut eng = Engine::new();
+//! let summary = eng.process_reader(csv.as_bytes()).unwrap();
+//!
+//! assert_eq!(summary.rows, 4);
+//! assert_eq!(summary.bytes, csv.len() as u64);
+//! assert_eq!(summary.by_type["withdrawal"], 2);
+//! assert_eq!(summary.rejects["insufficient-funds"], 1);
+//! assert_eq!(summary.rejects["unparsable"], 1);
+//! assert_eq!(summary.applied(), 2);
+//!
+//! let json = serde_json::to_string(&summary).unwrap();
+//! assert!(json.contains(r#""rows_per_sec":"#));
+//! ```
+
+use crate::bucket::RowBuckets;
+use crate::engine::Engine;
+use crate::errors::Result;
+use crate::feed::ProcessResult;
+use crate::idempotency::KeyedRow;
+use crate::models::TxType;
+use crate::processor::RowMeta;
+use csv::ReaderBuilder;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::time::Instant;
+
+/// Counters of one ingest run; see the module docs.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct IngestSummary {
+    /// Input rows read, whether they parsed or not.
+    pub rows: u64,
+    /// Input bytes read.
+    pub bytes: u64,
+    /// Parsed rows by transaction type.
+    pub by_type: BTreeMap<String, u64>,
+    /// Rows not applied, by reason.
+    pub rejects: BTreeMap<String, u64>,
+    /// Rows deferred until the transaction they name arrives.
+    pub deferred: u64,
+    /// Seconds from the start of the run to its summary.
+    pub wall_secs: f64,
+    pub rows_per_sec: f64,
+}
+
+impl IngestSummary {
+    /// Rows neither rejected nor deferred.
+    pub fn applied(&self) -> u64 {
+        let rejected: u64 = self.rejects.values().sum();
+        self.rows.saturating_sub(rejected + self.deferred)
+    }
+}
+
+/// An [`IngestSummary`] being filled in, row by row.
+#[derive(Debug, Clone)]
+pub struct IngestTally {
+    started: Instant,
+    summary: IngestSummary,
+}
+
+impl IngestTally {
+    /// Start the clock.
+    pub fn start() -> Self {
+        Self {
+            started: Instant::now(),
+            summary: IngestSummary::default(),
+        }
+    }
+
+    /// A row of type `kind` was read.
+    pub fn parsed(&mut self, kind: TxType) {
+        self.summary.rows += 1;
+        *self
+            .summary
+            .by_type
+            .entry(kind.as_str().to_owned())
+            .or_default() += 1;
+    }
+
+    /// A row was read but could not be parsed, for `reason`.
+    pub fn unparsable(&mut self, reason: &str) {
+        self.summary.rows += 1;
+        self.rejected(reason);
+    }
+
+    /// A parsed row was not applied, for `reason`.
+    pub fn rejected(&mut self, reason: &str) {
+        *self.summary.rejects.entry(reason.to_owned()).or_default() += 1;
+    }
+
+    /// What the engine did with a parsed row.
+    pub fn outcome(&mut self, result: &ProcessResult) {
+        match result {
+            ProcessResult::Applied(_) => {}
+            ProcessResult::Rejected { anomaly, .. } => self.rejected(anomaly.as_str()),
+            ProcessResult::Deferred { .. } => self.summary.deferred += 1,
+        }
+    }
+
+    /// The summary, with `bytes` read in all.
+    pub fn finish(self, bytes: u64) -> IngestSummary {
+        let wall_secs = self.started.elapsed().as_secs_f64();
+        let rows_per_sec = if wall_secs > 0.0 {
+            self.summary.rows as f64 / wall_secs
+        } else {
+            0.0
+        };
+        IngestSummary {
+            bytes,
+            wall_secs,
+            rows_per_sec,
+            ..self.summary
+        }
+    }
+}
+
+impl Engine {
+    /// Process every row of the CSV in `src` (with optional
+    /// `idempotency_key`, `bucket` and `to_bucket` columns) and summarize
+    /// the run. Rows that do not
+    /// parse are logged and skipped; like [`Engine::process`], this only
+    /// fails on an I/O error or an [`Action::Fatal`] anomaly.
+    ///
+    /// [`Action::Fatal`]: crate::anomaly::Action::Fatal
+    pub fn process_reader<R: Read>(&mut self, src: R) -> Result<IngestSummary> {
+        let mut tally = IngestTally::start();
+        let mut rdr = ReaderBuilder::new().trim(csv::Trim::All).from_reader(src);
+        let headers = rdr.headers()?.clone();
+        let mut record = csv::StringRecord::new();
+        let mut row = 0u64;
+        while rdr.read_record(&mut record)? {
+            row += 1;
+            match record.deserialize::<KeyedRow>(Some(&headers)) {
+                Ok(mut keyed) => {
+                    let (bucket, to_bucket) = (keyed.bucket.take(), keyed.to_bucket.take());
+                    let buckets = RowBuckets {
+                        bucket: bucket.as_deref().unwrap_or_default(),
+                        to_bucket: to_bucket.as_deref().unwrap_or_default(),
+                    };
+                    let (tx, key) = keyed.split();
+                    tally.parsed(tx.kind);
+                    let meta = RowMeta {
+                        key: key.as_deref(),
+                        buckets,
+                        ..RowMeta::default()
+                    };
+                    tally.outcome(&self.process_row(tx, meta)?);
+                }
+                Err(e) => {
+                    tracing::error!(row, %e, "deserialize");
+                    tally.unparsable("unparsable");
+                }
+            }
+        }
+        Ok(tally.finish(rdr.position().byte()))
+    }
+}
@@ -0,0 +1,167 @@
+//! Invariant checking after every row, for staging runs and bug hunts.
+//!
+//! With [`EngineConfig::check_invariants`] set, the engine re-checks the
+//! account a row was for once the row is done, and fails with a
+//! [`Violation`] naming the row if its bookkeeping no longer adds up:
+//!
+//! * held funds never go negative;
+//! * a row changes the account's total by exactly the money it moved in or
+//!   out — the operator's side of the ledger. Deposits, refunds and
+//!   promotional credits bring money in; withdrawals, captures and chargebacks take it out; disputes,
+//!   resolves, holds and releases only shift it between available and held;
+//! * an account locked before the row is left as it was;
+//! * a rejected row leaves the account as it was.
+//!
+//! A violation means corrupt input state (a hand-edited snapshot, say) or an
+//! engine bug. The check costs a couple of map look-ups per row, so it is
+//! off by default.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::engine::EngineConfig;
+//! use payments_engine::invariants::{Invariant, Violation};
+//! use payments_engine::{Engine, Transaction, TxType};
+//! use rust_decimal_macros::dec;
+//!
+//! let config = EngineConfig { check_invariants: true, ..Default::default() };
+//! let mut eng = Engine::with_config(config);
+//! eng.process(Transaction { kind: TxType::Deposit, client: 1, tx: 1, amount: Some(dec!(5)) })
+//!     .unwrap();
+//!
+//! // state that no sequence of rows can produce
+//! eng.accounts.get_mut(&1).unwrap().held = dec!(-2);
+//! let err = eng
+//!     .process(Transaction { kind: TxType::Deposit, client: 1, tx: 2, amount: Some(dec!(1)) })
+//!     .unwrap_err();
+//! let violation = err.downcast_ref::<Violation>().unwrap();
+//! assert_eq!((violation.seq, violation.invariant), (2, Invariant::NegativeHeld));
+//! ```
+//!
+//! [`EngineConfig::check_invariants`]: crate::engine::EngineConfig::check_invariants
+
+use crate::engine::Engine;
+use crate::models::{Account, Transaction, TxType};
+use rust_decimal::Decimal;
+use std::fmt;
+
+/// A property every processed row must preserve; see the module docs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Invariant {
+    NegativeHeld,
+    /// The total changed by something other than the row's money flow.
+    Conservation {
+        expected: Decimal,
+    },
+    LockedChanged,
+    RejectedChanged,
+}
+
+impl fmt::Display for Invariant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Invariant::NegativeHeld => f.write_str("held funds went negative"),
+            Invariant::Conservation { expected } => {
+                write!(f, "total should have changed by {expected}")
+            }
+            Invariant::LockedChanged => f.write_str("a locked account changed"),
+            Invariant::RejectedChanged => f.write_str("a rejected row changed the account"),
+        }
+    }
+}
+
+/// The first row found breaking an [`Invariant`], with its account's
+/// balances just before and after it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    /// [`Engine::seq`] of the row.
+    pub seq: u64,
+    pub kind: TxType,
+    pub client: u16,
+    pub tx: u32,
+    pub invariant: Invariant,
+    pub before: Account,
+    pub after: Account,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |a: &Account| {
+            format!(
+                "available {}, held {}, locked {}",
+                a.available, a.held, a.locked
+            )
+        };
+        write!(
+            f,
+            "row {}: invariant violated: {} ({} by client {}, tx {}; before: {}; after: {})",
+            self.seq,
+            self.invariant,
+            self.kind.as_str(),
+            self.client,
+            self.tx,
+            show(&self.before),
+            show(&self.after)
+        )
+    }
+}
+
+impl std::error::Error for Violation {}
+
+impl Engine {
+    /// Check the account of the row just processed, whose balances were
+    /// `before` ahead of it and whose [`Engine::flow`] was `expected`.
+    pub(crate) fn check_row(
+        &self,
+        tx: &Transaction,
+        before: &Account,
+        expected: Decimal,
+        applied: bool,
+    ) -> Result<(), Violation> {
+        let after = self.accounts.get(&tx.client).cloned().unwrap_or_default();
+        let invariant = if after.held < Decimal::ZERO {
+            Some(Invariant::NegativeHeld)
+        } else if !applied && after != *before {
+            Some(Invariant::RejectedChanged)
+        } else if before.locked && after != *before {
+            Some(Invariant::LockedChanged)
+        } else {
+            (applied && after.total() - before.total() != expected)
+                .then_some(Invariant::Conservation { expected })
+        };
+        match invariant {
+            None => Ok(()),
+            Some(invariant) => Err(Violation {
+                seq: self.seq,
+                kind: tx.kind,
+                client: tx.client,
+                tx: tx.tx,
+                invariant,
+                before: before.clone(),
+                after,
+            }),
+        }
+    }
+
+    /// Money `tx` brings into (positive) or takes out of the client's
+    /// account if it applies, from the row and the records it refers to.
+    /// Taken before the row is applied, as a charge-back may drop the
+    /// deposit it names.
+    pub(crate) fn flow(&self, tx: &Transaction) -> Decimal {
+        let amount = tx.amount.unwrap_or_default();
+        match tx.kind {
+            TxType::Deposit | TxType::Refund | TxType::Promo => amount,
+            TxType::Withdrawal => -amount,
+            TxType::Capture => self.holds.get(&tx.tx).map_or(Decimal::ZERO, |h| -h.amount),
+            TxType::Chargeback => match self.deposits.get(&tx.tx) {
+                Some(dep) => -dep.amount,
+                None => self
+                    .withdrawals
+                    .get(&tx.tx)
+                    .map_or(Decimal::ZERO, |w| -w.disputed),
+            },
+            TxType::Dispute | TxType::Resolve | TxType::Hold | TxType::Release | TxType::Move => {
+                Decimal::ZERO
+            }
+        }
+    }
+}
@@ -2,9 +2,67 @@
 
 //! Public API for the payments engine crate.
 
+pub mod aggregate;
+pub mod amount;
+pub mod anomaly;
+pub mod anonymize;
+pub mod batch;
+pub mod bucket;
+pub mod cases;
+pub mod cdc;
+pub mod chargeback;
+pub mod checkpoint;
+pub mod checksum;
+pub mod clock;
+pub mod codec;
+pub mod compare;
+pub mod completions;
+pub mod config;
+pub mod credit;
+pub mod deferred;
+pub mod deposit;
+pub mod dispute;
+pub mod encoding;
 pub mod engine;
 pub mod errors;
+pub mod escrow;
+pub mod explain;
+pub mod fasthash;
+pub mod feed;
+pub mod filter;
+pub mod fork;
+pub mod funds;
+pub mod generate;
+pub mod history;
+pub mod hold;
+pub mod idempotency;
+pub mod ingest;
+pub mod invariants;
+pub mod limits;
+pub mod manifest;
+pub mod merge;
 pub mod models;
+pub mod notes;
+pub mod notify;
+pub mod parallel;
+pub mod processor;
+pub mod promo;
+pub mod query;
+pub mod redis;
+pub mod replica;
+pub mod report;
+pub mod schema;
+pub mod screening;
+pub mod shutdown;
+pub mod sketch;
+pub mod snapshot;
+pub mod sort;
+pub mod split;
+pub mod structuring;
+pub mod tenant;
+pub mod testing;
+pub mod trace;
+pub mod twopass;
 
 pub use engine::Engine;
 pub use models::{Transaction, TxType};
@@ -0,0 +1,261 @@
+//! Size limits on CSV rows, enforced before the CSV reader buffers them.
+//!
+//! The CSV reader keeps a whole record in memory, so one corrupted line —
+//! a missing newline, an unbalanced quote swallowing the rest of the file —
+//! could grow without bound. A [`LimitedReader`] sits between the raw input
+//! and the CSV reader, holding at most [`RowLimits::max_row_len`] bytes of
+//! the current record. A record breaking a limit is dropped up to the next
+//! newline and remembered as a [`Breach`]; the rows around it are read as
+//! usual.
+//!
+//! Breaches are numbered by record, counting the header as record 0 and
+//! skipping blank lines like the CSV reader does, so they can be put back
+//! in place among the rows the CSV reader returns.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::limits::{BreachKind, LimitedReader, RowLimits};
+//! use std::io::Read;
+//!
+//! let limits = RowLimits { max_field_len: 8, ..RowLimits::default() };
+//! let csv = "type,client,tx,amount\n\
+//!            deposit,1,1,1.0\n\
+//!            deposit,1,2,100000000000.0\n\
+//!            deposit,1,3,2.0\n";
+//! let mut rdr = LimitedReader::new(csv.as_bytes(), limits);
+//! let mut kept = String::new();
+//! rdr.read_to_string(&mut kept).unwrap();
+//! assert_eq!(kept, "type,client,tx,amount\ndeposit,1,1,1.0\n\ndeposit,1,3,2.0\n");
+//!
+//! let breach = rdr.breaches().borrow_mut().pop_front().unwrap();
+//! assert_eq!((breach.record, breach.line, breach.kind), (2, 3, BreachKind::FieldTooLong));
+//! ```
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read};
+use std::rc::Rc;
+
+/// Raw bytes pulled from the input per refill.
+const CHUNK: usize = 64 * 1024;
+
+/// Largest row, field and column count accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RowLimits {
+    /// Bytes in one field, quotes included.
+    pub max_field_len: usize,
+    /// Bytes in one record, line ending excluded.
+    pub max_row_len: usize,
+    /// Fields in one record.
+    pub max_columns: usize,
+}
+
+impl Default for RowLimits {
+    /// Far above anything a valid transaction row needs, while keeping a
+    /// runaway line to a bounded amount of memory.
+    fn default() -> Self {
+        Self {
+            max_field_len: 64 * 1024,
+            max_row_len: 1024 * 1024,
+            max_columns: 64,
+        }
+    }
+}
+
+/// Which limit a record broke.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreachKind {
+    RowTooLong,
+    FieldTooLong,
+    TooManyColumns,
+}
+
+impl BreachKind {
+    /// Kebab-case name as used in reject files.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BreachKind::RowTooLong => "oversized-row",
+            BreachKind::FieldTooLong => "oversized-field",
+            BreachKind::TooManyColumns => "too-many-columns",
+        }
+    }
+}
+
+/// A record dropped by a [`LimitedReader`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breach {
+    /// Record number, the header being record 0.
+    pub record: u64,
+    /// 1-based line the record starts on.
+    pub line: u64,
+    pub kind: BreachKind,
+    /// The limit that was exceeded.
+    pub limit: usize,
+}
+
+impl fmt::Display for Breach {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let what = match self.kind {
+            BreachKind::RowTooLong => "row longer than",
+            BreachKind::FieldTooLong => "field longer than",
+            BreachKind::TooManyColumns => "row with more columns than",
+        };
+        write!(f, "line {}: {what} {}; row skipped", self.line, self.limit)
+    }
+}
+
+impl std::error::Error for Breach {}
+
+/// Breaches found so far, oldest first; shared with whoever consumes the
+/// rows so it can report them in place.
+pub type Breaches = Rc<RefCell<VecDeque<Breach>>>;
+
+/// Passes CSV bytes through, minus records breaking a [`RowLimits`]; see
+/// the module docs. A dropped record leaves its line ending behind, which
+/// the CSV reader skips as a blank line.
+pub struct LimitedReader<R> {
+    inner: R,
+    limits: RowLimits,
+    breaches: Breaches,
+    /// Bytes of the current record, once it is known to be within limits.
+    out: Vec<u8>,
+    pos: usize,
+    /// The current record so far.
+    record: Vec<u8>,
+    /// Bytes in the current field and fields started in the current record.
+    field_len: usize,
+    columns: usize,
+    in_quotes: bool,
+    /// Discarding the current record up to the next newline.
+    skipping: bool,
+    /// Records completed so far (blank lines excluded) and lines started.
+    records: u64,
+    line: u64,
+    record_line: u64,
+    eof: bool,
+}
+
+impl<R: Read> LimitedReader<R> {
+    pub fn new(inner: R, limits: RowLimits) -> Self {
+        Self {
+            inner,
+            limits,
+            breaches: Breaches::default(),
+            out: Vec::new(),
+            pos: 0,
+            record: Vec::new(),
+            field_len: 0,
+            columns: 1,
+            in_quotes: false,
+            skipping: false,
+            records: 0,
+            line: 1,
+            record_line: 1,
+            eof: false,
+        }
+    }
+
+    /// Handle on the breaches found so far; they are appended as reading
+    /// goes on.
+    pub fn breaches(&self) -> Breaches {
+        Rc::clone(&self.breaches)
+    }
+
+    fn breach(&mut self, kind: BreachKind, limit: usize) {
+        self.breaches.borrow_mut().push_back(Breach {
+            record: self.records,
+            line: self.record_line,
+            kind,
+            limit,
+        });
+        self.record.clear();
+        self.skipping = true;
+    }
+
+    /// A record ends: pass it on unless it was dropped.
+    fn end_record(&mut self) {
+        if self.skipping || !self.record.is_empty() {
+            self.records += 1;
+        }
+        self.out.append(&mut self.record);
+        self.field_len = 0;
+        self.columns = 1;
+        self.in_quotes = false;
+        self.skipping = false;
+    }
+
+    fn scan(&mut self, byte: u8) {
+        let newline = byte == b'\n' || byte == b'\r';
+        if self.skipping {
+            // a dropped record ends at the next physical newline, quoted or
+            // not: a corrupt row may well have an unbalanced quote
+            if newline {
+                self.end_record();
+                self.out.push(byte);
+            }
+        } else if newline && !self.in_quotes {
+            self.end_record();
+            self.out.push(byte);
+        } else {
+            if byte == b'"' {
+                self.in_quotes = !self.in_quotes;
+            }
+            if byte == b',' && !self.in_quotes {
+                self.field_len = 0;
+                self.columns += 1;
+            } else {
+                self.field_len += 1;
+            }
+            self.record.push(byte);
+            if self.record.len() > self.limits.max_row_len {
+                self.breach(BreachKind::RowTooLong, self.limits.max_row_len);
+            } else if self.field_len > self.limits.max_field_len {
+                self.breach(BreachKind::FieldTooLong, self.limits.max_field_len);
+            } else if self.columns > self.limits.max_columns {
+                self.breach(BreachKind::TooManyColumns, self.limits.max_columns);
+            }
+        }
+        if byte == b'\n' {
+            self.line += 1;
+        }
+        if newline && !self.in_quotes && self.record.is_empty() {
+            self.record_line = self.line;
+        }
+    }
+
+    /// Scan input until there is output or the input ends.
+    fn refill(&mut self) -> io::Result<()> {
+        self.out.clear();
+        self.pos = 0;
+        let mut raw = vec![0u8; CHUNK];
+        while self.out.is_empty() && !self.eof {
+            let n = match self.inner.read(&mut raw) {
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if n == 0 {
+                // a last record without a line ending
+                self.eof = true;
+                self.end_record();
+            }
+            for &byte in &raw[..n] {
+                self.scan(byte);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.out.len() {
+            self.refill()?;
+        }
+        let n = buf.len().min(self.out.len() - self.pos);
+        buf[..n].copy_from_slice(&self.out[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
@@ -1,18 +1,82 @@
-//! CLI wrapper that supports both:
+//! CLI wrapper organised as subcommands sharing the global
+//! `--input-format` flag. `process` runs the engine and is the default, so
+//! the original invocations keep working:
 //!   cargo run -- transactions.csv > accounts.csv
 //!   cargo run -- --input transactions.csv --output accounts.csv
+//! Besides it: `validate` dry-runs an input, `diff` compares two snapshots,
+//! `generate` writes synthetic input, `replay` (alias `shadow`) compares a
+//! replay against a golden snapshot or another configuration, `verify`
+//! checks a run manifest, `selfcheck` compares the multi-threaded engine
+//! against the single-threaded one, `split` and `merge` partition an input by client and
+//! join the per-shard results, `completions` prints shell completion
+//! scripts, plus `balance-at`, `explain`, `annotate`, `source-of-funds`,
+//! `statement`, `encode`, `config` and `schema`.
+//! Each subcommand's `--help` ends with usage examples.
 
-use anyhow::Result;
-use clap::{Arg, Command};
-use csv::{ReaderBuilder, WriterBuilder};
-use payments_engine::Engine;
+use anyhow::{Context, Result};
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, Command, value_parser};
+use csv::ReaderBuilder;
+use payments_engine::aggregate::{self, LockPolicy};
+use payments_engine::amount::AmountLocale;
+use payments_engine::anomaly::{self, Action, Anomaly};
+use payments_engine::anonymize::Pseudonymizer;
+use payments_engine::batch::{BatchRules, SummaryWriter};
+use payments_engine::bucket::RowBuckets;
+use payments_engine::cases::CaseRecorder;
+use payments_engine::cdc::CdcWriter;
+use payments_engine::chargeback::ChargebackLog;
+use payments_engine::checkpoint::{Checkpointer, Interval, write_snapshot_atomic};
+use payments_engine::checksum::HashingWriter;
+use payments_engine::clock::{FixedClock, ManualClock, parse_timestamp};
+use payments_engine::codec::{TxDecoder, TxEncoder};
+use payments_engine::completions::{self, Shell};
+use payments_engine::credit;
+use payments_engine::encoding::{Decoder, Encoding};
+use payments_engine::engine::EngineConfig;
+use payments_engine::feed::Watch;
+use payments_engine::filter::{IngestFilter, read_id_list};
+use payments_engine::funds::{self, FundsTracer, Order};
+use payments_engine::generate::Generator;
+use payments_engine::history::Retention;
+use payments_engine::idempotency::KeyedRow;
+use payments_engine::ingest::IngestTally;
+use payments_engine::limits::{Breach, LimitedReader, RowLimits};
+use payments_engine::manifest::{FileDigest, RunManifest, STDOUT, Status};
+use payments_engine::merge::MergePolicy;
+use payments_engine::models::AccountRow;
+use payments_engine::notes::AlertFlag;
+use payments_engine::notify::{JsonLinesSink, NotificationSink, SlackSink, StderrSink};
+use payments_engine::parallel::ParallelEngine;
+use payments_engine::processor::{PaymentsProcessor, RowMeta};
+use payments_engine::redis::RedisPublisher;
+use payments_engine::replica::Replica;
+#[cfg(feature = "bank-statements")]
+use payments_engine::report::bank_statement;
+use payments_engine::report::columns::{self, Column, Columns};
+use payments_engine::report::postgres::PgScript;
+use payments_engine::report::{self, ReportFilter};
+use payments_engine::schema::{self, Format, Target};
+use payments_engine::screening::Screening;
+use payments_engine::sketch::StreamStats;
+use payments_engine::sort::ExternalSort;
+use payments_engine::split::split_by_client;
+use payments_engine::structuring::StructuringRule;
+use payments_engine::tenant::TenantRouter;
+use payments_engine::trace::ClientTrace;
+use payments_engine::twopass::DepositIndex;
+use payments_engine::{Engine, Transaction, TxType, compare, config, merge, shutdown, snapshot};
+use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
 use std::{
-    env,
-    fs::File,
-    io::{self, Write},
-    path::PathBuf,
+    cell::Cell,
+    collections::{BTreeMap, HashSet},
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Seek, Write},
+    path::{Path, PathBuf},
 };
-use tracing::{error, info};
+use tracing::{error, info, warn};
 use tracing_subscriber::FmtSubscriber;
 
 fn main() -> Result<()> {
@@ -24,7 +88,744 @@ fn main() -> Result<()> {
     tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
 
     // ---------------------------------------------------------------- flags
-    let matches = Command::new("payments-engine")
+    let cli = cli();
+    let mut argv: Vec<OsString> = std::env::args_os().collect();
+    // anything that does not name a subcommand is the classic invocation
+    // (`payments-engine transactions.csv > accounts.csv`): run `process`
+    let top_level = ["-h", "--help", "-V", "--version"];
+    let sub_at = match subcommand_at(&cli, &argv) {
+        Some(at) => at,
+        None if argv
+            .get(1)
+            .is_some_and(|a| top_level.iter().any(|t| a == t)) =>
+        {
+            0
+        }
+        None => {
+            argv.insert(1, "process".into());
+            1
+        }
+    };
+    let matches = cli.clone().get_matches_from(&argv);
+
+    // --config: re-parse with the file's flags ahead of the real ones
+    let config_path = match matches.subcommand() {
+        Some(("process", sub)) => sub.get_one::<String>("config").cloned(),
+        _ => None,
+    };
+    let matches = match config_path {
+        Some(path) => {
+            let file_args = config_args(&cli, &path)?;
+            let at = sub_at + 1;
+            argv.splice(at..at, file_args.into_iter().map(Into::into));
+            cli.clone().get_matches_from(argv)
+        }
+        None => matches,
+    };
+
+    match matches.subcommand() {
+        Some(("process", sub)) => process(sub),
+        Some(("validate", sub)) => validate(sub),
+        Some(("diff", sub)) => diff(sub),
+        Some(("replay", sub)) => replay(&cli, sub),
+        Some(("generate", sub)) => generate(sub),
+        Some(("config", _)) => {
+            print!("{}", default_config(&cli));
+            Ok(())
+        }
+        Some(("schema", sub)) => print_schema(sub),
+        Some(("completions", sub)) => {
+            let shell = sub.get_one::<String>("shell").expect("required");
+            completions::generate(
+                shell.parse().map_err(anyhow::Error::msg)?,
+                &cli,
+                &mut io::stdout(),
+            )
+        }
+        Some(("encode", sub)) => encode(sub),
+        Some(("verify", sub)) => verify(sub),
+        Some(("selfcheck", sub)) => selfcheck(sub),
+        Some(("split", sub)) => split(sub),
+        Some(("merge", sub)) => merge(sub),
+        Some(("aggregate", sub)) => aggregate(sub),
+        Some(("balance-at", sub)) => balance_at(&cli, sub),
+        Some(("explain", sub)) => explain(&cli, sub),
+        Some(("annotate", sub)) => annotate(sub),
+        Some(("source-of-funds", sub)) => source_of_funds(&cli, sub),
+        #[cfg(feature = "bank-statements")]
+        Some(("statement", sub)) => statement(&cli, sub),
+        #[cfg(feature = "bank-statements")]
+        Some(("statements", sub)) => statements(&cli, sub),
+        _ => unreachable!("subcommand_required"),
+    }
+}
+
+/// Where in `argv` the subcommand is named: the first argument that is not
+/// a flag or a flag's value, if it names one. Flags are looked up among the
+/// global and `process` ones, so `--output config` is a value, not `config`.
+fn subcommand_at(cli: &Command, argv: &[OsString]) -> Option<usize> {
+    let process = cli.find_subcommand("process").expect("defined in cli()");
+    let takes_value = |flag: &str| {
+        cli.get_arguments().chain(process.get_arguments()).any(|a| {
+            let named = match flag.strip_prefix("--") {
+                Some(long) => a.get_long() == Some(long),
+                None => flag.len() == 2 && a.get_short() == flag.chars().nth(1),
+            };
+            named && a.get_action().takes_values()
+        })
+    };
+    let mut args = argv.iter().enumerate().skip(1);
+    while let Some((i, arg)) = args.next() {
+        let arg = arg.to_str()?;
+        if arg == "--" {
+            return None;
+        }
+        if arg.starts_with('-') && arg.len() > 1 {
+            if !arg.contains('=') && takes_value(arg) {
+                args.next();
+            }
+            continue;
+        }
+        return cli
+            .get_subcommands()
+            .any(|c| c.get_name() == arg || c.get_all_aliases().any(|n| n == arg))
+            .then_some(i);
+    }
+    None
+}
+
+/// The full command line: global flags plus every subcommand.
+fn cli() -> Command {
+    let cli = Command::new("payments-engine")
+        .arg(
+            Arg::new("input-format")
+                .long("input-format")
+                .value_parser(["csv", "binary"])
+                .default_value("csv")
+                .global(true)
+                .help("Encoding of the input file"),
+        )
+        .arg(
+            Arg::new("input-encoding")
+                .long("input-encoding")
+                .value_parser(Encoding::NAMES)
+                .default_value("auto")
+                .global(true)
+                .help("Text encoding of CSV inputs; auto detects a BOM or UTF-16"),
+        )
+        .subcommand(process_command())
+        .subcommand(
+            Command::new("validate")
+                .about("Check an input without writing anything: every row that would not be applied")
+                .after_long_help(examples(&[
+                    ("Dry run before a real one", "validate --input in.csv"),
+                ]))
+                .arg(
+                    Arg::new("input")
+                        .long("input")
+                        .value_name("FILE")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("diff")
+                .about("Compare the state recorded in two snapshots")
+                .after_long_help(examples(&[
+                    ("What changed between two checkpoints", "diff day1.snap day2.snap"),
+                ]))
+                .arg(Arg::new("old").required(true).value_name("SNAPSHOT"))
+                .arg(Arg::new("new").required(true).value_name("SNAPSHOT")),
+        )
+        .subcommand(
+            Command::new("generate")
+                .about("Write a reproducible synthetic transactions CSV")
+                .after_long_help(examples(&[
+                    ("A million rows over 5000 clients, reproducibly", "generate --rows 1000000 --clients 5000 --seed 7 --output in.csv"),
+                ]))
+                .arg(
+                    Arg::new("rows")
+                        .long("rows")
+                        .value_name("N")
+                        .value_parser(value_parser!(u64))
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("clients")
+                        .long("clients")
+                        .value_name("N")
+                        .value_parser(value_parser!(u16).range(1..))
+                        .default_value("1000"),
+                )
+                .arg(
+                    Arg::new("seed")
+                        .long("seed")
+                        .value_parser(value_parser!(u64))
+                        .default_value("1"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Write here instead of stdout"),
+                ),
+        )
+        .subcommand(
+            Command::new("verify")
+                .about("Check that the files recorded in a run manifest are unmodified")
+                .after_long_help(examples(&[
+                    ("Check a previous run's inputs and outputs", "verify --manifest run.json"),
+                ]))
+                .arg(
+                    Arg::new("manifest")
+                        .long("manifest")
+                        .value_name("FILE")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("replay")
+                .visible_alias("shadow")
+                .about("Replay an input and compare the final state with a golden snapshot or another configuration")
+                .after_long_help(examples(&[
+                    ("Compare this build against a golden snapshot", "replay --input in.csv --golden golden.snap"),
+                    ("Replay under the nightly flags", "replay --input in.csv --golden golden.snap --config nightly.toml"),
+                    ("What a rule change would change", "replay --input in.csv --config nightly.toml --against proposed.toml"),
+                ]))
+                .arg(
+                    Arg::new("input")
+                        .long("input")
+                        .value_name("FILE")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("golden")
+                        .long("golden")
+                        .value_name("SNAPSHOT")
+                        .required_unless_present("against")
+                        .help("Snapshot of the expected final state"),
+                )
+                .arg(
+                    Arg::new("config")
+                        .long("config")
+                        .value_name("FILE")
+                        .help("Replay under the `process` flags of this TOML file [default: none]"),
+                )
+                .arg(
+                    Arg::new("against")
+                        .long("against")
+                        .value_name("FILE")
+                        .conflicts_with("golden")
+                        .help("Replay under the `process` flags of this TOML file as well and compare the two"),
+                ),
+        )
+        .subcommand(
+            Command::new("selfcheck")
+                .about("Run the multi-threaded and single-threaded engines over an input and compare them")
+                .after_long_help(examples(&[
+                    ("Compare the engines over a large input", "selfcheck --input big.csv --threads 8"),
+                ]))
+                .arg(
+                    Arg::new("input")
+                        .long("input")
+                        .value_name("FILE")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("threads")
+                        .long("threads")
+                        .value_name("N")
+                        .value_parser(value_parser!(u16).range(1..))
+                        .default_value("8"),
+                ),
+        )
+        .subcommand(
+            Command::new("split")
+                .about("Partition an input CSV into per-shard files by client")
+                .after_long_help(examples(&[
+                    ("Sixteen shards, one file each", "split --input huge.csv --shards 16 --out-dir shards/"),
+                ]))
+                .arg(
+                    Arg::new("input")
+                        .long("input")
+                        .value_name("FILE")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("shards")
+                        .long("shards")
+                        .value_name("N")
+                        .value_parser(value_parser!(u16).range(1..))
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("by")
+                        .long("by")
+                        .value_parser(["client"])
+                        .default_value("client")
+                        .help("Partitioning key"),
+                )
+                .arg(
+                    Arg::new("out-dir")
+                        .long("out-dir")
+                        .value_name("DIR")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("merge")
+                .about("Merge snapshots or account reports of disjoint-client shards")
+                .after_long_help(examples(&[
+                    ("Merge shard snapshots into one report and snapshot", "merge shards/*.snap --output accounts.csv --save-snapshot all.snap"),
+                    ("Merge shard reports", "merge shards/*.accounts.csv"),
+                    ("Combine regional runs that share clients", "merge eu.snap us.snap --sum-clients --output accounts.csv"),
+                ]))
+                .arg(
+                    Arg::new("shards")
+                        .value_name("FILE")
+                        .num_args(1..)
+                        .required(true)
+                        .help("Shard snapshots or accounts CSVs"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Write the merged accounts CSV here [default: stdout]"),
+                )
+                .arg(
+                    Arg::new("save-snapshot")
+                        .long("save-snapshot")
+                        .value_name("FILE")
+                        .help("Also write a merged snapshot (all shards must be snapshots)"),
+                )
+                .arg(
+                    Arg::new("sum-clients")
+                        .long("sum-clients")
+                        .action(ArgAction::SetTrue)
+                        .help("Add up the balances of a client found in several inputs instead of refusing"),
+                ),
+        )
+        .subcommand(
+            Command::new("aggregate")
+                .about("Sum the accounts reports of regional runs into one global report")
+                .after_long_help(examples(&[
+                    ("Global view of two regions", "aggregate eu/accounts.csv us/accounts.csv --output combined.csv"),
+                    ("Refuse clients locked in one region only", "aggregate eu/accounts.csv us/accounts.csv --locked fail"),
+                ]))
+                .arg(
+                    Arg::new("runs")
+                        .value_name("FILE")
+                        .num_args(1..)
+                        .required(true)
+                        .help("Accounts CSVs of the runs"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Write the combined accounts CSV here [default: stdout]"),
+                )
+                .arg(
+                    Arg::new("locked")
+                        .long("locked")
+                        .value_name("POLICY")
+                        .value_parser(LockPolicy::NAMES)
+                        .default_value("any")
+                        .help("Client locked in some runs only: locked if locked in any run, only if in all, or fail"),
+                ),
+        )
+        .subcommand(
+            Command::new("balance-at")
+                .about("Show a client's balance just before and after a given transaction")
+                .after_long_help(examples(&[
+                    ("Client 7 around tx 4820", "balance-at --input in.csv --client 7 --tx 4820"),
+                    ("The same under the nightly flags", "balance-at --input in.csv --client 7 --tx 4820 --config nightly.toml"),
+                ]))
+                .arg(
+                    Arg::new("input")
+                        .long("input")
+                        .value_name("FILE")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("config")
+                        .long("config")
+                        .value_name("FILE")
+                        .help("Replay under the `process` flags of this TOML file [default: none]"),
+                )
+                .arg(
+                    Arg::new("client")
+                        .long("client")
+                        .value_name("ID")
+                        .value_parser(value_parser!(u16))
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("tx")
+                        .long("tx")
+                        .value_name("ID")
+                        .value_parser(value_parser!(u32))
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("explain")
+                .about("Explain why a transaction was applied or rejected")
+                .after_long_help(examples(&[
+                    ("Why was withdrawal 9981 ignored?", "explain --input in.csv --tx 9981"),
+                    (
+                        "The chargeback of deposit 17 rather than the deposit",
+                        "explain --input in.csv --tx 17 --type chargeback",
+                    ),
+                    ("The same under the nightly flags", "explain --input in.csv --tx 9981 --config nightly.toml"),
+                ]))
+                .arg(
+                    Arg::new("input")
+                        .long("input")
+                        .value_name("FILE")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("config")
+                        .long("config")
+                        .value_name("FILE")
+                        .help("Replay under the `process` flags of this TOML file [default: none]"),
+                )
+                .arg(
+                    Arg::new("tx")
+                        .long("tx")
+                        .value_name("ID")
+                        .value_parser(value_parser!(u32))
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("client")
+                        .long("client")
+                        .value_name("ID")
+                        .value_parser(value_parser!(u16))
+                        .help("Only a row of this client"),
+                )
+                .arg(
+                    Arg::new("type")
+                        .long("type")
+                        .value_name("TYPE")
+                        .value_parser(value_parser!(TxType))
+                        .help("Only a row of this type"),
+                ),
+        )
+        .subcommand(
+            Command::new("annotate")
+                .about("Set or clear account flags and add notes in a snapshot, or list them")
+                .after_long_help(examples(&[
+                    (
+                        "Put client 7 under review",
+                        "annotate --snapshot state.snap --client 7 --flag manual-review --note \"chargeback ratio\"",
+                    ),
+                    ("Show client 7's flags and notes", "annotate --snapshot state.snap --client 7"),
+                ]))
+                .arg(
+                    Arg::new("snapshot")
+                        .long("snapshot")
+                        .value_name("FILE")
+                        .required(true)
+                        .help("Snapshot to edit in place"),
+                )
+                .arg(
+                    Arg::new("client")
+                        .long("client")
+                        .value_name("ID")
+                        .value_parser(value_parser!(u16))
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("flag")
+                        .long("flag")
+                        .value_name("FLAG")
+                        .action(ArgAction::Append)
+                        .help("Set FLAG (repeatable)"),
+                )
+                .arg(
+                    Arg::new("unflag")
+                        .long("unflag")
+                        .value_name("FLAG")
+                        .action(ArgAction::Append)
+                        .help("Clear FLAG (repeatable)"),
+                )
+                .arg(
+                    Arg::new("note")
+                        .long("note")
+                        .value_name("TEXT")
+                        .help("Add a note"),
+                )
+                .arg(
+                    Arg::new("by")
+                        .long("by")
+                        .value_name("NAME")
+                        .default_value("admin")
+                        .help("Author recorded with --note"),
+                ),
+        )
+        .subcommand(
+            Command::new("source-of-funds")
+                .about("Attribute withdrawals to the deposits that funded them (FIFO or LIFO) and list unspent deposits")
+                .after_long_help(examples(&[
+                    (
+                        "Trace client 7 for a compliance request",
+                        "source-of-funds --input in.csv --client 7 --lots lots-7.csv > matches-7.csv",
+                    ),
+                    (
+                        "Realized amount per lot of a crypto ledger, newest lots first",
+                        "source-of-funds --input ledger.csv --order lifo --realized realized.csv",
+                    ),
+                ]))
+                .arg(
+                    Arg::new("input")
+                        .long("input")
+                        .value_name("FILE")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("config")
+                        .long("config")
+                        .value_name("FILE")
+                        .help("Replay under the `process` flags of this TOML file [default: none]"),
+                )
+                .arg(
+                    Arg::new("client")
+                        .long("client")
+                        .value_name("ID")
+                        .action(ArgAction::Append)
+                        .value_parser(value_parser!(u16))
+                        .help("Trace only this client (repeatable)"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Write the withdrawal-to-deposit matches here [default: stdout]"),
+                )
+                .arg(
+                    Arg::new("lots")
+                        .long("lots")
+                        .value_name("FILE")
+                        .default_value("lots.csv")
+                        .help("Write the unspent deposits and refunds here"),
+                )
+                .arg(
+                    Arg::new("order")
+                        .long("order")
+                        .value_name("ORDER")
+                        .value_parser(Order::NAMES)
+                        .default_value("fifo")
+                        .help("Take lots oldest first (fifo) or newest first (lifo)"),
+                )
+                .arg(
+                    Arg::new("realized")
+                        .long("realized")
+                        .value_name("FILE")
+                        .help("Write every lot with the amount it paid out here"),
+                ),
+        )
+        .subcommand(
+            Command::new("encode")
+                .about("Convert a transactions CSV to the compact binary format")
+                .after_long_help(examples(&[
+                    ("Convert once, then process the binary file", "encode in.csv in.bin"),
+                ]))
+                .arg(Arg::new("input").required(true).value_name("CSV"))
+                .arg(Arg::new("output").required(true).value_name("BIN")),
+        )
+        .subcommand(
+            Command::new("config")
+                .about("Configuration file helpers")
+                .after_long_help(examples(&[
+                    ("Start a configuration file", "config print-default > payments.toml"),
+                ]))
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("print-default")
+                        .about("Print a commented configuration file listing every flag"),
+                ),
+        )
+        .subcommand(
+            Command::new("schema")
+                .about("Print schemas for the transaction input and account output")
+                .after_long_help(examples(&[
+                    ("Avro schema of the accounts report", "schema --target account --format avro"),
+                ]))
+                .arg(
+                    Arg::new("target")
+                        .long("target")
+                        .value_parser(["transaction", "account", "all"])
+                        .default_value("all")
+                        .help("Which file format to describe"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_parser(["json-schema", "avro"])
+                        .default_value("json-schema")
+                        .help("Schema language to emit"),
+                ),
+        )
+        .subcommand(
+            Command::new("completions")
+                .about("Print a shell completion script")
+                .after_long_help(examples(&[
+                    ("Bash, for the current shell", "completions bash > ~/.local/share/bash-completion/completions/payments-engine"),
+                    ("Fish", "completions fish > ~/.config/fish/completions/payments-engine.fish"),
+                ]))
+                .arg(
+                    Arg::new("shell")
+                        .required(true)
+                        .value_parser(Shell::ALL),
+                ),
+        )
+        .subcommand_required(true)
+        .disable_help_subcommand(true);
+    #[cfg(feature = "bank-statements")]
+    let cli = cli.subcommand(
+        Command::new("statement")
+            .about("Render a client's history as a camt.053, MT940 or HTML statement")
+            .after_long_help(examples(&[
+                (
+                    "MT940 statement for client 7",
+                    "statement --input in.csv --client 7 --format mt940",
+                ),
+                (
+                    "Customer-facing HTML page",
+                    "statement --input in.csv --client 7 --format html > statement.html",
+                ),
+            ]))
+            .arg(
+                Arg::new("input")
+                    .long("input")
+                    .value_name("FILE")
+                    .required(true),
+            )
+            .arg(
+                Arg::new("config")
+                    .long("config")
+                    .value_name("FILE")
+                    .help("Replay under the `process` flags of this TOML file [default: none]"),
+            )
+            .arg(
+                Arg::new("client")
+                    .long("client")
+                    .value_name("ID")
+                    .value_parser(value_parser!(u16))
+                    .required(true),
+            )
+            .arg(
+                Arg::new("format")
+                    .long("format")
+                    .value_parser(bank_statement::Format::NAMES)
+                    .default_value("camt053"),
+            )
+            .arg(
+                Arg::new("currency")
+                    .long("currency")
+                    .value_name("ISO4217")
+                    .default_value("EUR"),
+            ),
+    );
+    #[cfg(feature = "bank-statements")]
+    let cli = cli.subcommand(
+        Command::new("statements")
+            .about("Write one statement file per client, with a manifest of them")
+            .after_long_help(examples(&[
+                (
+                    "HTML statements for every client, ready to mail",
+                    "statements --input in.csv --all --out-dir statements/",
+                ),
+                (
+                    "camt.053 for two clients",
+                    "statements --input in.csv --client 7 --client 9 --format camt053 --out-dir out/",
+                ),
+            ]))
+            .arg(
+                Arg::new("input")
+                    .long("input")
+                    .value_name("FILE")
+                    .required(true),
+            )
+            .arg(
+                Arg::new("config")
+                    .long("config")
+                    .value_name("FILE")
+                    .help("Replay under the `process` flags of this TOML file [default: none]"),
+            )
+            .arg(
+                Arg::new("all")
+                    .long("all")
+                    .action(ArgAction::SetTrue)
+                    .required_unless_present("client")
+                    .conflicts_with("client")
+                    .help("Every client with a row in the input"),
+            )
+            .arg(
+                Arg::new("client")
+                    .long("client")
+                    .value_name("ID")
+                    .value_parser(value_parser!(u16))
+                    .action(ArgAction::Append)
+                    .help("Only this client (repeatable)"),
+            )
+            .arg(
+                Arg::new("out-dir")
+                    .long("out-dir")
+                    .value_name("DIR")
+                    .required(true),
+            )
+            .arg(
+                Arg::new("format")
+                    .long("format")
+                    .value_parser(bank_statement::Format::NAMES)
+                    .default_value("html"),
+            )
+            .arg(
+                Arg::new("currency")
+                    .long("currency")
+                    .value_name("ISO4217")
+                    .default_value("EUR"),
+            )
+            .arg(
+                Arg::new("threads")
+                    .long("threads")
+                    .value_name("N")
+                    .value_parser(value_parser!(u16).range(1..))
+                    .default_value("8"),
+            ),
+    );
+    with_env_vars(cli).mut_subcommand("process", with_env_vars)
+}
+
+/// `Examples:` section of a subcommand's long `--help`: a description and
+/// the arguments after the binary name for each.
+fn examples(list: &[(&str, &str)]) -> String {
+    let mut out = String::from("Examples:");
+    for (what, args) in list {
+        out += &format!("\n  # {what}\n  payments-engine {args}\n");
+    }
+    out.trim_end().to_string()
+}
+
+/// `process`: the engine run itself, and what a bare invocation means.
+fn process_command() -> Command {
+    Command::new("process")
+        .about("Process transactions and write the closing balance of every client")
+        .after_long_help(examples(&[
+            ("Classic form: the input file as the only argument", "transactions.csv > accounts.csv"),
+            ("Only locked accounts, with rejected rows kept aside", "process --input in.csv --only-locked --rejects rejects.csv"),
+            ("Flags from a file, one overridden on the command line", "process --config nightly.toml --output out.csv"),
+        ]))
+        .args_override_self(true)
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .value_name("FILE")
+                .help("Read flags from a TOML file; command-line flags override it"),
+        )
         .arg(
             Arg::new("input")
                 .long("input")
@@ -37,69 +838,2549 @@ fn main() -> Result<()> {
                 .value_name("FILE")
                 .help("Output accounts CSV (defaults to stdout)"),
         )
-        .disable_help_subcommand(true)
-        .allow_external_subcommands(true)
-        .get_matches();
+        .arg(Arg::new("input-pos").value_name("INPUT").hide(true))
+        .arg(Arg::new("output-pos").value_name("OUTPUT").hide(true))
+        .arg(
+            Arg::new("only-locked")
+                .long("only-locked")
+                .action(ArgAction::SetTrue)
+                .help("Report only locked accounts"),
+        )
+        .arg(
+            Arg::new("client")
+                .long("client")
+                .value_name("ID")
+                .action(ArgAction::Append)
+                .value_parser(value_parser!(u16))
+                .help("Report only this client (repeatable)"),
+        )
+        .arg(
+            Arg::new("min-total")
+                .long("min-total")
+                .value_name("AMOUNT")
+                .value_parser(parse_amount)
+                .help("Report only accounts whose total is at least AMOUNT"),
+        )
+        .arg(
+            Arg::new("changed-only")
+                .long("changed-only")
+                .action(ArgAction::SetTrue)
+                .help("Report only accounts changed by an applied transaction"),
+        )
+        .arg(
+            Arg::new("baseline")
+                .long("baseline")
+                .value_name("FILE")
+                .help("Previous accounts CSV to diff against"),
+        )
+        .arg(
+            Arg::new("delta-output")
+                .long("delta-output")
+                .value_name("FILE")
+                .requires("baseline")
+                .help("Where to write the delta report (defaults to delta.csv)"),
+        )
+        .arg(
+            Arg::new("sort-by")
+                .long("sort-by")
+                .value_name("COLUMN")
+                .help("Sort the CSV input by COLUMN (e.g. timestamp) on disk before processing"),
+        )
+        .arg(
+            Arg::new("sort-dir")
+                .long("sort-dir")
+                .value_name("DIR")
+                .requires("sort-by")
+                .help("Scratch directory for --sort-by [default: system temp directory]"),
+        )
+        .arg(
+            Arg::new("amount-locale")
+                .long("amount-locale")
+                .value_name("LOCALE")
+                .value_parser(AmountLocale::NAMES)
+                .help("Read CSV amounts with thousand separators: en (1,234.56), de (1.234,56) or auto; ambiguous ones are logged [default: plain]"),
+        )
+        .arg(
+            Arg::new("two-pass")
+                .long("two-pass")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("sort-by")
+                .help("Index deposits first, then process with each deposit moved ahead of disputes naming it"),
+        )
+        .arg(
+            Arg::new("load-snapshot")
+                .long("load-snapshot")
+                .value_name("FILE")
+                .help("Start from a previously saved engine snapshot"),
+        )
+        .arg(
+            Arg::new("save-snapshot")
+                .long("save-snapshot")
+                .value_name("FILE")
+                .help("Write an engine snapshot after ingest"),
+        )
+        .arg(
+            Arg::new("snapshot-every")
+                .long("snapshot-every")
+                .value_name("INTERVAL")
+                .requires("save-snapshot")
+                .value_parser(value_parser!(Interval))
+                .help("Also checkpoint to --save-snapshot every N rows, period (30s, 10m, 1h) or `batch` end"),
+        )
+        .arg(
+            Arg::new("resume")
+                .long("resume")
+                .action(ArgAction::SetTrue)
+                .requires("save-snapshot")
+                // these files cover the whole run, so a resumed run can't add to them
+                .conflicts_with_all(["batch-summaries", "pg-script", "chargebacks-output"])
+                .help("Continue an interrupted run from the input row its --save-snapshot reached, appending to --cdc-out and --redis-out"),
+        )
+        .arg(
+            Arg::new("include-clients")
+                .long("include-clients")
+                .value_name("FILE")
+                .help("Process only clients listed in FILE (one id per line)"),
+        )
+        .arg(
+            Arg::new("exclude-clients")
+                .long("exclude-clients")
+                .value_name("FILE")
+                .help("Skip all rows for clients listed in FILE"),
+        )
+        .arg(
+            Arg::new("types")
+                .long("types")
+                .value_name("TYPE,...")
+                .value_delimiter(',')
+                .value_parser(value_parser!(TxType))
+                .help("Process only these transaction types (e.g. deposit,withdrawal)"),
+        )
+        .arg(
+            Arg::new("exclude-txs")
+                .long("exclude-txs")
+                .value_name("FILE")
+                .help("Skip rows whose tx id is listed in FILE (what-if analysis)"),
+        )
+        .arg(
+            Arg::new("notify")
+                .long("notify")
+                .value_name("SINK")
+                .action(ArgAction::Append)
+                .help("Alert sink: stderr, json:FILE (NDJSON) or slack:FILE (repeatable)"),
+        )
+        .arg(
+            Arg::new("held-alert")
+                .long("held-alert")
+                .value_name("AMOUNT")
+                .value_parser(parse_amount)
+                .help("Alert when a client's held funds reach AMOUNT"),
+        )
+        .arg(
+            Arg::new("max-amount")
+                .long("max-amount")
+                .value_name("[TYPE=]AMOUNT")
+                .action(ArgAction::Append)
+                .value_parser(parse_max_amount)
+                .help("Reject rows (of TYPE, or any type) above AMOUNT as amount-too-large, listed in --rejects or logged unless overridden (repeatable)"),
+        )
+        .arg(
+            Arg::new("batch-summaries")
+                .long("batch-summaries")
+                .value_name("FILE")
+                .help("Write one CSV row per batch (batches end where the `batch` column changes and at end of input) to FILE"),
+        )
+        .arg(
+            Arg::new("batch-max-disputes")
+                .long("batch-max-disputes")
+                .value_name("N")
+                .value_parser(value_parser!(u32))
+                .help("At each batch end, alert on clients that opened more than N disputes in the batch"),
+        )
+        .arg(
+            Arg::new("structuring")
+                .long("structuring")
+                .value_name("AMOUNT")
+                .value_parser(parse_amount)
+                .help("Flag clients making several deposits just under AMOUNT in a short stretch of input"),
+        )
+        .arg(
+            Arg::new("structuring-floor")
+                .long("structuring-floor")
+                .value_name("AMOUNT")
+                .value_parser(parse_amount)
+                .requires("structuring")
+                .help("Smallest deposit counted as just under the --structuring threshold [default: 90% of it]"),
+        )
+        .arg(
+            Arg::new("structuring-count")
+                .long("structuring-count")
+                .value_name("N")
+                .value_parser(value_parser!(u64).range(2..))
+                .default_value("3")
+                .help("Deposits just under the threshold that make a pattern"),
+        )
+        .arg(
+            Arg::new("structuring-window")
+                .long("structuring-window")
+                .value_name("ROWS")
+                .value_parser(value_parser!(u64).range(1..))
+                .default_value("10000")
+                .help("...when they fall within this many rows"),
+        )
+        .arg(
+            Arg::new("structuring-output")
+                .long("structuring-output")
+                .value_name("FILE")
+                .requires("structuring")
+                .help("Write flagged clients and the supporting deposit tx ids to FILE as CSV"),
+        )
+        .arg(
+            Arg::new("anonymize")
+                .long("anonymize")
+                .action(ArgAction::SetTrue)
+                .requires("salt-file")
+                .help("Replace client ids with salted pseudonyms in every output (ingest filters and --client still take real ids)"),
+        )
+        .arg(
+            Arg::new("salt-file")
+                .long("salt-file")
+                .value_name("FILE")
+                .requires("anonymize")
+                .help("Secret salt for --anonymize; the same salt gives the same pseudonyms"),
+        )
+        .arg(
+            Arg::new("case-dir")
+                .long("case-dir")
+                .value_name("DIR")
+                .help("Write a JSON case file (account, alerts, transactions) per alerted client into DIR"),
+        )
+        .arg(
+            Arg::new("retain-last")
+                .long("retain-last")
+                .value_name("N")
+                .value_parser(value_parser!(u64).range(1..))
+                .requires("case-dir")
+                .help("Keep only each client's latest N transactions and N alerts for --case-dir"),
+        )
+        .arg(
+            Arg::new("retain-rows")
+                .long("retain-rows")
+                .value_name("ROWS")
+                .value_parser(value_parser!(u64).range(1..))
+                .requires("case-dir")
+                .help("Keep only --case-dir transactions from the latest ROWS rows"),
+        )
+        .arg(
+            Arg::new("anomaly")
+                .long("anomaly")
+                .value_name("CLASS=ACTION")
+                .action(ArgAction::Append)
+                .value_parser(anomaly::parse_override)
+                .help("Handle an anomaly class (e.g. insufficient-funds) with ignore, log, record or fatal (repeatable)"),
+        )
+        .arg(
+            Arg::new("rejects")
+                .long("rejects")
+                .value_name("FILE")
+                .help("Write rows rejected under the `record` action, and normalized rows, to FILE as CSV"),
+        )
+        .arg(
+            Arg::new("normalize-negative")
+                .long("normalize-negative")
+                .action(ArgAction::SetTrue)
+                .help("Apply negative deposits as withdrawals and negative withdrawals as deposits"),
+        )
+        .arg(
+            Arg::new("stats")
+                .long("stats")
+                .action(ArgAction::SetTrue)
+                .help("Log approximate input statistics (distinct clients and tx ids, heaviest clients) at the end"),
+        )
+        .arg(
+            Arg::new("ingest-summary")
+                .long("ingest-summary")
+                .value_name("FILE")
+                .help("Write rows, bytes, throughput, rows per type and rejects by reason to FILE as JSON"),
+        )
+        .arg(
+            Arg::new("check-invariants")
+                .long("check-invariants")
+                .action(ArgAction::SetTrue)
+                .help("Re-check balances after every row and stop at the first one breaking an invariant"),
+        )
+        .arg(
+            Arg::new("gc-deposits")
+                .long("gc-deposits")
+                .action(ArgAction::SetTrue)
+                .help("Drop charged-back deposits, keeping only their ids, to bound memory"),
+        )
+        .arg(
+            Arg::new("defer-unknown-disputes")
+                .long("defer-unknown-disputes")
+                .action(ArgAction::SetTrue)
+                .help("Keep disputes, resolves and chargebacks naming an unseen tx until it arrives; reject the rest at the end"),
+        )
+        .arg(
+            Arg::new("unmatched-disputes")
+                .long("unmatched-disputes")
+                .value_name("FILE")
+                .requires("defer-unknown-disputes")
+                .help("Write the deferred rows whose tx never arrived to FILE as CSV"),
+        )
+        .arg(
+            Arg::new("flag-on")
+                .long("flag-on")
+                .value_name("EVENT=FLAG")
+                .action(ArgAction::Append)
+                .value_parser(|s: &str| s.parse::<AlertFlag>())
+                .help("Flag the client of every EVENT alert (e.g. structuring=manual-review) (repeatable)"),
+        )
+        .arg(
+            Arg::new("report-flags")
+                .long("report-flags")
+                .action(ArgAction::SetTrue)
+                .help("Add flags and notes columns to the accounts report"),
+        )
+        .arg(
+            Arg::new("columns")
+                .long("columns")
+                .value_name("LIST")
+                .value_parser(|s: &str| s.parse::<Columns>())
+                .help("Columns of the accounts report, in order, e.g. client,total,locked [default: client,available,held,total,locked; also currency, state, flags, notes, open_dispute_count]"),
+        )
+        .arg(
+            Arg::new("currency")
+                .long("currency")
+                .value_name("CODE")
+                .help("Value of the currency report column"),
+        )
+        .arg(
+            Arg::new("holds-output")
+                .long("holds-output")
+                .value_name("FILE")
+                .help("Write active and expired authorizations to FILE as CSV"),
+        )
+        .arg(
+            Arg::new("refunds-output")
+                .long("refunds-output")
+                .value_name("FILE")
+                .help("Write refunded withdrawals and their refund totals to FILE as CSV"),
+        )
+        .arg(
+            Arg::new("buckets-output")
+                .long("buckets-output")
+                .value_name("FILE")
+                .help("Write the per-bucket balances of clients with buckets other than main to FILE as CSV"),
+        )
+        .arg(
+            Arg::new("credit-output")
+                .long("credit-output")
+                .value_name("FILE")
+                .help("Write the credit line of every client with a credit limit to FILE as CSV"),
+        )
+        .arg(
+            Arg::new("promos-output")
+                .long("promos-output")
+                .value_name("FILE")
+                .help("Write every promo credit with what was used, expired unused and is left to FILE as CSV"),
+        )
+        .arg(
+            Arg::new("denylist")
+                .long("denylist")
+                .value_name("FILE")
+                .help("Block every row of the client ids listed in FILE (one per line, # comments) before processing"),
+        )
+        .arg(
+            Arg::new("screening-output")
+                .long("screening-output")
+                .value_name("FILE")
+                .requires("denylist")
+                .help("Write the rows blocked by --denylist to FILE as CSV"),
+        )
+        .arg(
+            Arg::new("chargebacks-output")
+                .long("chargebacks-output")
+                .value_name("FILE")
+                .help("Write every applied chargeback with its original tx, amount and dispute open/close rows to FILE as CSV"),
+        )
+        .arg(
+            Arg::new("pg-script")
+                .long("pg-script")
+                .value_name("FILE")
+                .help("Write a psql script upserting the accounts and appending a journal of applied rows, in one transaction"),
+        )
+        .arg(
+            Arg::new("cdc-out")
+                .long("cdc-out")
+                .value_name("FILE")
+                .help("Write one JSON record per applied row with the account before and after it (NDJSON)"),
+        )
+        .arg(
+            Arg::new("trace-client")
+                .long("trace-client")
+                .num_args(2)
+                .value_names(["CLIENT", "FILE"])
+                .help("Write every row of CLIENT, applied or rejected, with its account before and after, to FILE (NDJSON)"),
+        )
+        .arg(
+            Arg::new("redis-out")
+                .long("redis-out")
+                .value_name("FILE")
+                .help("Stream each client's balances after every applied row as Redis HSET commands (for `redis-cli --pipe`) to FILE"),
+        )
+        .arg(
+            Arg::new("escrow-clients")
+                .long("escrow-clients")
+                .value_name("FILE")
+                .help("Hold deposits of clients listed in FILE in escrow until `release` rows free them"),
+        )
+        .arg(
+            Arg::new("credit-limits")
+                .long("credit-limits")
+                .value_name("FILE")
+                .help("Let the clients in FILE (`client,limit` lines) draw past their deposits up to their limit"),
+        )
+        .arg(
+            Arg::new("credit-interest")
+                .long("credit-interest")
+                .value_name("RATE")
+                .requires("credit-limits")
+                .value_parser(parse_amount)
+                .help("Accrue simple interest at annual RATE (0.18 for 18%) on drawn credit, by the --clock time"),
+        )
+        .arg(
+            Arg::new("hold-expiry")
+                .long("hold-expiry")
+                .value_name("ROWS")
+                .value_parser(value_parser!(u64).range(1..))
+                .help("Release authorizations not captured or released within ROWS further rows"),
+        )
+        .arg(
+            Arg::new("hold-expiry-secs")
+                .long("hold-expiry-secs")
+                .value_name("SECS")
+                .value_parser(value_parser!(u64).range(1..))
+                .help("Release authorizations not captured or released within SECS of --clock time"),
+        )
+        .arg(
+            Arg::new("promo-expiry-secs")
+                .long("promo-expiry-secs")
+                .value_name("SECS")
+                .value_parser(value_parser!(u64).range(1..))
+                .help("Take back what is left of a promo credit SECS of --clock time after it was granted"),
+        )
+        .arg(
+            Arg::new("clock")
+                .long("clock")
+                .value_name("SOURCE")
+                .value_parser(parse_clock)
+                .default_value("wall")
+                .help("Time for time-based rules: wall, fixed:TIMESTAMP, or input (the `timestamp` column)"),
+        )
+        .arg(
+            Arg::new("max-row-len")
+                .long("max-row-len")
+                .value_name("BYTES")
+                .value_parser(value_parser!(usize))
+                .default_value(RowLimits::default().max_row_len.to_string())
+                .help("Skip CSV rows longer than this, reporting them as rejects"),
+        )
+        .arg(
+            Arg::new("max-field-len")
+                .long("max-field-len")
+                .value_name("BYTES")
+                .value_parser(value_parser!(usize))
+                .default_value(RowLimits::default().max_field_len.to_string())
+                .help("Skip CSV rows with a field longer than this"),
+        )
+        .arg(
+            Arg::new("max-columns")
+                .long("max-columns")
+                .value_name("N")
+                .value_parser(value_parser!(usize))
+                .default_value(RowLimits::default().max_columns.to_string())
+                .help("Skip CSV rows with more fields than this"),
+        )
+        .arg(
+            Arg::new("tenant-dir")
+                .long("tenant-dir")
+                .value_name("DIR")
+                .conflicts_with_all([
+                    "output",
+                    "output-pos",
+                    "load-snapshot",
+                    "save-snapshot",
+                    "baseline",
+                    "manifest",
+                    "rejects",
+                    "holds-output",
+                    "refunds-output",
+                    "buckets-output",
+                    "credit-output",
+                    "promos-output",
+                    "denylist",
+                    "batch-summaries",
+                    "chargebacks-output",
+                    "pg-script",
+                    "redis-out",
+                    "cdc-out",
+                    "trace-client",
+                    "defer-unknown-disputes",
+                    "unmatched-disputes",
+                    "report-flags",
+                    "columns",
+                    "ingest-summary",
+                    // one file per run, not per tenant
+                    "notify",
+                    "case-dir",
+                ])
+                .help("Route rows by their `tenant` column; write DIR/accounts-<tenant>.csv each"),
+        )
+        .arg(
+            Arg::new("manifest")
+                .long("manifest")
+                .value_name("FILE")
+                .help("Write a JSON run manifest with SHA-256 of all inputs and outputs"),
+        )
+}
 
+/// `process` subcommand: ingest, then write the report and every requested
+/// side output.
+fn process(matches: &clap::ArgMatches) -> Result<()> {
     // ---------------------------------------------------- positional fallback
-    let pos: Vec<PathBuf> = env::args_os().skip(1).map(PathBuf::from).collect();
-
     let in_path = matches
         .get_one::<String>("input")
-        .map(PathBuf::from)
-        // only clippy::get_first cares about index 0
-        .or_else(|| pos.first().cloned());
+        .or_else(|| matches.get_one::<String>("input-pos"))
+        .map(PathBuf::from);
 
     let out_path = matches
         .get_one::<String>("output")
-        .map(PathBuf::from)
-        .or_else(|| pos.get(1).cloned()); // index 1 is fine
+        .or_else(|| matches.get_one::<String>("output-pos"))
+        .map(PathBuf::from);
 
-    let infile = match in_path {
-        Some(p) => File::open(&p)?,
-        None => {
-            eprintln!("Usage: cargo run -- transactions.csv > accounts.csv");
-            std::process::exit(1);
-        }
+    let pseudonyms = pseudonymizer(matches)?;
+    let filter = report_filter(matches, pseudonyms.as_ref());
+    let columns = report_columns(matches)?;
+
+    let Some(in_path) = in_path else {
+        eprintln!("Usage: cargo run -- transactions.csv > accounts.csv");
+        std::process::exit(1);
     };
+    let limits = row_limits(matches);
+    // rows breaking a limit are dropped by the sort, so come back from it
+    let (infile, sort_breaches) = open_input(matches, &in_path, limits)?;
+
+    // record what we read and write when a manifest is requested
+    let mut manifest = matches
+        .get_one::<String>("manifest")
+        .map(|_| RunManifest::new());
+    if let Some(m) = manifest.as_mut() {
+        m.inputs
+            .push(FileDigest::of_file("input", &in_path.to_string_lossy())?);
+        if let Some(p) = matches.get_one::<String>("load-snapshot") {
+            m.inputs.push(FileDigest::of_file("snapshot", p)?);
+        }
+        if let Some(p) = matches.get_one::<String>("config") {
+            m.inputs.push(FileDigest::of_file("config", p)?);
+        }
+        if let Some(p) = matches.get_one::<String>("baseline") {
+            m.inputs.push(FileDigest::of_file("baseline", p)?);
+        }
+    }
 
     // ---------------------------------------------------------------- ingest
-    let mut rdr = ReaderBuilder::new()
-        .trim(csv::Trim::All)
-        .from_reader(infile);
+    // --resume picks up the checkpoint (which already includes any
+    // --load-snapshot state) and skips the rows it covers
+    let checkpoint = matches
+        .get_one::<String>("save-snapshot")
+        .filter(|p| matches.get_flag("resume") && Path::new(p).exists());
+    let (mut engine, input_clock) = open_engine(
+        matches,
+        checkpoint.or(matches.get_one::<String>("load-snapshot")),
+    )?;
+    let config = engine.config().clone();
+    let start = match checkpoint {
+        Some(p) => {
+            info!(row = engine.input_rows(), snapshot = %p, "resuming");
+            engine.input_rows()
+        }
+        None => 0,
+    };
+    let cases = matches.get_one::<String>("case-dir").map(|_| {
+        let cases = CaseRecorder::with_retention(Retention {
+            last: matches.get_one::<u64>("retain-last").map(|&n| n as usize),
+            max_age: matches.get_one::<u64>("retain-rows").copied(),
+        });
+        cases.attach(&mut engine);
+        cases
+    });
+    let trace_path = trace_client(matches, &mut engine, pseudonyms.as_ref())?;
 
-    let mut engine = Engine::new();
-    for (idx, row) in rdr.deserialize().enumerate() {
-        match row {
-            Ok(tx) => engine.process(tx)?,
-            Err(e) => error!(row = idx + 1, %e, "csv-deserialize"),
+    let ingest_filter = ingest_filter(matches)?;
+    let mut screening = screening(matches, pseudonyms.as_ref())?;
+
+    shutdown::install()?;
+    let mut stats = matches.get_flag("stats").then(|| StreamStats::new(5));
+    let input_bytes = infile.metadata()?.len();
+    let rows = input_rows(matches, infile, limits, input_clock.clone())?.inspect(|row| {
+        if let (Some(stats), Ok(row)) = (stats.as_mut(), row) {
+            stats.observe(&row.tx);
+        }
+    });
+    if let Some(dir) = matches.get_one::<String>("tenant-dir") {
+        // every tenant engine is set up like the one above, on one clock
+        let mut router = TenantRouter::with_config(config);
+        let flags = matches.clone();
+        router
+            .on_new_tenant(move |_, engine| configure_engine(&flags, engine, input_clock.as_ref()));
+        let ingested = ingest(
+            &mut router,
+            rows,
+            start,
+            &ingest_filter,
+            pseudonyms.as_ref(),
+            screening.as_mut(),
+            |_, _| Ok(()),
+        )?;
+        log_stats(stats.as_ref(), pseudonyms.as_ref());
+        return run_tenants(router, dir.as_ref(), &filter, &ingested);
+    }
+
+    let mut streams = Streams::open(matches, &mut engine, start)?;
+    let ingested = ingest(
+        &mut engine,
+        rows,
+        start,
+        &ingest_filter,
+        pseudonyms.as_ref(),
+        screening.as_mut(),
+        |engine, step| streams.step(engine, step),
+    )?;
+    let (filtered, interrupted) = (ingested.filtered, ingested.interrupted);
+    engine.set_input_rows(ingested.consumed);
+    // an interrupted run did not reach the end of its input: rows still
+    // waiting go into the snapshot for --resume instead
+    let unmatched = match interrupted {
+        None => engine.close_deferred()?,
+        Some(_) => Vec::new(),
+    };
+    let done = engine.finalize()?;
+    info!(
+        "Finished ingest: {} accounts, {filtered} rows skipped by ingest filters, state {}",
+        done.accounts, done.state_hash
+    );
+    let mut tally = ingested.tally;
+    sort_breaches
+        .iter()
+        .for_each(|b| tally.unparsable(b.kind.as_str()));
+    let summary = tally.finish(input_bytes);
+    info!(
+        rows = summary.rows,
+        bytes = summary.bytes,
+        applied = summary.applied(),
+        rejected = summary.rejects.values().sum::<u64>(),
+        rows_per_sec = summary.rows_per_sec as u64,
+        wall_secs = %format_args!("{:.3}", summary.wall_secs),
+        "ingest summary"
+    );
+    if let Some(p) = matches.get_one::<String>("ingest-summary") {
+        let mut out = BufWriter::new(File::create(p)?);
+        serde_json::to_writer_pretty(&mut out, &summary)?;
+        out.flush()?;
+        info!("ingest summary → {p}");
+        if let Some(m) = manifest.as_mut() {
+            m.outputs.push(FileDigest::of_file("ingest-summary", p)?);
+        }
+    }
+    log_stats(stats.as_ref(), pseudonyms.as_ref());
+    if config.gc_deposits {
+        info!(
+            reclaimed = done.reclaimed_deposits,
+            "charged-back deposits reclaimed"
+        );
+    }
+    if !unmatched.is_empty() {
+        warn!(
+            rows = unmatched.len(),
+            "deferred rows never matched a transaction"
+        );
+    }
+    if let Some(p) = matches.get_one::<String>("unmatched-disputes") {
+        let n = report::write_unmatched(&unmatched, File::create(p)?)?;
+        info!("{n} unmatched rows → {p}");
+        if let Some(m) = manifest.as_mut() {
+            m.outputs.push(FileDigest::of_file("unmatched", p)?);
+        }
+    }
+    if let Some(m) = manifest.as_mut() {
+        m.state_hash = Some(done.state_hash);
+    }
+
+    if let Some(p) = matches.get_one::<String>("save-snapshot") {
+        write_snapshot_atomic(&engine, p.as_ref())?;
+        if let Some(m) = manifest.as_mut() {
+            m.outputs.push(FileDigest::of_file("snapshot", p)?);
         }
     }
-    info!("Finished ingest: {} accounts", engine.accounts.len());
 
     // ---------------------------------------------------------------- emit
-    let sink: Box<dyn Write> = match out_path {
+    let sink: Box<dyn Write> = match &out_path {
         Some(p) => Box::new(File::create(p)?),
         None => Box::new(io::stdout()),
     };
+    let mut sink = HashingWriter::new(sink);
+    columns::write_accounts(&engine, &filter, &columns, &mut sink)?;
+    if let Some(m) = manifest.as_mut() {
+        let path = out_path.map_or(STDOUT.into(), |p| p.to_string_lossy().into_owned());
+        m.outputs
+            .push(FileDigest::from_hasher("report", &path, sink.finish().1));
+    }
+
+    let breaches = [sort_breaches, ingested.breaches].concat();
+    write_side_reports(matches, &engine, &breaches, &mut manifest)?;
 
-    let mut wtr = WriterBuilder::new().has_headers(true).from_writer(sink);
+    // -------------------------------------------------------------- screening
+    if let Some(screening) = &screening {
+        let blocked = screening.hits().len();
+        if blocked > 0 {
+            warn!(blocked, "rows of denylisted clients blocked");
+        }
+        if let Some(p) = matches.get_one::<String>("screening-output") {
+            let n = report::write_screening(screening, File::create(p)?)?;
+            info!("{n} blocked rows → {p}");
+            if let Some(m) = manifest.as_mut() {
+                m.outputs.push(FileDigest::of_file("screening", p)?);
+            }
+        }
+    }
 
-    // header row (no needless borrow)
-    wtr.write_record(["client", "available", "held", "total", "locked"])?;
+    streams.finish(matches, &engine, &filter, &mut manifest)?;
 
-    let mut clients: Vec<_> = engine.accounts.iter().collect();
-    clients.sort_by_key(|(id, _)| *id);
+    // ------------------------------------------------------------------ trace
+    if let (Some(trace), Some(p)) = (engine.set_trace(None), trace_path) {
+        info!(
+            client = trace.client(),
+            "{} traced rows → {p}",
+            trace.rows()
+        );
+        if let Some(m) = manifest.as_mut() {
+            m.outputs.push(FileDigest::of_file("trace", p)?);
+        }
+    }
 
-    for (id, acc) in clients {
-        wtr.write_record(&[
-            id.to_string(),
-            format!("{:.4}", acc.available),
-            format!("{:.4}", acc.held),
-            format!("{:.4}", acc.total()),
-            acc.locked.to_string(),
-        ])?;
+    // ------------------------------------------------------------------ cases
+    if let (Some(cases), Some(dir)) = (&cases, matches.get_one::<String>("case-dir")) {
+        let written = cases.export(&engine, dir.as_ref())?;
+        info!("{} case files → {dir}", written.len());
+        if let Some(m) = manifest.as_mut() {
+            for path in &written {
+                m.outputs
+                    .push(FileDigest::of_file("case", &path.display().to_string())?);
+            }
+        }
     }
-    wtr.flush()?;
-    Ok(())
+
+    if let (Some(m), Some(p)) = (manifest, matches.get_one::<String>("manifest")) {
+        m.write(p.as_ref())?;
+    }
+    if let Some(rows) = interrupted {
+        anyhow::bail!("interrupted after {rows} input rows; outputs reflect the partial ingest");
+    }
+    Ok(())
+}
+
+/// Log the `--stats` input statistics, clients as they appear in the
+/// outputs.
+fn log_stats(stats: Option<&StreamStats>, pseudonyms: Option<&Pseudonymizer>) {
+    let Some(stats) = stats else {
+        return;
+    };
+    let alias = |id| pseudonyms.map_or(id, |p| p.client(id));
+    let heaviest: Vec<String> = stats
+        .heavy_hitters()
+        .iter()
+        .map(|&(client, rows)| format!("{} (~{rows})", alias(client)))
+        .collect();
+    info!(
+        rows = stats.rows(),
+        clients = stats.distinct_clients(),
+        tx_ids = format!("~{}", stats.distinct_txs()),
+        heaviest = heaviest.join(", "),
+        "input statistics"
+    );
+}
+
+/// `--input-format binary` as given to any subcommand (the flag is global).
+fn is_binary(matches: &clap::ArgMatches) -> bool {
+    matches
+        .get_one::<String>("input-format")
+        .map(String::as_str)
+        == Some("binary")
+}
+
+/// Text encoding of the input rows: a sorted input is already re-encoded
+/// as UTF-8.
+fn row_encoding(matches: &clap::ArgMatches) -> Encoding {
+    match matches.contains_id("sort-by") {
+        true => Encoding::Utf8,
+        false => input_encoding(matches),
+    }
+}
+
+/// The `--anonymize` pseudonyms, from `--salt-file`.
+fn pseudonymizer(matches: &clap::ArgMatches) -> Result<Option<Pseudonymizer>> {
+    matches
+        .get_one::<String>("salt-file")
+        .map(|p| Pseudonymizer::from_salt_file(p.as_ref()))
+        .transpose()
+}
+
+/// The `--denylist` screening; the list is mapped like `--client`, since
+/// rows are screened after pseudonymization.
+fn screening(
+    matches: &clap::ArgMatches,
+    pseudonyms: Option<&Pseudonymizer>,
+) -> Result<Option<Screening>> {
+    matches
+        .get_one::<String>("denylist")
+        .map(|p| -> Result<_> {
+            let ids: HashSet<u16> = read_id_list(p.as_ref())?;
+            Ok(Screening::new(match pseudonyms {
+                Some(ps) => ids.into_iter().map(|id| ps.client(id)).collect(),
+                None => ids,
+            }))
+        })
+        .transpose()
+}
+
+/// The accounts report filter of `--only-locked`, `--client`, `--min-total`
+/// and `--changed-only`; `--client` ids are mapped to their pseudonyms.
+fn report_filter(matches: &clap::ArgMatches, pseudonyms: Option<&Pseudonymizer>) -> ReportFilter {
+    ReportFilter {
+        only_locked: matches.get_flag("only-locked"),
+        clients: matches
+            .get_many::<u16>("client")
+            .map(|ids| {
+                ids.map(|&id| pseudonyms.map_or(id, |p| p.client(id)))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        min_total: matches.get_one::<Decimal>("min-total").copied(),
+        changed_only: matches.get_flag("changed-only"),
+    }
+}
+
+/// The accounts report columns of `--columns`, `--report-flags` and
+/// `--currency`.
+fn report_columns(matches: &clap::ArgMatches) -> Result<Columns> {
+    let mut columns = matches
+        .get_one::<Columns>("columns")
+        .cloned()
+        .unwrap_or_default();
+    if matches.get_flag("report-flags") {
+        columns = columns.with(Column::Flags).with(Column::Notes);
+    }
+    if let Some(code) = matches.get_one::<String>("currency") {
+        columns = columns.currency(code);
+    }
+    if columns.contains(Column::Currency) && !matches.contains_id("currency") {
+        anyhow::bail!("--columns currency needs --currency CODE");
+    }
+    Ok(columns)
+}
+
+/// `--max-field-len`, `--max-row-len` and `--max-columns`.
+fn row_limits(matches: &clap::ArgMatches) -> RowLimits {
+    RowLimits {
+        max_field_len: *matches.get_one::<usize>("max-field-len").unwrap(),
+        max_row_len: *matches.get_one::<usize>("max-row-len").unwrap(),
+        max_columns: *matches.get_one::<usize>("max-columns").unwrap(),
+    }
+}
+
+/// The input at `path`, sorted by `--sort-by` or reordered by `--two-pass`
+/// if asked, with the rows the sort left out for breaking `limits`.
+fn open_input(
+    matches: &clap::ArgMatches,
+    path: &Path,
+    limits: RowLimits,
+) -> Result<(File, Vec<Breach>)> {
+    Ok(match matches.get_one::<String>("sort-by") {
+        Some(_) if is_binary(matches) => anyhow::bail!("--sort-by needs CSV input"),
+        Some(column) => sorted_input(
+            path,
+            input_encoding(matches),
+            limits,
+            column,
+            matches.get_one::<String>("sort-dir"),
+        )?,
+        None if matches.get_flag("two-pass") => {
+            if is_binary(matches)
+                || matches!(
+                    input_encoding(matches),
+                    Encoding::Utf16Le | Encoding::Utf16Be
+                )
+            {
+                anyhow::bail!("--two-pass needs UTF-8 CSV input")
+            }
+            (two_pass_input(path)?, Vec::new())
+        }
+        None => (File::open(path)?, Vec::new()),
+    })
+}
+
+/// The [`EngineConfig`] of the engine flags: expiries, anomaly handling,
+/// amount limits, escrow, credit and batch rules.
+fn engine_config(matches: &clap::ArgMatches) -> Result<EngineConfig> {
+    let mut config = EngineConfig {
+        hold_expiry: matches.get_one::<u64>("hold-expiry").copied(),
+        hold_expiry_secs: matches.get_one::<u64>("hold-expiry-secs").copied(),
+        promo_expiry_secs: matches.get_one::<u64>("promo-expiry-secs").copied(),
+        normalize_negative: matches.get_flag("normalize-negative"),
+        check_invariants: matches.get_flag("check-invariants"),
+        gc_deposits: matches.get_flag("gc-deposits"),
+        defer_unknown_disputes: matches.get_flag("defer-unknown-disputes"),
+        alert_flags: matches
+            .get_many::<AlertFlag>("flag-on")
+            .map(|rules| rules.cloned().collect())
+            .unwrap_or_default(),
+        escrow_clients: matches
+            .get_one::<String>("escrow-clients")
+            .map(|p| read_id_list(p.as_ref()))
+            .transpose()?
+            .unwrap_or_default(),
+        credit_limits: matches
+            .get_one::<String>("credit-limits")
+            .map(|p| credit::read_limits(p.as_ref()))
+            .transpose()?
+            .unwrap_or_default(),
+        credit_interest: matches.get_one::<Decimal>("credit-interest").copied(),
+        batch_rules: BatchRules {
+            max_disputes: matches.get_one::<u32>("batch-max-disputes").copied(),
+        },
+        ..Default::default()
+    };
+    if let Some(limits) = matches.get_many::<(Option<TxType>, Decimal)>("max-amount") {
+        for (kind, max) in limits {
+            config.max_amounts.set(*kind, *max);
+        }
+        // rows this far off are worth a look; --anomaly below can still say otherwise
+        let action = match matches.contains_id("rejects") {
+            true => Action::Record,
+            false => Action::Log,
+        };
+        config.anomalies.set(Anomaly::AmountTooLarge, action);
+    }
+    for (class, action) in matches
+        .get_many::<(Anomaly, Action)>("anomaly")
+        .into_iter()
+        .flatten()
+    {
+        config.anomalies.set(*class, *action);
+    }
+    Ok(config)
+}
+
+/// The engine the flags describe: read from `snapshot` if given, with the
+/// [`engine_config`] and set up by [`configure_engine`], whose input clock
+/// comes along.
+fn open_engine(
+    matches: &clap::ArgMatches,
+    snapshot: Option<&String>,
+) -> Result<(Engine, Option<ManualClock>)> {
+    let mut engine = match snapshot {
+        Some(p) => Engine::read_snapshot(BufReader::new(File::open(p)?))?,
+        None => Engine::new(),
+    };
+    engine.set_config(engine_config(matches)?);
+    let input_clock = input_clock(matches);
+    configure_engine(matches, &mut engine, input_clock.as_ref())?;
+    Ok((engine, input_clock))
+}
+
+/// The clock to move forward as rows are read, for `--clock input`.
+fn input_clock(matches: &clap::ArgMatches) -> Option<ManualClock> {
+    match matches.get_one::<ClockSource>("clock").unwrap() {
+        ClockSource::Input => Some(ManualClock::default()),
+        _ => None,
+    }
+}
+
+/// Set `engine`'s clock (`input_clock` under `--clock input`), alert sinks,
+/// held-funds alert and structuring rule from the flags.
+fn configure_engine(
+    matches: &clap::ArgMatches,
+    engine: &mut Engine,
+    input_clock: Option<&ManualClock>,
+) -> Result<()> {
+    match (
+        *matches.get_one::<ClockSource>("clock").unwrap(),
+        input_clock,
+    ) {
+        (ClockSource::Fixed(at), _) => engine.set_clock(Box::new(FixedClock(at))),
+        (ClockSource::Input, Some(clock)) => engine.set_clock(Box::new(clock.clone())),
+        _ => {}
+    }
+    for spec in matches.get_many::<String>("notify").into_iter().flatten() {
+        engine.add_sink(open_sink(spec)?);
+    }
+    engine.set_held_alert(matches.get_one::<Decimal>("held-alert").copied());
+    engine.set_structuring(matches.get_one::<Decimal>("structuring").map(|&threshold| {
+        StructuringRule {
+            floor: matches
+                .get_one::<Decimal>("structuring-floor")
+                .copied()
+                .unwrap_or(StructuringRule::new(threshold).floor),
+            count: *matches.get_one::<u64>("structuring-count").unwrap() as usize,
+            window: *matches.get_one::<u64>("structuring-window").unwrap(),
+            threshold,
+        }
+    }));
+    Ok(())
+}
+
+/// Start `--trace-client CLIENT FILE` on `engine`; returns the trace file.
+fn trace_client<'a>(
+    matches: &'a clap::ArgMatches,
+    engine: &mut Engine,
+    pseudonyms: Option<&Pseudonymizer>,
+) -> Result<Option<&'a String>> {
+    let Some(spec) = matches.get_many::<String>("trace-client") else {
+        return Ok(None);
+    };
+    let [id, path] = <[&String; 2]>::try_from(spec.collect::<Vec<_>>())
+        .map_err(|_| anyhow::anyhow!("--trace-client takes CLIENT FILE"))?;
+    let client: u16 = id
+        .parse()
+        .map_err(|e| anyhow::anyhow!("--trace-client {id}: {e}"))?;
+    // traced under its pseudonym, like --denylist ids
+    let client = pseudonyms.map_or(client, |p| p.client(client));
+    let out = io::BufWriter::new(File::create(path)?);
+    engine.set_trace(Some(ClientTrace::new(client, out)));
+    Ok(Some(path))
+}
+
+/// The ingest filter of `--include-clients`, `--exclude-clients`, `--types`
+/// and `--exclude-txs`.
+fn ingest_filter(matches: &clap::ArgMatches) -> Result<IngestFilter> {
+    Ok(IngestFilter {
+        include_clients: matches
+            .get_one::<String>("include-clients")
+            .map(|p| read_id_list(p.as_ref()))
+            .transpose()?,
+        exclude_clients: matches
+            .get_one::<String>("exclude-clients")
+            .map(|p| read_id_list(p.as_ref()))
+            .transpose()?
+            .unwrap_or_default(),
+        types: matches
+            .get_many::<TxType>("types")
+            .map(|ts| ts.copied().collect()),
+        exclude_txs: matches
+            .get_one::<String>("exclude-txs")
+            .map(|p| read_id_list(p.as_ref()))
+            .transpose()?
+            .unwrap_or_default(),
+    })
+}
+
+/// [`read_input_rows`] of `src` (as opened by [`open_input`]) in the input
+/// format and under `--amount-locale`, moving `input_clock` (see
+/// [`configure_engine`]) to each row's timestamp before it is processed.
+fn input_rows(
+    matches: &clap::ArgMatches,
+    src: File,
+    limits: RowLimits,
+    input_clock: Option<ManualClock>,
+) -> Result<impl Iterator<Item = Result<InputRow>>> {
+    let amounts = matches
+        .get_one::<String>("amount-locale")
+        .map_or(Ok(AmountLocale::Plain), |l| l.parse())
+        .map_err(anyhow::Error::msg)?;
+    let (binary, encoding) = (is_binary(matches), row_encoding(matches));
+    Ok(
+        read_input_rows(src, binary, encoding, limits, amounts)?.map(move |row| {
+            // the clock is read while the row is processed, so set it first
+            let row = row?;
+            if let (Some(clock), Some(ts)) = (&input_clock, &row.timestamp) {
+                let at =
+                    parse_timestamp(ts).ok_or_else(|| anyhow::anyhow!("bad timestamp {ts:?}"))?;
+                clock.advance(at);
+            }
+            Ok(row)
+        }),
+    )
+}
+
+/// Outputs written while rows are ingested: checkpoints, batch summaries,
+/// and those following the applied rows through [`Engine::watch`].
+struct Streams {
+    checkpointer: Option<Checkpointer>,
+    batches: Option<SummaryWriter<BufWriter<File>>>,
+    // the journal streams out with the rows; accounts follow at the end
+    pg: Option<(PgScript<BufWriter<File>>, Watch)>,
+    redis: Option<(RedisPublisher<BufWriter<File>>, Watch)>,
+    cdc: Option<(CdcWriter<BufWriter<File>>, Watch)>,
+    chargebacks: Option<(ChargebackLog, Watch)>,
+}
+
+impl Streams {
+    /// The streams the flags ask for, watching `engine` from input position
+    /// `start`.
+    fn open(matches: &clap::ArgMatches, engine: &mut Engine, start: u64) -> Result<Self> {
+        Ok(Self {
+            checkpointer: matches
+                .get_one::<Interval>("snapshot-every")
+                .zip(matches.get_one::<String>("save-snapshot"))
+                .map(|(every, path)| Checkpointer::new(*every, path.as_ref()).starting_at(start)),
+            batches: matches
+                .get_one::<String>("batch-summaries")
+                .map(|p| SummaryWriter::new(BufWriter::new(File::create(p)?)))
+                .transpose()?,
+            pg: matches
+                .get_one::<String>("pg-script")
+                .map(|p| -> Result<_> {
+                    let script = PgScript::new(BufWriter::new(File::create(p)?))?;
+                    Ok((script, engine.watch(|_| true)))
+                })
+                .transpose()?,
+            redis: matches
+                .get_one::<String>("redis-out")
+                .map(|p| -> Result<_> {
+                    let publisher =
+                        RedisPublisher::new(BufWriter::new(stream_file(p, start)?), "balance:");
+                    Ok((publisher, engine.watch(|_| true)))
+                })
+                .transpose()?,
+            cdc: matches
+                .get_one::<String>("cdc-out")
+                .map(|p| -> Result<_> {
+                    let out = BufWriter::new(stream_file(p, start)?);
+                    let writer = CdcWriter::new(out, Replica::from_engine(engine));
+                    Ok((writer, engine.watch(|_| true)))
+                })
+                .transpose()?,
+            chargebacks: matches
+                .contains_id("chargebacks-output")
+                .then(|| (ChargebackLog::new(), engine.watch(|_| true))),
+        })
+    }
+
+    /// Catch up after an ingest [`Step`]: summarize an ended batch, take a
+    /// checkpoint if one is due, and pass on the rows applied since the last
+    /// step.
+    fn step(&mut self, engine: &mut Engine, step: Step) -> Result<()> {
+        let consumed = match step {
+            Step::Row(consumed) => consumed,
+            Step::BatchEnd(label, consumed) => {
+                let summary = engine.end_batch()?;
+                // without a batch column the whole input is one batch
+                if !label.is_empty() {
+                    info!(
+                        batch = summary.batch,
+                        label,
+                        applied = summary.applied,
+                        rejected = summary.rejected,
+                        flagged = summary.flagged.len(),
+                        "batch ended"
+                    );
+                }
+                if let Some(writer) = self.batches.as_mut() {
+                    writer.write(label, &summary)?;
+                }
+                if let Some(cp) = self.checkpointer.as_mut()
+                    && cp.at_batch_ends()
+                {
+                    engine.set_input_rows(consumed);
+                    cp.write(engine, consumed)?;
+                }
+                return Ok(());
+            }
+        };
+        if let Some(cp) = self.checkpointer.as_mut()
+            && cp.due(consumed)
+        {
+            engine.set_input_rows(consumed);
+            cp.write(engine, consumed)?;
+        }
+        if let Some((script, feed)) = self.pg.as_mut() {
+            feed.pending().try_for_each(|d| script.journal(&d))?;
+        }
+        if let Some((log, feed)) = self.chargebacks.as_mut() {
+            feed.pending().for_each(|d| log.observe(&d));
+        }
+        if let Some((writer, feed)) = self.cdc.as_mut() {
+            feed.pending().try_for_each(|d| writer.record(&d))?;
+        }
+        if let Some((publisher, feed)) = self.redis.as_mut() {
+            for d in feed.pending() {
+                publisher.publish(d.client, &engine.accounts[&d.client])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Pass on the last applied rows, then complete and flush every stream,
+    /// recording its file in `manifest`.
+    fn finish(
+        self,
+        matches: &clap::ArgMatches,
+        engine: &Engine,
+        filter: &ReportFilter,
+        manifest: &mut Option<RunManifest>,
+    ) -> Result<()> {
+        // ------------------------------------------------------------ chargebacks
+        if let (Some((mut log, feed)), Some(p)) = (
+            self.chargebacks,
+            matches.get_one::<String>("chargebacks-output"),
+        ) {
+            feed.pending().for_each(|d| log.observe(&d));
+            let n = report::write_chargebacks(engine, &log, File::create(p)?)?;
+            info!("{n} chargebacks → {p}");
+            if let Some(m) = manifest.as_mut() {
+                m.outputs.push(FileDigest::of_file("chargebacks", p)?);
+            }
+        }
+
+        // --------------------------------------------------------------- postgres
+        if let (Some((mut script, feed)), Some(p)) =
+            (self.pg, matches.get_one::<String>("pg-script"))
+        {
+            feed.pending().try_for_each(|d| script.journal(&d))?;
+            let (_, (journal, accounts)) = script.finish(engine, filter)?;
+            info!("{journal} journal rows and {accounts} accounts → {p}");
+            if let Some(m) = manifest.as_mut() {
+                m.outputs.push(FileDigest::of_file("pg-script", p)?);
+            }
+        }
+
+        // -------------------------------------------------------------------- cdc
+        if let (Some((mut writer, feed)), Some(p)) =
+            (self.cdc, matches.get_one::<String>("cdc-out"))
+        {
+            feed.pending().try_for_each(|d| writer.record(&d))?;
+            writer.flush()?;
+            info!("{} change records → {p}", writer.written());
+            if let Some(m) = manifest.as_mut() {
+                m.outputs.push(FileDigest::of_file("cdc", p)?);
+            }
+        }
+
+        // ------------------------------------------------------------------ redis
+        if let (Some((mut publisher, feed)), Some(p)) =
+            (self.redis, matches.get_one::<String>("redis-out"))
+        {
+            for d in feed.pending() {
+                publisher.publish(d.client, &engine.accounts[&d.client])?;
+            }
+            publisher.flush()?;
+            info!("{} balance updates → {p}", publisher.published());
+            if let Some(m) = manifest.as_mut() {
+                m.outputs.push(FileDigest::of_file("redis", p)?);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Open the stream output `path`. A run resumed from input row `start`
+/// appends: the file already holds the records of the rows before it.
+fn stream_file(path: &str, start: u64) -> Result<File> {
+    Ok(OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(start > 0)
+        .truncate(start == 0)
+        .open(path)?)
+}
+
+/// Write the reports drawn from the final engine state: delta, rejects,
+/// holds, refunds, buckets, credit, promos and structuring, recording each
+/// file in `manifest`. `breaches` are the rows skipped for their size.
+fn write_side_reports(
+    matches: &clap::ArgMatches,
+    engine: &Engine,
+    breaches: &[Breach],
+    manifest: &mut Option<RunManifest>,
+) -> Result<()> {
+    // ---------------------------------------------------------------- delta
+    if let Some(base) = matches.get_one::<String>("baseline") {
+        let baseline = report::read_accounts(File::open(base)?)?;
+        let delta_path = matches
+            .get_one::<String>("delta-output")
+            .map_or("delta.csv", String::as_str);
+        let changed = report::write_delta(engine, &baseline, File::create(delta_path)?)?;
+        info!("Delta vs {base}: {changed} accounts changed → {delta_path}");
+        if let Some(m) = manifest.as_mut() {
+            m.outputs.push(FileDigest::of_file("delta", delta_path)?);
+        }
+    }
+
+    // ---------------------------------------------------------------- rejects
+    if let Some(p) = matches.get_one::<String>("rejects") {
+        let n = report::write_rejections(engine, breaches, File::create(p)?)?;
+        info!("{n} rejected or normalized rows recorded → {p}");
+        if let Some(m) = manifest.as_mut() {
+            m.outputs.push(FileDigest::of_file("rejects", p)?);
+        }
+    }
+
+    // ---------------------------------------------------------------- holds
+    if let Some(p) = matches.get_one::<String>("holds-output") {
+        let n = report::write_holds(engine, File::create(p)?)?;
+        info!("{n} active or expired authorizations → {p}");
+        if let Some(m) = manifest.as_mut() {
+            m.outputs.push(FileDigest::of_file("holds", p)?);
+        }
+    }
+
+    // ---------------------------------------------------------------- refunds
+    if let Some(p) = matches.get_one::<String>("refunds-output") {
+        let n = report::write_refunds(engine, File::create(p)?)?;
+        info!("{n} refunded withdrawals → {p}");
+        if let Some(m) = manifest.as_mut() {
+            m.outputs.push(FileDigest::of_file("refunds", p)?);
+        }
+    }
+
+    // ---------------------------------------------------------------- buckets
+    if let Some(p) = matches.get_one::<String>("buckets-output") {
+        let n = report::write_buckets(engine, File::create(p)?)?;
+        info!("{n} bucket balances → {p}");
+        if let Some(m) = manifest.as_mut() {
+            m.outputs.push(FileDigest::of_file("buckets", p)?);
+        }
+    }
+
+    // ----------------------------------------------------------------- credit
+    if let Some(p) = matches.get_one::<String>("credit-output") {
+        let n = report::write_credit(engine, File::create(p)?)?;
+        info!("{n} credit lines → {p}");
+        if let Some(m) = manifest.as_mut() {
+            m.outputs.push(FileDigest::of_file("credit", p)?);
+        }
+    }
+
+    // ----------------------------------------------------------------- promos
+    if let Some(p) = matches.get_one::<String>("promos-output") {
+        let (n, expired) = report::write_promos(engine, File::create(p)?)?;
+        info!("{n} promo credits, {expired} expired unused → {p}");
+        if let Some(m) = manifest.as_mut() {
+            m.outputs.push(FileDigest::of_file("promos", p)?);
+        }
+    }
+
+    // ------------------------------------------------------------ structuring
+    if let Some(p) = matches.get_one::<String>("structuring-output") {
+        let n = report::write_structuring(engine, File::create(p)?)?;
+        info!("{n} structuring patterns → {p}");
+        if let Some(m) = manifest.as_mut() {
+            m.outputs.push(FileDigest::of_file("structuring", p)?);
+        }
+    }
+    Ok(())
+}
+
+/// `replay` subcommand: replay the input with this build, as `process`
+/// would under the `--config` flags, and report every divergence from the
+/// golden snapshot, or from a replay under the `--against` flags; fails if
+/// there is any.
+fn replay(cli: &Command, sub: &clap::ArgMatches) -> Result<()> {
+    let input = Path::new(sub.get_one::<String>("input").unwrap());
+    let flags = process_flags(cli, sub, sub.get_one::<String>("config"))?;
+    let candidate = replayed(&flags, input)?;
+
+    let (expected, name) = match sub.get_one::<String>("against") {
+        Some(against) => {
+            let flags = process_flags(cli, sub, Some(against))?;
+            (replayed(&flags, input)?, against)
+        }
+        None => {
+            let golden = sub.get_one::<String>("golden").unwrap();
+            let engine = Engine::read_snapshot(BufReader::new(File::open(golden)?))?;
+            (engine, golden)
+        }
+    };
+
+    let diffs = compare::diff(&expected, &candidate);
+    for d in &diffs {
+        println!("{d}");
+    }
+    if !diffs.is_empty() {
+        anyhow::bail!("{} divergence(s) from {name}", diffs.len());
+    }
+    info!("No divergence from {name}");
+    Ok(())
+}
+
+/// The `process` flags of the configuration file at `path` (none without
+/// one) as `process` would parse them, with the global flags given to `sub`
+/// on top.
+fn process_flags(
+    cli: &Command,
+    sub: &clap::ArgMatches,
+    path: Option<&String>,
+) -> Result<clap::ArgMatches> {
+    let mut argv = vec!["payments-engine".to_string(), "process".to_string()];
+    if let Some(path) = path {
+        argv.extend(config_args(cli, path)?);
+    }
+    for global in ["input-format", "input-encoding"] {
+        if sub.value_source(global) != Some(ValueSource::DefaultValue)
+            && let Some(value) = sub.get_one::<String>(global)
+        {
+            argv.extend([format!("--{global}"), value.clone()]);
+        }
+    }
+    let mut matches = cli.clone().try_get_matches_from(argv)?;
+    let (_, flags) = matches.remove_subcommand().expect("parsed as process");
+    Ok(flags)
+}
+
+/// The engine `process` ends with on the input at `path` under the
+/// `process` flags in `matches`, without writing any of its outputs.
+fn replayed(matches: &clap::ArgMatches, path: &Path) -> Result<Engine> {
+    let (mut engine, input_clock) =
+        open_engine(matches, matches.get_one::<String>("load-snapshot"))?;
+    replay_into(matches, &mut engine, input_clock, path, |_| Ok(()))?;
+    Ok(engine)
+}
+
+/// Run the input at `path` through `engine` (as opened by [`open_engine`],
+/// with its input clock) as `process` would under the flags in `matches`,
+/// without writing any of its outputs, calling `on_step` after every row
+/// and batch.
+fn replay_into(
+    matches: &clap::ArgMatches,
+    engine: &mut Engine,
+    input_clock: Option<ManualClock>,
+    path: &Path,
+    mut on_step: impl FnMut(&mut Engine) -> Result<()>,
+) -> Result<()> {
+    let pseudonyms = pseudonymizer(matches)?;
+    let limits = row_limits(matches);
+    let (infile, _) = open_input(matches, path, limits)?;
+    ingest(
+        engine,
+        input_rows(matches, infile, limits, input_clock)?,
+        0,
+        &ingest_filter(matches)?,
+        pseudonyms.as_ref(),
+        screening(matches, pseudonyms.as_ref())?.as_mut(),
+        |engine, step| {
+            if let Step::BatchEnd(..) = step {
+                engine.end_batch()?;
+            }
+            on_step(engine)
+        },
+    )?;
+    engine.close_deferred()?;
+    engine.finalize()?;
+    Ok(())
+}
+
+/// `selfcheck` subcommand: process the input with [`ParallelEngine`] and
+/// with a plain [`Engine`]; fails unless both reach the same state hash.
+fn selfcheck(sub: &clap::ArgMatches) -> Result<()> {
+    let path = sub.get_one::<String>("input").unwrap();
+    let threads = *sub.get_one::<u16>("threads").unwrap() as usize;
+    let binary = sub.get_one::<String>("input-format").map(String::as_str) == Some("binary");
+
+    let mut single = Engine::new();
+    let mut parallel = ParallelEngine::new(threads, EngineConfig::default());
+    for (idx, row) in read_transactions(
+        File::open(path)?,
+        binary,
+        input_encoding(sub),
+        RowLimits::default(),
+    )?
+    .enumerate()
+    {
+        match row {
+            Ok(tx) => single.process(tx)?,
+            Err(e) => error!(row = idx + 1, %e, "deserialize"),
+        }
+    }
+    // parse again rather than clone: rows are consumed by value
+    for row in read_transactions(
+        File::open(path)?,
+        binary,
+        input_encoding(sub),
+        RowLimits::default(),
+    )?
+    .flatten()
+    {
+        parallel.process(row)?;
+    }
+    let single_done = single.finalize()?;
+    let parallel = parallel.finish()?;
+
+    let diffs = compare::diff(&single, &parallel);
+    for d in &diffs {
+        println!("{d}");
+    }
+    if !diffs.is_empty() || parallel.state_hash() != single_done.state_hash {
+        anyhow::bail!(
+            "{threads}-thread engine diverges from the single-threaded one ({} divergence(s))",
+            diffs.len()
+        );
+    }
+    info!(
+        "{threads}-thread engine matches: {} rows, {} accounts, state {}",
+        single_done.rows, single_done.accounts, single_done.state_hash
+    );
+    Ok(())
+}
+
+/// `split` subcommand: one CSV per shard, each holding whole clients.
+fn split(sub: &clap::ArgMatches) -> Result<()> {
+    let src = Decoder::new(
+        BufReader::new(File::open(sub.get_one::<String>("input").unwrap())?),
+        input_encoding(sub),
+    );
+    let src = LimitedReader::new(src, RowLimits::default());
+    let breaches = src.breaches();
+    let shards = *sub.get_one::<u16>("shards").unwrap() as usize;
+    let out_dir = sub.get_one::<String>("out-dir").unwrap();
+    for shard in split_by_client(src, shards, out_dir.as_ref())? {
+        info!(rows = shard.rows, "{}", shard.path.display());
+    }
+    for b in breaches.borrow().iter() {
+        error!(%b, "split");
+    }
+    Ok(())
+}
+
+/// `merge` subcommand: combine shard snapshots and/or reports, refusing
+/// shards that share a client unless told to add them up.
+fn merge(sub: &clap::ArgMatches) -> Result<()> {
+    let mut shards = Vec::new();
+    let mut all_snapshots = true;
+    for path in sub.get_many::<String>("shards").unwrap() {
+        let mut magic = [0u8; 4];
+        let mut f = File::open(path)?;
+        let is_snapshot = f.read_exact(&mut magic).is_ok() && magic == snapshot::MAGIC;
+        f.rewind()?;
+        let eng = if is_snapshot {
+            Engine::read_snapshot(BufReader::new(f))?
+        } else {
+            all_snapshots = false;
+            merge::from_report(report::read_accounts(f)?)
+        };
+        shards.push((path.clone(), eng));
+    }
+    let policy = match sub.get_flag("sum-clients") {
+        true => MergePolicy::SumBalances,
+        false => MergePolicy::Reject,
+    };
+    let merged = merge::merge_shards_with(shards, policy)?;
+
+    if let Some(p) = sub.get_one::<String>("save-snapshot") {
+        if !all_snapshots {
+            anyhow::bail!("--save-snapshot needs every shard to be a snapshot");
+        }
+        write_snapshot_atomic(&merged, p.as_ref())?;
+    }
+    let sink: Box<dyn Write> = match sub.get_one::<String>("output") {
+        Some(p) => Box::new(File::create(p)?),
+        None => Box::new(io::stdout()),
+    };
+    report::write_accounts(&merged, &ReportFilter::default(), sink)?;
+    info!("Merged {} accounts", merged.accounts.len());
+    Ok(())
+}
+
+/// `aggregate` subcommand: sum the accounts reports of several runs.
+fn aggregate(sub: &clap::ArgMatches) -> Result<()> {
+    let mut runs = Vec::new();
+    for path in sub.get_many::<String>("runs").unwrap() {
+        let read = || report::read_accounts(File::open(path)?);
+        let accounts = read().with_context(|| format!("reading accounts report {path}"))?;
+        runs.push((path.clone(), accounts));
+    }
+    let n = runs.len();
+    let locks = sub
+        .get_one::<String>("locked")
+        .unwrap()
+        .parse()
+        .map_err(anyhow::Error::msg)?;
+    let global = aggregate::aggregate(runs, locks)?;
+    if !global.split_locks.is_empty() {
+        let clients: Vec<String> = global.split_locks.iter().map(u16::to_string).collect();
+        warn!(
+            clients = clients.join(", "),
+            "clients locked in some runs only"
+        );
+    }
+    let sink: Box<dyn Write> = match sub.get_one::<String>("output") {
+        Some(p) => Box::new(File::create(p)?),
+        None => Box::new(io::stdout()),
+    };
+    let accounts = global.accounts.len();
+    report::write_accounts(
+        &merge::from_report(global.accounts),
+        &ReportFilter::default(),
+        sink,
+    )?;
+    info!(
+        "Aggregated {n} runs: {accounts} accounts, {} in more than one run",
+        global.shared
+    );
+    Ok(())
+}
+
+/// `validate` subcommand: run the input through an engine that records
+/// every anomaly, print a summary, and fail if any row is unusable.
+fn validate(sub: &clap::ArgMatches) -> Result<()> {
+    let binary = sub.get_one::<String>("input-format").map(String::as_str) == Some("binary");
+    let src = File::open(sub.get_one::<String>("input").unwrap())?;
+    let mut config = EngineConfig::default();
+    for class in Anomaly::ALL {
+        config.anomalies.set(class, Action::Record);
+    }
+    let mut engine = Engine::with_config(config);
+    let mut unparsable = 0u64;
+    let mut by_class: BTreeMap<&str, u64> = BTreeMap::new();
+    let rows = read_transactions(src, binary, input_encoding(sub), RowLimits::default())?;
+    for (idx, row) in rows.enumerate() {
+        match row {
+            Ok(tx) => engine.process(tx)?,
+            Err(e) => {
+                error!(row = idx + 1, %e, "deserialize");
+                match e.downcast_ref::<Breach>() {
+                    Some(b) => *by_class.entry(b.kind.as_str()).or_default() += 1,
+                    None => unparsable += 1,
+                }
+            }
+        }
+    }
+    let oversized: u64 = by_class.values().sum();
+    for r in engine.rejections() {
+        *by_class.entry(r.anomaly.as_str()).or_default() += 1;
+    }
+    println!("rows,{}", engine.seq() + unparsable + oversized);
+    println!("unparsable,{unparsable}");
+    for (class, n) in &by_class {
+        println!("{class},{n}");
+    }
+    let bad = unparsable + oversized + engine.rejections().len() as u64;
+    if bad > 0 {
+        anyhow::bail!("{bad} row(s) would not be applied");
+    }
+    Ok(())
+}
+
+/// `diff` subcommand: every divergence between two snapshots; fails if
+/// there is any.
+fn diff(sub: &clap::ArgMatches) -> Result<()> {
+    let [old, new] = ["old", "new"].map(|a| sub.get_one::<String>(a).unwrap());
+    let old_eng = Engine::read_snapshot(BufReader::new(File::open(old)?))?;
+    let new_eng = Engine::read_snapshot(BufReader::new(File::open(new)?))?;
+    let diffs = compare::diff(&old_eng, &new_eng);
+    for d in &diffs {
+        println!("{d}");
+    }
+    if !diffs.is_empty() {
+        anyhow::bail!("{} divergence(s) between {old} and {new}", diffs.len());
+    }
+    info!("{old} and {new} hold the same state");
+    Ok(())
+}
+
+/// `generate` subcommand: synthetic rows as a transactions CSV.
+fn generate(sub: &clap::ArgMatches) -> Result<()> {
+    let rows = *sub.get_one::<u64>("rows").unwrap();
+    let clients = *sub.get_one::<u16>("clients").unwrap();
+    let seed = *sub.get_one::<u64>("seed").unwrap();
+    let sink: Box<dyn Write> = match sub.get_one::<String>("output") {
+        Some(p) => Box::new(File::create(p)?),
+        None => Box::new(io::stdout().lock()),
+    };
+    let mut wtr = csv::Writer::from_writer(io::BufWriter::new(sink));
+    wtr.write_record(["type", "client", "tx", "amount"])?;
+    for tx in Generator::new(seed, clients).take(rows as usize) {
+        wtr.write_record([
+            tx.kind.as_str().to_string(),
+            tx.client.to_string(),
+            tx.tx.to_string(),
+            tx.amount.map(|a| a.to_string()).unwrap_or_default(),
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// `balance-at` subcommand: replay with history enabled, as `process`
+/// would under the `--config` flags, and print the client's state around
+/// the first row carrying the given tx id.
+fn balance_at(cli: &Command, sub: &clap::ArgMatches) -> Result<()> {
+    let client = *sub.get_one::<u16>("client").unwrap();
+    let tx_id = *sub.get_one::<u32>("tx").unwrap();
+    let input = Path::new(sub.get_one::<String>("input").unwrap());
+    let flags = process_flags(cli, sub, sub.get_one::<String>("config"))?;
+
+    let (mut engine, input_clock) = open_engine(&flags, flags.get_one::<String>("load-snapshot"))?;
+    engine.enable_history();
+    let pseudonyms = pseudonymizer(&flags)?;
+    let ingest_filter = ingest_filter(&flags)?;
+    let limits = row_limits(&flags);
+    let (infile, _) = open_input(&flags, input, limits)?;
+    // nothing after the row asked about can change the answer
+    let found = Cell::new(false);
+    let rows = input_rows(&flags, infile, limits, input_clock)?.take_while(|row| {
+        let more = !found.get();
+        if let Ok(row) = row {
+            let tx = &row.tx;
+            found.set(
+                found.get() || tx.client == client && tx.tx == tx_id && ingest_filter.admits(tx),
+            );
+        }
+        more
+    });
+    ingest(
+        &mut engine,
+        rows,
+        0,
+        &ingest_filter,
+        pseudonyms.as_ref(),
+        None,
+        |_, _| Ok(()),
+    )?;
+    if !found.get() {
+        anyhow::bail!("no row for client {client} with tx {tx_id}");
+    }
+    let seq = engine.seq();
+
+    // the client as the report shows it, --anonymize included
+    let client = pseudonyms.as_ref().map_or(client, |p| p.client(client));
+    println!("point,seq,available,held,total,locked");
+    for (point, at) in [("before", seq - 1), ("after", seq)] {
+        let acc = engine.balance_at(client, at).unwrap_or_default();
+        let row = AccountRow::from((&client, &acc));
+        println!(
+            "{point},{at},{},{},{},{}",
+            row.available, row.held, row.total, row.locked
+        );
+    }
+    Ok(())
+}
+
+/// `explain` subcommand: replay as `process` would under the `--config`
+/// flags up to the first row carrying the given tx id (and client and type,
+/// if given) and print why it was applied or rejected.
+fn explain(cli: &Command, sub: &clap::ArgMatches) -> Result<()> {
+    let tx_id = *sub.get_one::<u32>("tx").unwrap();
+    let client = sub.get_one::<u16>("client").copied();
+    let kind = sub.get_one::<TxType>("type").copied();
+    let input = Path::new(sub.get_one::<String>("input").unwrap());
+    let flags = process_flags(cli, sub, sub.get_one::<String>("config"))?;
+
+    let (mut engine, input_clock) = open_engine(&flags, flags.get_one::<String>("load-snapshot"))?;
+    let pseudonyms = pseudonymizer(&flags)?;
+    let ingest_filter = ingest_filter(&flags)?;
+    let limits = row_limits(&flags);
+    let (infile, _) = open_input(&flags, input, limits)?;
+    let mut rows = input_rows(&flags, infile, limits, input_clock)?;
+    // every row before the one asked about, which is kept back to explain
+    let mut found = None;
+    let before = std::iter::from_fn(|| match rows.next()? {
+        Ok(row)
+            if row.tx.tx == tx_id
+                && client.is_none_or(|c| c == row.tx.client)
+                && kind.is_none_or(|k| k == row.tx.kind)
+                && ingest_filter.admits(&row.tx) =>
+        {
+            found = Some(row);
+            None
+        }
+        row => Some(row),
+    });
+    ingest(
+        &mut engine,
+        before,
+        0,
+        &ingest_filter,
+        pseudonyms.as_ref(),
+        None,
+        |_, _| Ok(()),
+    )?;
+    let Some(row) = found else {
+        anyhow::bail!("no row with tx {tx_id} matches")
+    };
+    let tx = Transaction { ..row.tx };
+    let tx = match &pseudonyms {
+        Some(p) => p.apply(tx),
+        None => tx,
+    };
+    print!("{}", engine.explain_row(tx, row.meta())?);
+    Ok(())
+}
+
+/// `annotate` subcommand: edit one client's flags and notes in a snapshot,
+/// then print them.
+fn annotate(sub: &clap::ArgMatches) -> Result<()> {
+    let path = Path::new(sub.get_one::<String>("snapshot").unwrap());
+    let client = *sub.get_one::<u16>("client").unwrap();
+    let mut engine = Engine::read_snapshot(BufReader::new(File::open(path)?))?;
+
+    let mut changed = false;
+    for flag in sub.get_many::<String>("flag").into_iter().flatten() {
+        changed |= engine.flag(client, flag);
+    }
+    for flag in sub.get_many::<String>("unflag").into_iter().flatten() {
+        changed |= engine.unflag(client, flag);
+    }
+    if let Some(text) = sub.get_one::<String>("note") {
+        engine.add_note(client, sub.get_one::<String>("by").unwrap(), text);
+        changed = true;
+    }
+    if changed {
+        write_snapshot_atomic(&engine, path)?;
+        info!("annotations of client {client} saved to {}", path.display());
+    }
+
+    let flags: Vec<_> = engine.flags(client).collect();
+    println!("flags: {}", flags.join(", "));
+    for note in engine.annotations(client).map_or(&[][..], |a| &a.notes) {
+        println!("row {} by {}: {}", note.seq, note.by, note.text);
+    }
+    Ok(())
+}
+
+/// `source-of-funds` subcommand: replay as `process` would under the
+/// `--config` flags, feeding every change to the traced clients' accounts
+/// to a [`FundsTracer`], then write its matches, unspent lots and, if
+/// asked, what every lot paid out.
+fn source_of_funds(cli: &Command, sub: &clap::ArgMatches) -> Result<()> {
+    let input = Path::new(sub.get_one::<String>("input").unwrap());
+    let flags = process_flags(cli, sub, sub.get_one::<String>("config"))?;
+    // the clients as the engine sees them, --anonymize included
+    let pseudonyms = pseudonymizer(&flags)?;
+    let clients: Option<HashSet<u16>> = sub.get_many::<u16>("client").map(|ids| {
+        ids.map(|&id| pseudonyms.as_ref().map_or(id, |p| p.client(id)))
+            .collect()
+    });
+    let order: Order = sub
+        .get_one::<String>("order")
+        .unwrap()
+        .parse()
+        .map_err(anyhow::Error::msg)?;
+
+    let (mut engine, input_clock) = open_engine(&flags, flags.get_one::<String>("load-snapshot"))?;
+    let watch = engine.watch(move |client| clients.as_ref().is_none_or(|c| c.contains(&client)));
+    let mut tracer = FundsTracer::with_order(order);
+    replay_into(&flags, &mut engine, input_clock, input, |_| {
+        for delta in watch.pending() {
+            tracer.observe(&delta);
+        }
+        Ok(())
+    })?;
+
+    let sink: Box<dyn Write> = match sub.get_one::<String>("output") {
+        Some(p) => Box::new(File::create(p)?),
+        None => Box::new(io::stdout()),
+    };
+    let matched = funds::write_matches(&tracer, sink)?;
+    let lots_path = sub.get_one::<String>("lots").unwrap();
+    let unspent = funds::write_lots(&tracer, File::create(lots_path)?)?;
+    if let Some(p) = sub.get_one::<String>("realized") {
+        let n = funds::write_realized(&tracer, File::create(p)?)?;
+        info!("{n} lots → {p}");
+    }
+    info!(matched, unspent, lots = %lots_path, "source of funds traced");
+    Ok(())
+}
+
+/// The engine `process` ends with on `--input` under the `--config` flags
+/// (see [`process_flags`]), with history recorded for every client.
+#[cfg(feature = "bank-statements")]
+fn replay_history(flags: &clap::ArgMatches, sub: &clap::ArgMatches) -> Result<Engine> {
+    let input = Path::new(sub.get_one::<String>("input").unwrap());
+    let (mut engine, input_clock) = open_engine(flags, flags.get_one::<String>("load-snapshot"))?;
+    engine.enable_history();
+    replay_into(flags, &mut engine, input_clock, input, |_| Ok(()))?;
+    Ok(engine)
+}
+
+/// `--currency` of a statement subcommand, for every client of the run.
+#[cfg(feature = "bank-statements")]
+fn statement_info(sub: &clap::ArgMatches, client: u16) -> bank_statement::StatementInfo {
+    bank_statement::StatementInfo {
+        client,
+        currency: sub.get_one::<String>("currency").unwrap().clone(),
+        opening: Decimal::ZERO,
+        date: bank_statement::Date::today(),
+    }
+}
+
+/// `statement` subcommand: replay with history as `process` would under
+/// the `--config` flags and print one client's statement for the whole
+/// input.
+#[cfg(feature = "bank-statements")]
+fn statement(cli: &Command, sub: &clap::ArgMatches) -> Result<()> {
+    let flags = process_flags(cli, sub, sub.get_one::<String>("config"))?;
+    // the client as the engine sees it, --anonymize included
+    let client = *sub.get_one::<u16>("client").unwrap();
+    let client = pseudonymizer(&flags)?.map_or(client, |p| p.client(client));
+    let format: bank_statement::Format = sub
+        .get_one::<String>("format")
+        .unwrap()
+        .parse()
+        .map_err(anyhow::Error::msg)?;
+    let engine = replay_history(&flags, sub)?;
+    let info = statement_info(sub, client);
+    print!("{}", format.render(&info, engine.history(client)));
+    Ok(())
+}
+
+/// `statements` subcommand: replay with history as `process` would under
+/// the `--config` flags, write a statement file per client with history in
+/// the input, then a `manifest.json` of the input and every file written,
+/// for `verify`.
+#[cfg(feature = "bank-statements")]
+fn statements(cli: &Command, sub: &clap::ArgMatches) -> Result<()> {
+    let input = sub.get_one::<String>("input").unwrap();
+    let out_dir = Path::new(sub.get_one::<String>("out-dir").unwrap());
+    let threads = *sub.get_one::<u16>("threads").unwrap() as usize;
+    let format: bank_statement::Format = sub
+        .get_one::<String>("format")
+        .unwrap()
+        .parse()
+        .map_err(anyhow::Error::msg)?;
+    let flags = process_flags(cli, sub, sub.get_one::<String>("config"))?;
+    let pseudonyms = pseudonymizer(&flags)?;
+    let only: Option<HashSet<u16>> = sub.get_many::<u16>("client").map(|cs| {
+        cs.map(|&c| pseudonyms.as_ref().map_or(c, |p| p.client(c)))
+            .collect()
+    });
+    let engine = replay_history(&flags, sub)?;
+
+    let mut clients: Vec<u16> = engine
+        .accounts
+        .keys()
+        .copied()
+        .filter(|c| only.as_ref().is_none_or(|o| o.contains(c)))
+        .collect();
+    clients.sort_unstable();
+    let histories: Vec<(u16, &[payments_engine::history::HistoryEntry])> = clients
+        .into_iter()
+        .map(|c| (c, engine.history(c)))
+        .filter(|(_, h)| !h.is_empty())
+        .collect();
+    let template = statement_info(sub, 0);
+    let paths = bank_statement::write_batch(&histories, &template, format, out_dir, threads)?;
+
+    let mut manifest = RunManifest::new();
+    manifest.inputs.push(FileDigest::of_file("input", input)?);
+    for path in &paths {
+        manifest.outputs.push(FileDigest::of_file(
+            "statement",
+            &path.display().to_string(),
+        )?);
+    }
+    manifest.state_hash = Some(engine.state_hash());
+    let manifest_path = out_dir.join("manifest.json");
+    manifest.write(&manifest_path)?;
+    info!(
+        statements = paths.len(),
+        "{} (manifest {})",
+        out_dir.display(),
+        manifest_path.display()
+    );
+    Ok(())
+}
+
+/// `verify` subcommand: re-hash every file in a manifest; fails on any
+/// mismatch or missing file.
+fn verify(sub: &clap::ArgMatches) -> Result<()> {
+    let path = sub.get_one::<String>("manifest").unwrap();
+    let mut failed = 0;
+    for check in RunManifest::read(path.as_ref())?.verify() {
+        let verdict = match &check.status {
+            Status::Ok => "OK".to_string(),
+            Status::Unverifiable => "SKIPPED (stdout)".to_string(),
+            Status::Mismatch { sha256 } => {
+                failed += 1;
+                format!("MISMATCH (now {sha256})")
+            }
+            Status::Missing(e) => {
+                failed += 1;
+                format!("MISSING ({e})")
+            }
+        };
+        println!("{:<8} {}: {verdict}", check.file.role, check.file.path);
+    }
+    if failed > 0 {
+        anyhow::bail!("{failed} file(s) failed verification");
+    }
+    Ok(())
+}
+
+/// Tenant mode, once [`ingest`] has routed the rows: one report file per
+/// tenant.
+fn run_tenants(
+    mut router: TenantRouter,
+    dir: &Path,
+    filter: &ReportFilter,
+    ingested: &Ingested,
+) -> Result<()> {
+    if router.refused() > 0 {
+        warn!(
+            rows = router.refused(),
+            "rows refused for an invalid tenant name"
+        );
+    }
+    let done = PaymentsProcessor::finalize(&mut router)?;
+    info!(
+        "Finished ingest: {} accounts in {} tenants, {} rows skipped by ingest filters, state {}",
+        done.accounts,
+        router.engines().count(),
+        ingested.filtered,
+        done.state_hash
+    );
+
+    fs::create_dir_all(dir)?;
+    for (tenant, engine) in router.engines() {
+        let path = dir.join(format!("accounts-{tenant}.csv"));
+        report::write_accounts(engine, filter, File::create(&path)?)?;
+        info!(
+            "Tenant {tenant}: {} accounts → {}",
+            engine.accounts.len(),
+            path.display()
+        );
+    }
+    if let Some(rows) = ingested.interrupted {
+        anyhow::bail!("interrupted after {rows} input rows; outputs reflect the partial ingest");
+    }
+    Ok(())
+}
+
+/// Build a notification sink from its `--notify` spec.
+fn open_sink(spec: &str) -> Result<Box<dyn NotificationSink>> {
+    Ok(match spec.split_once(':') {
+        None if spec == "stderr" => Box::new(StderrSink),
+        Some(("json", path)) => Box::new(JsonLinesSink::create(path.as_ref())?),
+        Some(("slack", path)) => Box::new(SlackSink::create(path.as_ref())?),
+        _ => anyhow::bail!("unknown --notify sink {spec:?}"),
+    })
+}
+
+/// Sort the CSV at `path` by `column` into an unlinked scratch file and
+/// return it rewound, ready to be read instead of the original, along with
+/// the rows left out for breaking `limits`.
+fn sorted_input(
+    path: &Path,
+    encoding: Encoding,
+    limits: RowLimits,
+    column: &str,
+    dir: Option<&String>,
+) -> Result<(File, Vec<Breach>)> {
+    let mut sorter = ExternalSort::new(column);
+    if let Some(dir) = dir {
+        sorter = sorter.scratch_dir(dir.as_ref());
+    }
+    let scratch = dir.map_or_else(std::env::temp_dir, PathBuf::from);
+    let sorted_path = scratch.join(format!("payments-engine-sorted-{}.csv", std::process::id()));
+    let mut sorted = File::options()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&sorted_path)?;
+    // the open handle keeps the data alive; nothing is left behind on exit
+    fs::remove_file(&sorted_path)?;
+    let (stats, breaches) = {
+        let mut out = io::BufWriter::new(&mut sorted);
+        let src = Decoder::new(BufReader::new(File::open(path)?), encoding);
+        let src = LimitedReader::new(src, limits);
+        let breaches = src.breaches();
+        let stats = sorter.sort(src, &mut out)?;
+        out.flush()?;
+        (stats, breaches.take())
+    };
+    info!(
+        rows = stats.rows,
+        runs = stats.chunks,
+        "sorted input by {column}"
+    );
+    for b in &breaches {
+        error!(%b, "sort");
+    }
+    sorted.seek(io::SeekFrom::Start(0))?;
+    Ok((sorted, breaches.into()))
+}
+
+/// Index the deposits of the CSV at `path`, then copy it to an unlinked
+/// scratch file with each deposit moved ahead of the disputes naming it.
+fn two_pass_input(path: &Path) -> Result<File> {
+    let index = DepositIndex::build(BufReader::new(File::open(path)?))?;
+    let scratch = std::env::temp_dir().join(format!(
+        "payments-engine-two-pass-{}.csv",
+        std::process::id()
+    ));
+    let mut out = File::options()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&scratch)?;
+    // the open handle keeps the data alive; nothing is left behind on exit
+    fs::remove_file(&scratch)?;
+    let moved = index.reorder(
+        BufReader::new(File::open(path)?),
+        BufReader::new(File::open(path)?),
+        io::BufWriter::new(&mut out),
+    )?;
+    info!(
+        deposits = index.len(),
+        moved, "indexed deposits, moved those disputed early"
+    );
+    out.seek(io::SeekFrom::Start(0))?;
+    Ok(out)
+}
+
+/// Where [`ingest`] stopped.
+struct Ingested {
+    /// Input position reached (rows skipped by `--resume` included).
+    consumed: u64,
+    /// Rows dropped by the ingest filter.
+    filtered: u64,
+    /// Input rows read before a shutdown request stopped the loop.
+    interrupted: Option<usize>,
+    /// Rows skipped for breaking a size limit.
+    breaches: Vec<Breach>,
+    /// Counters of the rows read this run.
+    tally: IngestTally,
+}
+
+/// What [`ingest`] reports to its caller as it goes.
+enum Step<'a> {
+    /// A row was consumed; the input position after it.
+    Row(u64),
+    /// The batch labelled so (empty without a `batch` column) ended at this
+    /// input position: the next row carries another label, or the input is
+    /// exhausted.
+    BatchEnd(&'a str, u64),
+}
+
+/// Feed `rows` into `processor` from input position `start`, dropping rows
+/// the ingest filter rejects, pseudonymizing the rest if asked, holding back
+/// those `screening` blocks and stopping early on a shutdown request.
+/// `on_step` sees the input position after every consumed row, and every
+/// batch end, i.e. every change of the `batch` column and the end of input.
+fn ingest<P: PaymentsProcessor>(
+    processor: &mut P,
+    rows: impl Iterator<Item = Result<InputRow>>,
+    start: u64,
+    ingest_filter: &IngestFilter,
+    pseudonyms: Option<&Pseudonymizer>,
+    mut screening: Option<&mut Screening>,
+    mut on_step: impl FnMut(&mut P, Step) -> Result<()>,
+) -> Result<Ingested> {
+    let mut done = Ingested {
+        consumed: start,
+        filtered: 0,
+        interrupted: None,
+        breaches: Vec::new(),
+        tally: IngestTally::start(),
+    };
+    let mut batch: Option<String> = None;
+    for (idx, row) in rows.enumerate().skip(start as usize) {
+        if shutdown::requested() {
+            warn!(row = idx + 1, "shutdown requested; stopping ingest");
+            done.interrupted = Some(idx);
+            break;
+        }
+        if let Ok(InputRow {
+            batch: Some(label), ..
+        }) = &row
+            && batch.as_ref() != Some(label)
+            // the first label opens a batch without ending one
+            && let Some(ended) = batch.replace(label.clone())
+        {
+            on_step(processor, Step::BatchEnd(&ended, done.consumed))?;
+        }
+        match row {
+            Ok(row) if !ingest_filter.admits(&row.tx) => {
+                done.filtered += 1;
+                done.tally.parsed(row.tx.kind);
+                done.tally.rejected("filtered");
+            }
+            Ok(row) => {
+                let tx = Transaction { ..row.tx };
+                let tx = match pseudonyms {
+                    Some(p) => p.apply(tx),
+                    None => tx,
+                };
+                done.tally.parsed(tx.kind);
+                let blocked = screening
+                    .as_deref_mut()
+                    .is_some_and(|s| s.blocks(idx as u64 + 1, &tx));
+                if blocked {
+                    done.tally.rejected("screened");
+                } else {
+                    if let Some(result) = processor.process_row(tx, row.meta())? {
+                        done.tally.outcome(&result);
+                    }
+                }
+            }
+            Err(e) => {
+                error!(row = idx + 1, %e, "deserialize");
+                match e.downcast_ref::<Breach>() {
+                    Some(breach) => {
+                        done.tally.unparsable(breach.kind.as_str());
+                        done.breaches.push(breach.clone());
+                    }
+                    None => done.tally.unparsable("unparsable"),
+                }
+            }
+        }
+        done.consumed = idx as u64 + 1;
+        on_step(processor, Step::Row(done.consumed))?;
+    }
+    if done.interrupted.is_none() {
+        let label = batch.as_deref().unwrap_or_default();
+        on_step(processor, Step::BatchEnd(label, done.consumed))?;
+    }
+    Ok(done)
+}
+
+/// `--input-encoding` as given to any subcommand (the flag is global).
+fn input_encoding(matches: &clap::ArgMatches) -> Encoding {
+    matches
+        .get_one::<String>("input-encoding")
+        .and_then(|e| e.parse().ok())
+        .unwrap_or_default()
+}
+
+/// Row iterator over either a CSV or a binary transaction stream.
+/// CSV rows breaking `limits` come out as [`Breach`] errors in their place.
+fn read_transactions(
+    src: File,
+    binary: bool,
+    encoding: Encoding,
+    limits: RowLimits,
+) -> Result<Box<dyn Iterator<Item = Result<Transaction>>>> {
+    match binary {
+        true => Ok(Box::new(TxDecoder::new(src)?)),
+        false => read_rows(src, encoding, limits, AmountLocale::Plain),
+    }
+}
+
+/// A transaction with its idempotency key, batch label, timestamp, buckets
+/// and tenant, where the row has them.
+struct InputRow {
+    tx: Transaction,
+    key: Option<String>,
+    batch: Option<String>,
+    timestamp: Option<String>,
+    bucket: Option<String>,
+    to_bucket: Option<String>,
+    tenant: Option<String>,
+}
+
+impl InputRow {
+    /// What the row carries besides the transaction.
+    fn meta(&self) -> RowMeta<'_> {
+        RowMeta {
+            key: self.key.as_deref(),
+            buckets: RowBuckets {
+                bucket: self.bucket.as_deref().unwrap_or_default(),
+                to_bucket: self.to_bucket.as_deref().unwrap_or_default(),
+            },
+            tenant: self.tenant.as_deref(),
+        }
+    }
+}
+
+/// [`read_transactions`] with the idempotency key, batch label, timestamp,
+/// buckets and tenant of each row, when the input has those columns (binary input never does).
+/// CSV amounts are read under `amounts`.
+fn read_input_rows(
+    src: File,
+    binary: bool,
+    encoding: Encoding,
+    limits: RowLimits,
+    amounts: AmountLocale,
+) -> Result<Box<dyn Iterator<Item = Result<InputRow>>>> {
+    if binary {
+        let rows = TxDecoder::new(src)?.map(|row| {
+            row.map(|tx| InputRow {
+                tx,
+                key: None,
+                batch: None,
+                timestamp: None,
+                bucket: None,
+                to_bucket: None,
+                tenant: None,
+            })
+        });
+        return Ok(Box::new(rows));
+    }
+    let rows = read_rows::<KeyedRow>(src, encoding, limits, amounts)?;
+    Ok(Box::new(rows.map(|row| {
+        row.map(|mut row| {
+            let batch = row.batch.take().filter(|b| !b.is_empty());
+            let timestamp = row.timestamp.take().filter(|t| !t.is_empty());
+            let (bucket, to_bucket) = (row.bucket.take(), row.to_bucket.take());
+            let tenant = row.tenant.take();
+            let (tx, key) = row.split();
+            InputRow {
+                tx,
+                key,
+                batch,
+                timestamp,
+                bucket,
+                to_bucket,
+                tenant,
+            }
+        })
+    })))
+}
+
+/// CSV rows of type `T`; rows breaking `limits` come out as [`Breach`]
+/// errors in their place.
+fn read_rows<T: DeserializeOwned + 'static>(
+    src: File,
+    encoding: Encoding,
+    limits: RowLimits,
+    amounts: AmountLocale,
+) -> Result<Box<dyn Iterator<Item = Result<T>>>> {
+    let limited = LimitedReader::new(Decoder::new(src, encoding), limits);
+    let breaches = limited.breaches();
+    let rdr = ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(limited);
+    let mut rows = match amounts {
+        AmountLocale::Plain => Box::new(rdr.into_deserialize::<T>().map(|row| Ok(row?))),
+        locale => localized_rows(rdr, locale)?,
+    };
+    // record number of the next row; the header is record 0
+    let mut record = 1;
+    let mut pending = None;
+    Ok(Box::new(std::iter::from_fn(move || {
+        loop {
+            // a breach is known by the time the row after it has been read
+            let mut queue = breaches.borrow_mut();
+            if let Some(b) = queue.pop_front_if(|b| b.record <= record) {
+                // only the header (record 0) is behind the count
+                if b.record == record {
+                    record += 1;
+                }
+                return Some(Err(b.into()));
+            }
+            drop(queue);
+            if let Some(row) = pending.take() {
+                record += 1;
+                return Some(row);
+            }
+            match rows.next() {
+                Some(row) => pending = Some(row),
+                None => {
+                    record += 1;
+                    return breaches.borrow_mut().pop_front().map(|b| Err(b.into()));
+                }
+            }
+        }
+    })))
+}
+
+/// Rows of `rdr` with their amounts read under `locale` (see
+/// [`payments_engine::amount`]); ambiguous amounts are logged.
+fn localized_rows<T: DeserializeOwned + 'static>(
+    mut rdr: csv::Reader<impl Read + 'static>,
+    locale: AmountLocale,
+) -> Result<Box<dyn Iterator<Item = Result<T>>>> {
+    let headers = rdr.headers()?.clone();
+    let col = headers.iter().position(|h| h == "amount");
+    Ok(Box::new(rdr.into_records().map(move |record| {
+        let mut record = record?;
+        if let Some(col) = col
+            && let Some(raw) = record.get(col).filter(|a| !a.is_empty())
+        {
+            let line = record.position().map_or(0, |p| p.line());
+            let parsed = locale
+                .parse(raw)
+                .map_err(|e| anyhow::anyhow!("line {line}: {e}"))?;
+            if parsed.ambiguous {
+                warn!(line, raw, amount = %parsed.amount, "ambiguous amount");
+            }
+            let amount = parsed.amount.to_string();
+            record = record
+                .iter()
+                .enumerate()
+                .map(|(i, field)| if i == col { amount.as_str() } else { field })
+                .collect();
+        }
+        Ok(record.deserialize(Some(&headers))?)
+    })))
+}
+
+/// `encode` subcommand: CSV → binary, skipping rows that fail to parse.
+fn encode(sub: &clap::ArgMatches) -> Result<()> {
+    let src = File::open(sub.get_one::<String>("input").unwrap())?;
+    let mut enc = TxEncoder::new(File::create(sub.get_one::<String>("output").unwrap())?)?;
+    let mut written = 0;
+    for (idx, row) in
+        read_transactions(src, false, input_encoding(sub), RowLimits::default())?.enumerate()
+    {
+        match row {
+            Ok(tx) => {
+                enc.write(&tx)?;
+                written += 1;
+            }
+            Err(e) => error!(row = idx + 1, %e, "deserialize"),
+        }
+    }
+    enc.finish()?;
+    info!("Encoded {written} transactions");
+    Ok(())
+}
+
+/// Prefix of the environment variables mirroring the top-level flags.
+const ENV_PREFIX: &str = "PAYMENTS_ENGINE_";
+
+/// Environment variable for the flag `--long`: `PAYMENTS_ENGINE_HOLD_EXPIRY`
+/// for `--hold-expiry`.
+fn env_var(long: &str) -> String {
+    format!("{ENV_PREFIX}{}", long.to_uppercase().replace('-', "_"))
+}
+
+/// Let every flag of `cmd` be set from its environment variable as well;
+/// repeatable flags take a comma-separated list there.
+fn with_env_vars(cmd: Command) -> Command {
+    cmd.mut_args(|arg| {
+        let Some(var) = arg.get_long().map(env_var) else {
+            return arg;
+        };
+        match arg.get_action() {
+            ArgAction::Append => arg.env(var).value_delimiter(','),
+            _ => arg.env(var),
+        }
+    })
+}
+
+/// Command-line arguments for the configuration file at `path`, rejecting
+/// keys that are not `process` or global flags. Keys whose environment variable is
+/// set are left out, so the environment overrides the file.
+fn config_args(cli: &Command, path: &str) -> Result<Vec<String>> {
+    let process = cli.find_subcommand("process").expect("defined in cli()");
+    let entries =
+        config::parse(&fs::read_to_string(path)?).map_err(|e| anyhow::anyhow!("{path}: {e}"))?;
+    for e in &entries {
+        let known = process
+            .get_arguments()
+            .chain(cli.get_arguments())
+            .any(|a| a.get_long() == Some(e.key.as_str()) && e.key != "config");
+        if !known {
+            anyhow::bail!("{path}: line {}: unknown key {:?}", e.line, e.key);
+        }
+    }
+    let entries: Vec<_> = entries
+        .into_iter()
+        .filter(|e| std::env::var_os(env_var(&e.key)).is_none())
+        .collect();
+    Ok(config::to_args(&entries))
+}
+
+/// `config print-default`: every `process` and global flag as a
+/// commented-out key, with its help text and default value.
+fn default_config(cli: &Command) -> String {
+    let process = cli.find_subcommand("process").expect("defined in cli()");
+    let mut out = String::from(
+        "# payments-engine configuration (--config FILE).\n\
+         # Keys are command-line flags; flags given on the command line win.\n",
+    );
+    for arg in cli.get_arguments().chain(process.get_arguments()) {
+        let Some(long) = arg.get_long() else { continue };
+        if arg.is_hide_set() || long == "config" {
+            continue;
+        }
+        let placeholder = arg
+            .get_value_names()
+            .and_then(|names| names.first())
+            .map_or("VALUE".to_string(), |n| n.to_string());
+        let value = match arg.get_action() {
+            ArgAction::SetTrue => "false".to_string(),
+            ArgAction::Append => format!("[\"{placeholder}\"]"),
+            _ => match arg.get_default_values().first() {
+                Some(d) => format!("\"{}\"", d.to_string_lossy()),
+                None => format!("\"{placeholder}\""),
+            },
+        };
+        out.push('\n');
+        if let Some(help) = arg.get_help() {
+            out.push_str(&format!("# {help}\n"));
+        }
+        out.push_str(&format!("# {long} = {value}\n"));
+    }
+    out
+}
+
+/// `schema` subcommand: pretty-print the requested schema(s) to stdout.
+fn print_schema(sub: &clap::ArgMatches) -> Result<()> {
+    let format = match sub.get_one::<String>("format").map(String::as_str) {
+        Some("avro") => Format::Avro,
+        _ => Format::JsonSchema,
+    };
+    let out = match sub.get_one::<String>("target").map(String::as_str) {
+        Some("transaction") => schema::render(Target::Transaction, format),
+        Some("account") => schema::render(Target::Account, format),
+        _ => serde_json::json!({
+            "transaction": schema::render(Target::Transaction, format),
+            "account": schema::render(Target::Account, format),
+        }),
+    };
+    println!("{}", serde_json::to_string_pretty(&out)?);
+    Ok(())
+}
+
+/// clap value parser for monetary flags (`rust_decimal` is built without `std`,
+/// so its error type can't be boxed directly).
+fn parse_amount(s: &str) -> Result<Decimal, String> {
+    s.parse::<Decimal>().map_err(|e| e.to_string())
+}
+
+/// Where `--clock` takes the time from.
+#[derive(Debug, Clone, Copy)]
+enum ClockSource {
+    Wall,
+    Fixed(u64),
+    /// The `timestamp` column of each row; rows without one keep the time
+    /// of the row before.
+    Input,
+}
+
+fn parse_clock(s: &str) -> Result<ClockSource, String> {
+    match s {
+        "wall" => Ok(ClockSource::Wall),
+        "input" => Ok(ClockSource::Input),
+        _ => match s.strip_prefix("fixed:") {
+            Some(at) => parse_timestamp(at)
+                .map(ClockSource::Fixed)
+                .ok_or_else(|| format!("bad timestamp {at:?}")),
+            None => Err(format!(
+                "unknown clock {s:?} (use wall, fixed:TIMESTAMP or input)"
+            )),
+        },
+    }
+}
+
+/// `--max-amount`: `AMOUNT` for every type, or `TYPE=AMOUNT` for one type
+/// that carries an amount.
+fn parse_max_amount(s: &str) -> Result<(Option<TxType>, Decimal), String> {
+    let (kind, amount) = match s.split_once('=') {
+        Some((kind, amount)) => {
+            let kind: TxType = kind.parse()?;
+            if !kind.carries_amount() {
+                return Err(format!("{} rows carry no amount", kind.as_str()));
+            }
+            (Some(kind), amount)
+        }
+        None => (None, s),
+    };
+    let max = parse_amount(amount)?;
+    if max <= Decimal::ZERO {
+        return Err(format!("maximum must be positive, got {max}"));
+    }
+    Ok((kind, max))
 }
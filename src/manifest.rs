@@ -0,0 +1,135 @@
+//! Run manifest: a JSON record of which files a run read and wrote, with
+//! their SHA-256 digests, so an output can later be proven unmodified.
+//!
+//! ### Example
+//! ```rust,no_run
+//! use payments_engine::manifest::RunManifest;
+//!
+//! let manifest = RunManifest::read("run.json".as_ref()).unwrap();
+//! for check in manifest.verify() {
+//!     println!("{} {}: {:?}", check.file.role, check.file.path, check.status);
+//! }
+//! ```
+
+use crate::checksum::{Sha256, hash_file};
+use crate::errors::Result;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// Path recorded for data written to stdout; such entries can't be
+/// re-verified from disk.
+pub const STDOUT: &str = "-";
+
+/// Digest of one file read or written by the run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileDigest {
+    /// What the file was used for (`input`, `report`, `delta`, …).
+    pub role: String,
+    pub path: String,
+    pub bytes: u64,
+    pub sha256: String,
+}
+
+impl FileDigest {
+    /// Hash the file at `path` now.
+    pub fn of_file(role: &str, path: &str) -> Result<Self> {
+        Ok(Self::from_hasher(role, path, hash_file(Path::new(path))?))
+    }
+
+    /// Build from a hasher that saw exactly the file's bytes.
+    pub fn from_hasher(role: &str, path: &str, hasher: Sha256) -> Self {
+        Self {
+            role: role.to_string(),
+            path: path.to_string(),
+            bytes: hasher.len(),
+            sha256: hasher.hex_digest(),
+        }
+    }
+}
+
+/// Everything a run consumed and produced.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunManifest {
+    pub engine_version: String,
+    pub inputs: Vec<FileDigest>,
+    pub outputs: Vec<FileDigest>,
+    /// [`Engine::state_hash`](crate::Engine::state_hash) after ingest.
+    #[serde(default)]
+    pub state_hash: Option<String>,
+}
+
+/// Outcome of re-checking one recorded file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    /// Digest matches.
+    Ok,
+    /// File exists but its content differs; carries the current digest.
+    Mismatch { sha256: String },
+    /// File could not be read; carries the I/O error text.
+    Missing(String),
+    /// Written to stdout, nothing on disk to compare.
+    Unverifiable,
+}
+
+/// One row of [`RunManifest::verify`].
+#[derive(Debug, Clone)]
+pub struct Verification {
+    pub file: FileDigest,
+    pub status: Status,
+}
+
+impl RunManifest {
+    /// Empty manifest stamped with this crate's version.
+    pub fn new() -> Self {
+        Self {
+            engine_version: env!("CARGO_PKG_VERSION").to_string(),
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            state_hash: None,
+        }
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut out, self)?;
+        // a failed flush would otherwise leave a truncated manifest behind
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Re-hash every recorded input and output and compare with the
+    /// recorded digests.
+    pub fn verify(&self) -> Vec<Verification> {
+        self.inputs
+            .iter()
+            .chain(&self.outputs)
+            .map(|file| {
+                let status = if file.path == STDOUT {
+                    Status::Unverifiable
+                } else {
+                    match hash_file(Path::new(&file.path)) {
+                        Err(e) => Status::Missing(e.to_string()),
+                        Ok(h) => {
+                            let sha256 = h.hex_digest();
+                            if sha256 == file.sha256 {
+                                Status::Ok
+                            } else {
+                                Status::Mismatch { sha256 }
+                            }
+                        }
+                    }
+                };
+                Verification {
+                    file: file.clone(),
+                    status,
+                }
+            })
+            .collect()
+    }
+}
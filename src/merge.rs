@@ -0,0 +1,194 @@
+//! Combine engines built independently — shards processed separately (see
+//! [`crate::split`]), or regional runs — into one, for a single report or
+//! snapshot.
+//!
+//! [`Engine::merge`] unions two engines' accounts and transaction records.
+//! By default they must cover disjoint clients: a client found in both means
+//! the input was not split by client and merging would double-count it, so
+//! it is a [`MergeConflict`] rather than something to resolve. Regional runs
+//! may legitimately share clients; [`MergePolicy::SumBalances`] adds their
+//! balances up instead. A tx id recorded by both engines is always a
+//! conflict, as later disputes could not tell the two apart.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::merge::{MergeConflict, MergePolicy, merge_shards};
+//! use payments_engine::{Engine, Transaction, TxType};
+//! use rust_decimal_macros::dec;
+//!
+//! let shard = |client, tx| {
+//!     let mut eng = Engine::new();
+//!     eng.process(Transaction { kind: TxType::Deposit, client, tx, amount: Some(dec!(1)) })
+//!         .unwrap();
+//!     eng
+//! };
+//! let merged = merge_shards(vec![("a".into(), shard(1, 1)), ("b".into(), shard(2, 2))]).unwrap();
+//! assert_eq!(merged.accounts.len(), 2);
+//! assert_eq!(merged.seq(), 2);
+//!
+//! let Err(err) = merge_shards(vec![("a".into(), shard(1, 1)), ("b".into(), shard(1, 2))]) else {
+//!     panic!("client 1 is in both shards");
+//! };
+//! assert_eq!(err.to_string(), "client 1 appears in both a and b");
+//!
+//! // regional runs sharing client 1
+//! let mut eu = shard(1, 1);
+//! assert_eq!(eu.merge(shard(1, 2)), Err(MergeConflict::Client(1)));
+//! eu.merge_with(shard(1, 2), MergePolicy::SumBalances).unwrap();
+//! assert_eq!(eu.accounts[&1].available, dec!(2));
+//! assert_eq!(eu.merge(shard(3, 2)), Err(MergeConflict::Deposit(2)));
+//! ```
+
+use crate::engine::Engine;
+use crate::errors::Result;
+use crate::fasthash::Map;
+use crate::models::Account;
+use anyhow::bail;
+use std::collections::HashMap;
+use std::fmt;
+
+/// What two engines both have, so that they cannot be merged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MergeConflict {
+    Client(u16),
+    /// A deposit id, including ids of reclaimed deposits.
+    Deposit(u32),
+    Withdrawal(u32),
+    Hold(u32),
+}
+
+impl fmt::Display for MergeConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MergeConflict::Client(client) => write!(f, "client {client} is in both engines"),
+            MergeConflict::Deposit(tx) => write!(f, "deposit tx {tx} is in both engines"),
+            MergeConflict::Withdrawal(tx) => write!(f, "withdrawal tx {tx} is in both engines"),
+            MergeConflict::Hold(tx) => write!(f, "hold tx {tx} is in both engines"),
+        }
+    }
+}
+
+impl std::error::Error for MergeConflict {}
+
+/// How [`Engine::merge_with`] treats a client both engines have.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MergePolicy {
+    /// Refuse with [`MergeConflict::Client`].
+    #[default]
+    Reject,
+    /// Add available and held funds up; locked if locked in either.
+    /// Histories of the client are concatenated, this engine's first.
+    SumBalances,
+}
+
+impl Engine {
+    /// Fold in `other`, built independently of this engine, under
+    /// [`MergePolicy::Reject`]. Row counters ([`Engine::seq`],
+    /// [`Engine::input_rows`]) add up; configuration, sinks and alert
+    /// settings stay this engine's. On a conflict nothing is changed.
+    pub fn merge(&mut self, other: Engine) -> std::result::Result<(), MergeConflict> {
+        self.merge_with(other, MergePolicy::Reject)
+    }
+
+    /// [`Engine::merge`] with an explicit policy for shared clients.
+    pub fn merge_with(
+        &mut self,
+        mut other: Engine,
+        policy: MergePolicy,
+    ) -> std::result::Result<(), MergeConflict> {
+        if let Some(conflict) = other.claims(policy).find(|c| self.claimed(*c)) {
+            return Err(conflict);
+        }
+        let (seq, input_rows) = (self.seq + other.seq, self.input_rows + other.input_rows);
+        let shared: Vec<u16> = other
+            .accounts
+            .keys()
+            .copied()
+            .filter(|c| self.accounts.contains_key(c))
+            .collect();
+        for client in shared {
+            let theirs = other.accounts.remove(&client).expect("shared client");
+            let mine = self.accounts.get_mut(&client).expect("shared client");
+            mine.available += theirs.available;
+            mine.held += theirs.held;
+            mine.locked |= theirs.locked;
+            if let (Some(mine), Some(theirs)) = (self.history.as_mut(), other.history.as_mut())
+                && let Some(entries) = theirs.remove(&client)
+            {
+                mine.entry(client).or_default().extend(entries);
+            }
+        }
+        self.absorb(other);
+        self.seq = seq;
+        self.input_rows = input_rows;
+        Ok(())
+    }
+
+    /// Everything another engine must not also have, under `policy`.
+    fn claims(&self, policy: MergePolicy) -> impl Iterator<Item = MergeConflict> + '_ {
+        let clients = self.accounts.keys().map(|&c| MergeConflict::Client(c));
+        let clients = clients.filter(move |_| policy == MergePolicy::Reject);
+        let deposits = self.deposits.keys().chain(self.reclaimed.keys());
+        clients
+            .chain(deposits.map(|&tx| MergeConflict::Deposit(tx)))
+            .chain(
+                self.withdrawals
+                    .keys()
+                    .map(|&tx| MergeConflict::Withdrawal(tx)),
+            )
+            .chain(self.holds.keys().map(|&tx| MergeConflict::Hold(tx)))
+    }
+
+    fn claimed(&self, conflict: MergeConflict) -> bool {
+        match conflict {
+            MergeConflict::Client(c) => self.accounts.contains_key(&c),
+            MergeConflict::Deposit(tx) => {
+                self.deposits.contains_key(&tx) || self.reclaimed.contains_key(&tx)
+            }
+            MergeConflict::Withdrawal(tx) => self.withdrawals.contains_key(&tx),
+            MergeConflict::Hold(tx) => self.holds.contains_key(&tx),
+        }
+    }
+}
+
+/// An engine holding only the accounts of a shard's report (see
+/// [`crate::report::read_accounts`]), for merging reports rather than
+/// snapshots. It has no transactions, so later disputes cannot be applied.
+pub fn from_report(accounts: Map<u16, Account>) -> Engine {
+    let mut eng = Engine::new();
+    eng.accounts = accounts;
+    eng
+}
+
+/// Merge `(label, engine)` shards into one engine with [`Engine::merge`];
+/// labels only name the shards in errors.
+pub fn merge_shards(shards: Vec<(String, Engine)>) -> Result<Engine> {
+    merge_shards_with(shards, MergePolicy::Reject)
+}
+
+/// [`merge_shards`] with an explicit policy for shared clients.
+pub fn merge_shards_with(shards: Vec<(String, Engine)>, policy: MergePolicy) -> Result<Engine> {
+    // who claimed what first, to name both shards in a conflict
+    let mut owners: HashMap<MergeConflict, usize> = HashMap::new();
+    for (i, (_, eng)) in shards.iter().enumerate() {
+        for claim in eng.claims(policy) {
+            if let Some(first) = owners.insert(claim, i) {
+                let (a, b) = (&shards[first].0, &shards[i].0);
+                match claim {
+                    MergeConflict::Client(c) => bail!("client {c} appears in both {a} and {b}"),
+                    MergeConflict::Deposit(tx)
+                    | MergeConflict::Withdrawal(tx)
+                    | MergeConflict::Hold(tx) => bail!("tx {tx} recorded in both {a} and {b}"),
+                }
+            }
+        }
+    }
+
+    let mut merged = Engine::new();
+    for (_, eng) in shards {
+        merged
+            .merge_with(eng, policy)
+            .expect("conflicts were checked above");
+    }
+    Ok(merged)
+}
@@ -7,7 +7,7 @@ use serde::{Deserialize, Serialize};
 ///
 /// We derive `PartialEq`/`Eq` so we can compare directly
 /// (e.g. `kind == TxType::Deposit`).
-#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
 #[serde(rename_all = "lowercase")]
 pub enum TxType {
     Deposit,
@@ -15,12 +15,84 @@ pub enum TxType {
     Dispute,
     Resolve,
     Chargeback,
+    /// Card authorization: reserve funds (see [`crate::hold`]).
+    Hold,
+    /// Return an authorization's funds to available.
+    Release,
+    /// Settle an authorization as a withdrawal.
+    Capture,
+    /// Credit back (part of) an earlier withdrawal, referenced by its tx id.
+    Refund,
+    /// Shift available funds between two buckets of the account (see
+    /// [`crate::bucket`]).
+    Move,
+    /// Promotional credit, spent first and taken back if it expires unused
+    /// (see [`crate::promo`]).
+    Promo,
+}
+
+impl TxType {
+    /// Every variant, in declaration order.
+    pub const ALL: [TxType; 11] = [
+        TxType::Deposit,
+        TxType::Withdrawal,
+        TxType::Dispute,
+        TxType::Resolve,
+        TxType::Chargeback,
+        TxType::Hold,
+        TxType::Release,
+        TxType::Capture,
+        TxType::Refund,
+        TxType::Move,
+        TxType::Promo,
+    ];
+
+    /// Lowercase name as used in the CSV `type` column.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TxType::Deposit => "deposit",
+            TxType::Withdrawal => "withdrawal",
+            TxType::Dispute => "dispute",
+            TxType::Resolve => "resolve",
+            TxType::Chargeback => "chargeback",
+            TxType::Hold => "hold",
+            TxType::Release => "release",
+            TxType::Capture => "capture",
+            TxType::Refund => "refund",
+            TxType::Move => "move",
+            TxType::Promo => "promo",
+        }
+    }
+
+    /// `true` for kinds whose rows must carry a positive amount.
+    pub fn carries_amount(self) -> bool {
+        matches!(
+            self,
+            TxType::Deposit
+                | TxType::Withdrawal
+                | TxType::Hold
+                | TxType::Refund
+                | TxType::Move
+                | TxType::Promo
+        )
+    }
+}
+
+impl std::str::FromStr for TxType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TxType::ALL
+            .into_iter()
+            .find(|k| k.as_str() == s)
+            .ok_or_else(|| format!("unknown transaction type {s:?}"))
+    }
 }
 
 /// A single input row as parsed from the CSV.
 ///
 /// *The `amount` field is optional* – it is present **only**
-/// for `deposit` and `withdrawal` rows.
+/// for `deposit`, `withdrawal`, `hold`, `refund` and `move` rows.
 #[derive(Debug, Deserialize)]
 pub struct Transaction {
     /// Operation type (deposit, withdrawal, …).
@@ -28,10 +100,13 @@ pub struct Transaction {
     pub kind: TxType,
     /// Client identifier (0-65 535).
     pub client: u16,
-    /// Unique transaction id (0-4 294 967 295).
+    /// Unique transaction id (0-4 294 967 295); for dispute, resolve,
+    /// chargeback, release, capture and refund rows, the id of the
+    /// transaction they refer to.
     pub tx: u32,
-    /// Monetary amount (only for deposit / withdrawal).
-    #[serde(default)]
+    /// Monetary amount (only for deposit / withdrawal / hold / refund /
+    /// move).
+    #[serde(default, deserialize_with = "crate::amount::deserialize")]
     pub amount: Option<Decimal>,
 }
 
@@ -40,7 +115,7 @@ pub struct Transaction {
 /// * `available` – funds free to use or withdraw  
 /// * `held`      – funds locked in ongoing disputes  
 /// * `locked`    – `true` after a successful chargeback
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone, PartialEq)]
 pub struct Account {
     pub available: Decimal,
     pub held: Decimal,
@@ -0,0 +1,175 @@
+//! Account flags and notes: operational context (`manual-review`, `vip`,
+//! "called the client on 3 May") kept with the engine state instead of in
+//! a spreadsheet next to it.
+//!
+//! A flag is a short label, set at most once per account; a note is free
+//! text stamped with the [`Engine::seq`] it was written at and who wrote
+//! it. Both are set by admins through [`Engine::flag`] and
+//! [`Engine::add_note`] (the `annotate` subcommand edits a snapshot this
+//! way), or by rules: an [`AlertFlag`] in
+//! [`EngineConfig::alert_flags`](crate::engine::EngineConfig::alert_flags)
+//! flags the client of every alert of one kind, with a note quoting the
+//! alert the first time.
+//!
+//! Annotations never change balances and are not part of
+//! [`Engine::state_hash`]. They are saved in snapshots, undone with a
+//! [`Engine::fork`], and `--report-flags` adds them to the accounts report.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::engine::EngineConfig;
+//! use payments_engine::{Engine, Transaction, TxType};
+//! use rust_decimal_macros::dec;
+//!
+//! let config = EngineConfig {
+//!     alert_flags: vec!["account_locked=manual-review".parse().unwrap()],
+//!     ..Default::default()
+//! };
+//! let mut eng = Engine::with_config(config);
+//! eng.flag(2, "vip");
+//! eng.add_note(2, "ops", "prefers email");
+//! for kind in [TxType::Deposit, TxType::Dispute, TxType::Chargeback] {
+//!     let amount = (kind == TxType::Deposit).then_some(dec!(5));
+//!     eng.process(Transaction { kind, client: 1, tx: 1, amount }).unwrap();
+//! }
+//!
+//! assert_eq!(eng.flags(2).collect::<Vec<_>>(), ["vip"]);
+//! let locked = eng.annotations(1).unwrap();
+//! assert!(locked.flags.contains("manual-review"));
+//! assert_eq!(locked.notes[0].by, "rule:account_locked");
+//! assert_eq!(locked.notes[0].seq, 3);
+//!
+//! let mut buf = Vec::new();
+//! eng.write_snapshot(&mut buf).unwrap();
+//! let restored = Engine::read_snapshot(buf.as_slice()).unwrap();
+//! assert_eq!(restored.annotations(1), eng.annotations(1));
+//! ```
+
+use crate::engine::Engine;
+use crate::notify::Event;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+/// A free-text note on an account.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Note {
+    /// [`Engine::seq`] when the note was written.
+    pub seq: u64,
+    /// Who wrote it: an operator name, or `rule:<event>` for rules.
+    pub by: String,
+    pub text: String,
+}
+
+/// Flags and notes of one account.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Annotations {
+    pub flags: BTreeSet<String>,
+    /// Oldest first.
+    pub notes: Vec<Note>,
+}
+
+/// Rule flagging the client of every alert named `event` (one of
+/// [`Event::NAMES`]). Parses from `EVENT=FLAG`, e.g.
+/// `structuring=manual-review`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlertFlag {
+    pub event: &'static str,
+    pub flag: String,
+}
+
+impl FromStr for AlertFlag {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (event, flag) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected EVENT=FLAG, got {s:?}"))?;
+        let event = Event::NAMES
+            .into_iter()
+            .find(|&name| name == event)
+            .ok_or_else(|| {
+                format!(
+                    "unknown event {event:?} (one of {})",
+                    Event::NAMES.join(", ")
+                )
+            })?;
+        if flag.is_empty() {
+            return Err(format!("empty flag in {s:?}"));
+        }
+        Ok(Self {
+            event,
+            flag: flag.to_owned(),
+        })
+    }
+}
+
+impl Engine {
+    /// Flags and notes of `client`, if it has any.
+    pub fn annotations(&self, client: u16) -> Option<&Annotations> {
+        self.annotations.get(&client)
+    }
+
+    /// Flags of `client`, in alphabetical order.
+    pub fn flags(&self, client: u16) -> impl Iterator<Item = &str> + '_ {
+        self.annotations
+            .get(&client)
+            .into_iter()
+            .flat_map(|a| a.flags.iter().map(String::as_str))
+    }
+
+    /// Set `flag` on `client`; `false` if it was set already.
+    pub fn flag(&mut self, client: u16, flag: &str) -> bool {
+        if self.flags(client).any(|f| f == flag) {
+            return false;
+        }
+        self.annotate(client).flags.insert(flag.to_owned())
+    }
+
+    /// Clear `flag` on `client`; `false` if it was not set.
+    pub fn unflag(&mut self, client: u16, flag: &str) -> bool {
+        if !self.flags(client).any(|f| f == flag) {
+            return false;
+        }
+        let annotations = self.annotate(client);
+        annotations.flags.remove(flag);
+        if *annotations == Annotations::default() {
+            self.annotations.remove(&client);
+        }
+        true
+    }
+
+    /// Append a note by `by` to `client`.
+    pub fn add_note(&mut self, client: u16, by: &str, text: &str) {
+        let seq = self.seq;
+        self.annotate(client).notes.push(Note {
+            seq,
+            by: by.to_owned(),
+            text: text.to_owned(),
+        });
+    }
+
+    /// Apply the [`AlertFlag`] rules matching `event`.
+    pub(crate) fn flag_alert(&mut self, event: &Event) {
+        let flags: Vec<String> = self
+            .config()
+            .alert_flags
+            .iter()
+            .filter(|rule| rule.event == event.name())
+            .map(|rule| rule.flag.clone())
+            .collect();
+        for flag in flags {
+            if self.flag(event.client(), &flag) {
+                let by = format!("rule:{}", event.name());
+                let text = format!("flagged {flag}: {}", event.summary());
+                self.add_note(event.client(), &by, &text);
+            }
+        }
+    }
+
+    /// Annotations of `client` for writing, created if missing.
+    fn annotate(&mut self, client: u16) -> &mut Annotations {
+        self.save_annotations(client);
+        self.annotations.entry(client).or_default()
+    }
+}
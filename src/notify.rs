@@ -0,0 +1,234 @@
+//! Risk alerts raised while processing (chargebacks, locks, held-funds
+//! exposure, structuring, dispute bursts) and the sinks they are delivered to.
+//!
+//! Sinks are attached with [`Engine::add_sink`](crate::Engine::add_sink);
+//! every event goes to every sink in the order they were added. Transports
+//! live behind the [`NotificationSink`] trait so the same alerts work from
+//! any frontend.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::notify::{Event, NotificationSink};
+//! use payments_engine::{Engine, Transaction, TxType};
+//! use std::sync::{Arc, Mutex};
+//! use rust_decimal_macros::dec;
+//!
+//! struct Collect(Arc<Mutex<Vec<Event>>>);
+//! impl NotificationSink for Collect {
+//!     fn notify(&mut self, event: &Event) -> payments_engine::errors::Result<()> {
+//!         self.0.lock().unwrap().push(event.clone());
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let seen = Arc::new(Mutex::new(Vec::new()));
+//! let mut eng = Engine::new();
+//! eng.add_sink(Box::new(Collect(seen.clone())));
+//! for (kind, amount) in [(TxType::Deposit, Some(dec!(5))), (TxType::Dispute, None), (TxType::Chargeback, None)] {
+//!     eng.process(Transaction { kind, client: 1, tx: 1, amount }).unwrap();
+//! }
+//! assert_eq!(seen.lock().unwrap().len(), 2); // chargeback + lock
+//! ```
+
+use crate::errors::Result;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Something a risk team wants pushed to them rather than polled for.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A disputed deposit was charged back.
+    Chargeback {
+        client: u16,
+        tx: u32,
+        amount: Decimal,
+    },
+    /// The account was locked (currently always by a chargeback).
+    AccountLocked { client: u16, tx: u32 },
+    /// Held funds rose to or above the configured alert threshold.
+    HeldThreshold {
+        client: u16,
+        tx: u32,
+        held: Decimal,
+        threshold: Decimal,
+    },
+    /// Deposit `tx` completed a structuring pattern (see
+    /// [`crate::structuring`]) of these `deposits`.
+    Structuring {
+        client: u16,
+        tx: u32,
+        deposits: Vec<u32>,
+        total: Decimal,
+    },
+    /// The client opened more disputes in `batch` than the batch rules
+    /// allow (see [`crate::batch`]).
+    DisputeBurst {
+        client: u16,
+        batch: u64,
+        disputes: u32,
+        limit: u32,
+    },
+}
+
+impl Event {
+    /// Names returned by [`Event::name`].
+    pub const NAMES: [&'static str; 5] = [
+        "chargeback",
+        "account_locked",
+        "held_threshold",
+        "structuring",
+        "dispute_burst",
+    ];
+
+    /// The `event` tag of the serialised event, e.g. `account_locked`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::Chargeback { .. } => "chargeback",
+            Event::AccountLocked { .. } => "account_locked",
+            Event::HeldThreshold { .. } => "held_threshold",
+            Event::Structuring { .. } => "structuring",
+            Event::DisputeBurst { .. } => "dispute_burst",
+        }
+    }
+
+    /// The client the event is about.
+    pub fn client(&self) -> u16 {
+        match *self {
+            Event::Chargeback { client, .. }
+            | Event::AccountLocked { client, .. }
+            | Event::HeldThreshold { client, .. }
+            | Event::Structuring { client, .. }
+            | Event::DisputeBurst { client, .. } => client,
+        }
+    }
+
+    /// One-line human-readable summary.
+    pub fn summary(&self) -> String {
+        match self {
+            Event::Chargeback { client, tx, amount } => {
+                format!("chargeback of {amount} on client {client} (tx {tx})")
+            }
+            Event::AccountLocked { client, tx } => {
+                format!("client {client} locked (tx {tx})")
+            }
+            Event::HeldThreshold {
+                client,
+                held,
+                threshold,
+                ..
+            } => format!("client {client} holds {held} (threshold {threshold})"),
+            Event::Structuring {
+                client,
+                deposits,
+                total,
+                ..
+            } => format!(
+                "possible structuring by client {client}: {} deposits totalling {total} (tx {})",
+                deposits.len(),
+                deposits
+                    .iter()
+                    .map(u32::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Event::DisputeBurst {
+                client,
+                batch,
+                disputes,
+                limit,
+            } => format!(
+                "client {client} opened {disputes} disputes in batch {batch} (limit {limit})"
+            ),
+        }
+    }
+}
+
+/// Destination for [`Event`]s.
+pub trait NotificationSink: Send {
+    fn notify(&mut self, event: &Event) -> Result<()>;
+
+    /// Flush buffered output; called by [`Engine::flush_sinks`](crate::Engine::flush_sinks).
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Logs each event as a `WARN` line on the tracing subscriber (stderr in
+/// the CLI).
+pub struct StderrSink;
+
+impl NotificationSink for StderrSink {
+    fn notify(&mut self, event: &Event) -> Result<()> {
+        tracing::warn!(alert = %event.summary());
+        Ok(())
+    }
+}
+
+/// Appends one JSON object per event (NDJSON) to a writer.
+pub struct JsonLinesSink<W: Write> {
+    out: W,
+}
+
+impl JsonLinesSink<BufWriter<File>> {
+    /// Create (truncate) `path` and write events to it.
+    pub fn create(path: &Path) -> Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> JsonLinesSink<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+}
+
+impl<W: Write + Send> NotificationSink for JsonLinesSink<W> {
+    fn notify(&mut self, event: &Event) -> Result<()> {
+        serde_json::to_writer(&mut self.out, event)?;
+        self.out.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// Writes Slack incoming-webhook payloads (`{"text": …}`), one per line,
+/// ready to be POSTed by whatever transport the deployment uses.
+pub struct SlackSink<W: Write> {
+    out: W,
+}
+
+impl SlackSink<BufWriter<File>> {
+    /// Create (truncate) `path` and write payloads to it.
+    pub fn create(path: &Path) -> Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> SlackSink<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+}
+
+impl<W: Write + Send> NotificationSink for SlackSink<W> {
+    fn notify(&mut self, event: &Event) -> Result<()> {
+        let payload =
+            serde_json::json!({ "text": format!(":rotating_light: {}", event.summary()) });
+        serde_json::to_writer(&mut self.out, &payload)?;
+        self.out.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
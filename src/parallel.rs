@@ -0,0 +1,244 @@
+//! Multi-threaded engine: clients are sharded over worker threads, each
+//! running its own [`Engine`].
+//!
+//! A row goes to worker [`shard_of`]`(client, threads)`, so every client's
+//! rows are applied in input order by a single worker and the outcome does
+//! not depend on thread scheduling. Rows keep their global sequence number, which makes
+//! hold expiry and recorded rejections line up with the single-threaded
+//! engine. [`ParallelEngine::finish`] merges the shards into one [`Engine`]
+//! for reporting and snapshots.
+//!
+//! Sharding cannot see a tx id reused across clients: the single-threaded
+//! engine rejects the second deposit, withdrawal or hold as `duplicate-tx`,
+//! while two shards may both apply it. The `selfcheck` subcommand runs both
+//! engines over the same input and compares their [`Engine::state_hash`].
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::parallel::ParallelEngine;
+//! use payments_engine::processor::PaymentsProcessor;
+//! use payments_engine::{Engine, Transaction, TxType};
+//! use rust_decimal_macros::dec;
+//!
+//! let rows = || {
+//!     (1..=100u32).map(|tx| Transaction {
+//!         kind: if tx % 3 == 0 { TxType::Withdrawal } else { TxType::Deposit },
+//!         client: (tx % 7) as u16,
+//!         tx,
+//!         amount: Some(dec!(2)),
+//!     })
+//! };
+//! let mut single = Engine::new();
+//! let mut parallel = ParallelEngine::new(4, Default::default());
+//! for (a, b) in rows().zip(rows()) {
+//!     single.process(a).unwrap();
+//!     parallel.process(b).unwrap();
+//! }
+//! assert_eq!(parallel.account(3), single.accounts.get(&3).cloned());
+//! assert_eq!(parallel.finish().unwrap().state_hash(), single.state_hash());
+//! ```
+
+use crate::engine::{Engine, EngineConfig, Finalized};
+use crate::errors::Result;
+use crate::models::{Account, Transaction};
+use crate::processor::{PaymentsProcessor, Stats};
+use anyhow::{anyhow, bail};
+use std::cell::RefCell;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+/// Rows buffered per shard before they are handed to its worker.
+const BATCH: usize = 1024;
+
+enum Msg {
+    /// `(seq, row)` pairs in input order.
+    Rows(Vec<(u64, Transaction)>),
+    /// Run against the worker's engine once earlier rows are applied.
+    Query(Box<dyn FnOnce(&Engine) + Send>),
+}
+
+struct Shard {
+    tx: Sender<Msg>,
+    buf: RefCell<Vec<(u64, Transaction)>>,
+    worker: Option<JoinHandle<Result<Engine>>>,
+}
+
+impl Shard {
+    fn spawn(config: EngineConfig) -> Self {
+        let (tx, rx) = mpsc::channel();
+        Self {
+            tx,
+            buf: RefCell::new(Vec::with_capacity(BATCH)),
+            worker: Some(thread::spawn(move || run(config, rx))),
+        }
+    }
+
+    /// Hand buffered rows to the worker; `false` if it has stopped.
+    fn flush(&self) -> bool {
+        let rows = self.buf.replace(Vec::with_capacity(BATCH));
+        rows.is_empty() || self.tx.send(Msg::Rows(rows)).is_ok()
+    }
+
+    /// Apply `f` to the worker's engine after every row sent so far.
+    fn query<R: Send + 'static>(&self, f: impl FnOnce(&Engine) -> R + Send + 'static) -> Option<R> {
+        let (reply, rx) = mpsc::channel();
+        let job = Box::new(move |eng: &Engine| {
+            let _ = reply.send(f(eng));
+        });
+        if !self.flush() || self.tx.send(Msg::Query(job)).is_err() {
+            return None;
+        }
+        rx.recv().ok()
+    }
+
+    /// Wait for a stopped worker and return why it stopped.
+    fn failure(&mut self) -> anyhow::Error {
+        match self.worker.take().map(JoinHandle::join) {
+            Some(Ok(Err(e))) => e,
+            Some(Err(_)) => anyhow!("shard worker panicked"),
+            _ => anyhow!("shard worker stopped"),
+        }
+    }
+}
+
+fn run(config: EngineConfig, rx: Receiver<Msg>) -> Result<Engine> {
+    let mut eng = Engine::with_config(config);
+    for msg in rx {
+        match msg {
+            Msg::Rows(rows) => {
+                for (seq, tx) in rows {
+                    eng.seq = seq - 1;
+                    eng.process(tx)?;
+                }
+            }
+            Msg::Query(f) => f(&eng),
+        }
+    }
+    Ok(eng)
+}
+
+/// Shard (out of `shards`) that owns `client`. Also used by the `split`
+/// subcommand, so split files line up with the worker threads.
+pub fn shard_of(client: u16, shards: usize) -> usize {
+    client as usize % shards
+}
+
+/// [`Engine`] sharded by client over a fixed number of worker threads.
+///
+/// Errors from a worker ([`Action::Fatal`](crate::anomaly::Action::Fatal)
+/// anomalies) surface on a later [`process`](PaymentsProcessor::process) or
+/// on [`finish`](ParallelEngine::finish); rows already handed to other
+/// workers are still applied.
+pub struct ParallelEngine {
+    shards: Vec<Shard>,
+    config: EngineConfig,
+    /// Rows passed to [`PaymentsProcessor::process`] so far.
+    seq: u64,
+    /// The merged engine, once [`PaymentsProcessor::finalize`] has run.
+    merged: Option<Engine>,
+}
+
+impl ParallelEngine {
+    /// Start `threads` workers (at least one), each with its own copy of
+    /// `config`.
+    pub fn new(threads: usize, config: EngineConfig) -> Self {
+        Self {
+            shards: (0..threads.max(1))
+                .map(|_| Shard::spawn(config.clone()))
+                .collect(),
+            config,
+            seq: 0,
+            merged: None,
+        }
+    }
+
+    /// Wait for all workers and merge their state into a single engine,
+    /// as if it had processed every row itself.
+    pub fn finish(mut self) -> Result<Engine> {
+        self.merge()?;
+        Ok(self.merged.take().expect("merged above"))
+    }
+
+    fn merge(&mut self) -> Result<()> {
+        if self.merged.is_some() {
+            return Ok(());
+        }
+        let mut merged = Engine::with_config(self.config.clone());
+        merged.seq = self.seq;
+        for mut shard in std::mem::take(&mut self.shards) {
+            if !shard.flush() {
+                return Err(shard.failure());
+            }
+            drop(shard.tx);
+            let mut eng = match shard.worker.take().map(JoinHandle::join) {
+                Some(Ok(eng)) => eng?,
+                _ => bail!("shard worker panicked"),
+            };
+            // a shard only sweeps expired holds when it receives a row;
+            // catch up to the end of the input
+            eng.seq = self.seq;
+            eng.expire_holds();
+            merged.absorb(eng);
+        }
+        self.merged = Some(merged);
+        Ok(())
+    }
+
+    fn shard(&self, client: u16) -> usize {
+        shard_of(client, self.shards.len())
+    }
+}
+
+impl PaymentsProcessor for ParallelEngine {
+    fn process(&mut self, tx: Transaction) -> Result<()> {
+        if self.merged.is_some() {
+            bail!("parallel engine already finalized");
+        }
+        self.seq += 1;
+        let i = self.shard(tx.client);
+        let shard = &mut self.shards[i];
+        let full = {
+            let mut buf = shard.buf.borrow_mut();
+            buf.push((self.seq, tx));
+            buf.len() >= BATCH
+        };
+        if full && !shard.flush() {
+            return Err(shard.failure());
+        }
+        Ok(())
+    }
+
+    /// Balances after every row processed so far; `None` also when the
+    /// client's worker has stopped on an error.
+    fn account(&self, client: u16) -> Option<Account> {
+        if let Some(eng) = &self.merged {
+            return eng.accounts.get(&client).cloned();
+        }
+        self.shards[self.shard(client)]
+            .query(move |eng| eng.accounts.get(&client).cloned())
+            .flatten()
+    }
+
+    fn finalize(&mut self) -> Result<Finalized> {
+        self.merge()?;
+        self.merged.as_mut().expect("merged above").finalize()
+    }
+
+    fn stats(&self) -> Stats {
+        if let Some(eng) = &self.merged {
+            return eng.stats();
+        }
+        let mut total = Stats {
+            rows: self.seq,
+            ..Stats::default()
+        };
+        for shard in &self.shards {
+            if let Some(s) = shard.query(|eng| eng.stats()) {
+                total.accounts += s.accounts;
+                total.open_disputes += s.open_disputes;
+                total.active_holds += s.active_holds;
+            }
+        }
+        total
+    }
+}
@@ -0,0 +1,130 @@
+//! The [`PaymentsProcessor`] trait: what a frontend needs from an engine.
+//!
+//! The CLI ingest loop and integration tests are written against this trait
+//! rather than [`Engine`] itself, so another implementation (sharded,
+//! persistent, remote) can be dropped in without touching them. [`Engine`]
+//! is the reference implementation.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::processor::PaymentsProcessor;
+//! use payments_engine::{Engine, Transaction, TxType};
+//! use rust_decimal_macros::dec;
+//!
+//! fn deposit_twice(p: &mut impl PaymentsProcessor) {
+//!     for tx in [1, 2] {
+//!         p.process(Transaction { kind: TxType::Deposit, client: 7, tx, amount: Some(dec!(1.5)) })
+//!             .unwrap();
+//!     }
+//! }
+//!
+//! let mut eng = Engine::new();
+//! deposit_twice(&mut eng);
+//! assert_eq!(eng.account(7).unwrap().available, dec!(3));
+//! assert_eq!(eng.stats().rows, 2);
+//! assert_eq!(eng.finalize().unwrap().accounts, 1);
+//! ```
+
+use crate::bucket::RowBuckets;
+use crate::engine::{Engine, Finalized};
+use crate::errors::Result;
+use crate::feed::ProcessResult;
+use crate::hold;
+use crate::models::{Account, Transaction};
+
+/// What a row carries besides its [`Transaction`]: an idempotency key (see
+/// [`crate::idempotency`]; `None` or empty for none), the buckets it names
+/// (see [`crate::bucket`]) and the tenant it is routed to (see
+/// [`crate::tenant`]; engines ignore it). The default is a plain row.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RowMeta<'a> {
+    pub key: Option<&'a str>,
+    pub buckets: RowBuckets<'a>,
+    pub tenant: Option<&'a str>,
+}
+
+impl<'a> RowMeta<'a> {
+    /// A row carrying idempotency key `key`, in the main bucket.
+    pub fn keyed(key: Option<&'a str>) -> Self {
+        Self {
+            key,
+            ..Self::default()
+        }
+    }
+
+    /// The key, if the row carries a non-empty one.
+    pub fn key(&self) -> Option<&'a str> {
+        self.key.filter(|k| !k.is_empty())
+    }
+}
+
+/// Point-in-time counters of a [`PaymentsProcessor`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Stats {
+    /// Rows passed to [`PaymentsProcessor::process`].
+    pub rows: u64,
+    pub accounts: usize,
+    pub open_disputes: usize,
+    pub active_holds: usize,
+}
+
+/// A streaming payments engine as seen by frontends.
+pub trait PaymentsProcessor {
+    /// Apply one row; see [`Engine::process`] for the error contract.
+    fn process(&mut self, tx: Transaction) -> Result<()>;
+
+    /// Apply one row with what it carries besides the transaction; see
+    /// [`Engine::process_row`]. Returns what the row did where the
+    /// processor knows it at once; `None` otherwise (a sharded processor
+    /// applies rows later, on its workers). Processors without key or
+    /// bucket support refuse rows carrying a key or naming a bucket other
+    /// than main, rather than apply them unchecked.
+    fn process_row(&mut self, tx: Transaction, meta: RowMeta) -> Result<Option<ProcessResult>> {
+        if meta.key().is_some() {
+            anyhow::bail!("idempotency keys are not supported by this processor");
+        }
+        if !meta.buckets.is_main() {
+            anyhow::bail!("buckets are not supported by this processor");
+        }
+        self.process(tx).map(|()| None)
+    }
+
+    /// Current balances of `client`, if any row created the account.
+    fn account(&self, client: u16) -> Option<Account>;
+
+    /// End-of-input hook, called exactly once; see [`Engine::finalize`].
+    fn finalize(&mut self) -> Result<Finalized>;
+
+    fn stats(&self) -> Stats;
+}
+
+impl PaymentsProcessor for Engine {
+    fn process(&mut self, tx: Transaction) -> Result<()> {
+        Engine::process(self, tx)
+    }
+
+    fn process_row(&mut self, tx: Transaction, meta: RowMeta) -> Result<Option<ProcessResult>> {
+        Engine::process_row(self, tx, meta).map(Some)
+    }
+
+    fn account(&self, client: u16) -> Option<Account> {
+        self.accounts.get(&client).cloned()
+    }
+
+    fn finalize(&mut self) -> Result<Finalized> {
+        Engine::finalize(self)
+    }
+
+    fn stats(&self) -> Stats {
+        Stats {
+            rows: self.seq,
+            accounts: self.accounts.len(),
+            open_disputes: self.open_disputes().count(),
+            active_holds: self
+                .holds
+                .values()
+                .filter(|h| h.state == hold::State::Active)
+                .count(),
+        }
+    }
+}
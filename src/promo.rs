@@ -0,0 +1,175 @@
+//! Promotional balances: credits a wallet grants its clients that are
+//! spent before their own money and expire if left unused.
+//!
+//! A `promo,client,tx,amount` row credits available like a deposit, and
+//! takes a tx id from the same space as deposits, but cannot be disputed.
+//! Withdrawals use up the client's promotional credits first, oldest
+//! first, and only then its own funds; holds, captures and moves leave them
+//! alone.
+//!
+//! With
+//! [`EngineConfig::promo_expiry_secs`](crate::engine::EngineConfig::promo_expiry_secs),
+//! a credit expires that many seconds of [`Clock`](crate::clock::Clock)
+//! time after it was granted: before each row, whatever is left of every
+//! expired credit is taken out of available, which may go negative if the
+//! rest is held. Watchers see an expiry as a `promo` delta taking the value
+//! back. Without a window, promotional credits never expire. Every credit
+//! stays on record, with how much of it was used and how much expired, for
+//! [`Engine::promos`] and `--promos-output`.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::clock::ManualClock;
+//! use payments_engine::engine::EngineConfig;
+//! use payments_engine::{Engine, Transaction, TxType};
+//! use rust_decimal_macros::dec;
+//!
+//! let config = EngineConfig { promo_expiry_secs: Some(86_400), ..Default::default() };
+//! let mut eng = Engine::with_config(config);
+//! let clock = ManualClock::new(0);
+//! eng.set_clock(Box::new(clock.clone()));
+//! let row = |kind, tx, amount| Transaction { kind, client: 1, tx, amount: Some(amount) };
+//!
+//! eng.process(row(TxType::Deposit, 1, dec!(50))).unwrap();
+//! eng.process(row(TxType::Promo, 2, dec!(20))).unwrap();
+//! eng.process(row(TxType::Withdrawal, 3, dec!(15))).unwrap(); // all promotional
+//! assert_eq!(eng.promos(1)[0].left(), dec!(5));
+//!
+//! clock.advance(86_401);
+//! eng.process(row(TxType::Deposit, 4, dec!(1))).unwrap();
+//! let grant = eng.promos(1)[0];
+//! assert_eq!((grant.used, grant.expired), (dec!(15), dec!(5)));
+//! assert_eq!(eng.accounts[&1].available, dec!(51));
+//! ```
+
+use crate::engine::Engine;
+use crate::fasthash::Map;
+use crate::feed::AccountDelta;
+use crate::models::TxType;
+use rust_decimal::Decimal;
+use std::collections::VecDeque;
+
+/// One promotional credit and what became of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Grant {
+    pub tx: u32,
+    pub amount: Decimal,
+    /// Taken by withdrawals.
+    pub used: Decimal,
+    /// Taken back unused when the credit expired.
+    pub expired: Decimal,
+    /// Clock time after which it expires, if it does.
+    pub expires: Option<u64>,
+}
+
+impl Grant {
+    /// What is left to spend.
+    pub fn left(&self) -> Decimal {
+        self.amount - self.used - self.expired
+    }
+}
+
+/// Promotional credits by client in grant order, the client of every
+/// promotional tx id, and the credits waiting to expire in expiry order.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Book {
+    pub(crate) grants: Map<u16, Vec<Grant>>,
+    pub(crate) ids: Map<u32, u16>,
+    pub(crate) queue: VecDeque<(u64, u32)>,
+}
+
+impl Book {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// `true` if tx id `tx` was taken by a promotional credit.
+    pub(crate) fn contains(&self, tx: u32) -> bool {
+        self.ids.contains_key(&tx)
+    }
+
+    pub(crate) fn grant(&mut self, client: u16, grant: Grant) {
+        if let Some(at) = grant.expires {
+            self.queue.push_back((at, grant.tx));
+        }
+        self.ids.insert(grant.tx, client);
+        self.grants.entry(client).or_default().push(grant);
+    }
+
+    /// Use up to `amount` of the credits of `client`, oldest first.
+    pub(crate) fn consume(&mut self, client: u16, mut amount: Decimal) {
+        let Some(grants) = self.grants.get_mut(&client) else {
+            return;
+        };
+        for g in grants.iter_mut() {
+            if amount.is_zero() {
+                break;
+            }
+            let take = g.left().min(amount);
+            g.used += take;
+            amount -= take;
+        }
+    }
+
+    /// Expire credit `tx` of `client`; returns what was left of it.
+    fn expire(&mut self, client: u16, tx: u32) -> Decimal {
+        let grants = self.grants.get_mut(&client);
+        let Some(g) = grants.and_then(|gs| gs.iter_mut().find(|g| g.tx == tx)) else {
+            return Decimal::ZERO;
+        };
+        let left = g.left();
+        g.expired += left;
+        left
+    }
+}
+
+impl Engine {
+    /// Every promotional credit of `client`, in grant order.
+    pub fn promos(&self, client: u16) -> &[Grant] {
+        self.promos.grants.get(&client).map_or(&[], Vec::as_slice)
+    }
+
+    /// Clients granted a promotional credit, in ascending order.
+    pub fn promo_clients(&self) -> Vec<u16> {
+        let mut clients: Vec<u16> = self.promos.grants.keys().copied().collect();
+        clients.sort_unstable();
+        clients
+    }
+
+    /// Take back what is left of every promotional credit whose window
+    /// ended before the current row.
+    pub(crate) fn expire_promos(&mut self) {
+        if self.promos.queue.is_empty() {
+            return;
+        }
+        let now = self.now();
+        while let Some(&(at, tx)) = self.promos.queue.front() {
+            if at >= now {
+                break;
+            }
+            self.promos.queue.pop_front();
+            let client = self.promos.ids[&tx];
+            self.save_promo_expiry((at, tx), client);
+            let left = self.promos.expire(client, tx);
+            if left.is_zero() {
+                continue;
+            }
+            let acc = self
+                .accounts
+                .get_mut(&client)
+                .expect("promo has an account");
+            acc.available -= left;
+            self.touched.insert(client);
+            self.send(AccountDelta {
+                seq: self.seq(),
+                client,
+                tx,
+                kind: TxType::Promo,
+                available: -left,
+                held: Decimal::ZERO,
+                locked: false,
+            });
+            self.record_history(client, tx, TxType::Promo);
+        }
+    }
+}
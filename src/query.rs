@@ -0,0 +1,200 @@
+//! In-memory queries over an engine's accounts, for embedders that want to
+//! answer operational questions ("which locked accounts still hold funds?")
+//! without exporting a report into a database first.
+//!
+//! [`Engine::query`] starts a [`Query`]; each filter narrows it down and the
+//! terminal methods return matching accounts in ascending client order,
+//! either whole ([`Query::rows`]) or projected onto chosen [`Column`]s
+//! ([`Query::select`]).
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::query::{Column, Value};
+//! use payments_engine::{Engine, Transaction, TxType};
+//! use rust_decimal_macros::dec;
+//!
+//! let mut eng = Engine::new();
+//! for (client, tx, amount) in [(1, 1, dec!(50)), (2, 2, dec!(5000)), (3, 3, dec!(9000))] {
+//!     eng.process(Transaction { kind: TxType::Deposit, client, tx, amount: Some(amount) })
+//!         .unwrap();
+//! }
+//! eng.process(Transaction { kind: TxType::Dispute, client: 3, tx: 3, amount: None })
+//!     .unwrap();
+//!
+//! let big = eng.query().locked(false).total_above(dec!(1000)).clients();
+//! assert_eq!(big, [2, 3]);
+//!
+//! let disputed = eng
+//!     .query()
+//!     .with_open_disputes()
+//!     .select(&[Column::Client, Column::Held, Column::OpenDisputes]);
+//! assert_eq!(disputed, [vec![Value::Client(3), Value::Amount(dec!(9000)), Value::Count(1)]]);
+//! ```
+
+use crate::engine::Engine;
+use crate::models::Account;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+/// One matching account as seen by filters and returned by [`Query::rows`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row<'a> {
+    pub client: u16,
+    pub account: &'a Account,
+    /// Disputes currently holding funds of this client (deposits and
+    /// refunds alike).
+    pub open_disputes: usize,
+}
+
+/// Something [`Query::select`] can project a row onto.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    Client,
+    Available,
+    Held,
+    Total,
+    Locked,
+    OpenDisputes,
+}
+
+/// A projected value; displays like the accounts report does.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Client(u16),
+    Amount(Decimal),
+    Flag(bool),
+    Count(usize),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Client(c) => write!(f, "{c}"),
+            Value::Amount(a) => write!(f, "{:.4}", a.round_dp(4)),
+            Value::Flag(b) => write!(f, "{b}"),
+            Value::Count(n) => write!(f, "{n}"),
+        }
+    }
+}
+
+type Predicate<'a> = Box<dyn Fn(&Row<'_>) -> bool + 'a>;
+
+/// Builder for a query over one engine's accounts; see the module docs.
+/// Filters combine with AND.
+pub struct Query<'a> {
+    engine: &'a Engine,
+    predicates: Vec<Predicate<'a>>,
+}
+
+impl Engine {
+    /// Start a [`Query`] over every account.
+    pub fn query(&self) -> Query<'_> {
+        Query {
+            engine: self,
+            predicates: Vec::new(),
+        }
+    }
+}
+
+impl<'a> Query<'a> {
+    /// Keep rows passing `pred`, for anything the named filters do not cover.
+    pub fn filter(mut self, pred: impl Fn(&Row<'_>) -> bool + 'a) -> Self {
+        self.predicates.push(Box::new(pred));
+        self
+    }
+
+    /// Keep locked (`true`) or unlocked (`false`) accounts.
+    pub fn locked(self, locked: bool) -> Self {
+        self.filter(move |r| r.account.locked == locked)
+    }
+
+    /// Keep accounts whose total is strictly above `amount`.
+    pub fn total_above(self, amount: Decimal) -> Self {
+        self.filter(move |r| r.account.total() > amount)
+    }
+
+    /// Keep accounts whose total is strictly below `amount`.
+    pub fn total_below(self, amount: Decimal) -> Self {
+        self.filter(move |r| r.account.total() < amount)
+    }
+
+    /// Keep accounts whose available funds are strictly below `amount`
+    /// (negative after a chargeback, for instance).
+    pub fn available_below(self, amount: Decimal) -> Self {
+        self.filter(move |r| r.account.available < amount)
+    }
+
+    /// Keep accounts holding strictly more than `amount`.
+    pub fn held_above(self, amount: Decimal) -> Self {
+        self.filter(move |r| r.account.held > amount)
+    }
+
+    /// Keep accounts with at least one open dispute.
+    pub fn with_open_disputes(self) -> Self {
+        self.filter(|r| r.open_disputes > 0)
+    }
+
+    /// Keep clients in `range`.
+    pub fn clients_in(self, range: RangeInclusive<u16>) -> Self {
+        self.filter(move |r| range.contains(&r.client))
+    }
+
+    /// Keep accounts changed by at least one applied row (see
+    /// [`Engine::is_touched`]).
+    pub fn touched(self) -> Self {
+        let engine = self.engine;
+        self.filter(move |r| engine.is_touched(r.client))
+    }
+
+    /// Matching rows in ascending client order. Counting open disputes
+    /// walks the stored deposits and withdrawals once per call.
+    pub fn rows(&self) -> Vec<Row<'a>> {
+        let mut open: HashMap<u16, usize> = HashMap::new();
+        for (_, client, _) in self.engine.open_disputes() {
+            *open.entry(client).or_default() += 1;
+        }
+        let mut rows: Vec<Row<'a>> = self
+            .engine
+            .accounts
+            .iter()
+            .map(|(&client, account)| Row {
+                client,
+                account,
+                open_disputes: open.get(&client).copied().unwrap_or(0),
+            })
+            .filter(|r| self.predicates.iter().all(|p| p(r)))
+            .collect();
+        rows.sort_unstable_by_key(|r| r.client);
+        rows
+    }
+
+    /// Client ids of the matching accounts, ascending.
+    pub fn clients(&self) -> Vec<u16> {
+        self.rows().iter().map(|r| r.client).collect()
+    }
+
+    pub fn count(&self) -> usize {
+        self.rows().len()
+    }
+
+    /// Matching rows projected onto `columns`, in that order.
+    pub fn select(&self, columns: &[Column]) -> Vec<Vec<Value>> {
+        self.rows()
+            .iter()
+            .map(|r| columns.iter().map(|c| project(r, *c)).collect())
+            .collect()
+    }
+}
+
+fn project(r: &Row<'_>, column: Column) -> Value {
+    match column {
+        Column::Client => Value::Client(r.client),
+        Column::Available => Value::Amount(r.account.available),
+        Column::Held => Value::Amount(r.account.held),
+        Column::Total => Value::Amount(r.account.total()),
+        Column::Locked => Value::Flag(r.account.locked),
+        Column::OpenDisputes => Value::Count(r.open_disputes),
+    }
+}
@@ -0,0 +1,91 @@
+//! Balance cache publishing for Redis: each client's latest balances as a
+//! hash, kept up to date while a batch runs.
+//!
+//! A [`RedisPublisher`] writes Redis commands in the wire protocol (RESP),
+//! ready for `redis-cli --pipe`, which sends them in bulk. Every applied row
+//! becomes one `HSET <prefix><client>` of `available`, `held`, `total` and
+//! `locked` as they stand after the row, formatted as in the report, so a
+//! lookup mid-run sees the balances as of the last row written. Pointing
+//! the output at a named pipe streams the updates while processing goes on.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::models::Account;
+//! use payments_engine::redis::RedisPublisher;
+//! use rust_decimal_macros::dec;
+//!
+//! let mut redis = RedisPublisher::new(Vec::new(), "balance:");
+//! let acc = Account { available: dec!(2.5), held: dec!(0), locked: false };
+//! redis.publish(7, &acc).unwrap();
+//! let resp = String::from_utf8(redis.into_inner().unwrap()).unwrap();
+//! assert!(resp.starts_with("*10\r\n$4\r\nHSET\r\n$9\r\nbalance:7\r\n"));
+//! assert!(resp.ends_with("$6\r\nlocked\r\n$5\r\nfalse\r\n"));
+//! ```
+
+use crate::errors::Result;
+use crate::models::{Account, AccountRow};
+use std::io::Write;
+
+/// Writes `HSET` commands for `redis-cli --pipe`; see the module docs.
+pub struct RedisPublisher<W: Write> {
+    out: W,
+    prefix: String,
+    published: u64,
+}
+
+impl<W: Write> RedisPublisher<W> {
+    /// Publish to keys `<prefix><client>`.
+    pub fn new(out: W, prefix: &str) -> Self {
+        Self {
+            out,
+            prefix: prefix.to_owned(),
+            published: 0,
+        }
+    }
+
+    /// Write the current state of `client`'s account.
+    pub fn publish(&mut self, client: u16, acc: &Account) -> Result<()> {
+        let row = AccountRow::from((&client, acc));
+        let key = format!("{}{client}", self.prefix);
+        let locked = row.locked.to_string();
+        self.command(&[
+            "HSET",
+            &key,
+            "available",
+            &row.available,
+            "held",
+            &row.held,
+            "total",
+            &row.total,
+            "locked",
+            &locked,
+        ])?;
+        self.published += 1;
+        Ok(())
+    }
+
+    /// Updates written so far.
+    pub fn published(&self) -> u64 {
+        self.published
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+
+    /// Flush and return the writer.
+    pub fn into_inner(mut self) -> Result<W> {
+        self.flush()?;
+        Ok(self.out)
+    }
+
+    /// One command as a RESP array of bulk strings.
+    fn command(&mut self, args: &[&str]) -> Result<()> {
+        write!(self.out, "*{}\r\n", args.len())?;
+        for arg in args {
+            write!(self.out, "${}\r\n{arg}\r\n", arg.len())?;
+        }
+        Ok(())
+    }
+}
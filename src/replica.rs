@@ -0,0 +1,123 @@
+//! Read-only replicas of the account view, fed by the primary's deltas.
+//!
+//! A [`Replica`] rebuilds every client's available, held and locked state
+//! from the [`AccountDelta`] stream of an engine ([`Engine::watch`] with a
+//! filter that accepts every client), so balance queries can be served from
+//! another thread without touching the engine that is processing rows. It
+//! keeps no deposits, disputes or holds: only what the deltas carry.
+//!
+//! Convergence is checked with hashes: [`Replica::accounts_hash`] equals
+//! [`Engine::accounts_hash`] whenever the replica has applied every delta up
+//! to the primary's [`Engine::seq`]. Rejected rows send no delta, so a
+//! client whose rows were all rejected is in the primary's report with zero
+//! balances but absent from the replica; the hashes leave such empty
+//! accounts out. The primary publishes `(seq, hash)` pairs at whatever
+//! interval suits it and the replica checks them with [`Replica::verify`]
+//! once it has caught up to `seq`.
+//!
+//! A replica started after the primary has processed rows must be seeded
+//! with the accounts as of then ([`Replica::from_engine`], or the accounts
+//! of a snapshot) and only apply deltas with a later `seq`.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::replica::Replica;
+//! use payments_engine::{Engine, Transaction, TxType};
+//! use rust_decimal_macros::dec;
+//!
+//! let mut eng = Engine::new();
+//! let feed = eng.watch(|_| true);
+//! let mut replica = Replica::new();
+//!
+//! for (kind, client, tx, amount) in [
+//!     (TxType::Deposit, 1, 1, Some(dec!(10))),
+//!     (TxType::Withdrawal, 1, 2, Some(dec!(4))),
+//!     (TxType::Dispute, 1, 1, None),
+//! ] {
+//!     eng.process(Transaction { kind, client, tx, amount }).unwrap();
+//! }
+//! assert_eq!(replica.catch_up(&feed), 3);
+//!
+//! assert_eq!(replica.account(1).unwrap().held, dec!(10));
+//! replica.verify(eng.seq(), &eng.accounts_hash()).unwrap();
+//! ```
+
+use crate::engine::{Engine, accounts_digest};
+use crate::errors::Result;
+use crate::fasthash::Map;
+use crate::feed::{AccountDelta, Watch};
+use crate::models::Account;
+
+/// Account view rebuilt from deltas; see the module docs.
+#[derive(Debug, Clone, Default)]
+pub struct Replica {
+    accounts: Map<u16, Account>,
+    /// `seq` of the last applied delta.
+    seq: u64,
+}
+
+impl Replica {
+    /// Empty replica, for a primary that has not processed any row yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replica seeded with `engine`'s current accounts; apply only deltas
+    /// produced after this point.
+    pub fn from_engine(engine: &Engine) -> Self {
+        Self {
+            accounts: engine.accounts.clone(),
+            seq: engine.seq(),
+        }
+    }
+
+    /// Apply one delta of the primary. Deltas must come in `seq` order;
+    /// several may share a `seq` when held funds expire before the row.
+    pub fn apply(&mut self, delta: &AccountDelta) {
+        let acc = self.accounts.entry(delta.client).or_default();
+        acc.available += delta.available;
+        acc.held += delta.held;
+        acc.locked |= delta.locked;
+        self.seq = delta.seq;
+    }
+
+    /// Apply every delta already queued on `feed`, without blocking.
+    /// Returns how many were applied.
+    pub fn catch_up(&mut self, feed: &Watch) -> usize {
+        feed.pending().map(|delta| self.apply(&delta)).count()
+    }
+
+    /// `seq` of the last applied delta. Rows the primary rejected produce
+    /// no delta, so this may trail [`Engine::seq`] while in sync.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    pub fn account(&self, client: u16) -> Option<&Account> {
+        self.accounts.get(&client)
+    }
+
+    pub fn accounts(&self) -> &Map<u16, Account> {
+        &self.accounts
+    }
+
+    /// Same hash as [`Engine::accounts_hash`] over this view.
+    pub fn accounts_hash(&self) -> String {
+        accounts_digest(&self.accounts)
+    }
+
+    /// Check against the primary's `hash` as of its row `seq`; call once
+    /// every delta up to `seq` has been applied.
+    pub fn verify(&self, seq: u64, hash: &str) -> Result<()> {
+        anyhow::ensure!(
+            self.seq <= seq,
+            "replica is at seq {} past the checkpoint at {seq}",
+            self.seq
+        );
+        anyhow::ensure!(
+            self.accounts_hash() == hash,
+            "replica diverged from the primary by seq {seq}"
+        );
+        Ok(())
+    }
+}
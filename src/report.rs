@@ -0,0 +1,541 @@
+//! Account report generation: selects which accounts to emit and writes
+//! them as CSV in ascending client order. Also produces a delta report
+//! against a previous run's output (see [`write_delta`]) and lists recorded
+//! rejections, authorizations, refunds and structuring flags (see
+//! [`write_rejections`], [`write_holds`], [`write_refunds`],
+//! [`write_structuring`]). [`postgres`] writes the accounts and a journal
+//! as a `psql` load script; [`columns`] writes the accounts with a chosen
+//! column set. With the `bank-statements` feature,
+//! `bank_statement` renders per-client histories as camt.053 / MT940.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::{Engine, Transaction, TxType};
+//! use payments_engine::report::{self, ReportFilter};
+//! use rust_decimal_macros::dec;
+//!
+//! let mut eng = Engine::new();
+//! for (client, tx, amount) in [(1, 1, dec!(5)), (2, 2, dec!(2000))] {
+//!     eng.process(Transaction { kind: TxType::Deposit, client, tx, amount: Some(amount) })
+//!         .unwrap();
+//! }
+//!
+//! let filter = ReportFilter { min_total: Some(dec!(1000)), ..Default::default() };
+//! let mut out = Vec::new();
+//! report::write_accounts(&eng, &filter, &mut out).unwrap();
+//! assert_eq!(
+//!     String::from_utf8(out).unwrap(),
+//!     "client,available,held,total,locked\n2,2000.0000,0.0000,2000.0000,false\n"
+//! );
+//! ```
+
+#[cfg(feature = "bank-statements")]
+pub mod bank_statement;
+pub mod columns;
+pub mod postgres;
+
+use crate::chargeback::ChargebackLog;
+use crate::deferred::DeferredRow;
+use crate::engine::Engine;
+use crate::errors::Result;
+use crate::fasthash::Map;
+use crate::hold;
+use crate::limits::Breach;
+use crate::models::{Account, AccountRow};
+use crate::screening::Screening;
+use anyhow::bail;
+use csv::{ReaderBuilder, WriterBuilder};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io::{Read, Write};
+
+/// Predicates deciding which accounts make it into the report.
+///
+/// All set criteria must hold (logical AND); the default filter keeps every
+/// account.
+#[derive(Debug, Default, Clone)]
+pub struct ReportFilter {
+    /// Keep only locked (charged-back) accounts.
+    pub only_locked: bool,
+    /// Keep only these clients; empty means "all clients".
+    pub clients: HashSet<u16>,
+    /// Keep only accounts whose total is at least this amount.
+    pub min_total: Option<Decimal>,
+    /// Keep only accounts changed by at least one applied row in this run.
+    pub changed_only: bool,
+}
+
+impl ReportFilter {
+    /// `true` if the account passes every configured criterion.
+    pub fn matches(&self, engine: &Engine, client: u16, acc: &Account) -> bool {
+        if self.only_locked && !acc.locked {
+            return false;
+        }
+        if !self.clients.is_empty() && !self.clients.contains(&client) {
+            return false;
+        }
+        if let Some(min) = self.min_total
+            && acc.total() < min
+        {
+            return false;
+        }
+        !self.changed_only || engine.is_touched(client)
+    }
+}
+
+/// Accounts in ascending client order without collecting or sorting them:
+/// client ids are `u16`, so walking the whole id space costs 65,536 map
+/// lookups whatever the number of accounts, and memory stays constant.
+fn in_client_order(accounts: &Map<u16, Account>) -> impl Iterator<Item = (&u16, &Account)> {
+    (0..=u16::MAX).filter_map(|id| accounts.get_key_value(&id))
+}
+
+/// Write the header plus one row per matching account, sorted by client id.
+/// Rows are streamed to `sink` as they are produced.
+pub fn write_accounts<W: Write>(engine: &Engine, filter: &ReportFilter, sink: W) -> Result<()> {
+    let mut wtr = WriterBuilder::new().has_headers(true).from_writer(sink);
+
+    let mut written = false;
+    for row in
+        in_client_order(&engine.accounts).filter(|(id, acc)| filter.matches(engine, **id, acc))
+    {
+        wtr.serialize(AccountRow::from(row))?;
+        written = true;
+    }
+    if !written {
+        // serde only emits the header alongside the first record
+        wtr.write_record(["client", "available", "held", "total", "locked"])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// One row of a previously emitted accounts report.
+#[derive(Deserialize)]
+struct AccountsCsvRow {
+    client: u16,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+}
+
+/// Load an accounts CSV in the format written by [`write_accounts`] (a
+/// full or filtered report), keyed by client id, e.g. yesterday's report as
+/// opening balances or as the baseline of [`write_delta`].
+///
+/// The file is checked rather than trusted: every column must be present,
+/// a client may appear only once, and `total` must match `available + held`
+/// up to the 0.0001 the report's per-column rounding can introduce. Errors
+/// name the offending line.
+///
+/// ```rust
+/// use payments_engine::report::{self, ReportFilter};
+/// use payments_engine::{Engine, Transaction, TxType};
+/// use rust_decimal_macros::dec;
+///
+/// let mut eng = Engine::new();
+/// eng.process(Transaction { kind: TxType::Deposit, client: 4, tx: 1, amount: Some(dec!(2.5)) })
+///     .unwrap();
+/// let mut csv = Vec::new();
+/// report::write_accounts(&eng, &ReportFilter::default(), &mut csv).unwrap();
+/// assert_eq!(report::read_accounts(csv.as_slice()).unwrap(), eng.accounts);
+///
+/// let bad = "client,available,held,total,locked\n4,2.5,0,3.5,false\n";
+/// let err = report::read_accounts(bad.as_bytes()).unwrap_err();
+/// assert_eq!(err.to_string(), "line 2: client 4 total 3.5 is not available + held (2.5)");
+/// ```
+pub fn read_accounts<R: Read>(src: R) -> Result<Map<u16, Account>> {
+    let mut rdr = ReaderBuilder::new().trim(csv::Trim::All).from_reader(src);
+    let headers = rdr.headers()?.clone();
+    let mut out = Map::default();
+    let mut record = csv::StringRecord::new();
+    while rdr.read_record(&mut record)? {
+        let line = record.position().map_or(0, |p| p.line());
+        let row: AccountsCsvRow = record.deserialize(Some(&headers))?;
+        let sum = row.available + row.held;
+        if (row.total - sum).abs() > Decimal::new(1, 4) {
+            bail!(
+                "line {line}: client {} total {} is not available + held ({sum})",
+                row.client,
+                row.total
+            );
+        }
+        let account = Account {
+            available: row.available,
+            held: row.held,
+            locked: row.locked,
+        };
+        if out.insert(row.client, account).is_some() {
+            bail!("line {line}: client {} listed twice", row.client);
+        }
+    }
+    Ok(out)
+}
+
+/// Former name of [`read_accounts`].
+#[deprecated(note = "use read_accounts, which also checks the file")]
+pub fn read_baseline<R: Read>(src: R) -> Result<Map<u16, Account>> {
+    read_accounts(src)
+}
+
+/// One row of the delta report: signed change per balance column plus the
+/// lock state before and after.
+#[derive(Serialize)]
+struct DeltaRow {
+    client: u16,
+    available: String,
+    held: String,
+    total: String,
+    locked_before: bool,
+    locked_after: bool,
+}
+
+/// Write only the accounts whose balances or lock state differ from
+/// `baseline`, with the change for each column. Clients missing on either
+/// side count as an empty, unlocked account. Balances are compared as the
+/// accounts report writes them, rounded to 4 decimal places, so a baseline
+/// read back from that report matches the run that wrote it. Returns the
+/// number of rows written.
+///
+/// ### Example
+/// ```rust
+/// use payments_engine::report::{ReportFilter, read_accounts, write_accounts, write_delta};
+/// use payments_engine::{Engine, Transaction, TxType};
+/// use rust_decimal_macros::dec;
+///
+/// let mut eng = Engine::new();
+/// let deposit = |client, amount| Transaction { kind: TxType::Deposit, client, tx: client.into(), amount: Some(amount) };
+/// eng.process(deposit(1, dec!(1.23456))).unwrap();
+/// eng.process(deposit(2, dec!(5))).unwrap();
+///
+/// let mut report = Vec::new();
+/// write_accounts(&eng, &ReportFilter::default(), &mut report).unwrap();
+/// let baseline = read_accounts(report.as_slice()).unwrap();
+///
+/// // nothing changed since the report: no row for the 5dp balance either
+/// let mut delta = Vec::new();
+/// assert_eq!(write_delta(&eng, &baseline, &mut delta).unwrap(), 0);
+///
+/// eng.process(Transaction { kind: TxType::Withdrawal, client: 1, tx: 3, amount: Some(dec!(0.0001)) })
+///     .unwrap();
+/// let mut delta = Vec::new();
+/// assert_eq!(write_delta(&eng, &baseline, &mut delta).unwrap(), 1);
+/// assert!(String::from_utf8(delta).unwrap().contains("1,-0.0001,+0.0000,-0.0001,false,false"));
+/// ```
+pub fn write_delta<W: Write>(
+    engine: &Engine,
+    baseline: &Map<u16, Account>,
+    sink: W,
+) -> Result<usize> {
+    let mut wtr = WriterBuilder::new().has_headers(true).from_writer(sink);
+    let empty = Account::default();
+    let fmt = |d: Decimal| format!("{:+.4}", d);
+    // as written to the accounts report
+    let shown = |acc: &Account| {
+        (
+            acc.available.round_dp(4),
+            acc.held.round_dp(4),
+            acc.total().round_dp(4),
+        )
+    };
+
+    let mut written = 0;
+    // ascending client order without collecting, as in `write_accounts`
+    let clients =
+        (0..=u16::MAX).filter(|c| engine.accounts.contains_key(c) || baseline.contains_key(c));
+    for client in clients {
+        let before = baseline.get(&client).unwrap_or(&empty);
+        let after = engine.accounts.get(&client).unwrap_or(&empty);
+        let ((a0, h0, t0), (a1, h1, t1)) = (shown(before), shown(after));
+        if (a0, h0, before.locked) == (a1, h1, after.locked) {
+            continue;
+        }
+        wtr.serialize(DeltaRow {
+            client,
+            available: fmt(a1 - a0),
+            held: fmt(h1 - h0),
+            total: fmt(t1 - t0),
+            locked_before: before.locked,
+            locked_after: after.locked,
+        })?;
+        written += 1;
+    }
+    if written == 0 {
+        wtr.write_record([
+            "client",
+            "available",
+            "held",
+            "total",
+            "locked_before",
+            "locked_after",
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(written)
+}
+
+/// Write the rows kept under [`Action::Record`](crate::anomaly::Action::Record)
+/// as CSV: the anomaly class and sequence number followed by the original
+/// input columns. Rows applied after normalization (see
+/// [`Engine::normalizations`]) are listed too, with the class `normalized`,
+/// so the file accounts for every row not applied as written. Rows are in
+/// sequence order, after the rows skipped for breaking a size limit
+/// (`breaches`, see [`crate::limits`]), which never reached the engine and
+/// so have only a class. Returns the number of rows written.
+pub fn write_rejections<W: Write>(engine: &Engine, breaches: &[Breach], sink: W) -> Result<usize> {
+    let mut wtr = WriterBuilder::new().from_writer(sink);
+    wtr.write_record(["seq", "anomaly", "type", "client", "tx", "amount"])?;
+    for b in breaches {
+        wtr.write_record(["", b.kind.as_str(), "", "", "", ""])?;
+    }
+    let rejected = engine
+        .rejections()
+        .iter()
+        .map(|r| (r.seq, r.anomaly.as_str(), &r.tx));
+    let normalized = engine
+        .normalizations()
+        .iter()
+        .map(|n| (n.seq, "normalized", &n.tx));
+    let mut rows: Vec<_> = rejected.chain(normalized).collect();
+    rows.sort_by_key(|(seq, ..)| *seq);
+    for (seq, class, tx) in &rows {
+        wtr.write_record([
+            seq.to_string(),
+            class.to_string(),
+            tx.kind.as_str().to_string(),
+            tx.client.to_string(),
+            tx.tx.to_string(),
+            tx.amount.map(|a| a.to_string()).unwrap_or_default(),
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(breaches.len() + rows.len())
+}
+
+/// Write the authorizations still holding funds, then those released by the
+/// expiry sweep, as CSV (`client,tx,amount,state`), each group sorted by tx
+/// id. Active amounts are part of the `held` column of the accounts report.
+/// Returns the number of rows written.
+pub fn write_holds<W: Write>(engine: &Engine, sink: W) -> Result<usize> {
+    let mut wtr = WriterBuilder::new().from_writer(sink);
+    wtr.write_record(["client", "tx", "amount", "state"])?;
+    let mut written = 0;
+    for state in [hold::State::Active, hold::State::Expired] {
+        let mut holds: Vec<_> = engine
+            .holds
+            .iter()
+            .filter(|(_, h)| h.state == state)
+            .collect();
+        holds.sort_by_key(|(tx, _)| **tx);
+        for (tx, h) in &holds {
+            wtr.write_record([
+                h.client.to_string(),
+                tx.to_string(),
+                format!("{:.4}", h.amount.round_dp(4)),
+                state.as_str().to_string(),
+            ])?;
+        }
+        written += holds.len();
+    }
+    wtr.flush()?;
+    Ok(written)
+}
+
+/// Write every withdrawal with at least one refund as CSV
+/// (`client,tx,withdrawn,refunded`), sorted by the withdrawal's tx id.
+/// Returns the number of rows written.
+pub fn write_refunds<W: Write>(engine: &Engine, sink: W) -> Result<usize> {
+    let mut wtr = WriterBuilder::new().from_writer(sink);
+    wtr.write_record(["client", "tx", "withdrawn", "refunded"])?;
+    let fmt = |d: Decimal| format!("{:.4}", d.round_dp(4));
+    let mut refunded: Vec<_> = engine
+        .withdrawals
+        .iter()
+        .filter(|(_, w)| !w.refunded.is_zero())
+        .collect();
+    refunded.sort_by_key(|(tx, _)| **tx);
+    for (tx, w) in &refunded {
+        wtr.write_record([
+            w.client.to_string(),
+            tx.to_string(),
+            fmt(w.amount),
+            fmt(w.refunded),
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(refunded.len())
+}
+
+/// Write the balances of every bucket of the clients with buckets other than
+/// main as CSV (`client,bucket,available,held,total`), by client and with
+/// main first (see [`crate::bucket`]). Returns the number of rows written.
+pub fn write_buckets<W: Write>(engine: &Engine, sink: W) -> Result<usize> {
+    let mut wtr = WriterBuilder::new().from_writer(sink);
+    wtr.write_record(["client", "bucket", "available", "held", "total"])?;
+    let fmt = |d: Decimal| format!("{:.4}", d.round_dp(4));
+    let mut written = 0;
+    for client in engine.bucketed_clients() {
+        for (bucket, b) in engine.buckets(client) {
+            wtr.write_record([
+                client.to_string(),
+                bucket,
+                fmt(b.available),
+                fmt(b.held),
+                fmt(b.total()),
+            ])?;
+            written += 1;
+        }
+    }
+    wtr.flush()?;
+    Ok(written)
+}
+
+/// Write the credit line of every client with a credit limit as CSV
+/// (`client,limit,drawn,headroom,utilization,interest`), by client (see
+/// [`crate::credit`]). Returns the number of rows written.
+pub fn write_credit<W: Write>(engine: &Engine, sink: W) -> Result<usize> {
+    let mut wtr = WriterBuilder::new().from_writer(sink);
+    wtr.write_record([
+        "client",
+        "limit",
+        "drawn",
+        "headroom",
+        "utilization",
+        "interest",
+    ])?;
+    let fmt = |d: Decimal| format!("{:.4}", d.round_dp(4));
+    let clients = engine.credit_clients();
+    for &client in &clients {
+        let line = engine.credit_line(client).expect("client with a limit");
+        wtr.write_record([
+            client.to_string(),
+            fmt(line.limit),
+            fmt(line.drawn),
+            fmt(line.headroom),
+            fmt(line.utilization),
+            fmt(line.interest),
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(clients.len())
+}
+
+/// Write every promotional credit as CSV
+/// (`client,tx,amount,used,expired,left`), by client in grant order (see
+/// [`crate::promo`]). Returns the number of rows written and the value
+/// that expired unused.
+pub fn write_promos<W: Write>(engine: &Engine, sink: W) -> Result<(usize, Decimal)> {
+    let mut wtr = WriterBuilder::new().from_writer(sink);
+    wtr.write_record(["client", "tx", "amount", "used", "expired", "left"])?;
+    let fmt = |d: Decimal| format!("{:.4}", d.round_dp(4));
+    let (mut written, mut expired) = (0, Decimal::ZERO);
+    for client in engine.promo_clients() {
+        for g in engine.promos(client) {
+            wtr.write_record([
+                client.to_string(),
+                g.tx.to_string(),
+                fmt(g.amount),
+                fmt(g.used),
+                fmt(g.expired),
+                fmt(g.left()),
+            ])?;
+            written += 1;
+            expired += g.expired;
+        }
+    }
+    wtr.flush()?;
+    Ok((written, expired))
+}
+
+/// Write the structuring patterns detected so far as CSV
+/// (`client,seq,count,total,deposits`), in detection order; `deposits` lists
+/// the supporting tx ids separated by spaces. Returns the number of rows
+/// written.
+pub fn write_structuring<W: Write>(engine: &Engine, sink: W) -> Result<usize> {
+    let mut wtr = WriterBuilder::new().from_writer(sink);
+    wtr.write_record(["client", "seq", "count", "total", "deposits"])?;
+    let flags = engine.structuring_flags();
+    for flag in flags {
+        let ids: Vec<String> = flag.deposits.iter().map(u32::to_string).collect();
+        wtr.write_record([
+            flag.client.to_string(),
+            flag.seq.to_string(),
+            flag.deposits.len().to_string(),
+            format!("{:.4}", flag.total.round_dp(4)),
+            ids.join(" "),
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(flags.len())
+}
+
+/// Write the chargebacks in `log` as CSV
+/// (`client,tx,original,amount,dispute_seq,chargeback_seq`), in the order
+/// they were applied. `original` is `deposit` for a charged-back deposit and
+/// `refund` for disputed refunds of a withdrawal; `dispute_seq` is empty
+/// when the dispute predates the log. Returns the number of rows written.
+pub fn write_chargebacks<W: Write>(engine: &Engine, log: &ChargebackLog, sink: W) -> Result<usize> {
+    let mut wtr = WriterBuilder::new().from_writer(sink);
+    wtr.write_record([
+        "client",
+        "tx",
+        "original",
+        "amount",
+        "dispute_seq",
+        "chargeback_seq",
+    ])?;
+    for cb in log.records() {
+        // disputes look at deposits first, as the engine does
+        let deposit = engine.deposits.contains_key(&cb.tx) || engine.reclaimed.contains_key(&cb.tx);
+        wtr.write_record([
+            cb.client.to_string(),
+            cb.tx.to_string(),
+            if deposit { "deposit" } else { "refund" }.to_string(),
+            format!("{:.4}", cb.amount.round_dp(4)),
+            cb.dispute_seq.map(|s| s.to_string()).unwrap_or_default(),
+            cb.chargeback_seq.to_string(),
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(log.records().len())
+}
+
+/// Write deferred rows whose transaction never arrived
+/// ([`Engine::close_deferred`]) as CSV (`row,type,client,tx`); `row` is
+/// the engine sequence number the row came in under. Returns the number of
+/// rows written.
+pub fn write_unmatched<W: Write>(rows: &[DeferredRow], sink: W) -> Result<usize> {
+    let mut wtr = WriterBuilder::new().from_writer(sink);
+    wtr.write_record(["row", "type", "client", "tx"])?;
+    for row in rows {
+        wtr.write_record([
+            row.seq.to_string(),
+            row.tx.kind.as_str().to_string(),
+            row.tx.client.to_string(),
+            row.tx.tx.to_string(),
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(rows.len())
+}
+
+/// Write the rows blocked by `screening` as CSV
+/// (`row,type,client,tx,amount`), in input order; `row` is the input row
+/// number. Returns the number of rows written.
+pub fn write_screening<W: Write>(screening: &Screening, sink: W) -> Result<usize> {
+    let mut wtr = WriterBuilder::new().from_writer(sink);
+    wtr.write_record(["row", "type", "client", "tx", "amount"])?;
+    for hit in screening.hits() {
+        wtr.write_record([
+            hit.row.to_string(),
+            hit.tx.kind.as_str().to_string(),
+            hit.tx.client.to_string(),
+            hit.tx.tx.to_string(),
+            hit.tx.amount.map(|a| a.to_string()).unwrap_or_default(),
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(screening.hits().len())
+}
@@ -0,0 +1,491 @@
+//! Bank-statement renderings of a client's history: ISO 20022 camt.053 XML
+//! and SWIFT MT940 text, for treasury systems that ingest those directly,
+//! and a standalone HTML page for the customer (see [`html`]).
+//!
+//! Booked entries are the changes in the client's *total* balance, so a
+//! dispute or resolve (which only moves funds between available and held)
+//! produces no entry, while a chargeback books a debit. The engine has no
+//! notion of currency or booking time, so both come from [`StatementInfo`].
+//!
+//! [`write_batch`] renders one file per client in any [`Format`], spread
+//! over threads, for the `statements` subcommand.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::report::bank_statement::{self, Date, StatementInfo};
+//! use payments_engine::{Engine, Transaction, TxType};
+//! use rust_decimal_macros::dec;
+//!
+//! let mut eng = Engine::with_history();
+//! eng.process(Transaction { kind: TxType::Deposit, client: 7, tx: 1, amount: Some(dec!(10)) })
+//!     .unwrap();
+//!
+//! let info = StatementInfo {
+//!     client: 7,
+//!     currency: "EUR".into(),
+//!     opening: dec!(0),
+//!     date: Date { year: 2026, month: 1, day: 31 },
+//! };
+//! let mt = bank_statement::mt940(&info, eng.history(7));
+//! assert!(mt.contains(":61:260131C10,0000NTRF1//7"));
+//! assert!(mt.contains(":62F:C260131EUR10,0000"));
+//! ```
+
+use crate::errors::Result;
+use crate::history::HistoryEntry;
+use crate::models::TxType;
+use anyhow::bail;
+use rust_decimal::Decimal;
+use std::cmp::Ordering;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Statement file format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Camt053,
+    Mt940,
+    Html,
+}
+
+impl Format {
+    /// Names accepted by [`Format::from_str`], for `--format`.
+    pub const NAMES: [&'static str; 3] = ["camt053", "mt940", "html"];
+
+    /// File extension of a statement in this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Camt053 => "xml",
+            Format::Mt940 => "sta",
+            Format::Html => "html",
+        }
+    }
+
+    /// Render one statement in this format.
+    pub fn render(self, info: &StatementInfo, entries: &[HistoryEntry]) -> String {
+        match self {
+            Format::Camt053 => camt053(info, entries),
+            Format::Mt940 => mt940(info, entries),
+            Format::Html => html(info, entries),
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "camt053" => Ok(Format::Camt053),
+            "mt940" => Ok(Format::Mt940),
+            "html" => Ok(Format::Html),
+            _ => Err(format!(
+                "unknown statement format {s:?} (one of {})",
+                Self::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+/// Calendar date (proleptic Gregorian, UTC).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Date {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+}
+
+impl Date {
+    /// Today's date in UTC.
+    pub fn today() -> Self {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        Self::from_unix_days((secs / 86_400) as i64)
+    }
+
+    /// Civil date for a day count since 1970-01-01 (H. Hinnant's algorithm).
+    pub fn from_unix_days(days: i64) -> Self {
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = (yoe + era * 400 + i64::from(month <= 2)) as i32;
+        Self { year, month, day }
+    }
+
+    fn iso(self) -> String {
+        format!("{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+
+    fn yymmdd(self) -> String {
+        format!(
+            "{:02}{:02}{:02}",
+            self.year.rem_euclid(100),
+            self.month,
+            self.day
+        )
+    }
+}
+
+/// Statement-level data the engine doesn't track itself.
+#[derive(Debug, Clone)]
+pub struct StatementInfo {
+    pub client: u16,
+    /// ISO 4217 code printed on every amount.
+    pub currency: String,
+    /// Total balance before the first history entry (zero for a fresh run).
+    pub opening: Decimal,
+    /// Statement and booking date.
+    pub date: Date,
+}
+
+/// One booked movement derived from consecutive history entries.
+struct Booking<'a> {
+    entry: &'a HistoryEntry,
+    amount: Decimal,
+}
+
+fn bookings<'a>(opening: Decimal, entries: &'a [HistoryEntry]) -> (Vec<Booking<'a>>, Decimal) {
+    let mut prev = opening;
+    let mut out = Vec::new();
+    for entry in entries {
+        let total = entry.available + entry.held;
+        if total != prev {
+            out.push(Booking {
+                entry,
+                amount: total - prev,
+            });
+        }
+        prev = total;
+    }
+    (out, prev)
+}
+
+/// Fixed 4-dp amount without sign (both formats carry the sign separately).
+fn amount(d: Decimal) -> String {
+    format!("{:.4}", d.abs().round_dp(4))
+}
+
+/// ISO 20022 `camt.053.001.08` bank-to-customer statement.
+pub fn camt053(info: &StatementInfo, entries: &[HistoryEntry]) -> String {
+    let (bookings, closing) = bookings(info.opening, entries);
+    let ccy = &info.currency;
+    let date = info.date.iso();
+    let id = format!("STMT-{}-{}", info.client, info.date.yymmdd());
+    let cd = |d: Decimal| if d.is_sign_negative() { "DBIT" } else { "CRDT" };
+
+    let mut x = String::new();
+    let _ = writeln!(x, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    let _ = writeln!(
+        x,
+        r#"<Document xmlns="urn:iso:std:iso:20022:tech:xsd:camt.053.001.08">"#
+    );
+    let _ = writeln!(x, "  <BkToCstmrStmt>");
+    let _ = writeln!(
+        x,
+        "    <GrpHdr><MsgId>{id}</MsgId><CreDtTm>{date}T00:00:00</CreDtTm></GrpHdr>"
+    );
+    let _ = writeln!(x, "    <Stmt>");
+    let _ = writeln!(x, "      <Id>{id}</Id>");
+    let _ = writeln!(x, "      <CreDtTm>{date}T00:00:00</CreDtTm>");
+    let _ = writeln!(
+        x,
+        "      <Acct><Id><Othr><Id>{}</Id></Othr></Id><Ccy>{ccy}</Ccy></Acct>",
+        info.client
+    );
+    for (code, bal) in [("OPBD", info.opening), ("CLBD", closing)] {
+        let _ = writeln!(
+            x,
+            "      <Bal><Tp><CdOrPrtry><Cd>{code}</Cd></CdOrPrtry></Tp>\
+             <Amt Ccy=\"{ccy}\">{}</Amt><CdtDbtInd>{}</CdtDbtInd>\
+             <Dt><Dt>{date}</Dt></Dt></Bal>",
+            amount(bal),
+            cd(bal)
+        );
+    }
+    for b in &bookings {
+        let _ = writeln!(
+            x,
+            "      <Ntry><NtryRef>{tx}</NtryRef><Amt Ccy=\"{ccy}\">{}</Amt>\
+             <CdtDbtInd>{}</CdtDbtInd><Sts><Cd>BOOK</Cd></Sts>\
+             <BookgDt><Dt>{date}</Dt></BookgDt>\
+             <BkTxCd><Prtry><Cd>{}</Cd></Prtry></BkTxCd>\
+             <NtryDtls><TxDtls><Refs><EndToEndId>{tx}</EndToEndId></Refs></TxDtls></NtryDtls></Ntry>",
+            amount(b.amount),
+            cd(b.amount),
+            b.entry.kind.as_str(),
+            tx = b.entry.tx,
+        );
+    }
+    let _ = writeln!(x, "    </Stmt>");
+    let _ = writeln!(x, "  </BkToCstmrStmt>");
+    let _ = writeln!(x, "</Document>");
+    x
+}
+
+/// SWIFT MT940 customer statement (text block 4 only).
+pub fn mt940(info: &StatementInfo, entries: &[HistoryEntry]) -> String {
+    let (bookings, closing) = bookings(info.opening, entries);
+    let date = info.date.yymmdd();
+    let ccy = &info.currency;
+    // MT940 uses a decimal comma and a C/D mark instead of a sign
+    let amt = |d: Decimal| amount(d).replace('.', ",");
+    let cd = |d: Decimal| if d.is_sign_negative() { 'D' } else { 'C' };
+
+    let mut t = String::new();
+    let _ = writeln!(t, ":20:STMT-{}-{date}", info.client);
+    let _ = writeln!(t, ":25:{}", info.client);
+    let _ = writeln!(t, ":28C:1");
+    let _ = writeln!(
+        t,
+        ":60F:{}{date}{ccy}{}",
+        cd(info.opening),
+        amt(info.opening)
+    );
+    for b in &bookings {
+        let _ = writeln!(
+            t,
+            ":61:{date}{}{}NTRF{}//{}",
+            cd(b.amount),
+            amt(b.amount),
+            b.entry.tx,
+            info.client
+        );
+        let _ = writeln!(t, ":86:{} tx {}", b.entry.kind.as_str(), b.entry.tx);
+    }
+    let _ = writeln!(t, ":62F:{}{date}{ccy}{}", cd(closing), amt(closing));
+    t
+}
+
+/// Customer-facing HTML statement: one self-contained page with the opening
+/// and closing balances and every row of the history, booked or not, so
+/// that disputes, holds and locks show up as notes next to the funds they
+/// moved. Print it (or "save as PDF") from a browser for a paper copy; the
+/// page carries its own print styles.
+///
+/// ### Example
+/// ```rust
+/// use payments_engine::report::bank_statement::{self, Date, StatementInfo};
+/// use payments_engine::{Engine, Transaction, TxType};
+/// use rust_decimal_macros::dec;
+///
+/// let mut eng = Engine::with_history();
+/// let row = |kind, tx, amount| Transaction { kind, client: 7, tx, amount };
+/// eng.process(row(TxType::Deposit, 1, Some(dec!(10)))).unwrap();
+/// eng.process(row(TxType::Dispute, 1, None)).unwrap();
+///
+/// let info = StatementInfo {
+///     client: 7,
+///     currency: "EUR".into(),
+///     opening: dec!(0),
+///     date: Date { year: 2026, month: 1, day: 31 },
+/// };
+/// let page = bank_statement::html(&info, eng.history(7));
+/// assert!(page.contains("<td>Closing balance</td><td class=\"num\">10.0000 EUR</td>"));
+/// assert!(page.contains("<td class=\"note\">disputed, 10.0000 held</td>"));
+/// ```
+pub fn html(info: &StatementInfo, entries: &[HistoryEntry]) -> String {
+    let (_, closing) = bookings(info.opening, entries);
+    let ccy = escape(&info.currency);
+    let date = info.date.iso();
+    let signed = |d: Decimal| match d.is_sign_negative() {
+        true => format!("-{}", amount(d)),
+        false => format!("+{}", amount(d)),
+    };
+
+    let mut h = String::new();
+    let _ = writeln!(h, "<!DOCTYPE html>");
+    let _ = writeln!(h, r#"<html lang="en">"#);
+    let _ = writeln!(h, "<head>");
+    let _ = writeln!(h, r#"<meta charset="utf-8">"#);
+    let _ = writeln!(
+        h,
+        "<title>Statement for account {} on {date}</title>",
+        info.client
+    );
+    let _ = writeln!(h, "<style>{STYLE}</style>");
+    let _ = writeln!(h, "</head>");
+    let _ = writeln!(h, "<body>");
+    let _ = writeln!(h, "<h1>Account statement</h1>");
+    let _ = writeln!(
+        h,
+        "<p class=\"meta\">Account {} &middot; {ccy} &middot; {date}</p>",
+        info.client
+    );
+    let _ = writeln!(h, "<table class=\"balances\">");
+    for (label, bal) in [
+        ("Opening balance", info.opening),
+        ("Closing balance", closing),
+    ] {
+        let _ = writeln!(
+            h,
+            "<tr><td>{label}</td><td class=\"num\">{}{} {ccy}</td></tr>",
+            if bal.is_sign_negative() { "-" } else { "" },
+            amount(bal)
+        );
+    }
+    let _ = writeln!(h, "</table>");
+    let _ = writeln!(h, "<table class=\"entries\">");
+    let _ = writeln!(
+        h,
+        "<thead><tr><th>Row</th><th>Transaction</th><th>Type</th><th>Amount</th>\
+         <th>Available</th><th>Held</th><th>Note</th></tr></thead>"
+    );
+    let _ = writeln!(h, "<tbody>");
+    let (mut total, mut held, mut locked) = (info.opening, Decimal::ZERO, false);
+    for e in entries {
+        let booked = e.available + e.held - total;
+        let note = note(e, e.held - held, e.locked && !locked);
+        let class = match booked.cmp(&Decimal::ZERO) {
+            Ordering::Less => "num debit",
+            Ordering::Equal => "num",
+            Ordering::Greater => "num credit",
+        };
+        let booked = match booked.is_zero() {
+            true => String::new(),
+            false => signed(booked),
+        };
+        let _ = writeln!(
+            h,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td class=\"{class}\">{booked}</td>\
+             <td class=\"num\">{}</td><td class=\"num\">{}</td><td class=\"note\">{note}</td></tr>",
+            e.seq,
+            e.tx,
+            e.kind.as_str(),
+            plain(e.available),
+            plain(e.held),
+        );
+        (total, held, locked) = (e.available + e.held, e.held, e.locked);
+    }
+    let _ = writeln!(h, "</tbody>");
+    let _ = writeln!(h, "</table>");
+    let _ = writeln!(h, "</body>");
+    let _ = writeln!(h, "</html>");
+    h
+}
+
+/// Page styles of [`html`], screen and print.
+const STYLE: &str = "body{font-family:sans-serif;margin:2em;color:#222}\
+table{border-collapse:collapse;margin:1em 0}\
+td,th{padding:.3em .8em;border-bottom:1px solid #ddd;text-align:left}\
+.num{text-align:right;font-variant-numeric:tabular-nums}\
+.debit{color:#a00}.credit{color:#070}.note{font-style:italic}\
+@media print{body{margin:0}.debit,.credit{color:inherit}}";
+
+/// 4-dp amount with its sign, as balances may go negative.
+fn plain(d: Decimal) -> String {
+    format!("{:.4}", d.round_dp(4))
+}
+
+/// What a history entry did beyond its booking, for the customer: `held`
+/// is the change in held funds, `locked` set if the row locked the account.
+fn note(entry: &HistoryEntry, held: Decimal, locked: bool) -> String {
+    let mut note = match entry.kind {
+        TxType::Dispute if held > Decimal::ZERO => format!("disputed, {} held", amount(held)),
+        TxType::Resolve if held < Decimal::ZERO => {
+            format!("dispute resolved, {} released", amount(held))
+        }
+        TxType::Chargeback => "charged back".to_owned(),
+        TxType::Hold => format!("authorization, {} held", amount(held)),
+        TxType::Release => format!("{} released", amount(held)),
+        TxType::Capture => "authorization captured".to_owned(),
+        _ => String::new(),
+    };
+    if locked {
+        if !note.is_empty() {
+            note += "; ";
+        }
+        note += "account locked";
+    }
+    note
+}
+
+/// Escape text for HTML element content and attribute values.
+fn escape(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '&' => "&amp;".to_owned(),
+            '<' => "&lt;".to_owned(),
+            '>' => "&gt;".to_owned(),
+            '"' => "&quot;".to_owned(),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+/// Write one statement per `(client, history)` pair as
+/// `client-NNNNN.<ext>` under `out_dir` (created if missing), rendering on
+/// up to `threads` threads. `template` gives everything but the client.
+/// Returns the paths written, in the order of `statements`.
+///
+/// ### Example
+/// ```rust
+/// use payments_engine::report::bank_statement::{self, Date, Format, StatementInfo};
+/// use payments_engine::{Engine, Transaction, TxType};
+/// use rust_decimal_macros::dec;
+///
+/// let mut eng = Engine::with_history();
+/// for client in [3, 4] {
+///     let tx = Transaction { kind: TxType::Deposit, client, tx: client.into(), amount: Some(dec!(5)) };
+///     eng.process(tx).unwrap();
+/// }
+/// let template = StatementInfo {
+///     client: 0,
+///     currency: "EUR".into(),
+///     opening: dec!(0),
+///     date: Date { year: 2026, month: 1, day: 31 },
+/// };
+/// let dir = std::env::temp_dir().join("payments-engine-statements-doctest");
+/// let statements = [(3, eng.history(3)), (4, eng.history(4))];
+/// let paths = bank_statement::write_batch(&statements, &template, Format::Mt940, &dir, 2).unwrap();
+/// assert_eq!(paths[1], dir.join("client-00004.sta"));
+/// assert!(std::fs::read_to_string(&paths[1]).unwrap().contains(":25:4"));
+/// ```
+pub fn write_batch(
+    statements: &[(u16, &[HistoryEntry])],
+    template: &StatementInfo,
+    format: Format,
+    out_dir: &Path,
+    threads: usize,
+) -> Result<Vec<PathBuf>> {
+    fs::create_dir_all(out_dir)?;
+    let chunk = statements.len().div_ceil(threads.max(1)).max(1);
+    thread::scope(|scope| {
+        let workers: Vec<_> = statements
+            .chunks(chunk)
+            .map(|part| {
+                scope.spawn(move || {
+                    part.iter()
+                        .map(|&(client, entries)| {
+                            let info = StatementInfo {
+                                client,
+                                ..template.clone()
+                            };
+                            let path =
+                                out_dir.join(format!("client-{client:05}.{}", format.extension()));
+                            fs::write(&path, format.render(&info, entries))?;
+                            Ok(path)
+                        })
+                        .collect::<Result<Vec<_>>>()
+                })
+            })
+            .collect();
+        let mut paths = Vec::with_capacity(statements.len());
+        for worker in workers {
+            match worker.join() {
+                Ok(written) => paths.extend(written?),
+                Err(_) => bail!("statement worker panicked"),
+            }
+        }
+        Ok(paths)
+    })
+}
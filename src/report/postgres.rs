@@ -0,0 +1,150 @@
+//! Postgres load script: the run's final accounts and a journal of every
+//! applied row, as one `psql` script that runs in a single transaction.
+//!
+//! The script creates the two tables if missing, appends the journal rows
+//! with `COPY journal FROM stdin`, then upserts the accounts through a
+//! temporary table, so downstream services see either all of a run or
+//! none of it:
+//!
+//! ```sql
+//! CREATE TABLE accounts (client integer PRIMARY KEY, available numeric,
+//!     held numeric, total numeric, locked boolean);
+//! CREATE TABLE journal (seq bigint, client integer, tx bigint, type text,
+//!     available numeric, held numeric, locked boolean);
+//! ```
+//!
+//! Journal amounts are the signed changes of each row (see
+//! [`AccountDelta`]); `locked` says whether the row locked the account.
+//! Accounts are written as in the CSV report, rounded to 4 dp.
+//!
+//! Load it with `psql -v ON_ERROR_STOP=1 -f run.sql "$DATABASE_URL"`.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::report::{ReportFilter, postgres::PgScript};
+//! use payments_engine::{Engine, Transaction, TxType};
+//! use rust_decimal_macros::dec;
+//!
+//! let mut eng = Engine::new();
+//! let feed = eng.watch(|_| true);
+//! let mut script = PgScript::new(Vec::new()).unwrap();
+//! eng.process(Transaction { kind: TxType::Deposit, client: 3, tx: 1, amount: Some(dec!(7.5)) })
+//!     .unwrap();
+//! for delta in feed.pending() {
+//!     script.journal(&delta).unwrap();
+//! }
+//! let (out, summary) = script.finish(&eng, &ReportFilter::default()).unwrap();
+//! assert_eq!(summary, (1, 1));
+//!
+//! let sql = String::from_utf8(out).unwrap();
+//! assert!(sql.starts_with("BEGIN;\n"));
+//! assert!(sql.contains("\n1\t3\t1\tdeposit\t7.5\t0\tf\n"));
+//! assert!(sql.contains("\n3\t7.5000\t0.0000\t7.5000\tf\n"));
+//! assert!(sql.ends_with("COMMIT;\n"));
+//! ```
+
+use super::{ReportFilter, in_client_order};
+use crate::engine::Engine;
+use crate::errors::Result;
+use crate::feed::AccountDelta;
+use crate::models::AccountRow;
+use std::io::Write;
+
+const HEAD: &str = "\
+BEGIN;
+CREATE TABLE IF NOT EXISTS accounts (
+    client integer PRIMARY KEY,
+    available numeric NOT NULL,
+    held numeric NOT NULL,
+    total numeric NOT NULL,
+    locked boolean NOT NULL
+);
+CREATE TABLE IF NOT EXISTS journal (
+    seq bigint NOT NULL,
+    client integer NOT NULL,
+    tx bigint NOT NULL,
+    type text NOT NULL,
+    available numeric NOT NULL,
+    held numeric NOT NULL,
+    locked boolean NOT NULL
+);
+COPY journal (seq, client, tx, type, available, held, locked) FROM stdin;
+";
+
+const ACCOUNTS: &str = "\
+\\.
+CREATE TEMP TABLE accounts_in (LIKE accounts) ON COMMIT DROP;
+COPY accounts_in (client, available, held, total, locked) FROM stdin;
+";
+
+const TAIL: &str = "\
+\\.
+INSERT INTO accounts SELECT * FROM accounts_in
+ON CONFLICT (client) DO UPDATE SET
+    available = EXCLUDED.available,
+    held = EXCLUDED.held,
+    total = EXCLUDED.total,
+    locked = EXCLUDED.locked;
+COMMIT;
+";
+
+/// `COPY` text format of a boolean.
+fn flag(b: bool) -> char {
+    if b { 't' } else { 'f' }
+}
+
+/// Streams a load script; see the module docs.
+pub struct PgScript<W: Write> {
+    out: W,
+    journal: usize,
+}
+
+impl<W: Write> PgScript<W> {
+    /// Start the script: transaction, tables and the journal `COPY`.
+    pub fn new(mut out: W) -> Result<Self> {
+        out.write_all(HEAD.as_bytes())?;
+        Ok(Self { out, journal: 0 })
+    }
+
+    /// Append one applied row to the journal.
+    pub fn journal(&mut self, d: &AccountDelta) -> Result<()> {
+        writeln!(
+            self.out,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            d.seq,
+            d.client,
+            d.tx,
+            d.kind.as_str(),
+            d.available.normalize(),
+            d.held.normalize(),
+            flag(d.locked)
+        )?;
+        self.journal += 1;
+        Ok(())
+    }
+
+    /// Close the journal, upsert the accounts `filter` selects and commit.
+    /// Returns the writer and `(journal rows, accounts)` written.
+    pub fn finish(mut self, engine: &Engine, filter: &ReportFilter) -> Result<(W, (usize, usize))> {
+        self.out.write_all(ACCOUNTS.as_bytes())?;
+        let mut accounts = 0;
+        for (id, acc) in
+            in_client_order(&engine.accounts).filter(|(id, acc)| filter.matches(engine, **id, acc))
+        {
+            let row = AccountRow::from((id, acc));
+            writeln!(
+                self.out,
+                "{}\t{}\t{}\t{}\t{}",
+                row.client,
+                row.available,
+                row.held,
+                row.total,
+                flag(row.locked)
+            )?;
+            accounts += 1;
+        }
+        self.out.write_all(TAIL.as_bytes())?;
+        self.out.flush()?;
+        Ok((self.out, (self.journal, accounts)))
+    }
+}
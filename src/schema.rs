@@ -0,0 +1,286 @@
+//! Machine-readable descriptions of the input and output file formats, so
+//! partners can validate files before sending them.
+//!
+//! Enum values come straight from the serde representation of
+//! [`TxType`], and the properties from the model: the input columns are
+//! the fields serde reads into [`KeyedRow`], the output columns
+//! [`Column::ALL`]. Adding a variant or a column updates every schema
+//! automatically, and a column without a description fails loudly instead
+//! of going missing. Input amounts follow [`amount::PATTERN`], the syntax
+//! the parser accepts.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::schema::{self, Format, Target};
+//!
+//! let s = schema::render(Target::Transaction, Format::JsonSchema);
+//! assert_eq!(s["properties"]["type"]["enum"][0], "deposit");
+//! assert_eq!(s["properties"]["amount"]["pattern"], payments_engine::amount::PATTERN);
+//!
+//! let avro = schema::render(Target::Account, Format::Avro);
+//! assert_eq!(avro["fields"][0]["name"], "client");
+//! ```
+
+use crate::amount;
+use crate::idempotency::KeyedRow;
+use crate::models::TxType;
+use crate::report::columns::{Column, Columns};
+use serde::de::{self, DeserializeOwned, Deserializer, Visitor};
+use serde_json::{Map, Value, json};
+
+/// Which file format to describe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// One row of the transactions CSV.
+    Transaction,
+    /// One row of the accounts report.
+    Account,
+}
+
+/// Which schema language to emit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// JSON Schema (draft 2020-12).
+    JsonSchema,
+    /// Avro record schema (JSON-encoded).
+    Avro,
+}
+
+/// Build the schema for `target` in the requested `format`.
+pub fn render(target: Target, format: Format) -> Value {
+    match (target, format) {
+        (Target::Transaction, Format::JsonSchema) => transaction_json_schema(),
+        (Target::Transaction, Format::Avro) => transaction_avro(),
+        (Target::Account, Format::JsonSchema) => account_json_schema(),
+        (Target::Account, Format::Avro) => account_avro(),
+    }
+}
+
+/// Serde names of all transaction kinds (`"deposit"`, `"withdrawal"`, …).
+fn tx_type_names() -> Vec<Value> {
+    TxType::ALL
+        .iter()
+        .map(|k| serde_json::to_value(k).expect("unit variant serialises"))
+        .collect()
+}
+
+/// Input columns every row must have.
+const REQUIRED: [&str; 3] = ["type", "client", "tx"];
+
+/// The report writes amounts with at most four fractional digits.
+const REPORT_AMOUNT_PATTERN: &str = r"^-?[0-9]+(\.[0-9]{1,4})?$";
+
+/// One column: its JSON Schema and its Avro type (without the null branch
+/// optional columns get).
+struct Field {
+    json: Value,
+    avro: Value,
+}
+
+/// Schema of the transaction column `name`. Panics on a column without
+/// one, so a new row field cannot go undescribed.
+fn transaction_field(name: &str) -> Field {
+    let text = |description: &str| Field {
+        json: json!({ "description": description, "type": ["string", "null"] }),
+        avro: json!("string"),
+    };
+    match name {
+        "type" => Field {
+            json: json!({ "type": "string", "enum": tx_type_names() }),
+            avro: json!({ "type": "enum", "name": "TxType", "symbols": tx_type_names() }),
+        },
+        "client" => Field {
+            json: json!({ "type": "integer", "minimum": 0, "maximum": u16::MAX }),
+            avro: json!("int"),
+        },
+        "tx" => Field {
+            json: json!({ "type": "integer", "minimum": 0, "maximum": u32::MAX }),
+            avro: json!("long"),
+        },
+        "amount" => Field {
+            json: json!({
+                "description": "Present only for deposit, withdrawal, hold, refund, move and promo rows. At most 28 decimal places.",
+                "type": ["string", "null"],
+                "pattern": amount::PATTERN
+            }),
+            avro: json!("string"),
+        },
+        "idempotency_key" => text("Optional; a row is applied at most once per client and key."),
+        "batch" => text("Optional batch label; a batch ends where the label changes."),
+        "timestamp" => Field {
+            json: json!({
+                "description": "Optional; epoch seconds or milliseconds, or RFC 3339. Drives --clock input.",
+                "type": ["string", "integer", "null"]
+            }),
+            avro: json!("string"),
+        },
+        "bucket" => text("Optional sub-balance the row uses; empty means main."),
+        "to_bucket" => text("Optional; the bucket a move row goes to. Empty means main."),
+        "tenant" => Field {
+            json: json!({
+                "description": "Optional; with --tenant-dir, the tenant the row belongs to. Empty means default.",
+                "type": ["string", "null"],
+                "pattern": "^[A-Za-z0-9_-]*$"
+            }),
+            avro: json!("string"),
+        },
+        other => panic!("no schema for transaction column {other:?}"),
+    }
+}
+
+/// Schema of the report column `column`.
+fn account_field(column: Column) -> Field {
+    let amount = || Field {
+        json: json!({ "type": "string", "pattern": REPORT_AMOUNT_PATTERN }),
+        avro: json!("string"),
+    };
+    let text = |description: &str| Field {
+        json: json!({ "description": description, "type": "string" }),
+        avro: json!("string"),
+    };
+    match column {
+        Column::Client => Field {
+            json: json!({ "type": "integer", "minimum": 0, "maximum": u16::MAX }),
+            avro: json!("int"),
+        },
+        Column::Available | Column::Held | Column::Total => amount(),
+        Column::Locked => Field {
+            json: json!({ "type": "boolean" }),
+            avro: json!("boolean"),
+        },
+        Column::Currency => text("With --columns: the code given with --currency."),
+        Column::State => Field {
+            json: json!({ "type": "string", "enum": ["active", "disputed", "locked"] }),
+            avro: json!("string"),
+        },
+        Column::Flags => text("With --columns or --report-flags: account flags, ';'-separated."),
+        Column::Notes => {
+            text("With --columns or --report-flags: account notes as 'by: text', ' | '-separated.")
+        }
+        Column::OpenDisputeCount => Field {
+            json: json!({ "type": "integer", "minimum": 0 }),
+            avro: json!("long"),
+        },
+    }
+}
+
+fn json_schema<'a>(
+    title: &str,
+    description: &str,
+    fields: impl Iterator<Item = (&'a str, Field)>,
+    required: &[&str],
+) -> Value {
+    let properties: Map<String, Value> = fields.map(|(name, f)| (name.into(), f.json)).collect();
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": title,
+        "description": description,
+        "type": "object",
+        "properties": properties,
+        "required": required,
+        "additionalProperties": false
+    })
+}
+
+fn avro<'a>(
+    name: &str,
+    fields: impl Iterator<Item = (&'a str, Field)>,
+    required: &[&str],
+) -> Value {
+    let fields: Vec<Value> = fields
+        .map(|(field, f)| match required.contains(&field) {
+            true => json!({ "name": field, "type": f.avro }),
+            false => json!({ "name": field, "type": ["null", f.avro], "default": null }),
+        })
+        .collect();
+    json!({
+        "type": "record",
+        "name": name,
+        "namespace": "payments_engine",
+        "fields": fields
+    })
+}
+
+fn transaction_fields() -> impl Iterator<Item = (&'static str, Field)> {
+    field_names::<KeyedRow>()
+        .iter()
+        .map(|&name| (name, transaction_field(name)))
+}
+
+fn account_fields() -> impl Iterator<Item = (&'static str, Field)> {
+    Column::ALL
+        .into_iter()
+        .map(|c| (c.name(), account_field(c)))
+}
+
+/// The default report columns: always present.
+fn account_required() -> Vec<&'static str> {
+    let default = Columns::default();
+    Column::ALL
+        .into_iter()
+        .filter(|&c| default.contains(c))
+        .map(Column::name)
+        .collect()
+}
+
+fn transaction_json_schema() -> Value {
+    json_schema(
+        "Transaction",
+        "One row of the transactions CSV input.",
+        transaction_fields(),
+        &REQUIRED,
+    )
+}
+
+fn account_json_schema() -> Value {
+    json_schema(
+        "Account",
+        "One row of the accounts report output. The required columns are the default set; --columns may select others.",
+        account_fields(),
+        &account_required(),
+    )
+}
+
+fn transaction_avro() -> Value {
+    avro("Transaction", transaction_fields(), &REQUIRED)
+}
+
+fn account_avro() -> Value {
+    avro("Account", account_fields(), &account_required())
+}
+
+/// Serde names of the fields of `T`, in declaration order, as its derived
+/// `Deserialize` announces them.
+fn field_names<T: DeserializeOwned>() -> &'static [&'static str] {
+    let mut fields: &'static [&'static str] = &[];
+    // the deserializer stops at the announcement, so the error is expected
+    let _ = T::deserialize(FieldNames(&mut fields));
+    fields
+}
+
+/// Deserializer that only records the field list of a struct.
+struct FieldNames<'a>(&'a mut &'static [&'static str]);
+
+impl<'de> Deserializer<'de> for FieldNames<'_> {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("not a struct"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        fields: &'static [&'static str],
+        _: V,
+    ) -> Result<V::Value, Self::Error> {
+        *self.0 = fields;
+        Err(de::Error::custom("field names taken"))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
@@ -0,0 +1,88 @@
+//! Sanction-list screening: rows of denied clients are blocked before they
+//! reach the engine, and every blocked row is kept for a screening report.
+//!
+//! The list itself sits behind the [`DenyList`] trait, so it can be a plain
+//! set of client ids (as loaded from a file by the CLI's `--denylist`) or a
+//! lookup into whatever screening service a deployment uses. A long-running
+//! frontend can swap in a fresh list with [`Screening::replace`] without
+//! losing the hits recorded so far.
+//!
+//! Blocked rows differ from ingest filters ([`crate::filter`]) in intent:
+//! they are not out of scope but forbidden, so none is dropped silently.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::screening::Screening;
+//! use payments_engine::{Transaction, TxType};
+//! use rust_decimal_macros::dec;
+//! use std::collections::HashSet;
+//!
+//! let mut screening = Screening::new(HashSet::from([13]));
+//! let row = |client, tx| Transaction { kind: TxType::Deposit, client, tx, amount: Some(dec!(9)) };
+//!
+//! assert!(!screening.blocks(1, &row(7, 1)));
+//! assert!(screening.blocks(2, &row(13, 2)));
+//! assert_eq!(screening.hits()[0].row, 2);
+//! assert_eq!(screening.hits()[0].tx.client, 13);
+//! ```
+
+use crate::models::Transaction;
+use std::collections::HashSet;
+
+/// Source of screening decisions.
+pub trait DenyList: Send {
+    /// `true` if rows of `client` must be blocked.
+    fn denies(&self, client: u16) -> bool;
+}
+
+impl DenyList for HashSet<u16> {
+    fn denies(&self, client: u16) -> bool {
+        self.contains(&client)
+    }
+}
+
+/// A blocked row.
+#[derive(Debug)]
+pub struct Hit {
+    /// Input row number (1-based, header excluded).
+    pub row: u64,
+    pub tx: Transaction,
+}
+
+/// Screens rows against a [`DenyList`]; see the module docs.
+pub struct Screening {
+    list: Box<dyn DenyList>,
+    hits: Vec<Hit>,
+}
+
+impl Screening {
+    pub fn new(list: impl DenyList + 'static) -> Self {
+        Self {
+            list: Box::new(list),
+            hits: Vec::new(),
+        }
+    }
+
+    /// Screen from now on against `list`; hits so far are kept.
+    pub fn replace(&mut self, list: impl DenyList + 'static) {
+        self.list = Box::new(list);
+    }
+
+    /// Check input row `row`. Returns `true`, and records the row, if it
+    /// must not be processed.
+    pub fn blocks(&mut self, row: u64, tx: &Transaction) -> bool {
+        if !self.list.denies(tx.client) {
+            return false;
+        }
+        self.hits.push(Hit {
+            row,
+            tx: Transaction { ..*tx },
+        });
+        true
+    }
+
+    /// Blocked rows, in input order.
+    pub fn hits(&self) -> &[Hit] {
+        &self.hits
+    }
+}
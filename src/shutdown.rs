@@ -0,0 +1,43 @@
+//! Cooperative shutdown on SIGINT / SIGTERM.
+//!
+//! The handler only flips a flag; frontends poll [`requested`] between rows,
+//! stop reading input, and then run their normal end-of-input path
+//! ([`Engine::finalize`](crate::Engine::finalize), snapshot, report) so an
+//! interrupted run still leaves consistent output behind. A second signal
+//! gets the default disposition and terminates immediately.
+
+use crate::errors::Result;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+const SIG_ERR: libc::sighandler_t = libc::SIG_ERR;
+#[cfg(windows)]
+const SIG_ERR: libc::sighandler_t = libc::SIG_ERR as libc::sighandler_t;
+
+extern "C" fn on_signal(sig: libc::c_int) {
+    REQUESTED.store(true, Ordering::SeqCst);
+    // SAFETY: `signal` is async-signal-safe; restoring the default lets a
+    // second Ctrl-C kill a run that is stuck writing output.
+    unsafe {
+        libc::signal(sig, libc::SIG_DFL);
+    }
+}
+
+/// Install the SIGINT/SIGTERM handlers. Call once, before ingest starts.
+pub fn install() -> Result<()> {
+    let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    for sig in [libc::SIGINT, libc::SIGTERM] {
+        // SAFETY: the handler only touches an atomic and calls `signal`.
+        if unsafe { libc::signal(sig, handler) } == SIG_ERR {
+            return Err(std::io::Error::last_os_error().into());
+        }
+    }
+    Ok(())
+}
+
+/// `true` once a shutdown signal has been received.
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
@@ -0,0 +1,204 @@
+//! Streaming statistics in fixed memory, for sizing up an unknown input
+//! while it is being processed.
+//!
+//! [`StreamStats`] watches every row and answers, at any point:
+//!
+//! * how many rows and distinct clients it saw — exact, as client ids are
+//!   `u16` and a seen-bit per id takes 8 KiB;
+//! * roughly how many distinct tx ids — a [`HyperLogLog`] of 4 096 one-byte
+//!   registers, about 1.6% standard error. Distinct tx ids bound how many
+//!   records the engine will keep, so this is the number to plan memory by;
+//! * the heaviest clients by row count — a conservative-update [`CountMin`]
+//!   sketch (4 × 8 192 counters) with a short list of candidates. Counts may
+//!   be over-estimated by collisions, never under.
+//!
+//! Together that is about 270 KiB whatever the input size.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::sketch::StreamStats;
+//! use payments_engine::{Transaction, TxType};
+//!
+//! let mut stats = StreamStats::new(3);
+//! for tx in 0..20_000u32 {
+//!     // client 7 sends a third of the rows
+//!     let client = if tx % 3 == 0 { 7 } else { (tx % 500) as u16 + 100 };
+//!     stats.observe(&Transaction { kind: TxType::Deposit, client, tx, amount: None });
+//! }
+//!
+//! assert_eq!(stats.rows(), 20_000);
+//! let txs = stats.distinct_txs() as f64;
+//! assert!((txs - 20_000.0).abs() / 20_000.0 < 0.05);
+//! let (client, rows) = stats.heavy_hitters()[0];
+//! assert_eq!(client, 7);
+//! assert!(rows >= 6_667);
+//! ```
+
+use crate::models::Transaction;
+
+/// SplitMix64 finalizer: spreads small integer keys over all 64 bits.
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+/// HyperLogLog distinct counter over 64-bit hashes.
+#[derive(Debug, Clone)]
+pub struct HyperLogLog {
+    /// log2 of the number of registers.
+    precision: u32,
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    /// `2^precision` registers; standard error is about
+    /// `1.04 / sqrt(2^precision)`.
+    pub fn new(precision: u32) -> Self {
+        assert!((4..=18).contains(&precision), "precision out of range");
+        Self {
+            precision,
+            registers: vec![0; 1 << precision],
+        }
+    }
+
+    pub fn insert(&mut self, key: u64) {
+        let hash = mix(key);
+        let idx = (hash >> (64 - self.precision)) as usize;
+        // leading zeros of the remaining bits, plus one
+        let rest = (hash << self.precision) | (1 << (self.precision - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+        self.registers[idx] = self.registers[idx].max(rank);
+    }
+
+    /// Estimated number of distinct keys inserted.
+    pub fn estimate(&self) -> u64 {
+        let m = self.registers.len() as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self.registers.iter().map(|&r| 2f64.powi(-(r as i32))).sum();
+        let raw = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        // linear counting is more accurate while many registers are empty
+        let estimate = match raw <= 2.5 * m && zeros > 0 {
+            true => m * (m / zeros as f64).ln(),
+            false => raw,
+        };
+        estimate.round() as u64
+    }
+}
+
+/// Count-Min sketch: per-key counts that never under-estimate.
+#[derive(Debug, Clone)]
+pub struct CountMin {
+    width: usize,
+    counts: Vec<u64>,
+}
+
+impl CountMin {
+    /// `depth` rows of `width` counters.
+    pub fn new(depth: usize, width: usize) -> Self {
+        Self {
+            width,
+            counts: vec![0; depth * width],
+        }
+    }
+
+    fn depth(&self) -> usize {
+        self.counts.len() / self.width
+    }
+
+    /// Index of `key`'s counter in `row`.
+    fn cell(&self, key: u64, row: usize) -> usize {
+        let h = mix(key ^ (row as u64).wrapping_mul(0xD6E8_FEB8_6659_FD93));
+        row * self.width + (h % self.width as u64) as usize
+    }
+
+    /// Count `n` more for `key`. Conservative update: only counters that
+    /// would otherwise fall below the new estimate are raised, which keeps
+    /// collisions from inflating every key they touch.
+    pub fn add(&mut self, key: u64, n: u64) {
+        let target = self.estimate(key) + n;
+        for row in 0..self.depth() {
+            let cell = self.cell(key, row);
+            self.counts[cell] = self.counts[cell].max(target);
+        }
+    }
+
+    /// Upper bound on the count of `key`; exact unless it collided
+    /// in every row.
+    pub fn estimate(&self, key: u64) -> u64 {
+        (0..self.depth())
+            .map(|row| self.counts[self.cell(key, row)])
+            .min()
+            .unwrap_or(0)
+    }
+}
+
+/// Row, client, tx id and heavy-hitter statistics; see the module docs.
+#[derive(Debug, Clone)]
+pub struct StreamStats {
+    rows: u64,
+    clients: Vec<u64>,
+    distinct_clients: u32,
+    txs: HyperLogLog,
+    per_client: CountMin,
+    /// Up to `top` candidates `(client, estimated rows)`, heaviest first.
+    heavy: Vec<(u16, u64)>,
+    top: usize,
+}
+
+impl StreamStats {
+    /// Track the `top` heaviest clients.
+    pub fn new(top: usize) -> Self {
+        Self {
+            rows: 0,
+            clients: vec![0; (u16::MAX as usize + 1) / 64],
+            distinct_clients: 0,
+            txs: HyperLogLog::new(12),
+            per_client: CountMin::new(4, 8192),
+            heavy: Vec::with_capacity(top + 1),
+            top,
+        }
+    }
+
+    pub fn observe(&mut self, tx: &Transaction) {
+        self.rows += 1;
+        let (word, bit) = (tx.client as usize / 64, 1u64 << (tx.client % 64));
+        if self.clients[word] & bit == 0 {
+            self.clients[word] |= bit;
+            self.distinct_clients += 1;
+        }
+        self.txs.insert(tx.tx as u64);
+
+        self.per_client.add(tx.client as u64, 1);
+        if self.top == 0 {
+            return;
+        }
+        let estimate = self.per_client.estimate(tx.client as u64);
+        match self.heavy.iter_mut().find(|(c, _)| *c == tx.client) {
+            Some(entry) => entry.1 = estimate,
+            None => self.heavy.push((tx.client, estimate)),
+        }
+        self.heavy.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        self.heavy.truncate(self.top);
+    }
+
+    pub fn rows(&self) -> u64 {
+        self.rows
+    }
+
+    pub fn distinct_clients(&self) -> u32 {
+        self.distinct_clients
+    }
+
+    /// Estimated number of distinct tx ids.
+    pub fn distinct_txs(&self) -> u64 {
+        self.txs.estimate()
+    }
+
+    /// `(client, estimated rows)` of the heaviest clients, heaviest first.
+    pub fn heavy_hitters(&self) -> &[(u16, u64)] {
+        &self.heavy
+    }
+}
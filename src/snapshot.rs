@@ -0,0 +1,772 @@
+//! Engine snapshots: the full account, deposit, hold and withdrawal state
+//! (plus the ids of reclaimed deposits, the idempotency keys seen, deferred
+//! rows, account flags and notes, escrowed funds, buckets, credit interest
+//! and promotional credits) wrapped in a versioned, checksummed envelope.
+//!
+//! | bytes   | field                                   |
+//! | ------- | --------------------------------------- |
+//! | 0..4    | magic `PESN`                            |
+//! | 4..6    | format version, little-endian `u16`     |
+//! | 6..10   | CRC-32 (IEEE) of the payload            |
+//! | 10..18  | payload length, little-endian `u64`     |
+//! | 18..    | payload: JSON body for that version     |
+//!
+//! Loading always goes through `migrate`, which upgrades any older payload
+//! to the current in-memory layout, so snapshots written by earlier releases
+//! keep loading. Old fixtures are pinned in `tests/fixtures/`.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::{Engine, Transaction, TxType};
+//! use rust_decimal_macros::dec;
+//!
+//! let mut eng = Engine::new();
+//! eng.process(Transaction { kind: TxType::Deposit, client: 1, tx: 1, amount: Some(dec!(2)) })
+//!     .unwrap();
+//!
+//! let mut buf = Vec::new();
+//! eng.write_snapshot(&mut buf).unwrap();
+//! let restored = Engine::read_snapshot(buf.as_slice()).unwrap();
+//! assert_eq!(restored.accounts[&1].available, dec!(2));
+//! assert_eq!(restored.state_hash(), eng.state_hash());
+//! ```
+
+use crate::bucket::Balance;
+use crate::credit::Accrual;
+use crate::deferred::DeferredRow;
+use crate::dispute::{State, StateMachine};
+use crate::engine::{Engine, StoredHold, StoredTx, StoredWithdrawal};
+use crate::errors::Result;
+use crate::hold;
+use crate::models::{Account, Transaction, TxType};
+use crate::notes::{Annotations, Note};
+use crate::promo::Grant;
+use anyhow::{Context, bail};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+
+/// Leading bytes of every snapshot file.
+pub const MAGIC: [u8; 4] = *b"PESN";
+/// Format version written by [`Engine::write_snapshot`].
+pub const CURRENT_VERSION: u16 = 14;
+
+const HEADER_LEN: usize = 18;
+
+/// Version 1 payload: accounts and deposits only.
+#[derive(Deserialize)]
+struct PayloadV1 {
+    accounts: Vec<AccountV1>,
+    deposits: Vec<DepositV1>,
+}
+
+/// Version 2 payload: adds the engine's row sequence number so checkpoints
+/// are ordered.
+#[derive(Deserialize)]
+struct PayloadV2 {
+    seq: u64,
+    accounts: Vec<AccountV1>,
+    deposits: Vec<DepositV1>,
+}
+
+/// Version 3 payload: adds the input position reached, used by `--resume`.
+#[derive(Deserialize)]
+struct PayloadV3 {
+    seq: u64,
+    input_rows: u64,
+    accounts: Vec<AccountV1>,
+    deposits: Vec<DepositV1>,
+}
+
+/// Version 4 payload: deposits carry their full dispute state instead of an
+/// "under dispute" flag.
+#[derive(Deserialize)]
+struct PayloadV4 {
+    seq: u64,
+    input_rows: u64,
+    accounts: Vec<AccountV1>,
+    deposits: Vec<DepositV4>,
+}
+
+/// Version 5 payload: adds card authorizations.
+#[derive(Deserialize)]
+struct PayloadV5 {
+    seq: u64,
+    input_rows: u64,
+    accounts: Vec<AccountV1>,
+    deposits: Vec<DepositV4>,
+    holds: Vec<HoldV5>,
+}
+
+/// Version 6 payload: adds withdrawals and their refund totals.
+#[derive(Deserialize)]
+struct PayloadV6 {
+    seq: u64,
+    input_rows: u64,
+    accounts: Vec<AccountV1>,
+    deposits: Vec<DepositV4>,
+    holds: Vec<HoldV5>,
+    withdrawals: Vec<WithdrawalV6>,
+}
+
+/// Version 14 payload (current). Version 7 added the ids of deposits
+/// dropped under `gc_deposits`; every version since only adds fields, which
+/// load empty from the versions before it and are left out when empty.
+/// Entries are sorted by key so equal states produce byte-identical
+/// snapshots.
+#[derive(Serialize, Deserialize)]
+struct PayloadV14 {
+    seq: u64,
+    input_rows: u64,
+    accounts: Vec<AccountV1>,
+    deposits: Vec<DepositV4>,
+    holds: Vec<HoldV5>,
+    withdrawals: Vec<WithdrawalV6>,
+    reclaimed: Vec<ReclaimedV7>,
+    /// Idempotency keys of applied rows (v8).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    keys: Vec<KeyV8>,
+    /// Rows waiting for the tx they name, in input order (v9).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    deferred: Vec<DeferredV9>,
+    /// Account flags and notes (v10).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    annotations: Vec<AnnotationV10>,
+    /// Funds left in escrow per deposit (v11).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    escrow: Vec<EscrowV11>,
+    /// Buckets other than main, and where deposits outside main landed
+    /// (v12).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    buckets: Vec<BucketV12>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    deposit_buckets: Vec<DepositBucketV12>,
+    /// Interest settled on credit lines (v13).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    credit: Vec<CreditV13>,
+    /// Promotional credits (v14).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    promos: Vec<PromoV14>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct WithdrawalV6 {
+    tx: u32,
+    client: u16,
+    amount: Decimal,
+    refunded: Decimal,
+    /// Refund dispute state and held amount; added after the first v6
+    /// snapshots, which had no refund disputes.
+    #[serde(default)]
+    dispute: State,
+    #[serde(default)]
+    disputed: Decimal,
+}
+
+/// A charged-back deposit of which only the id and client are kept.
+#[derive(Serialize, Deserialize)]
+struct ReclaimedV7 {
+    tx: u32,
+    client: u16,
+}
+
+/// An idempotency key and the tx id applied under it.
+#[derive(Serialize, Deserialize)]
+struct KeyV8 {
+    client: u16,
+    key: String,
+    tx: u32,
+}
+
+/// A row deferred until the tx it names arrives.
+#[derive(Serialize, Deserialize)]
+struct DeferredV9 {
+    seq: u64,
+    kind: TxType,
+    client: u16,
+    tx: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    amount: Option<Decimal>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key: Option<String>,
+}
+
+/// Flags and notes of one account.
+#[derive(Serialize, Deserialize)]
+struct AnnotationV10 {
+    client: u16,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    flags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    notes: Vec<Note>,
+}
+
+/// Funds of a deposit still in escrow.
+#[derive(Serialize, Deserialize)]
+struct EscrowV11 {
+    tx: u32,
+    amount: Decimal,
+}
+
+/// Balances of one bucket other than main.
+#[derive(Serialize, Deserialize)]
+struct BucketV12 {
+    client: u16,
+    bucket: String,
+    available: Decimal,
+    held: Decimal,
+}
+
+/// The bucket a deposit landed in.
+#[derive(Serialize, Deserialize)]
+struct DepositBucketV12 {
+    tx: u32,
+    bucket: String,
+}
+
+/// Interest on a credit line, settled up to clock time `since`.
+#[derive(Serialize, Deserialize)]
+struct CreditV13 {
+    client: u16,
+    interest: Decimal,
+    since: u64,
+}
+
+/// A promotional credit and what became of it.
+#[derive(Serialize, Deserialize)]
+struct PromoV14 {
+    client: u16,
+    tx: u32,
+    amount: Decimal,
+    used: Decimal,
+    expired: Decimal,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AccountV1 {
+    client: u16,
+    available: Decimal,
+    held: Decimal,
+    locked: bool,
+}
+
+#[derive(Deserialize)]
+struct DepositV1 {
+    tx: u32,
+    client: u16,
+    amount: Decimal,
+    under_dispute: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DepositV4 {
+    tx: u32,
+    client: u16,
+    amount: Decimal,
+    dispute: State,
+}
+
+#[derive(Serialize, Deserialize)]
+struct HoldV5 {
+    tx: u32,
+    client: u16,
+    amount: Decimal,
+    state: hold::State,
+    /// Row sequence number of the hold, for expiry. Added after the first
+    /// v5 snapshots were written; holds without it restart their expiry
+    /// window at the restore point.
+    #[serde(default)]
+    seq: Option<u64>,
+    /// Clock time of the hold, kept only under time-based expiry; a hold
+    /// without it does not expire by time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    at: Option<u64>,
+}
+
+impl From<DepositV1> for DepositV4 {
+    /// Closed disputes were not distinguished before v4; they come back as
+    /// `None`, which allows exactly the same transitions as `Resolved`.
+    fn from(d: DepositV1) -> Self {
+        DepositV4 {
+            tx: d.tx,
+            client: d.client,
+            amount: d.amount,
+            dispute: if d.under_dispute {
+                State::Open
+            } else {
+                State::None
+            },
+        }
+    }
+}
+
+/// Decode a payload of any supported `version` into the current layout.
+///
+/// New versions add an arm here and convert the previous layout forward, so
+/// every historical version has a path to the current one.
+fn migrate(version: u16, payload: &[u8]) -> Result<PayloadV14> {
+    let v6 = match version {
+        1..=5 => migrate_v6(version, payload)?,
+        6 => serde_json::from_slice(payload)?,
+        // later versions only add fields, which load empty from earlier
+        // ones: v8 the idempotency keys, v9 deferred rows,
+        // v10 account flags and notes, v11 escrowed funds, v12 buckets, v13
+        // credit interest, v14 promotional credits
+        7..=CURRENT_VERSION => return Ok(serde_json::from_slice(payload)?),
+        v => bail!("snapshot version {v} is newer than this build (max {CURRENT_VERSION})"),
+    };
+    // nothing was reclaimed before v7: charged-back deposits were all kept
+    Ok(PayloadV14 {
+        seq: v6.seq,
+        input_rows: v6.input_rows,
+        accounts: v6.accounts,
+        deposits: v6.deposits,
+        holds: v6.holds,
+        withdrawals: v6.withdrawals,
+        reclaimed: Vec::new(),
+        keys: Vec::new(),
+        deferred: Vec::new(),
+        annotations: Vec::new(),
+        escrow: Vec::new(),
+        buckets: Vec::new(),
+        deposit_buckets: Vec::new(),
+        credit: Vec::new(),
+        promos: Vec::new(),
+    })
+}
+
+/// Versions without withdrawals, brought up to version 6.
+fn migrate_v6(version: u16, payload: &[u8]) -> Result<PayloadV6> {
+    let v5 = match version {
+        1..=4 => migrate_v5(version, payload)?,
+        _ => serde_json::from_slice(payload)?,
+    };
+    // withdrawals were not kept before v6, so they cannot be refunded
+    Ok(PayloadV6 {
+        seq: v5.seq,
+        input_rows: v5.input_rows,
+        accounts: v5.accounts,
+        deposits: v5.deposits,
+        holds: v5.holds,
+        withdrawals: Vec::new(),
+    })
+}
+
+/// Versions without authorizations, brought up to version 5.
+fn migrate_v5(version: u16, payload: &[u8]) -> Result<PayloadV5> {
+    let v4 = match version {
+        1..=3 => {
+            let v3 = migrate_v3(version, payload)?;
+            PayloadV4 {
+                seq: v3.seq,
+                input_rows: v3.input_rows,
+                accounts: v3.accounts,
+                deposits: v3.deposits.into_iter().map(Into::into).collect(),
+            }
+        }
+        _ => serde_json::from_slice(payload)?,
+    };
+    Ok(PayloadV5 {
+        seq: v4.seq,
+        input_rows: v4.input_rows,
+        accounts: v4.accounts,
+        deposits: v4.deposits,
+        holds: Vec::new(),
+    })
+}
+
+/// Versions that still used [`DepositV1`], brought up to version 3.
+fn migrate_v3(version: u16, payload: &[u8]) -> Result<PayloadV3> {
+    Ok(match version {
+        1 => {
+            let v1: PayloadV1 = serde_json::from_slice(payload)?;
+            PayloadV3 {
+                seq: 0,
+                input_rows: 0,
+                accounts: v1.accounts,
+                deposits: v1.deposits,
+            }
+        }
+        // v2 did not track filtered rows; `seq` is exact for unfiltered runs
+        // and never past the real position otherwise
+        2 => {
+            let v2: PayloadV2 = serde_json::from_slice(payload)?;
+            PayloadV3 {
+                seq: v2.seq,
+                input_rows: v2.seq,
+                accounts: v2.accounts,
+                deposits: v2.deposits,
+            }
+        }
+        _ => serde_json::from_slice(payload)?,
+    })
+}
+
+impl Engine {
+    /// Serialise the full engine state as a current-version snapshot.
+    pub fn write_snapshot<W: Write>(&self, mut out: W) -> Result<()> {
+        let mut accounts: Vec<_> = self
+            .accounts
+            .iter()
+            .map(|(&client, acc)| AccountV1 {
+                client,
+                available: acc.available,
+                held: acc.held,
+                locked: acc.locked,
+            })
+            .collect();
+        accounts.sort_by_key(|a| a.client);
+        let mut deposits: Vec<_> = self
+            .deposits
+            .iter()
+            .map(|(tx, dep)| DepositV4 {
+                tx,
+                client: dep.client,
+                amount: dep.amount,
+                dispute: dep.dispute.state(),
+            })
+            .collect();
+        deposits.sort_by_key(|d| d.tx);
+
+        let mut holds: Vec<_> = self
+            .holds
+            .iter()
+            .map(|(&tx, h)| HoldV5 {
+                tx,
+                client: h.client,
+                amount: h.amount,
+                state: h.state,
+                seq: Some(h.seq),
+                at: h.at,
+            })
+            .collect();
+        holds.sort_by_key(|h| h.tx);
+
+        let mut withdrawals: Vec<_> = self
+            .withdrawals
+            .iter()
+            .map(|(&tx, w)| WithdrawalV6 {
+                tx,
+                client: w.client,
+                amount: w.amount,
+                refunded: w.refunded,
+                dispute: w.dispute.state(),
+                disputed: w.disputed,
+            })
+            .collect();
+        withdrawals.sort_by_key(|w| w.tx);
+
+        let mut reclaimed: Vec<_> = self
+            .reclaimed
+            .iter()
+            .map(|(&tx, &client)| ReclaimedV7 { tx, client })
+            .collect();
+        reclaimed.sort_by_key(|r| r.tx);
+
+        let mut keys: Vec<_> = self
+            .keys
+            .iter()
+            .flat_map(|(&client, keys)| {
+                keys.iter().map(move |(key, &tx)| KeyV8 {
+                    client,
+                    key: key.clone(),
+                    tx,
+                })
+            })
+            .collect();
+        keys.sort_by(|a, b| (a.client, &a.key).cmp(&(b.client, &b.key)));
+
+        let mut deferred: Vec<_> = self
+            .deferred
+            .values()
+            .flatten()
+            .map(|d| DeferredV9 {
+                seq: d.seq,
+                kind: d.tx.kind,
+                client: d.tx.client,
+                tx: d.tx.tx,
+                amount: d.tx.amount,
+                key: d.key.clone(),
+            })
+            .collect();
+        deferred.sort_by_key(|d| d.seq);
+
+        let mut annotations: Vec<_> = self
+            .annotations
+            .iter()
+            .map(|(&client, a)| AnnotationV10 {
+                client,
+                flags: a.flags.iter().cloned().collect(),
+                notes: a.notes.clone(),
+            })
+            .collect();
+        annotations.sort_by_key(|a| a.client);
+
+        let mut escrow: Vec<_> = self
+            .escrow
+            .iter()
+            .map(|(&tx, &amount)| EscrowV11 { tx, amount })
+            .collect();
+        escrow.sort_by_key(|e| e.tx);
+
+        // balances are in bucket order per client
+        let mut buckets: Vec<_> = self
+            .buckets
+            .balances
+            .iter()
+            .flat_map(|(&client, buckets)| {
+                buckets.iter().map(move |(bucket, b)| BucketV12 {
+                    client,
+                    bucket: bucket.clone(),
+                    available: b.available,
+                    held: b.held,
+                })
+            })
+            .collect();
+        buckets.sort_by_key(|b| b.client);
+
+        let mut deposit_buckets: Vec<_> = self
+            .buckets
+            .deposits
+            .iter()
+            .map(|(&tx, bucket)| DepositBucketV12 {
+                tx,
+                bucket: bucket.clone(),
+            })
+            .collect();
+        deposit_buckets.sort_by_key(|d| d.tx);
+
+        let mut credit: Vec<_> = self
+            .credit
+            .iter()
+            .map(|(&client, a)| CreditV13 {
+                client,
+                interest: a.interest,
+                since: a.since,
+            })
+            .collect();
+        credit.sort_by_key(|c| c.client);
+
+        // credits are in grant order per client
+        let mut promos: Vec<_> = self
+            .promos
+            .grants
+            .iter()
+            .flat_map(|(&client, grants)| {
+                grants.iter().map(move |g| PromoV14 {
+                    client,
+                    tx: g.tx,
+                    amount: g.amount,
+                    used: g.used,
+                    expired: g.expired,
+                    expires: g.expires,
+                })
+            })
+            .collect();
+        promos.sort_by_key(|p| p.client);
+
+        let payload = serde_json::to_vec(&PayloadV14 {
+            seq: self.seq,
+            input_rows: self.input_rows,
+            accounts,
+            deposits,
+            holds,
+            withdrawals,
+            reclaimed,
+            keys,
+            deferred,
+            annotations,
+            escrow,
+            buckets,
+            deposit_buckets,
+            credit,
+            promos,
+        })?;
+        out.write_all(&MAGIC)?;
+        out.write_all(&CURRENT_VERSION.to_le_bytes())?;
+        out.write_all(&crc32(&payload).to_le_bytes())?;
+        out.write_all(&(payload.len() as u64).to_le_bytes())?;
+        out.write_all(&payload)?;
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Rebuild an engine from a snapshot written by any supported version.
+    ///
+    /// The "touched" set used by `--changed-only` starts empty: only rows
+    /// applied after the restore count as changes. [`Engine::seq`] continues
+    /// from the recorded value (`0` for version 1 snapshots), as does
+    /// [`Engine::input_rows`].
+    pub fn read_snapshot<R: Read>(mut src: R) -> Result<Engine> {
+        let mut header = [0u8; HEADER_LEN];
+        src.read_exact(&mut header)
+            .context("snapshot shorter than its header")?;
+        if header[..4] != MAGIC {
+            bail!("not an engine snapshot (bad magic)");
+        }
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        let checksum = u32::from_le_bytes(header[6..10].try_into().unwrap());
+        let len = u64::from_le_bytes(header[10..18].try_into().unwrap());
+
+        let mut payload = Vec::new();
+        src.take(len).read_to_end(&mut payload)?;
+        if payload.len() as u64 != len {
+            bail!(
+                "snapshot truncated: {} of {len} payload bytes",
+                payload.len()
+            );
+        }
+        if crc32(&payload) != checksum {
+            bail!("snapshot checksum mismatch");
+        }
+
+        let state = migrate(version, &payload)?;
+        let mut eng = Engine::new();
+        eng.seq = state.seq;
+        eng.input_rows = state.input_rows;
+        for a in state.accounts {
+            eng.accounts.insert(
+                a.client,
+                Account {
+                    available: a.available,
+                    held: a.held,
+                    locked: a.locked,
+                },
+            );
+        }
+        for d in state.deposits {
+            eng.deposits.insert(
+                d.tx,
+                StoredTx {
+                    client: d.client,
+                    amount: d.amount,
+                    dispute: StateMachine::from_state(d.dispute),
+                },
+            );
+        }
+        let mut queue = Vec::new();
+        for h in state.holds {
+            let seq = h.seq.unwrap_or(state.seq);
+            if h.state == hold::State::Active {
+                queue.push((seq, h.tx));
+            }
+            eng.holds.insert(
+                h.tx,
+                StoredHold {
+                    client: h.client,
+                    amount: h.amount,
+                    state: h.state,
+                    seq,
+                    at: h.at,
+                },
+            );
+        }
+        queue.sort_unstable();
+        eng.hold_queue = queue.into();
+        for w in state.withdrawals {
+            eng.withdrawals.insert(
+                w.tx,
+                StoredWithdrawal {
+                    client: w.client,
+                    amount: w.amount,
+                    refunded: w.refunded,
+                    dispute: StateMachine::from_state(w.dispute),
+                    disputed: w.disputed,
+                },
+            );
+        }
+        for r in state.reclaimed {
+            eng.reclaimed.insert(r.tx, r.client);
+        }
+        for k in state.keys {
+            eng.keys.entry(k.client).or_default().insert(k.key, k.tx);
+        }
+        for d in state.deferred {
+            let tx = Transaction {
+                kind: d.kind,
+                client: d.client,
+                tx: d.tx,
+                amount: d.amount,
+            };
+            eng.deferred.entry(d.tx).or_default().push(DeferredRow {
+                seq: d.seq,
+                tx,
+                key: d.key,
+            });
+        }
+        for a in state.annotations {
+            eng.annotations.insert(
+                a.client,
+                Annotations {
+                    flags: a.flags.into_iter().collect(),
+                    notes: a.notes,
+                },
+            );
+        }
+        for e in state.escrow {
+            eng.escrow.insert(e.tx, e.amount);
+        }
+        for b in state.buckets {
+            let balance = Balance {
+                available: b.available,
+                held: b.held,
+            };
+            eng.buckets
+                .balances
+                .entry(b.client)
+                .or_default()
+                .insert(b.bucket, balance);
+        }
+        for d in state.deposit_buckets {
+            eng.buckets.deposits.insert(d.tx, d.bucket);
+        }
+        for c in state.credit {
+            let accrual = Accrual {
+                interest: c.interest,
+                since: c.since,
+            };
+            eng.credit.insert(c.client, accrual);
+        }
+        for p in state.promos {
+            let grant = Grant {
+                tx: p.tx,
+                amount: p.amount,
+                used: p.used,
+                expired: p.expired,
+                expires: p.expires,
+            };
+            eng.promos.grant(p.client, grant);
+        }
+        // credits already expired or used up have nothing left to take back
+        let mut queue: Vec<_> = eng
+            .promos
+            .grants
+            .values()
+            .flatten()
+            .filter(|g| g.left() > Decimal::ZERO)
+            .filter_map(|g| g.expires.map(|at| (at, g.tx)))
+            .collect();
+        queue.sort_unstable();
+        eng.promos.queue = queue.into();
+        Ok(eng)
+    }
+}
+
+/// Bitwise CRC-32 (IEEE 802.3, reflected). Snapshots are written rarely, so
+/// a lookup table isn't worth carrying.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in bytes {
+        crc ^= u32::from(b);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
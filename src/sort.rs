@@ -0,0 +1,226 @@
+//! External merge sort of a transactions CSV by one of its columns, for
+//! producers that emit rows out of order but stamp each with a timestamp.
+//!
+//! Rows are read in chunks of [`ExternalSort::chunk_rows`], each chunk is
+//! sorted in memory and spilled to a scratch file, and the chunks are then
+//! merged into the output. Memory is bounded by the chunk size, not the
+//! input. The sort is stable: rows with equal keys keep their input order.
+//!
+//! Keys that parse as numbers (epoch seconds or milliseconds) compare
+//! numerically and sort before all other keys, which compare as text — so
+//! RFC 3339 timestamps in a single offset sort chronologically too.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::sort::ExternalSort;
+//!
+//! let csv = "type,client,tx,amount,timestamp\n\
+//!            withdrawal,1,2,1.0,20\n\
+//!            deposit,1,1,5.0,10\n\
+//!            deposit,1,3,2.0,20\n";
+//! let mut out = Vec::new();
+//! ExternalSort::new("timestamp")
+//!     .chunk_rows(2)
+//!     .sort(csv.as_bytes(), &mut out)
+//!     .unwrap();
+//! assert_eq!(
+//!     String::from_utf8(out).unwrap(),
+//!     "type,client,tx,amount,timestamp\n\
+//!      deposit,1,1,5.0,10\n\
+//!      withdrawal,1,2,1.0,20\n\
+//!      deposit,1,3,2.0,20\n"
+//! );
+//! ```
+
+use crate::errors::Result;
+use anyhow::anyhow;
+use csv::{ByteRecord, ReaderBuilder, WriterBuilder};
+use rust_decimal::Decimal;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Rows per in-memory chunk unless [`ExternalSort::chunk_rows`] says otherwise.
+pub const DEFAULT_CHUNK_ROWS: usize = 1_000_000;
+
+/// Sort key of one row; see the module docs for the ordering.
+#[derive(Debug, PartialEq, Eq)]
+struct Key {
+    num: Option<Decimal>,
+    raw: Vec<u8>,
+}
+
+impl Key {
+    fn of(field: &[u8]) -> Self {
+        let num = std::str::from_utf8(field)
+            .ok()
+            .and_then(|s| s.parse::<Decimal>().ok());
+        Self {
+            num,
+            raw: field.to_vec(),
+        }
+    }
+}
+
+impl Ord for Key {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.num, other.num) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => self.raw.cmp(&other.raw),
+        }
+    }
+}
+
+impl PartialOrd for Key {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Counters returned by [`ExternalSort::sort`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortStats {
+    pub rows: u64,
+    /// Sorted runs spilled to disk (`0` when the input fit in one chunk).
+    pub chunks: usize,
+}
+
+/// Sorts a CSV with a header row by the named column.
+#[derive(Debug, Clone)]
+pub struct ExternalSort {
+    column: String,
+    chunk_rows: usize,
+    scratch: PathBuf,
+}
+
+impl ExternalSort {
+    /// Sort by `column`, spilling to the system temp directory.
+    pub fn new(column: &str) -> Self {
+        Self {
+            column: column.to_string(),
+            chunk_rows: DEFAULT_CHUNK_ROWS,
+            scratch: std::env::temp_dir(),
+        }
+    }
+
+    /// Hold at most `rows` rows in memory at a time.
+    pub fn chunk_rows(mut self, rows: usize) -> Self {
+        self.chunk_rows = rows.max(1);
+        self
+    }
+
+    /// Spill sorted chunks under `dir` instead of the system temp directory.
+    pub fn scratch_dir(mut self, dir: &Path) -> Self {
+        self.scratch = dir.to_path_buf();
+        self
+    }
+
+    /// Read CSV from `src` and write it to `dst` sorted, header first.
+    pub fn sort<R: Read, W: Write>(&self, src: R, dst: W) -> Result<SortStats> {
+        let mut rdr = ReaderBuilder::new().trim(csv::Trim::All).from_reader(src);
+        let header = rdr.byte_headers()?.clone();
+        let col = header
+            .iter()
+            .position(|h| h == self.column.as_bytes())
+            .ok_or_else(|| anyhow!("input has no {:?} column to sort by", self.column))?;
+
+        let spill = ScratchDir::create(&self.scratch)?;
+        let mut runs = Vec::new();
+        let mut chunk = Vec::new();
+        let mut rows = 0;
+        let mut record = ByteRecord::new();
+        while rdr.read_byte_record(&mut record)? {
+            rows += 1;
+            chunk.push((Key::of(record.get(col).unwrap_or_default()), record.clone()));
+            if chunk.len() == self.chunk_rows {
+                runs.push(spill.write_run(runs.len(), &mut chunk)?);
+            }
+        }
+
+        let mut wtr = WriterBuilder::new().from_writer(dst);
+        wtr.write_byte_record(&header)?;
+        if runs.is_empty() {
+            // everything fit in memory: no need to touch the disk
+            chunk.sort_by(|a, b| a.0.cmp(&b.0));
+            for (_, rec) in &chunk {
+                wtr.write_byte_record(rec)?;
+            }
+        } else {
+            if !chunk.is_empty() {
+                runs.push(spill.write_run(runs.len(), &mut chunk)?);
+            }
+            merge(&runs, col, &mut wtr)?;
+        }
+        wtr.flush()?;
+        Ok(SortStats {
+            rows,
+            chunks: runs.len(),
+        })
+    }
+}
+
+/// k-way merge of sorted runs; ties go to the earlier run, keeping the sort
+/// stable.
+fn merge<W: Write>(runs: &[PathBuf], col: usize, wtr: &mut csv::Writer<W>) -> Result<()> {
+    let mut readers = runs
+        .iter()
+        .map(|p| {
+            Ok(ReaderBuilder::new()
+                .has_headers(false)
+                .from_reader(BufReader::new(File::open(p)?)))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut heads: Vec<ByteRecord> = vec![ByteRecord::new(); readers.len()];
+    let mut heap = BinaryHeap::new();
+    for (i, rdr) in readers.iter_mut().enumerate() {
+        if rdr.read_byte_record(&mut heads[i])? {
+            heap.push(Reverse((Key::of(heads[i].get(col).unwrap_or_default()), i)));
+        }
+    }
+    while let Some(Reverse((_, i))) = heap.pop() {
+        wtr.write_byte_record(&heads[i])?;
+        if readers[i].read_byte_record(&mut heads[i])? {
+            heap.push(Reverse((Key::of(heads[i].get(col).unwrap_or_default()), i)));
+        }
+    }
+    Ok(())
+}
+
+/// Private directory for sorted runs, removed with its contents on drop.
+struct ScratchDir(PathBuf);
+
+impl ScratchDir {
+    fn create(parent: &Path) -> Result<Self> {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .subsec_nanos();
+        let dir = parent.join(format!(
+            "payments-engine-sort-{}-{nanos}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir)?;
+        Ok(Self(dir))
+    }
+
+    /// Sort `chunk` and write it out as run number `n`, leaving it empty.
+    fn write_run(&self, n: usize, chunk: &mut Vec<(Key, ByteRecord)>) -> Result<PathBuf> {
+        chunk.sort_by(|a, b| a.0.cmp(&b.0));
+        let path = self.0.join(format!("run-{n}.csv"));
+        let mut wtr = WriterBuilder::new().from_writer(BufWriter::new(File::create(&path)?));
+        for (_, rec) in chunk.drain(..) {
+            wtr.write_byte_record(&rec)?;
+        }
+        wtr.flush()?;
+        Ok(path)
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,137 @@
+//! Structuring detection: many deposits just under a reporting threshold in
+//! a short stretch of input, the classic way of keeping each one below the
+//! radar.
+//!
+//! A [`StructuringRule`] names the threshold, the band below it that counts
+//! as "just under", and how many such deposits within how many rows make a
+//! pattern. With a rule set ([`Engine::set_structuring`]), every applied
+//! deposit in the band is remembered per client; once a client has `count`
+//! of them within `window` rows, the engine raises an
+//! [`Event::Structuring`](crate::notify::Event::Structuring) and keeps a
+//! [`Flag`] with the supporting tx ids. The client's window then starts over,
+//! so a long run of such deposits is flagged once per `count`.
+//!
+//! Like the held-funds alert, detection state is not part of snapshots.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::structuring::StructuringRule;
+//! use payments_engine::{Engine, Transaction, TxType};
+//! use rust_decimal_macros::dec;
+//!
+//! let mut eng = Engine::new();
+//! eng.set_structuring(Some(StructuringRule::new(dec!(10_000))));
+//! for (tx, amount) in [(1, dec!(9_500)), (2, dec!(120)), (3, dec!(9_900)), (4, dec!(9_990))] {
+//!     eng.process(Transaction { kind: TxType::Deposit, client: 5, tx, amount: Some(amount) })
+//!         .unwrap();
+//! }
+//!
+//! let flag = &eng.structuring_flags()[0];
+//! assert_eq!((flag.client, flag.deposits.as_slice()), (5, [1, 3, 4].as_slice()));
+//! assert_eq!(flag.total, dec!(29_390));
+//! ```
+
+use crate::engine::Engine;
+use rust_decimal::Decimal;
+use std::collections::{HashMap, VecDeque};
+
+/// What counts as structuring; see the module docs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StructuringRule {
+    /// The reporting threshold deposits stay under.
+    pub threshold: Decimal,
+    /// Smallest deposit that counts as "just under" `threshold`.
+    pub floor: Decimal,
+    /// Deposits in `floor..threshold` that make a pattern.
+    pub count: usize,
+    /// ...when they fall within this many rows.
+    pub window: u64,
+}
+
+impl StructuringRule {
+    /// Three deposits within 90–100% of `threshold` in 10 000 rows.
+    pub fn new(threshold: Decimal) -> Self {
+        Self {
+            threshold,
+            floor: threshold * Decimal::new(9, 1),
+            count: 3,
+            window: 10_000,
+        }
+    }
+}
+
+/// One detected pattern.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Flag {
+    pub client: u16,
+    /// [`Engine::seq`] of the deposit completing the pattern.
+    pub seq: u64,
+    /// Tx ids of the deposits, oldest first.
+    pub deposits: Vec<u32>,
+    pub total: Decimal,
+}
+
+/// Per-client sliding windows of deposits in the band.
+#[derive(Debug)]
+pub(crate) struct Detector {
+    rule: StructuringRule,
+    /// `(seq, tx, amount)` of recent deposits in the band.
+    recent: HashMap<u16, VecDeque<(u64, u32, Decimal)>>,
+    flags: Vec<Flag>,
+}
+
+impl Detector {
+    pub(crate) fn new(rule: StructuringRule) -> Self {
+        Self {
+            rule,
+            recent: HashMap::new(),
+            flags: Vec::new(),
+        }
+    }
+
+    /// Note an applied deposit; returns the flag it completes, if any.
+    pub(crate) fn observe(
+        &mut self,
+        seq: u64,
+        client: u16,
+        tx: u32,
+        amount: Decimal,
+    ) -> Option<&Flag> {
+        if amount < self.rule.floor || amount >= self.rule.threshold {
+            return None;
+        }
+        let recent = self.recent.entry(client).or_default();
+        recent.push_back((seq, tx, amount));
+        while recent
+            .front()
+            .is_some_and(|&(first, ..)| seq - first >= self.rule.window)
+        {
+            recent.pop_front();
+        }
+        if recent.len() < self.rule.count {
+            return None;
+        }
+        let (deposits, amounts): (Vec<u32>, Vec<Decimal>) =
+            recent.drain(..).map(|(_, tx, amount)| (tx, amount)).unzip();
+        self.flags.push(Flag {
+            client,
+            seq,
+            deposits,
+            total: amounts.into_iter().sum(),
+        });
+        self.flags.last()
+    }
+}
+
+impl Engine {
+    /// Detect structuring under `rule` from now on (`None` turns it off and
+    /// forgets what was seen).
+    pub fn set_structuring(&mut self, rule: Option<StructuringRule>) {
+        self.structuring = rule.map(Detector::new);
+    }
+
+    /// Patterns detected so far, in detection order.
+    pub fn structuring_flags(&self) -> &[Flag] {
+        self.structuring.as_ref().map_or(&[], |d| &d.flags)
+    }
+}
@@ -0,0 +1,236 @@
+//! Multi-tenant partitioning: one process, several business units, no state
+//! shared between them.
+//!
+//! Each tenant gets its own [`Engine`], so accounts, deposits and tx-id
+//! spaces are fully isolated — the same client id or tx id in two tenants
+//! refers to two unrelated things.
+//!
+//! The router is a [`PaymentsProcessor`]: rows go to the tenant named in
+//! their [`RowMeta::tenant`], so the CLI ingest loop feeds it like a single
+//! engine. A hook set with [`TenantRouter::on_new_tenant`] sets up each
+//! engine as it is created (clock, alert rules). Tenant names are
+//! restricted to `[A-Za-z0-9_-]` because they end up in report file names;
+//! a row naming any other tenant is refused on its own — logged and counted
+//! in [`TenantRouter::refused`] — and the run goes on.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::tenant::{TenantRouter, TenantRow};
+//! use payments_engine::{Transaction, TxType};
+//! use csv::ReaderBuilder;
+//!
+//! let csv = "type,client,tx,amount,tenant\n\
+//!            deposit,1,1,5.0,eu\n\
+//!            deposit,1,1,7.0,us\n\
+//!            withdrawal,1,2,1.0,eu\n";
+//! let mut router = TenantRouter::new();
+//! let mut rdr = ReaderBuilder::new().from_reader(csv.as_bytes());
+//! for row in rdr.deserialize::<TenantRow>() {
+//!     let (tenant, tx) = row.unwrap().split();
+//!     router.process(&tenant, tx).unwrap();
+//! }
+//! assert_eq!(router.engine("eu").unwrap().accounts[&1].available, rust_decimal_macros::dec!(4));
+//! assert_eq!(router.engine("us").unwrap().accounts[&1].available, rust_decimal_macros::dec!(7));
+//!
+//! let deposit = Transaction { kind: TxType::Deposit, client: 1, tx: 2, amount: None };
+//! router.process("../eu", deposit).unwrap();
+//! assert_eq!(router.refused(), 1);
+//! ```
+
+use crate::checksum::Sha256;
+use crate::engine::{Engine, EngineConfig, Finalized};
+use crate::errors::Result;
+use crate::feed::ProcessResult;
+use crate::models::{Account, Transaction, TxType};
+use crate::processor::{PaymentsProcessor, RowMeta, Stats};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// Tenant used for rows without a `tenant` column or with an empty value.
+pub const DEFAULT_TENANT: &str = "default";
+
+/// An input row carrying an optional `tenant` routing column next to the
+/// regular transaction fields.
+#[derive(Debug, Deserialize)]
+pub struct TenantRow {
+    #[serde(rename = "type")]
+    pub kind: TxType,
+    pub client: u16,
+    pub tx: u32,
+    #[serde(default, deserialize_with = "crate::amount::deserialize")]
+    pub amount: Option<Decimal>,
+    #[serde(default)]
+    pub tenant: Option<String>,
+}
+
+impl TenantRow {
+    /// Separate the routing key from the transaction itself.
+    pub fn split(self) -> (String, Transaction) {
+        let tenant = self
+            .tenant
+            .filter(|t| !t.is_empty())
+            .unwrap_or_else(|| DEFAULT_TENANT.to_string());
+        let tx = Transaction {
+            kind: self.kind,
+            client: self.client,
+            tx: self.tx,
+            amount: self.amount,
+        };
+        (tenant, tx)
+    }
+}
+
+/// Sets up a tenant's engine when it is created; see
+/// [`TenantRouter::on_new_tenant`].
+type Setup = Box<dyn FnMut(&str, &mut Engine) -> Result<()>>;
+
+/// Routes transactions to one isolated engine per tenant, created on first
+/// use.
+#[derive(Default)]
+pub struct TenantRouter {
+    engines: BTreeMap<String, Engine>,
+    config: EngineConfig,
+    setup: Option<Setup>,
+    refused: u64,
+}
+
+impl TenantRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Router whose tenant engines are all built with `config`.
+    pub fn with_config(config: EngineConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    /// Run `setup` on every tenant engine created from now on, right after
+    /// it is built with the router's config.
+    pub fn on_new_tenant(&mut self, setup: impl FnMut(&str, &mut Engine) -> Result<()> + 'static) {
+        self.setup = Some(Box::new(setup));
+    }
+
+    /// Apply `tx` to `tenant`'s engine; a row naming an invalid tenant is
+    /// refused, see the module docs.
+    pub fn process(&mut self, tenant: &str, tx: Transaction) -> Result<()> {
+        let meta = RowMeta {
+            tenant: Some(tenant),
+            ..RowMeta::default()
+        };
+        self.process_row(tx, meta).map(drop)
+    }
+
+    /// Rows refused so far for naming an invalid tenant.
+    pub fn refused(&self) -> u64 {
+        self.refused
+    }
+
+    /// [`Engine::finalize`] every tenant, returning the summaries by name.
+    pub fn finalize(&mut self) -> Result<BTreeMap<String, Finalized>> {
+        self.engines
+            .iter_mut()
+            .map(|(t, e)| Ok((t.clone(), e.finalize()?)))
+            .collect()
+    }
+
+    pub fn engine(&self, tenant: &str) -> Option<&Engine> {
+        self.engines.get(tenant)
+    }
+
+    /// All tenants in name order.
+    pub fn engines(&self) -> impl Iterator<Item = (&str, &Engine)> {
+        self.engines.iter().map(|(t, e)| (t.as_str(), e))
+    }
+
+    /// `tenant`'s engine, created and set up on first use.
+    fn tenant_engine(&mut self, tenant: &str) -> Result<&mut Engine> {
+        if !self.engines.contains_key(tenant) {
+            let mut engine = Engine::with_config(self.config.clone());
+            if let Some(setup) = self.setup.as_mut() {
+                setup(tenant, &mut engine)?;
+            }
+            self.engines.insert(tenant.to_string(), engine);
+        }
+        Ok(self
+            .engines
+            .get_mut(tenant)
+            .expect("tenant engine just created"))
+    }
+}
+
+/// Rows without a tenant go to [`DEFAULT_TENANT`], whose accounts
+/// [`account`](PaymentsProcessor::account) reads. [`finalize`](PaymentsProcessor::finalize)
+/// sums the tenants' summaries, with a state hash over every tenant's.
+impl PaymentsProcessor for TenantRouter {
+    fn process(&mut self, tx: Transaction) -> Result<()> {
+        self.process_row(tx, RowMeta::default()).map(drop)
+    }
+
+    fn process_row(&mut self, tx: Transaction, meta: RowMeta) -> Result<Option<ProcessResult>> {
+        let tenant = meta
+            .tenant
+            .filter(|t| !t.is_empty())
+            .unwrap_or(DEFAULT_TENANT);
+        if !valid_name(tenant) {
+            tracing::warn!(
+                tenant,
+                tx = tx.tx,
+                client = tx.client,
+                "invalid tenant name; row refused"
+            );
+            self.refused += 1;
+            return Ok(None);
+        }
+        self.tenant_engine(tenant)?.process_row(tx, meta).map(Some)
+    }
+
+    fn account(&self, client: u16) -> Option<Account> {
+        self.engines
+            .get(DEFAULT_TENANT)?
+            .accounts
+            .get(&client)
+            .cloned()
+    }
+
+    fn finalize(&mut self) -> Result<Finalized> {
+        let mut h = Sha256::new();
+        let mut total = Finalized {
+            rows: 0,
+            accounts: 0,
+            reclaimed_deposits: 0,
+            state_hash: String::new(),
+        };
+        for (tenant, done) in TenantRouter::finalize(self)? {
+            h.update(tenant.as_bytes());
+            h.update(done.state_hash.as_bytes());
+            total.rows += done.rows;
+            total.accounts += done.accounts;
+            total.reclaimed_deposits += done.reclaimed_deposits;
+        }
+        total.state_hash = h.hex_digest();
+        Ok(total)
+    }
+
+    fn stats(&self) -> Stats {
+        self.engines
+            .values()
+            .map(Engine::stats)
+            .fold(Stats::default(), |sum, s| Stats {
+                rows: sum.rows + s.rows,
+                accounts: sum.accounts + s.accounts,
+                open_disputes: sum.open_disputes + s.open_disputes,
+                active_holds: sum.active_holds + s.active_holds,
+            })
+    }
+}
+
+fn valid_name(tenant: &str) -> bool {
+    !tenant.is_empty()
+        && tenant
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+}
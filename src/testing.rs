@@ -0,0 +1,36 @@
+//! Aids for testing the engine rather than running it.
+//!
+//! [`reference`] is a second, deliberately naive implementation of the
+//! processing rules. `tests/differential.rs` feeds it and [`Engine`] the
+//! same random transaction sequences and fails on the first row where they
+//! disagree, so a semantic change to the engine has to be made in both
+//! places on purpose.
+//!
+//! [`chaos`] (feature `chaos`) delays, duplicates and reorders the rows on
+//! their way to a processor, for robustness tests of the streaming and
+//! sharded paths.
+//!
+//! [`Engine`]: crate::Engine
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::testing::reference::Reference;
+//! use payments_engine::{Engine, Transaction, TxType};
+//! use rust_decimal_macros::dec;
+//!
+//! let rows = [
+//!     Transaction { kind: TxType::Deposit, client: 1, tx: 1, amount: Some(dec!(3)) },
+//!     Transaction { kind: TxType::Withdrawal, client: 1, tx: 2, amount: Some(dec!(5)) },
+//! ];
+//! let mut engine = Engine::new();
+//! let mut model = Reference::new();
+//! for row in rows {
+//!     let _ = model.process(&row);
+//!     engine.process(row).unwrap();
+//! }
+//! assert_eq!(engine.accounts, model.accounts());
+//! ```
+
+#[cfg(feature = "chaos")]
+pub mod chaos;
+pub mod reference;
@@ -0,0 +1,225 @@
+//! Failure injection for robustness tests (feature `chaos`): a
+//! [`PaymentsProcessor`] wrapper that delays, duplicates and reorders rows
+//! on their way to the processor it wraps, the way an at-least-once
+//! transport would.
+//!
+//! Every disturbance is bounded by a [`ChaosConfig`] and drawn from its
+//! seed, so a failing run is reproduced from the seed alone; only the
+//! pauses take wall-clock time, and they do not change what is delivered.
+//!
+//! * a duplicated row is delivered again right after the original. A row
+//!   with an idempotency key that creates a record (deposit, withdrawal) is
+//!   resent under a fresh tx id, as an upstream retry would, so only the
+//!   key check can catch it; any other row is resent as is. Withdrawal
+//!   ids are not checked for reuse, so a withdrawal resent without a key
+//!   is applied twice;
+//! * each row is held back for a random number of later rows, fewer than
+//!   [`ChaosConfig::reorder_window`], so rows are released out of order
+//!   but none ends up more than `reorder_window - 1` places behind;
+//! * every delivered row is preceded by a pause of up to
+//!   [`ChaosConfig::max_delay`], which shifts the timing between a
+//!   frontend and worker threads such as those of
+//!   [`ParallelEngine`](crate::parallel::ParallelEngine).
+//!
+//! `tests/chaos.rs` uses it to check that duplicates leave balances as they
+//! were, that the engine's invariants hold in any order, and that the
+//! sharded engine agrees with the single-threaded one on the same disturbed
+//! stream.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::processor::{PaymentsProcessor, RowMeta};
+//! use payments_engine::testing::chaos::{Chaos, ChaosConfig};
+//! use payments_engine::{Engine, Transaction, TxType};
+//! use rust_decimal_macros::dec;
+//!
+//! let config = ChaosConfig { seed: 7, duplicate_per_mille: 1000, ..Default::default() };
+//! let mut chaos = Chaos::new(Engine::new(), config);
+//! for tx in 1..=3 {
+//!     let row = Transaction { kind: TxType::Deposit, client: 1, tx, amount: Some(dec!(5)) };
+//!     chaos.process_row(row, RowMeta::keyed(Some(&format!("pay-{tx}")))).unwrap();
+//! }
+//! let eng = chaos.into_inner().unwrap();
+//!
+//! // every row arrived twice, and every retry was caught by its key
+//! assert_eq!(eng.seq(), 6);
+//! assert_eq!(eng.accounts[&1].available, dec!(15));
+//! ```
+
+use crate::engine::Finalized;
+use crate::errors::Result;
+use crate::feed::ProcessResult;
+use crate::models::{Account, Transaction, TxType};
+use crate::processor::{PaymentsProcessor, RowMeta, Stats};
+use std::time::Duration;
+
+/// Bounds of the disturbances; the default disturbs nothing.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChaosConfig {
+    pub seed: u64,
+    /// Per mille of rows delivered twice.
+    pub duplicate_per_mille: u32,
+    /// Rows are held back for fewer than this many later rows; 0 or 1
+    /// keeps the order.
+    pub reorder_window: usize,
+    /// Longest pause before a row is delivered.
+    pub max_delay: Duration,
+}
+
+/// Disturbances injected so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Injected {
+    pub duplicated: u64,
+    /// Rows delivered ahead of a row sent before them.
+    pub reordered: u64,
+    pub delayed: Duration,
+}
+
+/// A row held back.
+struct Pending {
+    /// Position in the order rows were sent.
+    sent: u64,
+    /// Released once row `due` has been sent.
+    due: u64,
+    tx: Transaction,
+    key: Option<String>,
+}
+
+/// Disturbs the rows passed to a [`PaymentsProcessor`]; see the module
+/// docs.
+pub struct Chaos<P> {
+    inner: P,
+    config: ChaosConfig,
+    state: u64,
+    /// Rows held back, in no particular order.
+    window: Vec<Pending>,
+    sent: u64,
+    /// Tx ids given to resent keyed rows, counting down from `u32::MAX`.
+    next_retry_tx: u32,
+    injected: Injected,
+}
+
+impl<P: PaymentsProcessor> Chaos<P> {
+    pub fn new(inner: P, config: ChaosConfig) -> Self {
+        Self {
+            // xorshift must not start at zero
+            state: config.seed ^ 0x9E37_79B9_7F4A_7C15,
+            inner,
+            config,
+            window: Vec::new(),
+            sent: 0,
+            next_retry_tx: u32::MAX,
+            injected: Injected::default(),
+        }
+    }
+
+    pub fn injected(&self) -> Injected {
+        self.injected
+    }
+
+    /// Deliver every row still held back.
+    pub fn flush(&mut self) -> Result<()> {
+        self.release(u64::MAX)
+    }
+
+    /// Flush held rows and hand back the wrapped processor.
+    pub fn into_inner(mut self) -> Result<P> {
+        self.flush()?;
+        Ok(self.inner)
+    }
+
+    /// xorshift64*, as in [`crate::generate`].
+    fn below(&mut self, n: u64) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) % n
+    }
+
+    fn send(&mut self, tx: Transaction, key: Option<&str>) -> Result<()> {
+        self.sent += 1;
+        let hold = self.below(self.config.reorder_window.max(1) as u64);
+        self.window.push(Pending {
+            sent: self.sent,
+            due: self.sent + hold,
+            tx,
+            key: key.map(str::to_owned),
+        });
+        self.release(self.sent)
+    }
+
+    /// Deliver the held rows due by row `now`, in order of due row, then
+    /// of sending.
+    fn release(&mut self, now: u64) -> Result<()> {
+        while let Some(next) = (0..self.window.len())
+            .filter(|&i| self.window[i].due <= now)
+            .min_by_key(|&i| (self.window[i].due, self.window[i].sent))
+        {
+            let Pending { sent, tx, key, .. } = self.window.swap_remove(next);
+            if self.window.iter().any(|p| p.sent < sent) {
+                self.injected.reordered += 1;
+            }
+            self.emit(tx, key)?;
+        }
+        Ok(())
+    }
+
+    /// Deliver a row, and deliver it again if it draws a duplicate.
+    fn emit(&mut self, tx: Transaction, key: Option<String>) -> Result<()> {
+        let twice = self.below(1000) < u64::from(self.config.duplicate_per_mille);
+        let retry = twice.then(|| match (tx.kind, &key) {
+            (TxType::Deposit | TxType::Withdrawal, Some(_)) => {
+                let id = self.next_retry_tx;
+                self.next_retry_tx -= 1;
+                Transaction { tx: id, ..tx }
+            }
+            _ => Transaction { ..tx },
+        });
+        self.deliver(tx, key.as_deref())?;
+        if let Some(retry) = retry {
+            self.injected.duplicated += 1;
+            self.deliver(retry, key.as_deref())?;
+        }
+        Ok(())
+    }
+
+    fn deliver(&mut self, tx: Transaction, key: Option<&str>) -> Result<()> {
+        let max = self.config.max_delay.as_micros() as u64;
+        if max > 0 {
+            let pause = Duration::from_micros(self.below(max + 1));
+            self.injected.delayed += pause;
+            std::thread::sleep(pause);
+        }
+        self.inner.process_row(tx, RowMeta::keyed(key)).map(drop)
+    }
+}
+
+impl<P: PaymentsProcessor> PaymentsProcessor for Chaos<P> {
+    fn process(&mut self, tx: Transaction) -> Result<()> {
+        self.send(tx, None)
+    }
+
+    /// Rows naming a bucket other than main are refused: their buckets
+    /// are not held back with them.
+    fn process_row(&mut self, tx: Transaction, meta: RowMeta) -> Result<Option<ProcessResult>> {
+        if !meta.buckets.is_main() {
+            anyhow::bail!("buckets are not supported by this processor");
+        }
+        self.send(tx, meta.key()).map(|()| None)
+    }
+
+    /// Balances as delivered so far; rows still in the window are not
+    /// applied yet.
+    fn account(&self, client: u16) -> Option<Account> {
+        self.inner.account(client)
+    }
+
+    fn finalize(&mut self) -> Result<Finalized> {
+        self.flush()?;
+        self.inner.finalize()
+    }
+
+    fn stats(&self) -> Stats {
+        self.inner.stats()
+    }
+}
@@ -0,0 +1,302 @@
+//! A slow, obviously-correct model of the processing rules.
+//!
+//! [`Reference`] keeps nothing but the rows it applied. Every question — is
+//! this id taken, is this dispute open, what is available — is answered by
+//! scanning that log from the start, and balances are summed from it on
+//! demand. No index can drift out of step with the balances, which makes it
+//! a good oracle for [`Engine`](crate::Engine) and a poor engine: n rows
+//! cost O(n²).
+//!
+//! Every [`TxType`] is covered, as are hold expiry, negative-amount
+//! normalization and amount limits from [`EngineConfig`]. Anomaly policies, history, sinks and
+//! alerts are not: a rejected row just reports its [`Anomaly`]. Nor is
+//! clock time, so promotional credits never expire here.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::anomaly::Anomaly;
+//! use payments_engine::testing::reference::Reference;
+//! use payments_engine::{Transaction, TxType};
+//! use rust_decimal_macros::dec;
+//!
+//! let row = |kind, tx, amount| Transaction { kind, client: 1, tx, amount };
+//! let mut model = Reference::new();
+//! model.process(&row(TxType::Deposit, 1, Some(dec!(10)))).unwrap();
+//! model.process(&row(TxType::Dispute, 1, None)).unwrap();
+//! assert_eq!(model.process(&row(TxType::Dispute, 1, None)), Err(Anomaly::AlreadyDisputed));
+//! model.process(&row(TxType::Chargeback, 1, None)).unwrap();
+//!
+//! let acc = &model.accounts()[&1];
+//! assert_eq!((acc.available, acc.held, acc.locked), (dec!(0), dec!(0), true));
+//! ```
+
+use crate::anomaly::Anomaly;
+use crate::engine::{AmountLimits, EngineConfig};
+use crate::fasthash::Map;
+use crate::models::{Account, Transaction, TxType};
+use rust_decimal::Decimal;
+use std::collections::BTreeSet;
+
+/// What a dispute-family row named: a deposit, or the refunds of a
+/// withdrawal with that id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Credit {
+    Deposit,
+    Refunds,
+}
+
+/// One applied row.
+#[derive(Debug, Clone)]
+struct Entry {
+    seq: u64,
+    kind: TxType,
+    client: u16,
+    tx: u32,
+    /// Money the row moved: its own amount, the hold's for a release or
+    /// capture, the disputed amount for a dispute, resolve or chargeback.
+    amount: Decimal,
+    /// Set for dispute-family rows only.
+    credit: Option<Credit>,
+}
+
+/// Reference implementation of [`Engine`](crate::Engine); see the module
+/// docs.
+#[derive(Debug, Clone, Default)]
+pub struct Reference {
+    hold_expiry: Option<u64>,
+    normalize_negative: bool,
+    max_amounts: AmountLimits,
+    seq: u64,
+    /// Clients whose account exists: any row past the amount check opens
+    /// one, applied or not.
+    clients: BTreeSet<u16>,
+    log: Vec<Entry>,
+}
+
+impl Reference {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Model an engine built with `config`.
+    pub fn with_config(config: &EngineConfig) -> Self {
+        Self {
+            hold_expiry: config.hold_expiry,
+            normalize_negative: config.normalize_negative,
+            max_amounts: config.max_amounts.clone(),
+            ..Self::default()
+        }
+    }
+
+    /// Apply `row`, or say which anomaly the engine should reject it with.
+    pub fn process(&mut self, row: &Transaction) -> Result<(), Anomaly> {
+        self.seq += 1;
+        let (kind, amount) = match (row.kind, row.amount) {
+            (TxType::Deposit, Some(a)) if self.normalize_negative && a < Decimal::ZERO => {
+                (TxType::Withdrawal, Some(-a))
+            }
+            (TxType::Withdrawal, Some(a)) if self.normalize_negative && a < Decimal::ZERO => {
+                (TxType::Deposit, Some(-a))
+            }
+            other => other,
+        };
+        let amount = match amount {
+            _ if !kind.carries_amount() => Decimal::ZERO,
+            None => return Err(Anomaly::MissingAmount),
+            Some(a) if a <= Decimal::ZERO => return Err(Anomaly::NonPositiveAmount),
+            Some(a) if self.max_amounts.max_for(kind).is_some_and(|max| a > max) => {
+                return Err(Anomaly::AmountTooLarge);
+            }
+            Some(a) => a,
+        };
+        self.clients.insert(row.client);
+        if self.locked(row.client) {
+            return Err(Anomaly::LockedAccount);
+        }
+        let (amount, credit) = self.check(kind, row.client, row.tx, amount)?;
+        self.log.push(Entry {
+            seq: self.seq,
+            kind,
+            client: row.client,
+            tx: row.tx,
+            amount,
+            credit,
+        });
+        Ok(())
+    }
+
+    /// Every account, as [`Engine::accounts`](crate::Engine) holds them.
+    pub fn accounts(&self) -> Map<u16, Account> {
+        self.clients
+            .iter()
+            .map(|&client| (client, self.account(client)))
+            .collect()
+    }
+
+    /// Decide whether a row for an unlocked account applies, and what it
+    /// moves.
+    fn check(
+        &self,
+        kind: TxType,
+        client: u16,
+        tx: u32,
+        amount: Decimal,
+    ) -> Result<(Decimal, Option<Credit>), Anomaly> {
+        match kind {
+            // promotional credits take their ids from the deposits'
+            TxType::Deposit | TxType::Promo
+                if self.find(TxType::Deposit, tx).is_some()
+                    || self.find(TxType::Promo, tx).is_some() =>
+            {
+                Err(Anomaly::DuplicateTx)
+            }
+            TxType::Deposit | TxType::Promo => Ok((amount, None)),
+            TxType::Withdrawal if self.find(TxType::Withdrawal, tx).is_some() => {
+                Err(Anomaly::DuplicateTx)
+            }
+            TxType::Withdrawal if self.account(client).available < amount => {
+                Err(Anomaly::InsufficientFunds)
+            }
+            TxType::Withdrawal => Ok((amount, None)),
+            TxType::Refund => {
+                let w = self
+                    .find(TxType::Withdrawal, tx)
+                    .ok_or(Anomaly::UnknownTx)?;
+                if w.client != client {
+                    Err(Anomaly::ClientMismatch)
+                } else if self.refunded(tx) + amount > w.amount {
+                    Err(Anomaly::OverRefund)
+                } else {
+                    Ok((amount, None))
+                }
+            }
+            TxType::Hold if self.find(TxType::Hold, tx).is_some() => Err(Anomaly::DuplicateTx),
+            TxType::Hold if self.account(client).available < amount => {
+                Err(Anomaly::InsufficientFunds)
+            }
+            TxType::Hold => Ok((amount, None)),
+            // rows name no buckets here, so a move goes from main to main
+            TxType::Move => Err(Anomaly::BadBucket),
+            TxType::Release | TxType::Capture => {
+                let hold = self.find(TxType::Hold, tx).ok_or(Anomaly::UnknownTx)?;
+                if hold.client != client {
+                    Err(Anomaly::ClientMismatch)
+                } else if self.closed(hold) || self.expired(hold) {
+                    Err(Anomaly::HoldNotActive)
+                } else {
+                    Ok((hold.amount, None))
+                }
+            }
+            TxType::Dispute | TxType::Resolve | TxType::Chargeback => {
+                let (credit, owner) = match self.find(TxType::Deposit, tx) {
+                    Some(dep) => (Credit::Deposit, dep.client),
+                    None => {
+                        let w = self
+                            .find(TxType::Withdrawal, tx)
+                            .ok_or(Anomaly::UnknownTx)?;
+                        let open = self
+                            .last_dispute(Credit::Refunds, tx)
+                            .is_some_and(|e| e.kind == TxType::Dispute);
+                        if self.refunded(tx).is_zero() && !open {
+                            return Err(Anomaly::UnknownTx);
+                        }
+                        (Credit::Refunds, w.client)
+                    }
+                };
+                if owner != client {
+                    return Err(Anomaly::ClientMismatch);
+                }
+                let last = self.last_dispute(credit, tx);
+                match (kind, last.map(|e| e.kind)) {
+                    (TxType::Dispute, None | Some(TxType::Resolve)) => {
+                        let disputed = match credit {
+                            Credit::Deposit => self.find(TxType::Deposit, tx).unwrap().amount,
+                            Credit::Refunds => self.refunded(tx),
+                        };
+                        Ok((disputed, Some(credit)))
+                    }
+                    (TxType::Dispute, _) => Err(Anomaly::AlreadyDisputed),
+                    (_, Some(TxType::Dispute)) => Ok((last.unwrap().amount, Some(credit))),
+                    _ => Err(Anomaly::NotDisputed),
+                }
+            }
+        }
+    }
+
+    /// The applied row of `kind` with id `tx`.
+    fn find(&self, kind: TxType, tx: u32) -> Option<&Entry> {
+        self.log.iter().find(|e| e.kind == kind && e.tx == tx)
+    }
+
+    /// The latest dispute, resolve or chargeback of a credit.
+    fn last_dispute(&self, credit: Credit, tx: u32) -> Option<&Entry> {
+        self.log
+            .iter()
+            .rev()
+            .find(|e| e.credit == Some(credit) && e.tx == tx)
+    }
+
+    /// Refunds of withdrawal `tx` not taken back by a chargeback.
+    fn refunded(&self, tx: u32) -> Decimal {
+        self.log
+            .iter()
+            .filter(|e| e.tx == tx)
+            .map(|e| match (e.kind, e.credit) {
+                (TxType::Refund, _) => e.amount,
+                (TxType::Chargeback, Some(Credit::Refunds)) => -e.amount,
+                _ => Decimal::ZERO,
+            })
+            .sum()
+    }
+
+    fn locked(&self, client: u16) -> bool {
+        self.log
+            .iter()
+            .any(|e| e.kind == TxType::Chargeback && e.client == client)
+    }
+
+    /// A hold that was released or captured.
+    fn closed(&self, hold: &Entry) -> bool {
+        self.log
+            .iter()
+            .any(|e| matches!(e.kind, TxType::Release | TxType::Capture) && e.tx == hold.tx)
+    }
+
+    /// A hold left open past its expiry window, as of the current row.
+    fn expired(&self, hold: &Entry) -> bool {
+        !self.closed(hold)
+            && self
+                .hold_expiry
+                .is_some_and(|ttl| hold.seq + ttl < self.seq)
+    }
+
+    /// Balances of `client`, summed over the log.
+    fn account(&self, client: u16) -> Account {
+        let mut acc = Account::default();
+        for e in self.log.iter().filter(|e| e.client == client) {
+            match e.kind {
+                TxType::Deposit | TxType::Refund | TxType::Promo => acc.available += e.amount,
+                TxType::Withdrawal => acc.available -= e.amount,
+                TxType::Hold | TxType::Dispute => {
+                    acc.available -= e.amount;
+                    acc.held += e.amount;
+                }
+                TxType::Release | TxType::Resolve => {
+                    acc.available += e.amount;
+                    acc.held -= e.amount;
+                }
+                TxType::Capture => acc.held -= e.amount,
+                TxType::Chargeback => {
+                    acc.held -= e.amount;
+                    acc.locked = true;
+                }
+                TxType::Move => {}
+            }
+            if e.kind == TxType::Hold && self.expired(e) {
+                acc.available += e.amount;
+                acc.held -= e.amount;
+            }
+        }
+        acc
+    }
+}
@@ -0,0 +1,162 @@
+//! Per-client processing trace: every row of one client, applied or
+//! rejected, with its account before and after, as NDJSON.
+//!
+//! History ([`crate::history`]) keeps applied rows of every client in
+//! memory; a trace follows a single client and streams out as it goes, so
+//! an investigation of one account costs a client id comparison per row
+//! and nothing for the others. Attach a [`ClientTrace`] with
+//! [`Engine::set_trace`]; the engine writes one [`TraceEntry`] per row of
+//! the client, and [`Engine::finalize`] flushes it.
+//!
+//! ```json
+//! {"seq":3,"client":42,"tx":9981,"type":"withdrawal","amount":"50","outcome":"rejected",
+//!  "anomaly":"insufficient-funds",
+//!  "before":{"available":"12","held":"0","total":"12","locked":false},
+//!  "after":{"available":"12","held":"0","total":"12","locked":false}}
+//! ```
+//!
+//! `before` is taken after the hold expiry sweep the row triggers, so funds
+//! an expiring hold returned show up between one entry's `after` and the
+//! next entry's `before`. A row rewritten by negative-amount normalization
+//! is traced as applied. What-if rows of an [`Engine::fork`] are not traced.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::trace::ClientTrace;
+//! use payments_engine::{Engine, Transaction, TxType};
+//! use rust_decimal_macros::dec;
+//! use std::sync::{Arc, Mutex};
+//!
+//! # #[derive(Clone, Default)]
+//! # struct Shared(Arc<Mutex<Vec<u8>>>);
+//! # impl std::io::Write for Shared {
+//! #     fn write(&mut self, b: &[u8]) -> std::io::Result<usize> { self.0.lock().unwrap().write(b) }
+//! #     fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+//! # }
+//! let out = Shared::default();
+//! let mut eng = Engine::new();
+//! eng.set_trace(Some(ClientTrace::new(42, out.clone())));
+//! for (client, tx, kind, amount) in [
+//!     (42, 1, TxType::Deposit, dec!(12)),
+//!     (7, 2, TxType::Deposit, dec!(90)),
+//!     (42, 3, TxType::Withdrawal, dec!(50)),
+//! ] {
+//!     eng.process(Transaction { kind, client, tx, amount: Some(amount) }).unwrap();
+//! }
+//! eng.finalize().unwrap();
+//!
+//! let text = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+//! let entries: Vec<serde_json::Value> =
+//!     text.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+//! assert_eq!(entries.len(), 2);
+//! assert_eq!(entries[0]["after"]["available"], "12");
+//! assert_eq!(entries[1]["seq"], 3);
+//! assert_eq!(entries[1]["anomaly"], "insufficient-funds");
+//! assert_eq!(entries[1]["after"], entries[1]["before"]);
+//! ```
+
+use crate::anomaly::Anomaly;
+use crate::cdc::Balances;
+use crate::engine::Engine;
+use crate::errors::Result;
+use crate::models::{Account, Transaction, TxType};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::io::Write;
+
+/// One traced row.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TraceEntry {
+    pub seq: u64,
+    pub client: u16,
+    pub tx: u32,
+    #[serde(rename = "type")]
+    pub kind: TxType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amount: Option<Decimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// `applied` or `rejected`.
+    pub outcome: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anomaly: Option<Anomaly>,
+    pub before: Balances,
+    pub after: Balances,
+}
+
+/// Destination of one client's trace; see the module docs.
+pub struct ClientTrace {
+    client: u16,
+    out: Box<dyn Write + Send>,
+    rows: u64,
+}
+
+impl ClientTrace {
+    pub fn new(client: u16, out: impl Write + Send + 'static) -> Self {
+        Self {
+            client,
+            out: Box::new(out),
+            rows: 0,
+        }
+    }
+
+    pub fn client(&self) -> u16 {
+        self.client
+    }
+
+    /// Entries written so far.
+    pub fn rows(&self) -> u64 {
+        self.rows
+    }
+
+    fn write(&mut self, entry: &TraceEntry) -> Result<()> {
+        serde_json::to_writer(&mut self.out, entry)?;
+        self.out.write_all(b"\n")?;
+        self.rows += 1;
+        Ok(())
+    }
+
+    pub(crate) fn flush(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+impl Engine {
+    /// Trace the rows of one client from now on (`None` stops tracing),
+    /// returning the trace attached until now, unflushed.
+    pub fn set_trace(&mut self, trace: Option<ClientTrace>) -> Option<ClientTrace> {
+        std::mem::replace(&mut self.trace, trace)
+    }
+
+    /// Write the trace entry of the row just processed, if its client is
+    /// the traced one.
+    pub(crate) fn trace_row(
+        &mut self,
+        tx: &Transaction,
+        key: Option<&str>,
+        before: &Account,
+        anomaly: Option<Anomaly>,
+    ) -> Result<()> {
+        let Some(trace) = self.trace.as_mut().filter(|t| t.client == tx.client) else {
+            return Ok(());
+        };
+        let after = self.accounts.get(&tx.client).cloned().unwrap_or_default();
+        trace.write(&TraceEntry {
+            seq: self.seq,
+            client: tx.client,
+            tx: tx.tx,
+            kind: tx.kind,
+            amount: tx.amount,
+            key: key.map(str::to_owned),
+            outcome: if anomaly.is_some() {
+                "rejected"
+            } else {
+                "applied"
+            },
+            anomaly,
+            before: before.into(),
+            after: (&after).into(),
+        })
+    }
+}
@@ -0,0 +1,182 @@
+//! Two-pass processing of a seekable CSV input, for producers that cannot
+//! keep a dispute behind the deposit it names.
+//!
+//! The first pass builds a [`DepositIndex`]: the tx id, client and byte
+//! offset of every deposit in the file. The second pass,
+//! [`DepositIndex::reorder`], copies the rows in input order except that a
+//! dispute, resolve or chargeback naming a deposit further down the file
+//! pulls that deposit (read back at its offset) in just ahead of itself;
+//! the deposit is then skipped where it stood. Whatever the row order,
+//! every dispute thus finds its deposit, and the result goes to the engine
+//! as a normal input.
+//!
+//! Only the first deposit with a given id is indexed (later ones are
+//! rejected as `duplicate-tx` anyway), and a dispute row moves a deposit
+//! only if it names the same client. Refunds are not indexed: a dispute of
+//! a refunded withdrawal must still come after the refund. The index costs
+//! about 24 bytes per deposit; the rows themselves stay on disk.
+//!
+//! ### Example
+//! ```rust
+//! use payments_engine::twopass::DepositIndex;
+//! use std::io::Cursor;
+//!
+//! let csv = "type,client,tx,amount\n\
+//!            dispute,1,7,\n\
+//!            deposit,1,3,1.0\n\
+//!            deposit,1,7,5.0\n";
+//! let index = DepositIndex::build(csv.as_bytes()).unwrap();
+//! assert_eq!(index.len(), 2);
+//!
+//! let mut out = Vec::new();
+//! let moved = index
+//!     .reorder(Cursor::new(csv), Cursor::new(csv), &mut out)
+//!     .unwrap();
+//! assert_eq!(moved, 1);
+//! assert_eq!(
+//!     String::from_utf8(out).unwrap(),
+//!     "type,client,tx,amount\n\
+//!      deposit,1,7,5.0\n\
+//!      dispute,1,7,\n\
+//!      deposit,1,3,1.0\n"
+//! );
+//! ```
+
+use crate::errors::Result;
+use crate::fasthash::Map;
+use crate::models::TxType;
+use anyhow::anyhow;
+use csv::{ByteRecord, Position, ReaderBuilder, StringRecord, WriterBuilder};
+use std::collections::HashSet;
+use std::io::{Read, Seek, Write};
+
+/// Where a deposit row starts, and whose it is.
+#[derive(Debug, Clone, Copy)]
+struct Indexed {
+    offset: u64,
+    client: u16,
+}
+
+/// Positions of the `type`, `client` and `tx` columns.
+#[derive(Debug, Clone, Copy)]
+struct Columns {
+    kind: usize,
+    client: usize,
+    tx: usize,
+}
+
+impl Columns {
+    fn of(header: &StringRecord) -> Result<Self> {
+        let find = |name: &str| {
+            header
+                .iter()
+                .position(|h| h == name)
+                .ok_or_else(|| anyhow!("input has no {name:?} column"))
+        };
+        Ok(Self {
+            kind: find("type")?,
+            client: find("client")?,
+            tx: find("tx")?,
+        })
+    }
+
+    /// Type, client and tx id of a row; `None` if any does not parse.
+    fn parse(&self, record: &ByteRecord) -> Option<(TxType, u16, u32)> {
+        let field = |i| std::str::from_utf8(record.get(i)?).ok();
+        Some((
+            field(self.kind)?.parse().ok()?,
+            field(self.client)?.parse().ok()?,
+            field(self.tx)?.parse().ok()?,
+        ))
+    }
+}
+
+/// Deposits of a CSV input by tx id; see the module docs.
+#[derive(Debug, Clone, Default)]
+pub struct DepositIndex {
+    deposits: Map<u32, Indexed>,
+}
+
+impl DepositIndex {
+    /// First pass: index the deposits of the CSV in `src`. Rows that do not
+    /// parse are left for the engine to report.
+    pub fn build<R: Read>(src: R) -> Result<Self> {
+        let mut rdr = ReaderBuilder::new().trim(csv::Trim::All).from_reader(src);
+        let cols = Columns::of(rdr.headers()?)?;
+        let mut deposits = Map::default();
+        let mut record = ByteRecord::new();
+        loop {
+            let offset = rdr.position().byte();
+            if !rdr.read_byte_record(&mut record)? {
+                break;
+            }
+            if let Some((TxType::Deposit, client, tx)) = cols.parse(&record) {
+                deposits.entry(tx).or_insert(Indexed { offset, client });
+            }
+        }
+        Ok(Self { deposits })
+    }
+
+    /// Deposits indexed.
+    pub fn len(&self) -> usize {
+        self.deposits.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deposits.is_empty()
+    }
+
+    /// Second pass: copy the CSV in `src` to `dst`, header first, moving
+    /// each deposit ahead of the first dispute row naming it. `lookup`
+    /// reads the same data as `src` and is used to fetch moved deposits.
+    /// Returns the number of deposits moved.
+    pub fn reorder<R: Read + Seek, W: Write>(&self, src: R, lookup: R, dst: W) -> Result<u64> {
+        let mut rdr = ReaderBuilder::new().trim(csv::Trim::All).from_reader(src);
+        let cols = Columns::of(rdr.headers()?)?;
+        let mut fetch = ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(lookup);
+        let mut wtr = WriterBuilder::new().flexible(true).from_writer(dst);
+        wtr.write_byte_record(rdr.byte_headers()?)?;
+
+        let mut moved = HashSet::new();
+        let mut record = ByteRecord::new();
+        let mut deposit = ByteRecord::new();
+        loop {
+            let offset = rdr.position().byte();
+            if !rdr.read_byte_record(&mut record)? {
+                break;
+            }
+            match cols.parse(&record) {
+                // already sent ahead of its dispute (a later deposit
+                // reusing the id is copied as usual)
+                Some((TxType::Deposit, _, tx))
+                    if moved.contains(&tx) && self.deposits[&tx].offset == offset =>
+                {
+                    continue;
+                }
+                Some((TxType::Dispute | TxType::Resolve | TxType::Chargeback, client, tx)) => {
+                    if let Some(d) = self.deposits.get(&tx)
+                        && d.offset > offset
+                        && d.client == client
+                        && moved.insert(tx)
+                    {
+                        let mut at = Position::new();
+                        at.set_byte(d.offset);
+                        fetch.seek(at)?;
+                        if !fetch.read_byte_record(&mut deposit)? {
+                            anyhow::bail!("deposit {tx} vanished from the input");
+                        }
+                        wtr.write_byte_record(&deposit)?;
+                    }
+                }
+                _ => {}
+            }
+            wtr.write_byte_record(&record)?;
+        }
+        wtr.flush()?;
+        Ok(moved.len() as u64)
+    }
+}
@@ -0,0 +1,161 @@
+//! The amount parsing policy of `payments_engine::amount`, case by case:
+//! what is accepted and the exact value (and scale) it is read as, what is
+//! rejected, and that CSV rows go through the same parser.
+
+use payments_engine::Transaction;
+use payments_engine::amount::{AmountLocale, parse_amount};
+use rust_decimal::Decimal;
+
+/// Input and the exact decimal it must be read as, scale included.
+const ACCEPTED: &[(&str, &str)] = &[
+    ("0", "0"),
+    ("5", "5"),
+    ("12.50", "12.50"),
+    ("-3", "-3"),
+    ("+3", "3"),
+    ("-0", "0"),
+    ("0.0001", "0.0001"),
+    (".5", "0.5"),
+    ("-.5", "-0.5"),
+    ("5.", "5"),
+    ("007", "7"),
+    ("000.10", "0.10"),
+    ("1e3", "1000"),
+    ("1E3", "1000"),
+    ("1e+3", "1000"),
+    ("1e0", "1"),
+    ("1.5e3", "1500"),
+    ("1.5e1", "15"),
+    ("1.50e1", "15.0"),
+    ("2.5E-2", "0.025"),
+    ("-2.5e-2", "-0.025"),
+    (".5e1", "5"),
+    ("5.e-1", "0.5"),
+    ("0e9999", "0"),
+    ("0e-5", "0.00000"),
+    ("1e-28", "0.0000000000000000000000000001"),
+    ("1000e-31", "0.0000000000000000000000000001"),
+    ("123456789e-9", "0.123456789"),
+    (
+        "1.5000000000000000000000000000000000000000",
+        "1.5000000000000000000000000000",
+    ),
+    (
+        "79228162514264337593543950335",
+        "79228162514264337593543950335",
+    ),
+    (
+        "-79228162514264337593543950335",
+        "-79228162514264337593543950335",
+    ),
+    (
+        "7.9228162514264337593543950335e28",
+        "79228162514264337593543950335",
+    ),
+    ("1e28", "10000000000000000000000000000"),
+];
+
+const REJECTED: &[&str] = &[
+    "",
+    "-",
+    "+",
+    ".",
+    "-.",
+    "e3",
+    ".e3",
+    "1e",
+    "1e+",
+    "1e-",
+    "1e1.5",
+    "1e3e3",
+    "1ee3",
+    "1..5",
+    "1.5.",
+    "--1",
+    "+-1",
+    "1-",
+    "1_000",
+    "1 000",
+    "1,5",
+    "0x10",
+    "NaN",
+    "nan",
+    "inf",
+    "-inf",
+    "Infinity",
+    "١٢",
+    "1e10000",
+    "1e-10000",
+    "8e28",
+    "1e29",
+    "79228162514264337593543950336",
+    "0.00000000000000000000000000001",
+    "1e-29",
+    "0.11111111111111111111111111111",
+];
+
+#[test]
+fn accepted_amounts_keep_value_and_scale() {
+    for &(raw, want) in ACCEPTED {
+        let got = parse_amount(raw).unwrap_or_else(|e| panic!("{raw:?} rejected: {e}"));
+        assert_eq!(got.to_string(), want, "{raw:?}");
+    }
+}
+
+#[test]
+fn rejected_amounts_say_which() {
+    for &raw in REJECTED {
+        match parse_amount(raw) {
+            Ok(d) => panic!("{raw:?} accepted as {d}"),
+            Err(e) => assert!(e.contains(&format!("{raw:?}")), "{raw:?}: {e}"),
+        }
+    }
+}
+
+#[test]
+fn plain_amounts_agree_with_the_decimal_library() {
+    // without an exponent the policy only narrows what is accepted
+    for &(raw, _) in ACCEPTED.iter().filter(|(raw, _)| !raw.contains(['e', 'E'])) {
+        if let Ok(lib) = raw.parse::<Decimal>() {
+            assert_eq!(parse_amount(raw).unwrap(), lib, "{raw:?}");
+        }
+    }
+}
+
+#[test]
+fn csv_rows_use_the_policy() {
+    let csv = "type,client,tx,amount\n\
+               deposit,1,1,1e3\n\
+               deposit,1,2,.5\n\
+               dispute,1,2,\n\
+               deposit,1,3,1_000\n\
+               deposit,1,4, 2.5E-2 \n\
+               withdrawal,1,5,NaN\n";
+    let rows: Vec<_> = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(csv.as_bytes())
+        .into_deserialize::<Transaction>()
+        .map(|row| row.map(|tx| tx.amount.map(|a| a.to_string())).ok())
+        .collect();
+    assert_eq!(
+        rows,
+        [
+            Some(Some("1000".into())),
+            Some(Some("0.5".into())),
+            Some(None),
+            None,
+            Some(Some("0.025".into())),
+            None,
+        ]
+    );
+}
+
+#[test]
+fn locales_end_in_the_same_policy() {
+    let en = AmountLocale::En;
+    assert_eq!(en.parse("1,234.5").unwrap().amount.to_string(), "1234.5");
+    assert!(en.parse("1,234e3").is_err());
+    assert!(AmountLocale::Plain.parse("1e3").is_ok());
+    assert!(AmountLocale::Plain.parse("1_000").is_err());
+    assert!(AmountLocale::De.parse("NaN").is_err());
+}
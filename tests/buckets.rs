@@ -0,0 +1,225 @@
+//! Buckets (`payments_engine::bucket`) end to end: CSV rows with `bucket`
+//! and `to_bucket` columns, the rows refused as `bad-bucket`, disputes on
+//! the deposit's bucket and escrow.
+
+mod common;
+
+use common::{anomaly_row, engine, row};
+use payments_engine::anomaly::Anomaly;
+use payments_engine::bucket::{Balance, RowBuckets};
+use payments_engine::engine::{Decision, EngineConfig};
+use payments_engine::explain::Verdict;
+use payments_engine::processor::RowMeta;
+use payments_engine::{Engine, TxType};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+fn buckets<'a>(bucket: &'a str, to_bucket: &'a str) -> RowBuckets<'a> {
+    RowBuckets { bucket, to_bucket }
+}
+
+fn meta(b: RowBuckets) -> RowMeta {
+    RowMeta {
+        buckets: b,
+        ..Default::default()
+    }
+}
+
+/// `(bucket, available, held)` of every bucket of client 1.
+fn by_bucket(eng: &Engine) -> Vec<(String, Decimal, Decimal)> {
+    eng.buckets(1)
+        .into_iter()
+        .map(|(name, Balance { available, held })| (name, available, held))
+        .collect()
+}
+
+fn line(bucket: &str, available: Decimal, held: Decimal) -> (String, Decimal, Decimal) {
+    (bucket.to_string(), available, held)
+}
+
+#[test]
+fn csv_columns_route_rows() {
+    let csv = "type,client,tx,amount,bucket,to_bucket\n\
+               deposit,1,1,100,,\n\
+               deposit,1,2,40,savings,\n\
+               move,1,3,15,,rewards\n\
+               withdrawal,1,4,50,savings,\n\
+               withdrawal,1,5,10,savings,\n\
+               move,1,6,5,rewards,rewards\n";
+    let (mut eng, _) = engine(EngineConfig::default());
+    let summary = eng.process_reader(csv.as_bytes()).unwrap();
+
+    assert_eq!(summary.rejects["insufficient-funds"], 1);
+    assert_eq!(summary.rejects["bad-bucket"], 1);
+    assert_eq!(
+        by_bucket(&eng),
+        [
+            line("main", dec!(85), dec!(0)),
+            line("rewards", dec!(15), dec!(0)),
+            line("savings", dec!(30), dec!(0)),
+        ]
+    );
+    assert_eq!(eng.accounts[&1].available, dec!(130));
+    assert_eq!(eng.deposit_bucket(2), Some("savings"));
+    assert_eq!(eng.deposit_bucket(1), Some("main"));
+}
+
+#[test]
+fn rows_that_cannot_use_a_bucket() {
+    let (mut eng, _) = engine(EngineConfig::default());
+    eng.process_row(
+        row(TxType::Deposit, 1, 1, Some(dec!(10))),
+        meta(buckets("savings", "")),
+    )
+    .unwrap();
+    eng.process(row(TxType::Deposit, 1, 2, Some(dec!(10))))
+        .unwrap();
+    eng.process(row(TxType::Withdrawal, 1, 3, Some(dec!(5))))
+        .unwrap();
+    let cases = [
+        (row(TxType::Move, 1, 4, Some(dec!(1))), buckets("", "")),
+        (
+            row(TxType::Move, 1, 4, Some(dec!(1))),
+            buckets("savings", "savings"),
+        ),
+        (
+            row(TxType::Deposit, 1, 4, Some(dec!(1))),
+            buckets("", "savings"),
+        ),
+        (
+            row(TxType::Hold, 1, 4, Some(dec!(1))),
+            buckets("savings", ""),
+        ),
+        (
+            row(TxType::Refund, 1, 3, Some(dec!(1))),
+            buckets("savings", ""),
+        ),
+        // a dispute may name its deposit's bucket, but no other
+        (row(TxType::Dispute, 1, 1, None), buckets("rewards", "")),
+        (row(TxType::Dispute, 1, 2, None), buckets("savings", "")),
+    ];
+    for (tx, b) in cases {
+        let what = format!("{tx:?} in {b:?}");
+        assert_eq!(
+            anomaly_row(&mut eng, tx, meta(b)),
+            Some(Anomaly::BadBucket),
+            "{what}"
+        );
+    }
+    let ok = anomaly_row(
+        &mut eng,
+        row(TxType::Dispute, 1, 1, None),
+        meta(buckets("savings", "")),
+    );
+    assert_eq!(ok, None);
+}
+
+#[test]
+fn main_cannot_spend_what_was_moved_away() {
+    let (mut eng, _) = engine(EngineConfig::default());
+    eng.process(row(TxType::Deposit, 1, 1, Some(dec!(10))))
+        .unwrap();
+    eng.process_row(
+        row(TxType::Move, 1, 2, Some(dec!(8))),
+        meta(buckets("", "savings")),
+    )
+    .unwrap();
+    let withdraw = row(TxType::Withdrawal, 1, 3, Some(dec!(5)));
+    assert_eq!(
+        anomaly_row(&mut eng, withdraw, meta(buckets("", ""))),
+        Some(Anomaly::InsufficientFunds)
+    );
+    assert_eq!(
+        eng.can_withdraw(1, dec!(5)),
+        Decision::Decline(Anomaly::InsufficientFunds)
+    );
+    let why = eng
+        .explain_row(
+            row(TxType::Withdrawal, 1, 3, Some(dec!(5))),
+            meta(buckets("", "")),
+        )
+        .unwrap();
+    let failed = why.rules.iter().find(|r| r.verdict == Verdict::Failed);
+    assert_eq!(
+        failed.unwrap().text,
+        "bucket main covers the amount (2.0000 spendable, 5.0000 asked)"
+    );
+    let withdraw = row(TxType::Withdrawal, 1, 3, Some(dec!(5)));
+    assert_eq!(
+        anomaly_row(&mut eng, withdraw, meta(buckets("savings", ""))),
+        None
+    );
+}
+
+#[test]
+fn disputes_follow_the_deposit_bucket() {
+    let (mut eng, _) = engine(EngineConfig::default());
+    eng.process_row(
+        row(TxType::Deposit, 1, 1, Some(dec!(60))),
+        meta(buckets("savings", "")),
+    )
+    .unwrap();
+    eng.process(row(TxType::Deposit, 1, 2, Some(dec!(40))))
+        .unwrap();
+    eng.process_row(
+        row(TxType::Move, 1, 3, Some(dec!(60))),
+        meta(buckets("savings", "")),
+    )
+    .unwrap();
+    eng.process(row(TxType::Dispute, 1, 1, None)).unwrap();
+    assert_eq!(
+        by_bucket(&eng),
+        [
+            line("main", dec!(100), dec!(0)),
+            line("savings", dec!(-60), dec!(60))
+        ]
+    );
+    eng.process(row(TxType::Resolve, 1, 1, None)).unwrap();
+    assert_eq!(
+        by_bucket(&eng),
+        [
+            line("main", dec!(100), dec!(0)),
+            line("savings", dec!(0), dec!(0))
+        ]
+    );
+    eng.process(row(TxType::Dispute, 1, 1, None)).unwrap();
+    eng.process(row(TxType::Chargeback, 1, 1, None)).unwrap();
+    assert_eq!(
+        by_bucket(&eng),
+        [
+            line("main", dec!(100), dec!(0)),
+            line("savings", dec!(-60), dec!(0))
+        ]
+    );
+    assert!(eng.accounts[&1].locked);
+    assert_eq!(eng.deposit_bucket(1), Some("main"));
+}
+
+#[test]
+fn escrowed_deposits_release_into_their_bucket() {
+    let (mut eng, _) = engine(EngineConfig {
+        escrow_clients: [1].into(),
+        ..Default::default()
+    });
+    eng.process_row(
+        row(TxType::Deposit, 1, 1, Some(dec!(50))),
+        meta(buckets("rewards", "")),
+    )
+    .unwrap();
+    assert_eq!(
+        by_bucket(&eng),
+        [
+            line("main", dec!(0), dec!(0)),
+            line("rewards", dec!(0), dec!(50))
+        ]
+    );
+    eng.process(row(TxType::Release, 1, 1, Some(dec!(20))))
+        .unwrap();
+    assert_eq!(
+        by_bucket(&eng),
+        [
+            line("main", dec!(0), dec!(0)),
+            line("rewards", dec!(20), dec!(30))
+        ]
+    );
+}
@@ -0,0 +1,137 @@
+//! Robustness of the engine under the disturbances of `testing::chaos`:
+//! rows delivered late, twice or out of order.
+//!
+//! * duplicates alone must leave every balance as a clean run leaves it,
+//!   caught by the duplicate-id check, the dispute states or idempotency
+//!   keys. Withdrawal ids are not checked for reuse, so a withdrawal sent
+//!   twice is only caught by its key; withdrawals always carry one here;
+//! * in any delivery order the engine's invariants must hold row by row;
+//! * the sharded engine must agree with the single-threaded one on the
+//!   same disturbed stream, whatever the pauses do to thread timing.
+//!
+//! Inputs come from `generate::Generator`, whose tx ids are unique across
+//! clients. Set `CHAOS_CASES` to run more seeds.
+#![cfg(feature = "chaos")]
+
+use payments_engine::engine::EngineConfig;
+use payments_engine::generate::Generator;
+use payments_engine::parallel::ParallelEngine;
+use payments_engine::processor::{PaymentsProcessor, RowMeta};
+use payments_engine::testing::chaos::{Chaos, ChaosConfig};
+use payments_engine::{Engine, Transaction, TxType};
+use std::time::Duration;
+
+const ROWS: usize = 400;
+
+fn cases() -> u64 {
+    std::env::var("CHAOS_CASES")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(50)
+}
+
+fn checked() -> EngineConfig {
+    EngineConfig {
+        check_invariants: true,
+        ..EngineConfig::default()
+    }
+}
+
+/// Idempotency key of a generated row: deposits and withdrawals name the
+/// operation, dispute rows go without.
+fn key(kind: TxType, tx: u32) -> Option<String> {
+    matches!(kind, TxType::Deposit | TxType::Withdrawal).then(|| format!("op-{tx}"))
+}
+
+#[test]
+fn duplicates_leave_balances_unchanged() {
+    for case in 0..cases() {
+        let mut clean = Engine::with_config(checked());
+        let config = ChaosConfig {
+            seed: case,
+            duplicate_per_mille: 300,
+            ..ChaosConfig::default()
+        };
+        let mut keyed = Chaos::new(Engine::with_config(checked()), config.clone());
+        let mut by_id = Chaos::new(Engine::with_config(checked()), config);
+        for row in Generator::new(case, 20).take(ROWS) {
+            let key = key(row.kind, row.tx);
+            keyed
+                .process_row(Transaction { ..row }, RowMeta::keyed(key.as_deref()))
+                .unwrap();
+            let withdrawal_key = key.filter(|_| row.kind == TxType::Withdrawal);
+            by_id
+                .process_row(
+                    Transaction { ..row },
+                    RowMeta::keyed(withdrawal_key.as_deref()),
+                )
+                .unwrap();
+            clean.process(row).unwrap();
+        }
+        assert!(
+            keyed.injected().duplicated > 0,
+            "case {case}: nothing duplicated"
+        );
+        let keyed = keyed.into_inner().unwrap();
+        let by_id = by_id.into_inner().unwrap();
+        assert_eq!(
+            keyed.accounts, clean.accounts,
+            "case {case}: keyed retries applied"
+        );
+        assert_eq!(
+            by_id.accounts, clean.accounts,
+            "case {case}: duplicate rows applied"
+        );
+    }
+}
+
+#[test]
+fn invariants_hold_in_any_order() {
+    for case in 0..cases() {
+        let config = ChaosConfig {
+            seed: case,
+            duplicate_per_mille: 100,
+            reorder_window: 16,
+            ..ChaosConfig::default()
+        };
+        let mut chaos = Chaos::new(Engine::with_config(checked()), config);
+        for row in Generator::new(case, 20).take(ROWS) {
+            let key = key(row.kind, row.tx);
+            chaos
+                .process_row(row, RowMeta::keyed(key.as_deref()))
+                .unwrap_or_else(|e| panic!("case {case}: {e:#}"));
+        }
+        assert!(
+            chaos.injected().reordered > 0,
+            "case {case}: nothing reordered"
+        );
+        chaos
+            .finalize()
+            .unwrap_or_else(|e| panic!("case {case}: {e:#}"));
+    }
+}
+
+#[test]
+fn sharded_engine_agrees_under_chaos() {
+    for case in 0..cases().min(10) {
+        let config = ChaosConfig {
+            seed: case,
+            duplicate_per_mille: 100,
+            reorder_window: 16,
+            max_delay: Duration::from_micros(50),
+        };
+        let mut single = Chaos::new(Engine::with_config(checked()), config.clone());
+        let mut sharded = Chaos::new(ParallelEngine::new(4, checked()), config);
+        for row in Generator::new(case, 20).take(ROWS) {
+            single.process(Transaction { ..row }).unwrap();
+            sharded.process(row).unwrap();
+        }
+        let single = single.into_inner().unwrap();
+        let sharded = sharded.into_inner().unwrap().finish().unwrap();
+        assert_eq!(
+            sharded.state_hash(),
+            single.state_hash(),
+            "case {case}: shards disagree"
+        );
+    }
+}
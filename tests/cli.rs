@@ -0,0 +1,337 @@
+//! The `payments_engine` binary end to end, run on files in a scratch
+//! directory.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// An empty directory of its own for test `name`.
+fn scratch(name: &str) -> PathBuf {
+    let dir =
+        std::env::temp_dir().join(format!("payments_engine_cli_{name}_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn bin() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+}
+
+fn run<I, S>(args: I) -> Output
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<std::ffi::OsStr>,
+{
+    bin().args(args).output().unwrap()
+}
+
+fn read(path: &Path) -> String {
+    fs::read_to_string(path).unwrap()
+}
+
+#[test]
+fn resume_refuses_whole_run_outputs() {
+    let dir = scratch("resume_refuses");
+    let snap = dir.join("snap.bin");
+    for flag in ["--pg-script", "--batch-summaries", "--chargebacks-output"] {
+        let out = run([
+            "process".as_ref(),
+            "--input".as_ref(),
+            dir.join("in.csv").as_os_str(),
+            "--save-snapshot".as_ref(),
+            snap.as_os_str(),
+            "--resume".as_ref(),
+            flag.as_ref(),
+            dir.join("out").as_os_str(),
+        ]);
+        assert!(!out.status.success(), "{flag}");
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert!(stderr.contains("cannot be used with"), "{flag}: {stderr}");
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn interrupted_run_resumes_appending_to_cdc_out() {
+    use std::io::Write;
+    use std::os::unix::ffi::OsStrExt;
+    use std::time::{Duration, Instant};
+
+    let dir = scratch("resume_cdc");
+    let header = "type,client,tx,amount\n";
+    let rows: Vec<String> = (1..=20)
+        .map(|tx| format!("deposit,{},{tx},1.5\n", tx % 3))
+        .collect();
+    let input = dir.join("in.csv");
+    fs::write(&input, [header.to_owned(), rows.concat()].concat()).unwrap();
+
+    let full = run([
+        "process".as_ref(),
+        "--input".as_ref(),
+        input.as_os_str(),
+        "--output".as_ref(),
+        dir.join("full.csv").as_os_str(),
+        "--cdc-out".as_ref(),
+        dir.join("full.ndjson").as_os_str(),
+    ]);
+    assert!(full.status.success());
+
+    // the same rows through a fifo, interrupted once the first checkpoint
+    // shows ingest has started
+    let fifo = dir.join("in.fifo");
+    let path = std::ffi::CString::new(fifo.as_os_str().as_bytes()).unwrap();
+    // SAFETY: `path` is a valid NUL-terminated string.
+    assert_eq!(unsafe { libc::mkfifo(path.as_ptr(), 0o600) }, 0);
+    let snap = dir.join("snap.bin");
+    let cdc = dir.join("cdc.ndjson");
+    let mut child = bin()
+        .args([
+            "process".as_ref(),
+            "--input".as_ref(),
+            fifo.as_os_str(),
+            "--output".as_ref(),
+            dir.join("part.csv").as_os_str(),
+            "--save-snapshot".as_ref(),
+            snap.as_os_str(),
+            "--snapshot-every".as_ref(),
+            "1".as_ref(),
+            "--cdc-out".as_ref(),
+            cdc.as_os_str(),
+        ])
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut feed = fs::OpenOptions::new().write(true).open(&fifo).unwrap();
+    feed.write_all([header.to_owned(), rows[..5].concat()].concat().as_bytes())
+        .unwrap();
+    feed.flush().unwrap();
+    let waited = Instant::now();
+    while !snap.exists() {
+        assert!(waited.elapsed() < Duration::from_secs(30), "no checkpoint");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    // SAFETY: plain kill(2) of our own child.
+    assert_eq!(unsafe { libc::kill(child.id() as i32, libc::SIGINT) }, 0);
+    std::thread::sleep(Duration::from_millis(100));
+    // the run stops at the next row it reads, and may be gone by the end
+    let _ = feed.write_all(rows[5..].concat().as_bytes());
+    drop(feed);
+    assert!(!child.wait().unwrap().success(), "run was not interrupted");
+    let partial = read(&cdc);
+    assert!(partial.lines().count() < rows.len());
+
+    let resumed = run([
+        "process".as_ref(),
+        "--input".as_ref(),
+        input.as_os_str(),
+        "--output".as_ref(),
+        dir.join("resumed.csv").as_os_str(),
+        "--save-snapshot".as_ref(),
+        snap.as_os_str(),
+        "--resume".as_ref(),
+        "--cdc-out".as_ref(),
+        cdc.as_os_str(),
+    ]);
+    assert!(resumed.status.success());
+    assert!(read(&cdc).starts_with(&partial));
+    assert_eq!(read(&cdc), read(&dir.join("full.ndjson")));
+    assert_eq!(read(&dir.join("resumed.csv")), read(&dir.join("full.csv")));
+}
+
+#[test]
+fn flags_beat_env_and_env_beats_the_config_file() {
+    let dir = scratch("precedence");
+    let input = dir.join("in.csv");
+    fs::write(
+        &input,
+        "type,client,tx,amount\ndeposit,1,1,1\ndeposit,2,2,2\ndeposit,3,3,3\nwithdrawal,1,4,9\n",
+    )
+    .unwrap();
+    let config = dir.join("engine.toml");
+    fs::write(
+        &config,
+        format!(
+            "output = {:?}\nclient = [1]\nanomaly = [\"insufficient-funds=fatal\"]\n",
+            dir.join("file.csv").to_str().unwrap()
+        ),
+    )
+    .unwrap();
+    let clients = |name: &str| -> Vec<String> {
+        read(&dir.join(name))
+            .lines()
+            .skip(1)
+            .map(|l| l.split(',').next().unwrap().to_owned())
+            .collect()
+    };
+    let process = |env: &[(&str, &str)], flags: &[&str]| {
+        let mut cmd = bin();
+        cmd.args(["process".as_ref(), "--config".as_ref(), config.as_os_str()])
+            .args(["--input".as_ref(), input.as_os_str()])
+            .args(flags)
+            .envs(env.iter().copied());
+        cmd.output().unwrap().status.success()
+    };
+    let env_out = dir.join("env.csv");
+    let env_out = env_out.to_str().unwrap();
+    let cli_out = dir.join("cli.csv");
+    let ignore = ["--anomaly", "insufficient-funds=ignore"];
+
+    assert!(
+        !process(&[], &[]),
+        "the file's fatal anomaly was not applied"
+    );
+    // a repeatable flag's file values come first, so the command line's
+    // later ones win or add to them
+    assert!(process(&[], &ignore));
+    assert_eq!(clients("file.csv"), ["1"]);
+
+    // an env var drops the file's key
+    assert!(process(
+        &[("PAYMENTS_ENGINE_OUTPUT", env_out)],
+        &[&ignore[..], &["--client", "3"]].concat()
+    ));
+    assert_eq!(clients("env.csv"), ["1", "3"]);
+    assert!(process(
+        &[
+            ("PAYMENTS_ENGINE_OUTPUT", env_out),
+            ("PAYMENTS_ENGINE_CLIENT", "2"),
+            ("PAYMENTS_ENGINE_ANOMALY", "insufficient-funds=ignore"),
+        ],
+        &["--output", cli_out.to_str().unwrap()]
+    ));
+    assert_eq!(clients("cli.csv"), ["2"]);
+    assert_eq!(clients("env.csv"), ["1", "3"], "env output was rewritten");
+}
+
+#[test]
+fn bare_invocation_takes_subcommand_names_as_flag_values() {
+    let dir = scratch("bare");
+    fs::write(
+        dir.join("in.csv"),
+        "type,client,tx,amount\ndeposit,1,1,2.5\n",
+    )
+    .unwrap();
+    for name in ["config", "explain"] {
+        let out = bin()
+            .current_dir(&dir)
+            .args(["--input-format", "csv", "--output", name, "in.csv"])
+            .output()
+            .unwrap();
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        assert!(
+            read(&dir.join(name)).contains("1,2.5000,0.0000,2.5000,false"),
+            "{name}"
+        );
+    }
+}
+
+#[test]
+fn explain_checks_the_idempotency_key_as_process_does() {
+    let dir = scratch("explain_key");
+    let input = dir.join("in.csv");
+    fs::write(
+        &input,
+        "type,client,tx,amount,idempotency_key\n\
+         deposit,1,1,5,k1\n\
+         deposit,1,2,5,k1\n\
+         withdrawal,1,3,8,\n",
+    )
+    .unwrap();
+    let processed = run(["process".as_ref(), "--input".as_ref(), input.as_os_str()]);
+    assert!(String::from_utf8_lossy(&processed.stdout).contains("1,5.0000,0.0000,5.0000"));
+
+    let explained = run([
+        "explain".as_ref(),
+        "--input".as_ref(),
+        input.as_os_str(),
+        "--tx".as_ref(),
+        "2".as_ref(),
+    ]);
+    assert!(explained.status.success());
+    let why = String::from_utf8_lossy(&explained.stdout);
+    assert!(why.contains("Idempotency key: k1"), "{why}");
+    assert!(
+        why.contains("FAIL no earlier row of client 1 used key \"k1\" (tx 1 used it)"),
+        "{why}"
+    );
+    assert!(why.contains("Decision: rejected (duplicate-key)"), "{why}");
+}
+
+#[cfg(feature = "bank-statements")]
+#[test]
+fn statement_leaves_out_rows_process_rejects() {
+    let dir = scratch("statement_key");
+    let input = dir.join("in.csv");
+    fs::write(
+        &input,
+        "type,client,tx,amount,idempotency_key\n\
+         deposit,1,1,5,k1\n\
+         deposit,1,2,5,k1\n\
+         withdrawal,1,3,8,\n",
+    )
+    .unwrap();
+    let out = run([
+        "statement".as_ref(),
+        "--input".as_ref(),
+        input.as_os_str(),
+        "--client".as_ref(),
+        "1".as_ref(),
+        "--format".as_ref(),
+        "html".as_ref(),
+    ]);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let html = String::from_utf8_lossy(&out.stdout);
+    assert!(
+        html.contains("<tr><td>1</td><td>1</td><td>deposit</td>"),
+        "{html}"
+    );
+    assert!(!html.contains("<td>2</td><td>deposit</td>"), "{html}");
+    assert!(
+        html.contains("<tr><td>Closing balance</td><td class=\"num\">5.0000 EUR</td></tr>"),
+        "{html}"
+    );
+}
+
+#[test]
+fn source_of_funds_skips_rows_process_rejects() {
+    let dir = scratch("funds_key");
+    let input = dir.join("in.csv");
+    fs::write(
+        &input,
+        "type,client,tx,amount,idempotency_key\n\
+         deposit,1,1,5,k1\n\
+         deposit,1,2,5,k1\n\
+         withdrawal,1,3,8,\n\
+         deposit,2,4,1,\n",
+    )
+    .unwrap();
+    let lots = dir.join("lots.csv");
+    let out = run([
+        "source-of-funds".as_ref(),
+        "--input".as_ref(),
+        input.as_os_str(),
+        "--client".as_ref(),
+        "1".as_ref(),
+        "--lots".as_ref(),
+        lots.as_os_str(),
+    ]);
+    assert!(out.status.success());
+    // deposit 2 reused the key and withdrawal 3 found too little left
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout),
+        "client,debit,type,lot,source,amount\n"
+    );
+    assert_eq!(
+        read(&lots),
+        "client,tx,source,amount,remaining,frozen\n1,1,deposit,5.0000,5.0000,false\n"
+    );
+}
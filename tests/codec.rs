@@ -0,0 +1,176 @@
+//! The binary transaction encoding of `payments_engine::codec`: every kind
+//! and amount round-trips, damaged streams fail on the record they break,
+//! and a v1 byte stream stays readable as written.
+
+use payments_engine::codec::{TxDecoder, TxEncoder};
+use payments_engine::{Transaction, TxType};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+/// A v1 stream, byte for byte: a deposit, a dispute without an amount, a
+/// refund (first kind with a high tag bit) with multi-byte varints and a
+/// negative promo.
+const V1_STREAM: &[u8] = &[
+    b'P', b'E', b'T', b'X', 1, //
+    0x08, 0x01, 0x07, 0x01, 0x0f, //
+    0x02, 0x01, 0x07, //
+    0x18, 0xac, 0x02, 0xf0, 0xa2, 0x04, 0x04, 0x01, //
+    0x1a, 0x02, 0x09, 0x82, 0xe2, 0x09,
+];
+
+/// A transaction as comparable fields.
+type Fields = (TxType, u16, u32, Option<Decimal>);
+
+fn row(kind: TxType, client: u16, tx: u32, amount: Option<Decimal>) -> Transaction {
+    Transaction {
+        kind,
+        client,
+        tx,
+        amount,
+    }
+}
+
+fn encode(txs: &[Transaction]) -> Vec<u8> {
+    let mut enc = TxEncoder::new(Vec::new()).unwrap();
+    for tx in txs {
+        enc.write(tx).unwrap();
+    }
+    enc.finish().unwrap()
+}
+
+fn fields(tx: &Transaction) -> Fields {
+    (tx.kind, tx.client, tx.tx, tx.amount)
+}
+
+/// Every record the stream yields, errors as their message.
+fn decode(bytes: &[u8]) -> Vec<Result<Fields, String>> {
+    TxDecoder::new(bytes)
+        .unwrap()
+        .map(|r| r.map(|tx| fields(&tx)).map_err(|e| format!("{e:#}")))
+        .collect()
+}
+
+fn stream(records: &[u8]) -> Vec<u8> {
+    [&V1_STREAM[..5], records].concat()
+}
+
+#[test]
+fn every_kind_round_trips_with_and_without_an_amount() {
+    let txs: Vec<_> = TxType::ALL
+        .iter()
+        .flat_map(|&kind| {
+            [
+                row(kind, 1, 2, None),
+                row(kind, u16::MAX, u32::MAX, Some(dec!(1.5))),
+            ]
+        })
+        .collect();
+    let bytes = encode(&txs);
+    let decoded: Vec<_> = decode(&bytes).into_iter().map(Result::unwrap).collect();
+    assert_eq!(decoded, txs.iter().map(fields).collect::<Vec<_>>());
+}
+
+#[test]
+fn kind_index_skips_the_amount_bit() {
+    // the tag of an amountless row is the kind index with bit 3 skipped
+    for (index, &kind) in TxType::ALL.iter().enumerate() {
+        let bytes = encode(&[row(kind, 0, 0, None)]);
+        let index = index as u8;
+        assert_eq!(bytes[5], (index & 0b0111) | ((index & !0b0111) << 1));
+        assert_eq!(bytes[5] & 0b1000, 0, "{kind:?}");
+    }
+}
+
+#[test]
+fn amounts_keep_sign_and_scale() {
+    let amounts = [
+        dec!(-1.5),
+        dec!(-0.0001),
+        dec!(12.50),
+        dec!(0),
+        Decimal::new(1, 28),
+        Decimal::new(-1, 28),
+        Decimal::MAX,
+        Decimal::MIN,
+    ];
+    let txs: Vec<_> = amounts
+        .iter()
+        .map(|&a| row(TxType::Deposit, 1, 1, Some(a)))
+        .collect();
+    let decoded: Vec<_> = decode(&encode(&txs))
+        .into_iter()
+        .map(|r| r.unwrap().3.unwrap())
+        .collect();
+    assert_eq!(decoded, amounts);
+    for (got, want) in decoded.iter().zip(amounts) {
+        assert_eq!(got.scale(), want.scale(), "{want}");
+    }
+}
+
+#[test]
+fn truncated_record_fails_after_the_whole_ones() {
+    let bytes = encode(&[
+        row(TxType::Deposit, 1, 1, Some(dec!(2))),
+        row(TxType::Refund, 300, 70_000, Some(dec!(0.0001))),
+    ]);
+    let first = 5 + 5;
+    for cut in first + 1..bytes.len() {
+        let records = decode(&bytes[..cut]);
+        assert_eq!(records.len(), 2, "cut at {cut}");
+        assert!(records[0].is_ok());
+        assert_eq!(
+            records[1],
+            Err("record 2: truncated: failed to fill whole buffer".into())
+        );
+    }
+    assert_eq!(decode(&bytes[..first]).len(), 1);
+}
+
+#[test]
+fn varint_overflow_is_rejected() {
+    // client 65536 needs 17 bits
+    let client = decode(&stream(&[0x02, 0x80, 0x80, 0x04, 0x01]));
+    assert_eq!(client, [Err("record 1: varint overflows 16 bits".into())]);
+
+    // a tx with more continuation bytes than 32 bits allow
+    let tx = decode(&stream(&[0x02, 0x01, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]));
+    assert_eq!(tx, [Err("record 1: varint overflows 32 bits".into())]);
+
+    // 2^96 does not fit a decimal mantissa
+    let mut amount = vec![0x08, 0x01, 0x01, 0x00];
+    amount.extend([0x80; 13]);
+    amount.push(0x20);
+    let amount = decode(&stream(&amount));
+    assert_eq!(amount, [Err("record 1: varint overflows 96 bits".into())]);
+}
+
+#[test]
+fn unknown_kind_and_bad_header_are_rejected() {
+    let unknown = decode(&stream(&[0x13, 0x01, 0x01]));
+    assert_eq!(unknown, [Err("record 1: unknown kind 11".into())]);
+
+    for (header, message) in [
+        (
+            &b"PETY\x01"[..],
+            "not a binary transaction stream (bad magic)",
+        ),
+        (b"PETX\x02", "unsupported binary stream version 2"),
+        (b"PET", "binary stream shorter than its header"),
+    ] {
+        let err = TxDecoder::new(header).err().unwrap();
+        assert_eq!(err.to_string(), message);
+    }
+}
+
+#[test]
+fn v1_stream_is_pinned() {
+    let txs = [
+        row(TxType::Deposit, 1, 7, Some(dec!(1.5))),
+        row(TxType::Dispute, 1, 7, None),
+        row(TxType::Refund, 300, 70_000, Some(dec!(0.0001))),
+        row(TxType::Promo, 2, 9, Some(dec!(-12.50))),
+    ];
+    let decoded: Vec<_> = decode(V1_STREAM).into_iter().map(Result::unwrap).collect();
+    assert_eq!(decoded, txs.iter().map(fields).collect::<Vec<_>>());
+    assert_eq!(encode(&txs), V1_STREAM);
+}
@@ -0,0 +1,53 @@
+//! Fixtures shared by the integration tests that run one engine feature
+//! against the rest.
+
+// each test crate uses its own subset
+#![allow(dead_code)]
+
+use payments_engine::anomaly::Anomaly;
+use payments_engine::clock::ManualClock;
+use payments_engine::engine::EngineConfig;
+use payments_engine::feed::ProcessResult;
+use payments_engine::processor::RowMeta;
+use payments_engine::{Engine, Transaction, TxType};
+use rust_decimal::Decimal;
+
+/// An engine under `config` that checks its invariants row by row, on a
+/// manual clock starting at 0.
+pub fn engine(config: EngineConfig) -> (Engine, ManualClock) {
+    let mut eng = Engine::with_config(EngineConfig {
+        check_invariants: true,
+        ..config
+    });
+    let clock = ManualClock::new(0);
+    eng.set_clock(Box::new(clock.clone()));
+    (eng, clock)
+}
+
+pub fn row(kind: TxType, client: u16, tx: u32, amount: Option<Decimal>) -> Transaction {
+    Transaction {
+        kind,
+        client,
+        tx,
+        amount,
+    }
+}
+
+/// The anomaly `tx` was rejected with, if it was.
+pub fn anomaly(eng: &mut Engine, tx: Transaction) -> Option<Anomaly> {
+    anomaly_row(eng, tx, RowMeta::default())
+}
+
+/// [`anomaly`] for a row carrying `meta`.
+pub fn anomaly_row(eng: &mut Engine, tx: Transaction, meta: RowMeta) -> Option<Anomaly> {
+    match eng.process_row(tx, meta).unwrap() {
+        ProcessResult::Rejected { anomaly, .. } => Some(anomaly),
+        _ => None,
+    }
+}
+
+/// `(available, held, locked)` of `client`.
+pub fn balances(eng: &Engine, client: u16) -> (Decimal, Decimal, bool) {
+    let acc = &eng.accounts[&client];
+    (acc.available, acc.held, acc.locked)
+}
@@ -0,0 +1,130 @@
+//! The TOML subset `payments_engine::config` reads: strings, numbers,
+//! booleans and one-line arrays, comments around them, and the errors for
+//! everything outside it.
+
+use payments_engine::config::{self, Value};
+
+/// The value of the only key in `text`.
+fn value(text: &str) -> Value {
+    let entries = config::parse(text).unwrap();
+    assert_eq!(entries.len(), 1, "{text}");
+    entries[0].value.clone()
+}
+
+fn scalar(s: &str) -> Value {
+    Value::Scalar(s.into())
+}
+
+/// The full error of parsing `text`, context included.
+fn error(text: &str) -> String {
+    format!("{:#}", config::parse(text).unwrap_err())
+}
+
+#[test]
+fn basic_strings_take_escapes() {
+    assert_eq!(value(r#"a = "plain""#), scalar("plain"));
+    assert_eq!(
+        value(r#"a = "say \"hi\"\tand\\or\nbye""#),
+        scalar("say \"hi\"\tand\\or\nbye")
+    );
+    assert_eq!(value(r#"a = """#), scalar(""));
+}
+
+#[test]
+fn literal_strings_are_taken_as_written() {
+    assert_eq!(value(r"a = 'C:\out\n.csv'"), scalar(r"C:\out\n.csv"));
+    assert_eq!(value(r#"a = 'say "hi"'"#), scalar(r#"say "hi""#));
+}
+
+#[test]
+fn hash_inside_strings_is_not_a_comment() {
+    assert_eq!(value(r##"a = "x # y" # note"##), scalar("x # y"));
+    assert_eq!(value("a = 'x # y' # note"), scalar("x # y"));
+    // an escaped quote does not end the string early
+    assert_eq!(value(r##"a = "\"# y" # note"##), scalar("\"# y"));
+    assert_eq!(
+        value(r##"a = ["#1", '#2'] # note"##),
+        Value::List(vec!["#1".into(), "#2".into()])
+    );
+}
+
+#[test]
+fn comments_blank_lines_and_other_values() {
+    let entries = config::parse(
+        "# header\n\n  flag = true  \noff = false # trailing\nn = -1_000.5\nlist = [1, \"two\", ]\nempty = []\n",
+    )
+    .unwrap();
+    let got: Vec<_> = entries
+        .iter()
+        .map(|e| (e.key.as_str(), e.value.clone(), e.line))
+        .collect();
+    assert_eq!(
+        got,
+        [
+            ("flag", Value::Bool(true), 3),
+            ("off", Value::Bool(false), 4),
+            ("n", scalar("-1000.5"), 5),
+            ("list", Value::List(vec!["1".into(), "two".into()]), 6),
+            ("empty", Value::List(Vec::new()), 7),
+        ]
+    );
+}
+
+#[test]
+fn duplicate_keys_are_rejected() {
+    assert_eq!(error("a = 1\nb = 2\na = 3"), r#"line 3: duplicate key "a""#);
+}
+
+#[test]
+fn tables_are_rejected() {
+    for text in ["[process]", "x = 1\n  [[jobs]]"] {
+        assert!(
+            error(text).ends_with("tables are not supported; use top-level keys"),
+            "{text}"
+        );
+    }
+}
+
+#[test]
+fn bad_arrays_are_rejected() {
+    for (text, message) in [
+        (
+            "a = [1, 2",
+            "unterminated array (arrays must fit on one line)",
+        ),
+        (
+            "a = [\n1]",
+            "unterminated array (arrays must fit on one line)",
+        ),
+        (r#"a = ["x" "y"]"#, "expected `,` between array items"),
+        (
+            "a = [1,, 2]",
+            "expected a string, number, boolean or array, got \", 2\"",
+        ),
+        (
+            "a = [true]",
+            "expected a string, number, boolean or array, got \"true\"",
+        ),
+    ] {
+        assert_eq!(error(text), format!("line 1: a: {message}"), "{text}");
+    }
+}
+
+#[test]
+fn bad_lines_and_values_are_rejected() {
+    for (text, message) in [
+        ("a", "line 1: expected `key = value`"),
+        ("a b = 1", r#"line 1: bad key "a b""#),
+        ("= 1", r#"line 1: bad key """#),
+        (r#"a = "open"#, "line 1: a: unterminated string"),
+        ("a = 'open", "line 1: a: unterminated string"),
+        (r#"a = "\q""#, r"line 1: a: unsupported escape \q"),
+        (
+            "a = yes",
+            r#"line 1: a: expected a string, number, boolean or array, got "yes""#,
+        ),
+        (r#"a = "x" y"#, r#"line 1: a: unexpected "y" after value"#),
+    ] {
+        assert_eq!(error(text), message, "{text}");
+    }
+}
@@ -0,0 +1,148 @@
+//! Credit lines (`payments_engine::credit`) against the rest of the engine:
+//! drawing through withdrawals, holds and bucket moves, interest while the
+//! balance changes and after a lock.
+
+mod common;
+
+use common::{anomaly, engine, row};
+use payments_engine::TxType;
+use payments_engine::anomaly::Anomaly;
+use payments_engine::bucket::RowBuckets;
+use payments_engine::engine::{Decision, EngineConfig};
+use payments_engine::feed::ProcessResult;
+use payments_engine::processor::RowMeta;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+const YEAR: u64 = 365 * 86_400;
+
+/// Client 1 may draw 100 at `rate` a year.
+fn credit(rate: Option<Decimal>) -> EngineConfig {
+    EngineConfig {
+        credit_limits: [(1, dec!(100))].into(),
+        credit_interest: rate,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn only_listed_clients_draw_past_zero() {
+    let (mut eng, _) = engine(credit(None));
+    eng.process(row(TxType::Deposit, 1, 1, Some(dec!(10))))
+        .unwrap();
+    eng.process(row(TxType::Deposit, 2, 2, Some(dec!(10))))
+        .unwrap();
+    assert_eq!(
+        anomaly(&mut eng, row(TxType::Withdrawal, 2, 3, Some(dec!(11)))),
+        Some(Anomaly::InsufficientFunds)
+    );
+    assert_eq!(eng.can_withdraw(1, dec!(110)), Decision::Approve);
+    assert_eq!(
+        eng.can_withdraw(1, dec!(110.01)),
+        Decision::Decline(Anomaly::InsufficientFunds)
+    );
+    assert_eq!(
+        anomaly(&mut eng, row(TxType::Hold, 1, 4, Some(dec!(80)))),
+        None
+    );
+    assert_eq!(
+        anomaly(&mut eng, row(TxType::Withdrawal, 1, 5, Some(dec!(31)))),
+        Some(Anomaly::InsufficientFunds)
+    );
+    eng.process(row(TxType::Withdrawal, 1, 5, Some(dec!(30))))
+        .unwrap();
+    assert_eq!(eng.accounts[&1].available, dec!(-100));
+
+    // releasing the hold pays back part of the drawn amount
+    eng.process(row(TxType::Release, 1, 4, None)).unwrap();
+    let line = eng.credit_line(1).unwrap();
+    assert_eq!(
+        (line.drawn, line.headroom, line.utilization),
+        (dec!(20), dec!(80), dec!(0.2))
+    );
+    assert_eq!(eng.credit_line(2), None);
+    assert_eq!(eng.credit_clients(), [1]);
+}
+
+#[test]
+fn credit_is_drawn_through_main() {
+    let (mut eng, _) = engine(credit(None));
+    let savings = RowBuckets {
+        bucket: "savings",
+        to_bucket: "",
+    };
+    eng.process_row(
+        row(TxType::Deposit, 1, 1, Some(dec!(50))),
+        RowMeta {
+            buckets: savings,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let from_savings = row(TxType::Withdrawal, 1, 2, Some(dec!(60)));
+    let r = eng
+        .process_row(
+            from_savings,
+            RowMeta {
+                buckets: savings,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    assert!(matches!(
+        r,
+        ProcessResult::Rejected {
+            anomaly: Anomaly::InsufficientFunds,
+            ..
+        }
+    ));
+    eng.process(row(TxType::Withdrawal, 1, 3, Some(dec!(100))))
+        .unwrap();
+    assert_eq!(eng.accounts[&1].available, dec!(-50));
+    assert_eq!(eng.credit_line(1).unwrap().drawn, dec!(50));
+}
+
+#[test]
+fn interest_follows_the_drawn_amount() {
+    let (mut eng, clock) = engine(credit(Some(dec!(0.10))));
+    eng.process(row(TxType::Withdrawal, 1, 1, Some(dec!(100))))
+        .unwrap();
+    clock.advance(YEAR / 2);
+    // half a year on 100, then half a year on 40
+    eng.process(row(TxType::Deposit, 1, 2, Some(dec!(60))))
+        .unwrap();
+    clock.advance(YEAR);
+    assert_eq!(eng.credit_line(1).unwrap().interest, dec!(7));
+
+    // nothing drawn, nothing accrues
+    eng.process(row(TxType::Deposit, 1, 3, Some(dec!(40))))
+        .unwrap();
+    clock.advance(2 * YEAR);
+    assert_eq!(eng.credit_line(1).unwrap().interest, dec!(7));
+    assert_eq!(eng.accounts[&1].available, dec!(0));
+}
+
+#[test]
+fn chargeback_locks_the_line_and_stops_interest() {
+    let (mut eng, clock) = engine(credit(Some(dec!(0.10))));
+    eng.process(row(TxType::Deposit, 1, 1, Some(dec!(50))))
+        .unwrap();
+    eng.process(row(TxType::Withdrawal, 1, 2, Some(dec!(100))))
+        .unwrap();
+    eng.process(row(TxType::Dispute, 1, 1, None)).unwrap();
+    // the dispute takes the line past its limit
+    let line = eng.credit_line(1).unwrap();
+    assert_eq!(
+        (line.drawn, line.headroom, line.utilization),
+        (dec!(100), dec!(0), dec!(1))
+    );
+    clock.advance(YEAR);
+    eng.process(row(TxType::Chargeback, 1, 1, None)).unwrap();
+    clock.advance(3 * YEAR);
+    let line = eng.credit_line(1).unwrap();
+    assert_eq!((line.drawn, line.interest), (dec!(100), dec!(10)));
+    assert_eq!(
+        anomaly(&mut eng, row(TxType::Withdrawal, 1, 3, Some(dec!(1)))),
+        Some(Anomaly::LockedAccount)
+    );
+}
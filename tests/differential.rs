@@ -0,0 +1,418 @@
+//! Differential test of [`Engine`] against the reference model in
+//! `testing::reference`, on random transaction sequences. Every row must
+//! get the same verdict — applied, or rejected with the same anomaly — and
+//! every account must end with the same balances. The engine also checks
+//! its invariants after every row.
+//!
+//! Withdrawals are also checked against the prediction of
+//! [`Engine::can_withdraw`] just before them.
+//!
+//! [`Engine::explain`] must reach the same verdicts and blame every
+//! rejection on one of the rules it lists.
+//!
+//! The same sequences also check [`Engine::fork`]: random what-if rows
+//! pushed through a fork between real rows must leave no trace once it is
+//! dropped. Fixed cases do the same for escrow, buckets, credit interest and
+//! promotional credits, and carry that state through a snapshot.
+//!
+//! Clients and ids come from small ranges so rows keep running into earlier
+//! ones: reused deposit, withdrawal and hold ids, disputes of refunds, holds
+//! closed twice. Set
+//! `DIFFERENTIAL_CASES` to run more sequences per configuration, e.g. for a
+//! longer fuzzing session.
+
+mod common;
+
+use common::{engine, row};
+use payments_engine::anomaly::Anomaly;
+use payments_engine::bucket::RowBuckets;
+use payments_engine::engine::{Decision, EngineConfig};
+use payments_engine::explain::Verdict;
+use payments_engine::feed::ProcessResult;
+use payments_engine::processor::RowMeta;
+use payments_engine::testing::reference::Reference;
+use payments_engine::{Engine, Transaction, TxType};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+const ROWS: usize = 80;
+
+/// xorshift64*, so a failing case is reproducible from its number alone.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D) % n
+    }
+}
+
+fn random_row(rng: &mut Rng) -> Transaction {
+    // deposits are weighted up so there is money to move around
+    const KINDS: [TxType; 13] = [
+        TxType::Deposit,
+        TxType::Deposit,
+        TxType::Deposit,
+        TxType::Promo,
+        TxType::Withdrawal,
+        TxType::Dispute,
+        TxType::Resolve,
+        TxType::Chargeback,
+        TxType::Hold,
+        TxType::Release,
+        TxType::Capture,
+        TxType::Refund,
+        TxType::Refund,
+    ];
+    let kind = KINDS[rng.below(KINDS.len() as u64) as usize];
+    let amount = match rng.below(20) {
+        0 => None,
+        1 => Some(Decimal::ZERO),
+        2 => Some(-Decimal::new(rng.below(5_000) as i64 + 1, 2)),
+        _ => Some(Decimal::new(rng.below(5_000) as i64 + 1, 2)),
+    };
+    Transaction {
+        kind,
+        client: 1 + rng.below(3) as u16,
+        tx: 1 + rng.below(10) as u32,
+        amount,
+    }
+}
+
+/// A row's outcome as the reference model reports it; a deferred row has
+/// not found its tx yet.
+fn verdict(result: ProcessResult) -> Result<(), Anomaly> {
+    match result {
+        ProcessResult::Applied(_) => Ok(()),
+        ProcessResult::Rejected { anomaly, .. } => Err(anomaly),
+        ProcessResult::Deferred { .. } => Err(Anomaly::UnknownTx),
+    }
+}
+
+fn cases() -> u64 {
+    std::env::var("DIFFERENTIAL_CASES")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(300)
+}
+
+fn run(config: EngineConfig) {
+    for case in 0..cases() {
+        let mut rng = Rng::new(case);
+        let rows: Vec<Transaction> = (0..ROWS).map(|_| random_row(&mut rng)).collect();
+        let mut engine = Engine::with_config(EngineConfig {
+            check_invariants: true,
+            ..config.clone()
+        });
+        let mut model = Reference::with_config(&config);
+        for (i, row) in rows.iter().enumerate() {
+            // expiry before the row and sign flipping are not predicted
+            let predicted = match (row.kind, row.amount) {
+                (TxType::Withdrawal, Some(amount))
+                    if config.hold_expiry.is_none() && !config.normalize_negative =>
+                {
+                    Some(engine.can_withdraw(row.client, amount))
+                }
+                _ => None,
+            };
+            let expected = model.process(row);
+            let got = verdict(engine.process_with_result(Transaction { ..*row }).unwrap());
+            assert_eq!(
+                got,
+                expected,
+                "{config:?}, case {case}: verdicts differ at row {}\n{:#?}",
+                i + 1,
+                &rows[..=i]
+            );
+            // can_withdraw has no tx id, so it can't see one reused
+            if let Some(decision) = predicted
+                && got != Err(Anomaly::DuplicateTx)
+            {
+                let verdict = match decision {
+                    Decision::Approve => Ok(()),
+                    Decision::Decline(anomaly) => Err(anomaly),
+                };
+                assert_eq!(
+                    verdict,
+                    got,
+                    "{config:?}, case {case}: can_withdraw at row {}",
+                    i + 1
+                );
+            }
+        }
+        assert_eq!(
+            engine.accounts,
+            model.accounts(),
+            "{config:?}, case {case}: balances differ\n{rows:#?}"
+        );
+    }
+}
+
+#[test]
+fn default_config() {
+    run(EngineConfig::default());
+}
+
+#[test]
+fn hold_expiry() {
+    run(EngineConfig {
+        hold_expiry: Some(4),
+        ..EngineConfig::default()
+    });
+}
+
+#[test]
+fn gc_deposits() {
+    run(EngineConfig {
+        gc_deposits: true,
+        ..EngineConfig::default()
+    });
+}
+
+#[test]
+fn max_amounts() {
+    let mut config = EngineConfig::default();
+    config.max_amounts.set(None, Decimal::new(40, 0));
+    config
+        .max_amounts
+        .set(Some(TxType::Deposit), Decimal::new(45, 0));
+    run(config);
+}
+
+#[test]
+fn normalize_negative() {
+    run(EngineConfig {
+        normalize_negative: true,
+        ..EngineConfig::default()
+    });
+}
+
+/// Run every case on two engines, forking one of them before each row and
+/// throwing a few random rows at the fork; the two must agree throughout.
+fn run_forked(config: EngineConfig) {
+    for case in 0..cases() {
+        let mut rng = Rng::new(case);
+        let mut what_if = Rng::new(!case);
+        let mut engine = Engine::with_config(config.clone());
+        let mut plain = Engine::with_config(config.clone());
+        for i in 0..ROWS {
+            let row = random_row(&mut rng);
+            {
+                let mut fork = engine.fork();
+                for _ in 0..what_if.below(4) {
+                    fork.process(random_row(&mut what_if)).unwrap();
+                }
+            }
+            let got: Result<(), Anomaly> =
+                verdict(engine.process_with_result(Transaction { ..row }).unwrap());
+            let expected = verdict(plain.process_with_result(row).unwrap());
+            assert_eq!(
+                got,
+                expected,
+                "{config:?}, case {case}: verdicts differ at row {}",
+                i + 1
+            );
+        }
+        assert_eq!(engine.seq(), plain.seq());
+        assert_eq!(
+            engine.state_hash(),
+            plain.state_hash(),
+            "{config:?}, case {case}: a fork left state behind"
+        );
+    }
+}
+
+#[test]
+fn forks_leave_no_trace() {
+    run_forked(EngineConfig::default());
+    run_forked(EngineConfig {
+        hold_expiry: Some(4),
+        gc_deposits: true,
+        normalize_negative: true,
+        defer_unknown_disputes: true,
+        ..EngineConfig::default()
+    });
+}
+
+/// A step of a [`forks_and_snapshots_keep_feature_state`] case.
+#[derive(Clone, Copy)]
+enum Step {
+    /// A row of client 1 with its `bucket` and `to_bucket`.
+    Row(TxType, u32, Option<Decimal>, &'static str, &'static str),
+    /// The clock moves on to this time.
+    At(u64),
+}
+
+/// A feature under `config`: the rows setting up its state, what-if rows
+/// changing it in a fork, and rows to run after a snapshot.
+struct ForkCase {
+    name: &'static str,
+    config: EngineConfig,
+    setup: Vec<Step>,
+    what_if: Vec<Step>,
+    after: Vec<Step>,
+}
+
+#[test]
+fn forks_and_snapshots_keep_feature_state() {
+    const DAY: u64 = 86_400;
+    const YEAR: u64 = 365 * DAY;
+    let deposit = |tx, amount| Step::Row(TxType::Deposit, tx, Some(amount), "", "");
+    let cases = [
+        ForkCase {
+            name: "escrow",
+            config: EngineConfig {
+                escrow_clients: [1].into(),
+                ..Default::default()
+            },
+            setup: vec![
+                deposit(1, dec!(20)),
+                Step::Row(TxType::Release, 1, Some(dec!(5)), "", ""),
+            ],
+            what_if: vec![Step::Row(TxType::Release, 1, None, "", "")],
+            after: vec![Step::Row(TxType::Release, 1, Some(dec!(15)), "", "")],
+        },
+        ForkCase {
+            name: "buckets",
+            config: EngineConfig::default(),
+            setup: vec![Step::Row(TxType::Deposit, 1, Some(dec!(30)), "savings", "")],
+            what_if: vec![
+                Step::Row(TxType::Move, 2, Some(dec!(10)), "savings", "rewards"),
+                Step::Row(TxType::Dispute, 1, None, "", ""),
+            ],
+            after: vec![Step::Row(TxType::Dispute, 1, None, "", "")],
+        },
+        ForkCase {
+            name: "credit interest",
+            config: EngineConfig {
+                credit_limits: [(1, dec!(100))].into(),
+                credit_interest: Some(dec!(0.10)),
+                ..Default::default()
+            },
+            setup: vec![
+                Step::Row(TxType::Withdrawal, 1, Some(dec!(100)), "", ""),
+                Step::At(YEAR),
+                deposit(2, dec!(50)),
+            ],
+            what_if: vec![Step::At(2 * YEAR), deposit(3, dec!(50))],
+            after: vec![deposit(3, dec!(50))],
+        },
+        ForkCase {
+            name: "promotional credits",
+            config: EngineConfig {
+                promo_expiry_secs: Some(DAY),
+                ..Default::default()
+            },
+            setup: vec![
+                Step::Row(TxType::Promo, 1, Some(dec!(30)), "", ""),
+                Step::Row(TxType::Withdrawal, 2, Some(dec!(5)), "", ""),
+            ],
+            what_if: vec![
+                Step::Row(TxType::Promo, 3, Some(dec!(10)), "", ""),
+                Step::At(DAY + 1),
+                deposit(4, dec!(1)),
+            ],
+            after: vec![deposit(5, dec!(1))],
+        },
+    ];
+    for case in cases {
+        let name = case.name;
+        let (mut eng, clock) = engine(case.config);
+        // run `steps` through `process_row`, moving `clock` on as they say
+        let run = |steps: &[Step], process_row: &mut dyn FnMut(Transaction, RowMeta)| {
+            for &step in steps {
+                match step {
+                    Step::Row(kind, tx, amount, bucket, to_bucket) => {
+                        let buckets = RowBuckets { bucket, to_bucket };
+                        process_row(
+                            row(kind, 1, tx, amount),
+                            RowMeta {
+                                buckets,
+                                ..Default::default()
+                            },
+                        );
+                    }
+                    Step::At(now) => clock.advance(now),
+                }
+            }
+        };
+        run(&case.setup, &mut |tx, meta| {
+            eng.process_row(tx, meta).unwrap();
+        });
+        let hash = eng.state_hash();
+        {
+            let mut fork = eng.fork();
+            run(&case.what_if, &mut |tx, meta| {
+                fork.process_row(tx, meta).unwrap();
+            });
+            assert_ne!(
+                fork.state_hash(),
+                hash,
+                "{name}: the what-if changed nothing"
+            );
+        }
+        assert_eq!(eng.state_hash(), hash, "{name}: the fork left state behind");
+
+        let mut buf = Vec::new();
+        eng.write_snapshot(&mut buf).unwrap();
+        let mut restored = Engine::read_snapshot(buf.as_slice()).unwrap();
+        restored.set_config(eng.config().clone());
+        restored.set_clock(Box::new(clock.clone()));
+        assert_eq!(restored.state_hash(), hash, "{name}: snapshot");
+        run(&case.after, &mut |tx, meta| {
+            let again = Transaction { ..tx };
+            let got = verdict(restored.process_row(tx, meta).unwrap());
+            assert_eq!(got, Ok(()), "{name}: after the snapshot");
+            assert_eq!(verdict(eng.process_row(again, meta).unwrap()), got);
+        });
+        assert_eq!(restored.state_hash(), eng.state_hash(), "{name}");
+    }
+}
+
+/// Run every case on two engines, one explaining each row; they must agree
+/// and every rejection must fail exactly one listed rule, of its anomaly.
+fn run_explained(config: EngineConfig) {
+    for case in 0..cases() {
+        let mut rng = Rng::new(case);
+        let mut engine = Engine::with_config(config.clone());
+        let mut plain = Engine::with_config(config.clone());
+        for i in 0..ROWS {
+            let row = random_row(&mut rng);
+            let why = engine.explain(Transaction { ..row }).unwrap();
+            let expected = verdict(plain.process_with_result(row).unwrap());
+            let at = format!("{config:?}, case {case}, row {}\n{why}", i + 1);
+            assert_eq!(why.outcome, expected, "{at}");
+            let failed: Vec<_> = why
+                .rules
+                .iter()
+                .filter(|r| r.verdict == Verdict::Failed)
+                .map(|r| r.guards)
+                .collect();
+            assert_eq!(
+                failed,
+                why.outcome.err().into_iter().collect::<Vec<_>>(),
+                "{at}"
+            );
+        }
+        assert_eq!(engine.state_hash(), plain.state_hash());
+    }
+}
+
+#[test]
+fn explanations_blame_a_listed_rule() {
+    run_explained(EngineConfig::default());
+    let mut config = EngineConfig {
+        hold_expiry: Some(4),
+        gc_deposits: true,
+        normalize_negative: true,
+        ..EngineConfig::default()
+    };
+    config.max_amounts.set(None, Decimal::new(40, 0));
+    run_explained(config);
+}
@@ -0,0 +1,279 @@
+//! Every disputable transaction type × every dispute action, from every
+//! dispute state. Disputes hold the disputed amount at the client the
+//! original transaction credited. With deferral on, dispute rows sent
+//! ahead of their transaction must end as if sent right after it.
+
+use payments_engine::anomaly::Anomaly;
+use payments_engine::engine::EngineConfig;
+use payments_engine::feed::ProcessResult;
+use payments_engine::{Engine, Transaction, TxType};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+/// A transaction a dispute can refer to, and the amount it credited.
+#[derive(Debug, Clone, Copy)]
+enum Subject {
+    /// tx 1: deposit of 100.
+    Deposit,
+    /// tx 2: withdrawal of 40, of which 10 was refunded.
+    Refund,
+}
+
+impl Subject {
+    fn tx(self) -> u32 {
+        match self {
+            Subject::Deposit => 1,
+            Subject::Refund => 2,
+        }
+    }
+
+    fn amount(self) -> Decimal {
+        match self {
+            Subject::Deposit => dec!(100),
+            Subject::Refund => dec!(10),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum From {
+    Never,
+    Open,
+    Resolved,
+    ChargedBack,
+}
+
+/// What the action should do: apply with the resulting balances, or be
+/// rejected.
+#[derive(Debug, PartialEq)]
+enum Expect {
+    Applied {
+        available: Decimal,
+        held: Decimal,
+        locked: bool,
+    },
+    Rejected(Anomaly),
+}
+
+fn row(kind: TxType, client: u16, tx: u32, amount: Option<Decimal>) -> Transaction {
+    Transaction {
+        kind,
+        client,
+        tx,
+        amount,
+    }
+}
+
+/// [`credit`], then `subject` taken to `from`.
+fn engine_in(subject: Subject, from: From) -> Engine {
+    let mut eng = Engine::new();
+    credit(&mut eng);
+    for &kind in steps(from) {
+        let res = eng
+            .process_with_result(row(kind, 1, subject.tx(), None))
+            .unwrap();
+        assert!(
+            matches!(res, ProcessResult::Applied(_)),
+            "{subject:?} {kind:?}"
+        );
+    }
+    eng
+}
+
+/// Client 1 with available 70: +100 deposit, -40 withdrawal, +10 refund.
+fn credit(eng: &mut Engine) {
+    eng.process(row(TxType::Deposit, 1, 1, Some(dec!(100))))
+        .unwrap();
+    eng.process(row(TxType::Withdrawal, 1, 2, Some(dec!(40))))
+        .unwrap();
+    eng.process(row(TxType::Refund, 1, 2, Some(dec!(10))))
+        .unwrap();
+}
+
+/// Dispute rows taking a subject from [`From::Never`] to `from`.
+fn steps(from: From) -> &'static [TxType] {
+    match from {
+        From::Never => &[],
+        From::Open => &[TxType::Dispute],
+        From::Resolved => &[TxType::Dispute, TxType::Resolve],
+        From::ChargedBack => &[TxType::Dispute, TxType::Chargeback],
+    }
+}
+
+fn expected(subject: Subject, from: From, action: TxType) -> Expect {
+    let a = subject.amount();
+    let bal = |available, held, locked| Expect::Applied {
+        available,
+        held,
+        locked,
+    };
+    match (from, action) {
+        (From::ChargedBack, _) => Expect::Rejected(Anomaly::LockedAccount),
+        (From::Never | From::Resolved, TxType::Dispute) => bal(dec!(70) - a, a, false),
+        (From::Never | From::Resolved, _) => Expect::Rejected(Anomaly::NotDisputed),
+        (From::Open, TxType::Dispute) => Expect::Rejected(Anomaly::AlreadyDisputed),
+        (From::Open, TxType::Resolve) => bal(dec!(70), dec!(0), false),
+        (From::Open, _) => bal(dec!(70) - a, dec!(0), true),
+    }
+}
+
+#[test]
+fn every_subject_state_and_action() {
+    for subject in [Subject::Deposit, Subject::Refund] {
+        for from in [From::Never, From::Open, From::Resolved, From::ChargedBack] {
+            for action in [TxType::Dispute, TxType::Resolve, TxType::Chargeback] {
+                let mut eng = engine_in(subject, from);
+                let before = eng.accounts[&1].clone();
+                let res = eng
+                    .process_with_result(row(action, 1, subject.tx(), None))
+                    .unwrap();
+                let acc = &eng.accounts[&1];
+                let got = match res {
+                    ProcessResult::Applied(_) => Expect::Applied {
+                        available: acc.available,
+                        held: acc.held,
+                        locked: acc.locked,
+                    },
+                    ProcessResult::Rejected { anomaly, .. } => {
+                        assert_eq!(*acc, before, "rejected row changed the account");
+                        Expect::Rejected(anomaly)
+                    }
+                    ProcessResult::Deferred { .. } => unreachable!("deferral is off"),
+                };
+                assert_eq!(
+                    got,
+                    expected(subject, from, action),
+                    "{subject:?} from {from:?}, {action:?}"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn early_steps_wait_for_their_subject() {
+    let deferring = || {
+        Engine::with_config(EngineConfig {
+            defer_unknown_disputes: true,
+            ..EngineConfig::default()
+        })
+    };
+    for subject in [Subject::Deposit, Subject::Refund] {
+        for from in [From::Open, From::Resolved, From::ChargedBack] {
+            let mut early = deferring();
+            for &kind in steps(from) {
+                let res = early
+                    .process_with_result(row(kind, 1, subject.tx(), None))
+                    .unwrap();
+                assert!(
+                    matches!(res, ProcessResult::Deferred { .. }),
+                    "{subject:?} {kind:?}"
+                );
+            }
+            credit(&mut early);
+            assert_eq!(early.deferred_rows(), 0, "{subject:?} from {from:?}");
+
+            // the steps go through right after the row creating the subject
+            let mut in_order = deferring();
+            let creating = match subject {
+                Subject::Deposit => TxType::Deposit,
+                Subject::Refund => TxType::Refund,
+            };
+            for (kind, tx, amount) in [
+                (TxType::Deposit, 1, dec!(100)),
+                (TxType::Withdrawal, 2, dec!(40)),
+                (TxType::Refund, 2, dec!(10)),
+            ] {
+                in_order.process(row(kind, 1, tx, Some(amount))).unwrap();
+                if kind == creating {
+                    for &step in steps(from) {
+                        in_order.process(row(step, 1, tx, None)).unwrap();
+                    }
+                }
+            }
+            assert_eq!(
+                early.accounts[&1], in_order.accounts[&1],
+                "{subject:?} from {from:?}"
+            );
+        }
+    }
+}
+
+#[test]
+fn state_hash_covers_waiting_steps() {
+    let waiting = |kinds: &[TxType]| {
+        let mut eng = Engine::with_config(EngineConfig {
+            defer_unknown_disputes: true,
+            ..EngineConfig::default()
+        });
+        eng.process(row(TxType::Deposit, 1, 1, Some(dec!(100))))
+            .unwrap();
+        for &kind in kinds {
+            eng.process(row(kind, 1, 9, None)).unwrap();
+        }
+        eng
+    };
+    let none = waiting(&[]);
+    let both = waiting(&[TxType::Dispute, TxType::Resolve]);
+    let swapped = waiting(&[TxType::Resolve, TxType::Dispute]);
+    assert_eq!(none.accounts_hash(), both.accounts_hash());
+    assert_ne!(none.state_hash(), both.state_hash());
+    // the order they came in is the order they go through
+    assert_ne!(both.state_hash(), swapped.state_hash());
+}
+
+#[test]
+fn charged_back_refund_locks_the_account() {
+    let mut eng = engine_in(Subject::Refund, From::ChargedBack);
+    // the reversed refund no longer counts towards the refunded total, but
+    // the chargeback locked the account
+    let res = eng
+        .process_with_result(row(TxType::Refund, 1, 2, Some(dec!(40))))
+        .unwrap();
+    assert!(matches!(
+        res,
+        ProcessResult::Rejected {
+            anomaly: Anomaly::LockedAccount,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn disputes_name_the_credited_client() {
+    for subject in [Subject::Deposit, Subject::Refund] {
+        let mut eng = engine_in(subject, From::Never);
+        let res = eng
+            .process_with_result(row(TxType::Dispute, 2, subject.tx(), None))
+            .unwrap();
+        assert!(
+            matches!(
+                res,
+                ProcessResult::Rejected {
+                    anomaly: Anomaly::ClientMismatch,
+                    ..
+                }
+            ),
+            "{subject:?}"
+        );
+    }
+}
+
+#[test]
+fn withdrawal_without_refunds_is_not_disputable() {
+    let mut eng = Engine::new();
+    eng.process(row(TxType::Deposit, 1, 1, Some(dec!(5))))
+        .unwrap();
+    eng.process(row(TxType::Withdrawal, 1, 2, Some(dec!(5))))
+        .unwrap();
+    let res = eng
+        .process_with_result(row(TxType::Dispute, 1, 2, None))
+        .unwrap();
+    assert!(matches!(
+        res,
+        ProcessResult::Rejected {
+            anomaly: Anomaly::UnknownTx,
+            ..
+        }
+    ));
+}
@@ -0,0 +1,88 @@
+//! Inputs as Windows tools and banking exports write them: byte-order
+//! marks, CRLF or bare CR line endings, UTF-16 in either byte order. Every
+//! fixture in `tests/fixtures/encodings/` holds the same rows.
+
+use csv::ReaderBuilder;
+use payments_engine::encoding::{Decoder, Encoding};
+use payments_engine::{Engine, Transaction};
+use rust_decimal_macros::dec;
+
+fn run(raw: &[u8], encoding: Encoding) -> (Engine, Vec<String>) {
+    let mut eng = Engine::new();
+    let mut errors = Vec::new();
+    let mut rdr = ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(Decoder::new(raw, encoding));
+    for row in rdr.deserialize::<Transaction>() {
+        match row {
+            Ok(tx) => eng.process(tx).unwrap(),
+            Err(e) => errors.push(e.to_string()),
+        }
+    }
+    (eng, errors)
+}
+
+fn assert_fixture_state(eng: &Engine) {
+    assert_eq!(eng.accounts[&1].available, dec!(1.25));
+    let acc = &eng.accounts[&2];
+    assert_eq!((acc.available, acc.held), (dec!(0), dec!(2)));
+}
+
+#[test]
+fn every_encoding_is_detected_and_gives_the_same_state() {
+    for (name, raw) in [
+        (
+            "utf8-bom-crlf",
+            &include_bytes!("fixtures/encodings/utf8-bom-crlf.csv")[..],
+        ),
+        ("utf8-cr", include_bytes!("fixtures/encodings/utf8-cr.csv")),
+        (
+            "utf16le-bom",
+            include_bytes!("fixtures/encodings/utf16le-bom.csv"),
+        ),
+        (
+            "utf16be-bom",
+            include_bytes!("fixtures/encodings/utf16be-bom.csv"),
+        ),
+        (
+            "utf16le-no-bom",
+            include_bytes!("fixtures/encodings/utf16le-no-bom.csv"),
+        ),
+    ] {
+        let (eng, errors) = run(raw, Encoding::Auto);
+        assert!(errors.is_empty(), "{name}: {errors:?}");
+        assert_fixture_state(&eng);
+    }
+}
+
+#[test]
+fn explicit_encoding_overrides_detection_and_still_drops_the_bom() {
+    let raw = include_bytes!("fixtures/encodings/utf16le-bom.csv");
+    let (eng, errors) = run(raw, Encoding::Utf16Le);
+    assert!(errors.is_empty(), "{errors:?}");
+    assert_fixture_state(&eng);
+
+    // the wrong byte order turns every line ending into another character:
+    // one long header and no rows, but no panic either
+    let (eng, _) = run(raw, Encoding::Utf16Be);
+    assert!(eng.accounts.is_empty());
+}
+
+#[test]
+fn malformed_utf16_names_the_offset_after_the_rows_before_it() {
+    let raw = include_bytes!("fixtures/encodings/utf16le-unpaired-surrogate.csv");
+    let (eng, errors) = run(raw, Encoding::Auto);
+    assert_eq!(eng.accounts[&1].available, dec!(1.25));
+    // the deposit after the bad character is never read
+    assert!(
+        eng.accounts
+            .get(&2)
+            .is_none_or(|acc| acc.total() == dec!(0))
+    );
+    assert_eq!(errors.len(), 1);
+    assert!(
+        errors[0].contains("invalid UTF-16 at byte 152: unpaired surrogate 0xD800"),
+        "{}",
+        errors[0]
+    );
+}
@@ -0,0 +1,127 @@
+//! Escrow accounts (`payments_engine::escrow`) against the rest of the
+//! engine: releases that must be refused, disputes and chargebacks of
+//! partly released deposits, the state hash and explanations.
+
+mod common;
+
+use common::{anomaly, balances, engine, row};
+use payments_engine::TxType;
+use payments_engine::anomaly::Anomaly;
+use payments_engine::engine::EngineConfig;
+use payments_engine::explain::Verdict;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+const SELLER: u16 = 1;
+const BUYER: u16 = 2;
+
+/// The seller's deposits go into escrow.
+fn escrow() -> EngineConfig {
+    EngineConfig {
+        escrow_clients: [SELLER].into(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn refused_releases_change_nothing() {
+    let (mut eng, _) = engine(escrow());
+    eng.process(row(TxType::Deposit, SELLER, 1, Some(dec!(10))))
+        .unwrap();
+    eng.process(row(TxType::Deposit, BUYER, 2, Some(dec!(10))))
+        .unwrap();
+    let cases = [
+        (
+            row(TxType::Release, SELLER, 1, Some(dec!(11))),
+            Anomaly::OverRelease,
+        ),
+        (
+            row(TxType::Release, SELLER, 1, Some(dec!(0))),
+            Anomaly::NonPositiveAmount,
+        ),
+        (
+            row(TxType::Release, SELLER, 1, Some(dec!(-1))),
+            Anomaly::NonPositiveAmount,
+        ),
+        (
+            row(TxType::Release, BUYER, 1, None),
+            Anomaly::ClientMismatch,
+        ),
+        // not an escrow account: a release must name a hold
+        (row(TxType::Release, BUYER, 2, None), Anomaly::UnknownTx),
+    ];
+    for (tx, expected) in cases {
+        let what = format!("{tx:?}");
+        assert_eq!(anomaly(&mut eng, tx), Some(expected), "{what}");
+    }
+    assert_eq!(balances(&eng, SELLER), (dec!(0), dec!(10), false));
+
+    eng.process(row(TxType::Release, SELLER, 1, None)).unwrap();
+    assert_eq!(
+        anomaly(&mut eng, row(TxType::Release, SELLER, 1, None)),
+        Some(Anomaly::OverRelease)
+    );
+    assert_eq!(balances(&eng, SELLER), (dec!(10), dec!(0), false));
+}
+
+#[test]
+fn chargeback_takes_released_and_escrowed_funds() {
+    let (mut eng, _) = engine(escrow());
+    eng.process(row(TxType::Deposit, SELLER, 1, Some(dec!(100))))
+        .unwrap();
+    eng.process(row(TxType::Deposit, SELLER, 2, Some(dec!(5))))
+        .unwrap();
+    eng.process(row(TxType::Release, SELLER, 1, Some(dec!(40))))
+        .unwrap();
+    eng.process(row(TxType::Dispute, SELLER, 1, None)).unwrap();
+    assert_eq!(balances(&eng, SELLER), (dec!(0), dec!(105), false));
+    assert_eq!(
+        anomaly(&mut eng, row(TxType::Release, SELLER, 1, Some(dec!(1)))),
+        Some(Anomaly::OverRelease)
+    );
+
+    eng.process(row(TxType::Chargeback, SELLER, 1, None))
+        .unwrap();
+    // the other deposit stays in escrow
+    assert_eq!(balances(&eng, SELLER), (dec!(0), dec!(5), true));
+    assert_eq!(eng.escrowed(1), None);
+    assert_eq!(eng.escrowed(2), Some(dec!(5)));
+}
+
+#[test]
+fn state_hash_covers_escrowed_funds() {
+    // same balances, 100 held in escrow either way, split differently
+    let escrow = |split: [Decimal; 2]| {
+        let (mut eng, _) = engine(escrow());
+        for (tx, amount) in [1, 2].into_iter().zip(split) {
+            eng.process(row(TxType::Deposit, SELLER, tx, Some(amount)))
+                .unwrap();
+        }
+        eng
+    };
+    let a = escrow([dec!(50), dec!(50)]);
+    let b = escrow([dec!(70), dec!(30)]);
+    assert_eq!(balances(&a, SELLER), balances(&b, SELLER));
+    assert_eq!(a.accounts_hash(), b.accounts_hash());
+    assert_ne!(a.state_hash(), b.state_hash());
+}
+
+#[test]
+fn explanation_names_the_escrow_rule() {
+    let (mut eng, _) = engine(escrow());
+    eng.process(row(TxType::Deposit, SELLER, 1, Some(dec!(20))))
+        .unwrap();
+    let why = eng
+        .explain(row(TxType::Release, SELLER, 1, Some(dec!(25))))
+        .unwrap();
+    assert_eq!(why.outcome, Err(Anomaly::OverRelease));
+    let failed = why
+        .rules
+        .iter()
+        .find(|r| r.verdict == Verdict::Failed)
+        .unwrap();
+    assert_eq!(
+        failed.text,
+        "escrow covers the release (20.0000 in escrow, 25.0000 asked)"
+    );
+}
@@ -0,0 +1,61 @@
+//! Per-client history (`payments_engine::history`) across expiries: a hold
+//! or promo credit that runs out changes the balance without a row of the
+//! client's own, and point-in-time queries must still see it.
+
+use payments_engine::clock::ManualClock;
+use payments_engine::engine::EngineConfig;
+use payments_engine::{Engine, Transaction, TxType};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+fn row(kind: TxType, client: u16, tx: u32, amount: Option<Decimal>) -> Transaction {
+    Transaction {
+        kind,
+        client,
+        tx,
+        amount,
+    }
+}
+
+#[test]
+fn hold_expiry_is_recorded_as_a_release() {
+    let mut eng = Engine::with_config(EngineConfig {
+        hold_expiry: Some(1),
+        ..Default::default()
+    });
+    eng.enable_history();
+    eng.process(row(TxType::Deposit, 1, 1, Some(dec!(10))))
+        .unwrap();
+    eng.process(row(TxType::Hold, 1, 2, Some(dec!(4)))).unwrap();
+    // rows of another client move the expiry window along
+    eng.process(row(TxType::Deposit, 2, 3, Some(dec!(1))))
+        .unwrap();
+    eng.process(row(TxType::Deposit, 2, 4, Some(dec!(1))))
+        .unwrap();
+
+    let before = eng.balance_at(1, 3).unwrap();
+    assert_eq!((before.available, before.held), (dec!(6), dec!(4)));
+    let after = eng.balance_at(1, 4).unwrap();
+    assert_eq!((after.available, after.held), (dec!(10), dec!(0)));
+    let last = eng.history(1).last().unwrap();
+    assert_eq!((last.seq, last.tx, last.kind), (4, 2, TxType::Release));
+}
+
+#[test]
+fn promo_expiry_is_recorded() {
+    let mut eng = Engine::with_config(EngineConfig {
+        promo_expiry_secs: Some(60),
+        ..Default::default()
+    });
+    let clock = ManualClock::new(0);
+    eng.set_clock(Box::new(clock.clone()));
+    eng.enable_history();
+    eng.process(row(TxType::Promo, 1, 1, Some(dec!(5))))
+        .unwrap();
+    clock.advance(120);
+    eng.process(row(TxType::Deposit, 2, 2, Some(dec!(1))))
+        .unwrap();
+
+    assert_eq!(eng.balance_at(1, 1).unwrap().available, dec!(5));
+    assert_eq!(eng.balance_at(1, 2).unwrap().available, dec!(0));
+}
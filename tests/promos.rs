@@ -0,0 +1,124 @@
+//! Promotional credits (`payments_engine::promo`) against the rest of the
+//! engine: spending order, shared tx ids, expiry and what watchers see of
+//! it.
+
+mod common;
+
+use common::{anomaly, engine, row};
+use payments_engine::anomaly::Anomaly;
+use payments_engine::engine::EngineConfig;
+use payments_engine::{Engine, TxType};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+const DAY: u64 = 86_400;
+
+/// Promotional credits expiring `expiry` seconds after they are granted.
+fn promos(expiry: Option<u64>) -> EngineConfig {
+    EngineConfig {
+        promo_expiry_secs: expiry,
+        ..Default::default()
+    }
+}
+
+/// `(tx, used, expired, left)` of every promotional credit of client 1.
+fn grants(eng: &Engine) -> Vec<(u32, Decimal, Decimal, Decimal)> {
+    eng.promos(1)
+        .iter()
+        .map(|g| (g.tx, g.used, g.expired, g.left()))
+        .collect()
+}
+
+#[test]
+fn withdrawals_spend_promos_oldest_first() {
+    let (mut eng, _) = engine(promos(None));
+    eng.process(row(TxType::Promo, 1, 1, Some(dec!(10))))
+        .unwrap();
+    eng.process(row(TxType::Deposit, 1, 2, Some(dec!(100))))
+        .unwrap();
+    eng.process(row(TxType::Promo, 1, 3, Some(dec!(5))))
+        .unwrap();
+    eng.process(row(TxType::Hold, 1, 4, Some(dec!(20))))
+        .unwrap();
+    eng.process(row(TxType::Withdrawal, 1, 5, Some(dec!(12))))
+        .unwrap();
+    assert_eq!(
+        grants(&eng),
+        [
+            (1, dec!(10), dec!(0), dec!(0)),
+            (3, dec!(2), dec!(0), dec!(3))
+        ]
+    );
+    eng.process(row(TxType::Withdrawal, 1, 6, Some(dec!(50))))
+        .unwrap();
+    assert_eq!(grants(&eng)[1], (3, dec!(5), dec!(0), dec!(0)));
+    assert_eq!(eng.accounts[&1].available, dec!(33));
+}
+
+#[test]
+fn promos_share_deposit_ids_and_cannot_be_disputed() {
+    let (mut eng, _) = engine(promos(None));
+    eng.process(row(TxType::Deposit, 1, 1, Some(dec!(10))))
+        .unwrap();
+    eng.process(row(TxType::Promo, 1, 2, Some(dec!(10))))
+        .unwrap();
+    let cases = [
+        (
+            row(TxType::Promo, 1, 1, Some(dec!(5))),
+            Anomaly::DuplicateTx,
+        ),
+        (
+            row(TxType::Promo, 1, 2, Some(dec!(5))),
+            Anomaly::DuplicateTx,
+        ),
+        (
+            row(TxType::Deposit, 1, 2, Some(dec!(5))),
+            Anomaly::DuplicateTx,
+        ),
+        (row(TxType::Promo, 1, 3, None), Anomaly::MissingAmount),
+        (row(TxType::Dispute, 1, 2, None), Anomaly::UnknownTx),
+    ];
+    for (tx, expected) in cases {
+        let what = format!("{tx:?}");
+        assert_eq!(anomaly(&mut eng, tx), Some(expected), "{what}");
+    }
+    assert_eq!(eng.accounts[&1].available, dec!(20));
+}
+
+#[test]
+fn expiry_takes_back_what_is_left() {
+    let (mut eng, clock) = engine(promos(Some(DAY)));
+    let watch = eng.watch(|_| true);
+    eng.process(row(TxType::Deposit, 1, 1, Some(dec!(100))))
+        .unwrap();
+    eng.process(row(TxType::Promo, 1, 2, Some(dec!(30))))
+        .unwrap();
+    clock.advance(DAY / 2);
+    eng.process(row(TxType::Promo, 1, 3, Some(dec!(20))))
+        .unwrap();
+    eng.process(row(TxType::Withdrawal, 1, 4, Some(dec!(10))))
+        .unwrap();
+
+    // a window ends after the second it lasts
+    clock.advance(DAY);
+    eng.process(row(TxType::Deposit, 1, 5, Some(dec!(1))))
+        .unwrap();
+    assert_eq!(eng.accounts[&1].available, dec!(141));
+    clock.advance(DAY + 1);
+    eng.process(row(TxType::Deposit, 1, 6, Some(dec!(1))))
+        .unwrap();
+    assert_eq!(
+        grants(&eng),
+        [
+            (2, dec!(10), dec!(20), dec!(0)),
+            (3, dec!(0), dec!(0), dec!(20))
+        ]
+    );
+    assert_eq!(eng.accounts[&1].available, dec!(122));
+
+    let expiry = watch
+        .pending()
+        .find(|d| d.kind == TxType::Promo && d.available < Decimal::ZERO)
+        .unwrap();
+    assert_eq!((expiry.tx, expiry.available), (2, dec!(-20)));
+}
@@ -0,0 +1,329 @@
+//! Snapshots written by earlier releases must keep loading. Each fixture in
+//! `tests/fixtures/` is pinned forever; add a new one whenever the snapshot
+//! version is bumped.
+
+use payments_engine::anomaly::Anomaly;
+use payments_engine::clock::ManualClock;
+use payments_engine::engine::EngineConfig;
+use payments_engine::feed::ProcessResult;
+use payments_engine::processor::RowMeta;
+use payments_engine::{Engine, Transaction, TxType};
+use rust_decimal_macros::dec;
+
+const V1: &[u8] = include_bytes!("fixtures/snapshot_v1.bin");
+const V2: &[u8] = include_bytes!("fixtures/snapshot_v2.bin");
+const V3: &[u8] = include_bytes!("fixtures/snapshot_v3.bin");
+const V4: &[u8] = include_bytes!("fixtures/snapshot_v4.bin");
+const V5: &[u8] = include_bytes!("fixtures/snapshot_v5.bin");
+const V6: &[u8] = include_bytes!("fixtures/snapshot_v6.bin");
+const V7: &[u8] = include_bytes!("fixtures/snapshot_v7.bin");
+const V8: &[u8] = include_bytes!("fixtures/snapshot_v8.bin");
+const V9: &[u8] = include_bytes!("fixtures/snapshot_v9.bin");
+const V10: &[u8] = include_bytes!("fixtures/snapshot_v10.bin");
+const V11: &[u8] = include_bytes!("fixtures/snapshot_v11.bin");
+const V12: &[u8] = include_bytes!("fixtures/snapshot_v12.bin");
+const V13: &[u8] = include_bytes!("fixtures/snapshot_v13.bin");
+const V14: &[u8] = include_bytes!("fixtures/snapshot_v14.bin");
+
+#[test]
+fn v1_fixture_loads_and_keeps_dispute_state() {
+    let mut eng = Engine::read_snapshot(V1).unwrap();
+
+    let acc = &eng.accounts[&1];
+    assert_eq!(
+        (acc.available, acc.held, acc.locked),
+        (dec!(9.5), dec!(2.25), false)
+    );
+    assert!(eng.accounts[&2].locked);
+    assert_eq!(eng.accounts[&3].total(), dec!(7));
+
+    // tx 2 is still under dispute, so it can be resolved after the restore
+    eng.process(Transaction {
+        kind: TxType::Resolve,
+        client: 1,
+        tx: 2,
+        amount: None,
+    })
+    .unwrap();
+    assert_eq!(eng.accounts[&1].available, dec!(11.75));
+}
+
+#[test]
+fn v2_fixture_matches_v1_state_and_keeps_seq() {
+    let v1 = Engine::read_snapshot(V1).unwrap();
+    let v2 = Engine::read_snapshot(V2).unwrap();
+    assert_eq!(v1.state_hash(), v2.state_hash());
+    assert_eq!(v1.seq(), 0);
+    assert_eq!(v2.seq(), 8);
+    // v2 had no input position; resume from the last processed row
+    assert_eq!(v2.input_rows(), 8);
+}
+
+#[test]
+fn v3_fixture_matches_v1_state_and_keeps_input_position() {
+    let v1 = Engine::read_snapshot(V1).unwrap();
+    let v3 = Engine::read_snapshot(V3).unwrap();
+    assert_eq!(v1.state_hash(), v3.state_hash());
+    assert_eq!((v3.seq(), v3.input_rows()), (8, 8));
+}
+
+#[test]
+fn v4_fixture_keeps_closed_dispute_states() {
+    let v1 = Engine::read_snapshot(V1).unwrap();
+    let mut v4 = Engine::read_snapshot(V4).unwrap();
+    assert_eq!(v1.state_hash(), v4.state_hash());
+
+    // tx 4 was charged back: even on an unlocked account it stays final,
+    // which a v1 snapshot (open/closed flag only) could not express
+    v4.accounts.get_mut(&2).unwrap().locked = false;
+    v4.process(Transaction {
+        kind: TxType::Dispute,
+        client: 2,
+        tx: 4,
+        amount: None,
+    })
+    .unwrap();
+    assert_eq!(v4.accounts[&2].held, dec!(0));
+}
+
+#[test]
+fn v5_fixture_keeps_authorizations() {
+    let mut v5 = Engine::read_snapshot(V5).unwrap();
+    let acc = &v5.accounts[&3];
+    assert_eq!(
+        (acc.available, acc.held, acc.total()),
+        (dec!(4), dec!(2), dec!(6))
+    );
+
+    // tx 6 is still active and can be released; tx 7 was already captured
+    let row = |kind, tx| Transaction {
+        kind,
+        client: 3,
+        tx,
+        amount: None,
+    };
+    v5.process(row(TxType::Release, 6)).unwrap();
+    v5.process(row(TxType::Release, 7)).unwrap();
+    assert_eq!(
+        (v5.accounts[&3].available, v5.accounts[&3].held),
+        (dec!(6), dec!(0))
+    );
+}
+
+#[test]
+fn v6_fixture_keeps_refund_totals() {
+    let mut v6 = Engine::read_snapshot(V6).unwrap();
+    assert_eq!(v6.accounts[&3].available, dec!(3.5));
+
+    // 1 of the 1.5 withdrawn (tx 8) was refunded; only 0.5 is left
+    let refund = |amount| Transaction {
+        kind: TxType::Refund,
+        client: 3,
+        tx: 8,
+        amount: Some(amount),
+    };
+    v6.process(refund(dec!(0.75))).unwrap();
+    v6.process(refund(dec!(0.5))).unwrap();
+    assert_eq!(v6.accounts[&3].available, dec!(4));
+}
+
+#[test]
+fn v7_fixture_keeps_reclaimed_deposit_ids() {
+    let mut v7 = Engine::read_snapshot(V7).unwrap();
+    let v6 = Engine::read_snapshot(V6).unwrap();
+    assert!(v7.accounts[&4].locked);
+
+    // tx 20 was charged back and dropped; its id is still taken and still
+    // belongs to client 4
+    let row = |kind, amount| Transaction {
+        kind,
+        client: 5,
+        tx: 20,
+        amount,
+    };
+    let rejected = |r: ProcessResult| match r {
+        ProcessResult::Rejected { anomaly, .. } => Some(anomaly),
+        ProcessResult::Applied(_) | ProcessResult::Deferred { .. } => None,
+    };
+    let dup = v7.process_with_result(row(TxType::Deposit, Some(dec!(1))));
+    assert_eq!(rejected(dup.unwrap()), Some(Anomaly::DuplicateTx));
+    let dispute = v7.process_with_result(row(TxType::Dispute, None));
+    assert_eq!(rejected(dispute.unwrap()), Some(Anomaly::ClientMismatch));
+
+    // the v6 state is untouched apart from client 4
+    assert_eq!(v7.accounts[&3], v6.accounts[&3]);
+}
+
+#[test]
+fn v8_fixture_keeps_idempotency_keys() {
+    let mut v8 = Engine::read_snapshot(V8).unwrap();
+    let v7 = Engine::read_snapshot(V7).unwrap();
+    assert_eq!(
+        (v8.idempotent_tx(3, "pay-30"), v8.idempotent_tx(1, "pay-31")),
+        (Some(30), Some(31))
+    );
+    assert_eq!(v7.idempotent_tx(3, "pay-30"), None);
+
+    // a retry of tx 30 under a fresh id is caught after the restore
+    let retry = Transaction {
+        kind: TxType::Deposit,
+        client: 3,
+        tx: 32,
+        amount: Some(dec!(1)),
+    };
+    let r = v8
+        .process_row(retry, RowMeta::keyed(Some("pay-30")))
+        .unwrap();
+    assert!(matches!(
+        r,
+        ProcessResult::Rejected {
+            anomaly: Anomaly::DuplicateKey,
+            ..
+        }
+    ));
+    assert_eq!(v8.accounts[&3].available, dec!(4.5));
+    assert_eq!(v8.accounts[&4], v7.accounts[&4]);
+}
+
+#[test]
+fn v9_fixture_keeps_deferred_rows() {
+    let mut v9 = Engine::read_snapshot(V9).unwrap();
+    v9.set_config(EngineConfig {
+        defer_unknown_disputes: true,
+        ..Default::default()
+    });
+    assert_eq!(v9.deferred_rows(), 1);
+
+    // the dispute of tx 80 waits across the restore and goes through when
+    // the deposit arrives, claiming its key
+    v9.process(Transaction {
+        kind: TxType::Deposit,
+        client: 9,
+        tx: 80,
+        amount: Some(dec!(12)),
+    })
+    .unwrap();
+    assert_eq!(v9.deferred_rows(), 0);
+    assert_eq!(v9.accounts[&9].held, dec!(12));
+    assert_eq!(v9.idempotent_tx(9, "dsp-80"), Some(80));
+}
+
+#[test]
+fn v10_fixture_keeps_flags_and_notes() {
+    let v10 = Engine::read_snapshot(V10).unwrap();
+    let v9 = Engine::read_snapshot(V9).unwrap();
+    assert_eq!(v10.flags(3).collect::<Vec<_>>(), ["manual-review", "vip"]);
+    let notes = &v10.annotations(3).unwrap().notes;
+    assert_eq!((notes[0].by.as_str(), notes[0].seq), ("ops", 20));
+    assert!(v9.annotations(3).is_none());
+    assert_eq!(v10.state_hash(), v9.state_hash());
+    assert_eq!(v10.idempotent_tx(3, "pay-30"), Some(30));
+}
+
+#[test]
+fn v11_fixture_keeps_escrowed_funds() {
+    let mut v11 = Engine::read_snapshot(V11).unwrap();
+    v11.set_config(EngineConfig {
+        escrow_clients: [6].into(),
+        ..Default::default()
+    });
+    let acc = &v11.accounts[&6];
+    assert_eq!((acc.available, acc.held), (dec!(30), dec!(70)));
+    assert_eq!(v11.escrowed(40), Some(dec!(70)));
+
+    // the rest of tx 40 can still be released after the restore
+    v11.process(Transaction {
+        kind: TxType::Release,
+        client: 6,
+        tx: 40,
+        amount: None,
+    })
+    .unwrap();
+    let acc = &v11.accounts[&6];
+    assert_eq!((acc.available, acc.held), (dec!(100), dec!(0)));
+    assert_eq!(v11.flags(3).count(), 2);
+}
+
+#[test]
+fn v12_fixture_keeps_buckets() {
+    let mut v12 = Engine::read_snapshot(V12).unwrap();
+    let savings = |eng: &Engine| {
+        let buckets = eng.buckets(3);
+        let (_, b) = buckets.iter().find(|(name, _)| name == "savings").unwrap();
+        (b.available, b.held)
+    };
+    assert_eq!(savings(&v12), (dec!(10), dec!(0)));
+    assert_eq!(v12.deposit_bucket(50), Some("savings"));
+
+    // a dispute of tx 50 still holds the funds in the bucket they landed in
+    v12.process(Transaction {
+        kind: TxType::Dispute,
+        client: 3,
+        tx: 50,
+        amount: None,
+    })
+    .unwrap();
+    assert_eq!(savings(&v12), (dec!(0), dec!(10)));
+    assert_eq!(v12.escrowed(40), Some(dec!(70)));
+}
+
+#[test]
+fn v13_fixture_keeps_credit_interest() {
+    const YEAR: u64 = 365 * 86_400;
+    let mut v13 = Engine::read_snapshot(V13).unwrap();
+    v13.set_config(EngineConfig {
+        credit_limits: [(7, dec!(100))].into(),
+        credit_interest: Some(dec!(0.10)),
+        ..Default::default()
+    });
+    let clock = ManualClock::new(YEAR);
+    v13.set_clock(Box::new(clock.clone()));
+    assert_eq!(v13.accounts[&7].available, dec!(-30));
+    assert_eq!(v13.credit_line(7).unwrap().interest, dec!(4));
+
+    // interest goes on from where the snapshot settled it
+    clock.advance(2 * YEAR);
+    assert_eq!(v13.credit_line(7).unwrap().interest, dec!(7));
+    assert_eq!(v13.deposit_bucket(50), Some("savings"));
+}
+
+#[test]
+fn v14_fixture_keeps_promotional_credits() {
+    let mut v14 = Engine::read_snapshot(V14).unwrap();
+    let clock = ManualClock::new(0);
+    v14.set_clock(Box::new(clock.clone()));
+    let grant = v14.promos(8)[0];
+    assert_eq!(
+        (grant.tx, grant.used, grant.left()),
+        (70, dec!(5), dec!(15))
+    );
+
+    // tx 70 still expires on the restored clock
+    clock.advance(86_401);
+    v14.process(Transaction {
+        kind: TxType::Deposit,
+        client: 8,
+        tx: 72,
+        amount: Some(dec!(1)),
+    })
+    .unwrap();
+    assert_eq!(v14.promos(8)[0].expired, dec!(15));
+    assert_eq!(v14.accounts[&8].available, dec!(1));
+    assert_eq!(v14.accounts[&7].available, dec!(-30));
+}
+
+#[test]
+fn corrupted_payload_is_rejected() {
+    let mut bytes = V1.to_vec();
+    let last = bytes.len() - 2;
+    bytes[last] ^= 0xff;
+    let err = Engine::read_snapshot(bytes.as_slice()).err().unwrap();
+    assert!(err.to_string().contains("checksum"));
+}
+
+#[test]
+fn unknown_future_version_is_rejected() {
+    let mut bytes = V1.to_vec();
+    bytes[4] = 0xff;
+    assert!(Engine::read_snapshot(bytes.as_slice()).is_err());
+}